@@ -6,12 +6,14 @@ use winit::{
     dpi::PhysicalSize,
     dpi::PhysicalPosition,
 };
-use crate::engine::renderer::Renderer;
+use crate::engine::renderer::{
+    CullingStats, CustomPass, Instance, PostEffect, RenderPath, RenderTarget, Renderer, SkyMode, TransparencyMode, View, Viewport,
+};
 use crate::engine::scene::camera::Camera;
-use crate::engine::scene::light::SunController;
+use crate::engine::scene::light::{SceneLights, SunController};
 use crate::engine::input::controller::InputController;
 use std::time::{Instant, Duration};
-use cgmath::{Point3, Rad, SquareMatrix};
+use cgmath::{Point3, Rad};
 use wgpu::util::DeviceExt;
 
 /// Uniform buffer data structure for shader
@@ -22,11 +24,20 @@ pub struct Uniforms {
     pub model: [[f32; 4]; 4],
 }
 
+/// A camera besides the one the player controls, and where it renders
+struct SecondaryCamera {
+    camera: Camera,
+    target: Option<RenderTarget>, // Offscreen target materials sample, or None to draw into `viewport`
+    viewport: Viewport,           // Rectangle of the window, unused with a target
+}
+
 /// Main application for the game engine
 pub struct Application {
     window: Window,
     renderer: Renderer,
     camera: Camera,
+    viewport: Viewport,                    // Rectangle of the window the player's camera draws into
+    secondary_cameras: Vec<SecondaryCamera>,
     input: InputController,
     last_update: Instant,
     running: bool,
@@ -34,6 +45,7 @@ pub struct Application {
     sun_light_buffer: wgpu::Buffer,
     cube_uniform_buffer: wgpu::Buffer,  // Store the cube's uniform buffer
     game_objects: Vec<crate::engine::scene::GameObject>, // Game objects container
+    culling_stats: CullingStats,  // Last culling counts shown in the window title
 }
 
 impl Application {
@@ -43,8 +55,22 @@ impl Application {
         window.set_title("Mather Engine");
         window.set_maximized(true);
         window.set_resizable(true);
-        // Create renderer
-        let renderer = Renderer::new(&window).await;
+        // Create renderer, with 4x MSAA where the adapter supports it
+        let mut renderer = Renderer::new(&window).await;
+        if let Err(e) = renderer.set_sample_count(4) {
+            eprintln!("Rendering without MSAA: {}", e);
+        }
+        
+        // Bloom and a light vignette on by default; number keys toggle each effect
+        let mut post_process = renderer.post_process_settings().clone();
+        post_process.bloom.enabled = true;
+        post_process.vignette.enabled = true;
+        renderer.set_post_process_settings(post_process);
+        
+        // Ambient occlusion in creases and under objects; G toggles it
+        let mut ambient_occlusion = *renderer.ambient_occlusion_settings();
+        ambient_occlusion.enabled = true;
+        renderer.set_ambient_occlusion_settings(ambient_occlusion);
         
         // Create camera
         let size = window.inner_size();
@@ -83,60 +109,31 @@ impl Application {
         // Create a cube mesh with the mesh utility function
         let cube_mesh = crate::engine::renderer::mesh::Mesh::create_cube(renderer.device(), 2.0);
         
-        // Create a material for the cube
-        use crate::engine::renderer::texture::Texture;
-        let texture = Texture::create_colored_texture(
-            renderer.device(),
-            renderer.queue(),
+        // Create a white model for the cube
+        let cube_model = renderer.create_solid_model(
+            cube_mesh,
             [1.0, 1.0, 1.0], // White
-            "Cube Texture"
+            &uniform_buffer,
+            &sun_light_buffer,
+            "Cube"
         );
         
-        // Create a bind group for the cube
-        let bind_group = renderer.device().create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Cube Bind Group"),
-            layout: renderer.bind_group_layout(),
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: uniform_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: sun_light_buffer.as_entire_binding(),
-                },
-            ],
-        });
-        
-        // Create material instance
-        let material_instance = crate::engine::scene::model::MaterialInstance {
-            material_id: 0,
-            diffuse_bind_group: bind_group,
-        };
-        
-        // Create model
-        let cube_model = crate::engine::scene::Model {
-            meshes: vec![cube_mesh],
-            materials: vec![material_instance],
-        };
-        
-        // Add the model to the renderer
+        // Place the cube above the grid and add it to the renderer
+        let mut cube_model = cube_model;
+        cube_model.set_transform(renderer.queue(), &uniform_buffer, Self::cube_model_matrix());
         let mut renderer_mut = renderer;
         renderer_mut.add_model(cube_model);
         
+        // Start with the procedural sky so it follows the sun
+        renderer_mut.set_sky_mode(SkyMode::Atmosphere);
+        
         // Create the application
         let app = Self {
             window,
             renderer: renderer_mut,
             camera,
+            viewport: Viewport::FULL,
+            secondary_cameras: Vec::new(),
             input,
             last_update: Instant::now(),
             running: true,
@@ -144,62 +141,121 @@ impl Application {
             sun_light_buffer,
             cube_uniform_buffer: uniform_buffer,
             game_objects: Vec::new(),
+            culling_stats: CullingStats::default(),
         };
         
-        // Initialize the cube's uniform buffer
+        // Initialize the cube's and grid's uniform buffers
         app.update_cube_transform();
+        app.update_grid_transform();
         
         app
     }
     
-    /// Update the cube's transform
+    /// The test cube's transform, above the grid
+    fn cube_model_matrix() -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(cgmath::Vector3::new(0.0, 1.0, 0.0))
+    }
+    
+    /// Update the camera the cube is drawn with; its model matrix is set once when it is created
     fn update_cube_transform(&self) {
-        // Create uniform data with view-projection and model matrices
-        #[repr(C)]
-        #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-        struct Uniforms {
-            view_proj: [[f32; 4]; 4],
-            model: [[f32; 4]; 4],
-        }
-        
-        // Get the camera's view-projection matrix
-        let view_proj = self.camera.get_view_projection_matrix();
-        
-        // Create model matrix for the cube (positioned above the grid)
-        let model = cgmath::Matrix4::from_translation(cgmath::Vector3::new(0.0, 1.0, 0.0));
-        
-        // Create uniforms struct
-        let uniforms = Uniforms {
-            view_proj: view_proj.into(),
-            model: model.into(),
-        };
-        
-        // Write to the uniform buffer
+        let view_proj: [[f32; 4]; 4] = self.camera.get_view_projection_matrix().into();
         self.renderer.queue().write_buffer(
             &self.cube_uniform_buffer,
             0,
-            bytemuck::cast_slice(&[uniforms])
+            bytemuck::cast_slice(&[view_proj])
         );
     }
     
     /// Update the grid's transform
     fn update_grid_transform(&self) {
-        // Create uniform data with view-projection and model matrices
-        let uniforms = Uniforms {
-            view_proj: self.camera.get_view_projection_matrix().into(),
-            model: cgmath::Matrix4::<f32>::identity().into(),
-        };
-        
-        // Write to the uniform buffer only once instead of in a loop
-        if let Some(_) = self.renderer.get_model(0) {
-            self.renderer.queue().write_buffer(
-                &self.cube_uniform_buffer,
-                0,
-                bytemuck::cast_slice(&[uniforms])
-            );
+        self.renderer.update_grid_transform(self.camera.get_view_projection_matrix());
+    }
+    
+    #[allow(dead_code)]
+    /// Returns the scene's point and spot lights so they can be added or removed at runtime
+    pub fn lights_mut(&mut self) -> &mut SceneLights {
+        self.renderer.lights_mut()
+    }
+    
+    /// Light the scene forward or deferred. The deferred path renders without MSAA.
+    pub fn set_render_path(&mut self, path: RenderPath) -> anyhow::Result<()> {
+        self.renderer.set_render_path(path)?;
+        self.window.request_redraw();
+        Ok(())
+    }
+    
+    /// Sort alpha-blended meshes back to front, or composite them with weighted blended
+    /// order-independent transparency
+    pub fn set_transparency_mode(&mut self, mode: TransparencyMode) {
+        self.renderer.set_transparency_mode(mode);
+        self.window.request_redraw();
+    }
+    
+    #[allow(dead_code)]
+    /// Run `pass` every frame at its stage of the render graph, after the built-in passes of that stage
+    pub fn add_custom_pass(&mut self, pass: impl CustomPass + 'static) {
+        self.renderer.add_custom_pass(pass);
+    }
+    
+    #[allow(dead_code)]
+    /// Draw the player's camera into a rectangle of the window instead of all of it
+    pub fn set_viewport(&mut self, viewport: Viewport) {
+        self.viewport = viewport;
+        let size = self.window.inner_size();
+        self.fit_cameras_to_window(size.width.max(1), size.height.max(1));
+    }
+    
+    #[allow(dead_code)]
+    /// Add a camera drawing into a rectangle of the window next to the player's, for
+    /// split-screen. Returns its index among the secondary cameras.
+    pub fn add_viewport_camera(&mut self, camera: Camera, viewport: Viewport) -> usize {
+        self.secondary_cameras.push(SecondaryCamera { camera, target: None, viewport });
+        let size = self.window.inner_size();
+        self.fit_cameras_to_window(size.width.max(1), size.height.max(1));
+        self.secondary_cameras.len() - 1
+    }
+    
+    #[allow(dead_code)]
+    /// Add a camera rendering into an offscreen target of the given size every frame, for
+    /// mirrors, security monitors and minimaps. Bind the texture of `render_target` in a
+    /// material to show what it sees. Returns its index among the secondary cameras.
+    pub fn add_render_target_camera(&mut self, mut camera: Camera, width: u32, height: u32, label: &str) -> usize {
+        let target = self.renderer.create_render_target(width, height, label);
+        camera.set_aspect_ratio(target.width(), target.height());
+        self.secondary_cameras.push(SecondaryCamera { camera, target: Some(target), viewport: Viewport::FULL });
+        self.secondary_cameras.len() - 1
+    }
+    
+    #[allow(dead_code)]
+    /// Returns a camera added with `add_viewport_camera` or `add_render_target_camera`, to move it
+    pub fn secondary_camera_mut(&mut self, index: usize) -> Option<&mut Camera> {
+        self.secondary_cameras.get_mut(index).map(|secondary| &mut secondary.camera)
+    }
+    
+    #[allow(dead_code)]
+    /// Returns the offscreen target of a camera added with `add_render_target_camera`
+    pub fn render_target(&self, index: usize) -> Option<&RenderTarget> {
+        self.secondary_cameras.get(index).and_then(|secondary| secondary.target.as_ref())
+    }
+    
+    /// Match the aspect ratio of every camera drawing into the window to its viewport
+    fn fit_cameras_to_window(&mut self, width: u32, height: u32) {
+        let (_, _, viewport_width, viewport_height) = self.viewport.pixels(width, height);
+        self.camera.set_aspect_ratio(viewport_width, viewport_height);
+        for secondary in self.secondary_cameras.iter_mut().filter(|secondary| secondary.target.is_none()) {
+            let (_, _, viewport_width, viewport_height) = secondary.viewport.pixels(width, height);
+            secondary.camera.set_aspect_ratio(viewport_width, viewport_height);
         }
     }
     
+    /// Load an equirectangular `.hdr` or `.exr` image for image-based lighting and show it as the skybox
+    pub fn load_environment(&mut self, path: &str) -> anyhow::Result<()> {
+        self.renderer.load_environment(std::path::Path::new(path))?;
+        self.renderer.set_sky_mode(SkyMode::Cubemap);
+        self.window.request_redraw();
+        Ok(())
+    }
+    
     #[allow(dead_code)]
     /// Load a 3D model
     pub async fn load_model(&mut self, path: &str) -> anyhow::Result<()> {
@@ -218,11 +274,12 @@ impl Application {
             self.renderer.device(),
             self.renderer.queue(),
             self.renderer.bind_group_layout(),
+            self.renderer.material_bind_group_layout(),
         );
         
         // Load the model
         let model = model_loader.load_obj(
-            &Path::new(path),
+            Path::new(path),
             &uniform_buffer,
             Some(&self.sun_light_buffer),
         ).await?;
@@ -269,7 +326,7 @@ impl Application {
                             self.camera.set_rotation_origin(current_position);
                             
                             // Hide cursor but keep it in its original position
-                            let _ = self.window.set_cursor_visible(false);
+                            self.window.set_cursor_visible(false);
                             // Use Confined mode to keep cursor within window
                             let _ = self.window.set_cursor_grab(winit::window::CursorGrabMode::Confined);
                             
@@ -280,7 +337,7 @@ impl Application {
                             self.camera.end_rotation();
                             
                             // Return cursor to normal
-                            let _ = self.window.set_cursor_visible(true);
+                            self.window.set_cursor_visible(true);
                             let _ = self.window.set_cursor_grab(winit::window::CursorGrabMode::None);
                         }
                         self.window.request_redraw();
@@ -320,6 +377,78 @@ impl Application {
                             );
                             self.window.request_redraw();
                         }
+                        
+                        // Light cluster heatmap
+                        if keycode == winit::event::VirtualKeyCode::H && input.state == winit::event::ElementState::Pressed {
+                            let visible = !self.renderer.light_heatmap_visible();
+                            self.renderer.set_light_heatmap_visible(visible);
+                            self.window.request_redraw();
+                        }
+                        
+                        // Sky: clear color, environment cubemap or atmosphere
+                        if keycode == winit::event::VirtualKeyCode::B && input.state == winit::event::ElementState::Pressed {
+                            let mode = self.renderer.sky_mode().next();
+                            self.renderer.set_sky_mode(mode);
+                            self.window.request_redraw();
+                        }
+                        
+                        // MSAA: cycle through the sample counts the adapter supports
+                        if keycode == winit::event::VirtualKeyCode::V && input.state == winit::event::ElementState::Pressed {
+                            let counts = self.renderer.supported_sample_counts();
+                            let current = counts.iter().position(|&count| count == self.renderer.sample_count()).unwrap_or(0);
+                            let next = counts[(current + 1) % counts.len()];
+                            if let Err(e) = self.renderer.set_sample_count(next) {
+                                eprintln!("Failed to change MSAA sample count: {}", e);
+                            }
+                            self.window.request_redraw();
+                        }
+                        
+                        // Screen-space ambient occlusion, and the view of its raw buffer
+                        if keycode == winit::event::VirtualKeyCode::G && input.state == winit::event::ElementState::Pressed {
+                            let mut settings = *self.renderer.ambient_occlusion_settings();
+                            settings.enabled = !settings.enabled;
+                            self.renderer.set_ambient_occlusion_settings(settings);
+                            self.window.request_redraw();
+                        }
+                        if keycode == winit::event::VirtualKeyCode::Key6 && input.state == winit::event::ElementState::Pressed {
+                            let visible = !self.renderer.ambient_occlusion_debug_view();
+                            self.renderer.set_ambient_occlusion_debug_view(visible);
+                            self.window.request_redraw();
+                        }
+                        
+                        // Post effects: 1 to 5 toggle them in chain order
+                        if input.state == winit::event::ElementState::Pressed {
+                            let effect = match keycode {
+                                winit::event::VirtualKeyCode::Key1 => Some(PostEffect::ALL[0]),
+                                winit::event::VirtualKeyCode::Key2 => Some(PostEffect::ALL[1]),
+                                winit::event::VirtualKeyCode::Key3 => Some(PostEffect::ALL[2]),
+                                winit::event::VirtualKeyCode::Key4 => Some(PostEffect::ALL[3]),
+                                winit::event::VirtualKeyCode::Key5 => Some(PostEffect::ALL[4]),
+                                _ => None,
+                            };
+                            if let Some(effect) = effect {
+                                let mut settings = self.renderer.post_process_settings().clone();
+                                settings.set_enabled(effect, !settings.is_enabled(effect));
+                                self.renderer.set_post_process_settings(settings);
+                                self.window.request_redraw();
+                            }
+                        }
+                        
+                        // Tonemapping operator and exposure
+                        if input.state == winit::event::ElementState::Pressed {
+                            let mut settings = *self.renderer.tonemap_settings();
+                            match keycode {
+                                winit::event::VirtualKeyCode::T => settings.operator = settings.operator.next(),
+                                winit::event::VirtualKeyCode::X => settings.auto_exposure = !settings.auto_exposure,
+                                winit::event::VirtualKeyCode::LBracket => settings.exposure -= 0.5,
+                                winit::event::VirtualKeyCode::RBracket => settings.exposure += 0.5,
+                                _ => {}
+                            }
+                            if settings != *self.renderer.tonemap_settings() {
+                                self.renderer.set_tonemap_settings(settings);
+                                self.window.request_redraw();
+                            }
+                        }
                     }
                 }
                 
@@ -335,7 +464,7 @@ impl Application {
                         // On some platforms like Vulkan, certain dimensions might be problematic
                         // We'll resize and let the renderer handle any fallbacks
                         self.renderer.resize(width, height);
-                        self.camera.set_aspect_ratio(width, height);
+                        self.fit_cameras_to_window(width, height);
                         
                         // Update transforms after resize
                         self.update_cube_transform();
@@ -347,7 +476,7 @@ impl Application {
                         let height = new_inner_size.height.max(1);
                         
                         self.renderer.resize(width, height);
-                        self.camera.set_aspect_ratio(width, height);
+                        self.fit_cameras_to_window(width, height);
                         
                         // Update transforms after resize
                         self.update_cube_transform();
@@ -401,6 +530,9 @@ impl Application {
             bytemuck::cast_slice(&[self.sun_controller.sun_light])
         );
         
+        // Let auto exposure adapt to the scene
+        self.renderer.update_exposure(dt.as_secs_f32());
+        
         // Reset input state for next frame
         self.input.update();
         
@@ -410,67 +542,59 @@ impl Application {
     
     /// Render the frame
     fn render(&mut self) {
-        // Create a command encoder 
-        let mut encoder = self.renderer.device().create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Render Encoder"),
-        });
-        
-        // Add a timestamp query at the start of the frame (if available)
-        // This is a placeholder for future performance metrics
-        
-        // Render the scene
-        if let Err(e) = self.renderer.render_scene() {
+        // Render the offscreen targets first so the window shows this frame's images in them,
+        // then the player's camera and the split-screen cameras
+        let mut views: Vec<View> = self.secondary_cameras
+            .iter()
+            .filter_map(|secondary| secondary.target.as_ref().map(|target| View::with_target(&secondary.camera, target)))
+            .collect();
+        views.push(View::with_viewport(&self.camera, self.viewport));
+        views.extend(self.secondary_cameras
+            .iter()
+            .filter(|secondary| secondary.target.is_none())
+            .map(|secondary| View::with_viewport(&secondary.camera, secondary.viewport)));
+        
+        // Render the scene from each camera, with the shadows, lights and sky following it
+        if let Err(e) = self.renderer.render_views(&views, &self.sun_controller.sun_light, &self.sun_controller.shadow) {
             eprintln!("Failed to render scene: {:?}", e);
         }
         
-        // Add a timestamp query at the end of the frame (if available)
-        // This is a placeholder for future performance metrics
-        
-        // Submit the command buffer and make sure it contains at least one command
-        // to avoid the "No work has been submitted for this frame" error
-        encoder.push_debug_group("End of frame marker");
-        encoder.pop_debug_group();
-        
-        self.renderer.queue().submit(Some(encoder.finish()));
+        // Report how many meshes were culled when it changes
+        let culling_stats = self.renderer.culling_stats();
+        if culling_stats != self.culling_stats {
+            self.culling_stats = culling_stats;
+            self.window.set_title(&format!(
+                "Mather Engine - {} meshes drawn, {} culled",
+                culling_stats.meshes_drawn, culling_stats.meshes_culled
+            ));
+        }
     }
     
-    /// Add a game object to the scene
+    /// Add a game object to the scene. The renderer batches game objects loaded from the same
+    /// file into one instanced model, so they are drawn together with a single draw call per mesh.
     pub async fn add_game_object_with_model(&mut self, name: &str, model_path: &str, position: cgmath::Vector3<f32>) -> anyhow::Result<()> {
-        // Create game object with model
-        let game_object = crate::engine::scene::GameObject::with_model(
-            name,
-            model_path,
-            position,
-            self.renderer.device(),
-            self.renderer.queue(),
-            self.renderer.bind_group_layout(),
-            Some(&self.sun_light_buffer),
-        ).await?;
-        
-        // Add the model to the renderer
-        if let Some(model_component) = game_object.get_component::<crate::engine::scene::ModelComponent>() {
-            #[allow(unused)]
-            if let Some(ref model) = model_component.model {
-                // We can't clone the model, so we load it again for the renderer
-                #[allow(unused)]
-                let model_loader = crate::engine::resources::ModelLoader::new(
-                    self.renderer.device(),
-                    self.renderer.queue(),
-                    self.renderer.bind_group_layout(),
-                );
-                
-                // Load the model directly for the renderer
-                let model_for_renderer = model_loader.load_obj(
-                    std::path::Path::new(model_component.model_path.as_ref().unwrap()),
-                    &model_component.uniform_buffer.as_ref().unwrap(),
-                    Some(&self.sun_light_buffer),
-                ).await?;
-                
-                self.renderer.add_model(model_for_renderer);
-            }
+        // Load the model the first time it is used
+        if !self.renderer.has_model_batch(model_path) {
+            let uniform_buffer = self.renderer.create_batch_uniform_buffer(model_path);
+            let model_loader = crate::engine::resources::ModelLoader::new(
+                self.renderer.device(),
+                self.renderer.queue(),
+                self.renderer.bind_group_layout(),
+                self.renderer.material_bind_group_layout(),
+            );
+            let model = model_loader.load_obj(
+                std::path::Path::new(model_path),
+                &uniform_buffer,
+                Some(&self.sun_light_buffer),
+            ).await?;
+            
+            self.renderer.add_model_batch(model_path, model, uniform_buffer);
         }
         
-        // Store game object
+        // The game object only refers to the model; its transform becomes an instance
+        let mut game_object = crate::engine::scene::GameObject::new(name);
+        game_object.transform = crate::engine::scene::Transform::with_position(position);
+        game_object.add_component(crate::engine::scene::ModelComponent::with_path(model_path));
         self.game_objects.push(game_object);
         
         Ok(())
@@ -478,19 +602,21 @@ impl Application {
     
     /// Update all game objects
     fn update_game_objects(&mut self, dt: f32) {
+        let mut instances = Vec::new();
         for game_object in &mut self.game_objects {
-            // Create a copy of the transform by implementing Clone or manually copying values
-            #[allow(unused)]
-            let transform = game_object.transform.clone();
             game_object.update(dt);
+            if !game_object.active {
+                continue;
+            }
             
-            // Update model uniforms if has a model component
+            // Every game object with a model becomes an instance of that model's batch
+            let model_matrix = game_object.transform.model_matrix();
             if let Some(model_component) = game_object.get_component::<crate::engine::scene::ModelComponent>() {
-                let view_proj = self.camera.get_view_projection_matrix();
-                let model_matrix = game_object.transform.get_model_matrix();
-                
-                model_component.update_transform(self.renderer.queue(), view_proj, model_matrix);
+                if let Some(path) = &model_component.model_path {
+                    instances.push((path.as_str(), Instance::with_tint(model_matrix, model_component.tint)));
+                }
             }
         }
+        self.renderer.set_batched_instances(instances);
     }
 } 
\ No newline at end of file
@@ -0,0 +1,603 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, SquareMatrix, Vector3};
+use wgpu::util::DeviceExt;
+use crate::engine::renderer::instance::{Instance, InstanceBuffer, InstancedModel};
+use crate::engine::renderer::mesh;
+use crate::engine::renderer::texture::Texture;
+use crate::engine::scene::{Camera, Model};
+
+/// First binding of the ambient occlusion in the light bind group, after the environment
+pub const AMBIENT_OCCLUSION_FIRST_BINDING: u32 = 11;
+/// Most hemisphere samples taken per pixel
+pub const MAX_AMBIENT_OCCLUSION_SAMPLES: u32 = 64;
+/// Format of the raw, half-blurred and blurred occlusion targets
+const OCCLUSION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+/// Format of the view-space normals written by the prepass
+const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// WGSL struct shared by the occlusion passes and the lit shaders
+pub const AMBIENT_OCCLUSION_TYPES_WGSL: &str = r#"
+struct AmbientOcclusionUniforms {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    inverse_projection: mat4x4<f32>,
+    kernel: array<vec4<f32>, 64>,
+    screen_size: vec2<f32>,
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+    sample_count: u32,
+    enabled: u32,
+    output_srgb: u32,
+};
+"#;
+
+/// WGSL declarations for the lit shaders. Expects the ambient occlusion in the light
+/// bind group at group 2, from `AMBIENT_OCCLUSION_FIRST_BINDING` on.
+pub const AMBIENT_OCCLUSION_WGSL: &str = r#"
+@group(2) @binding(11)
+var<uniform> ambient_occlusion: AmbientOcclusionUniforms;
+@group(2) @binding(12)
+var ambient_occlusion_map: texture_2d<f32>;
+
+// Blurred screen-space occlusion of the pixel, 1 while the pass is off
+fn screen_space_occlusion(frag_coord: vec2<f32>) -> f32 {
+    if (ambient_occlusion.enabled == 0u) {
+        return 1.0;
+    }
+    return textureLoad(ambient_occlusion_map, vec2<i32>(frag_coord), 0).r;
+}
+"#;
+
+/// Screen-space ambient occlusion parameters
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AmbientOcclusionSettings {
+    pub enabled: bool,
+    pub radius: f32,       // View-space radius of the sampled hemisphere
+    pub intensity: f32,    // Exponent applied to the unoccluded fraction, higher darkens
+    pub sample_count: u32, // Hemisphere samples per pixel, up to MAX_AMBIENT_OCCLUSION_SAMPLES
+    pub bias: f32,         // Depth difference below which a sample does not occlude, against acne
+}
+
+impl Default for AmbientOcclusionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            radius: 0.5,
+            intensity: 1.0,
+            sample_count: 16,
+            bias: 0.025,
+        }
+    }
+}
+
+/// `index`-th element of the Halton low-discrepancy sequence in `base`, in [0, 1)
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// Hemisphere kernel around +Z with `count` samples, spread evenly by a Halton sequence
+/// and packed closer to the center so nearby geometry counts more
+pub fn sample_kernel(count: u32) -> Vec<[f32; 4]> {
+    let count = count.clamp(1, MAX_AMBIENT_OCCLUSION_SAMPLES);
+    (0..count)
+        .map(|i| {
+            let direction = Vector3::new(
+                halton(i + 1, 2) * 2.0 - 1.0,
+                halton(i + 1, 3) * 2.0 - 1.0,
+                halton(i + 1, 5),
+            )
+            .normalize();
+            let t = (i + 1) as f32 / count as f32;
+            let sample = direction * (0.1 + 0.9 * t * t);
+            [sample.x, sample.y, sample.z, 0.0]
+        })
+        .collect()
+}
+
+/// Camera, kernel and parameters as laid out in the shaders
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct AmbientOcclusionUniforms {
+    view: [[f32; 4]; 4],
+    projection: [[f32; 4]; 4],
+    inverse_projection: [[f32; 4]; 4],
+    kernel: [[f32; 4]; MAX_AMBIENT_OCCLUSION_SAMPLES as usize],
+    screen_size: [f32; 2],
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+    sample_count: u32,
+    enabled: u32,     // Set when the lit shaders darken their ambient term with the result
+    output_srgb: u32, // Set when the debug view writes to an sRGB target, which encodes on store
+}
+
+/// Depth, normals and occlusion targets at the output size, with the bind groups reading them
+pub struct Targets {
+    depth: Texture,
+    normals: Texture,
+    raw: Texture,     // Unblurred occlusion, shown by the debug view
+    blurred: Texture, // Occlusion blurred along rows
+    occlusion: Texture,
+    ssao_bind_group: wgpu::BindGroup,
+    blur_horizontal_bind_group: wgpu::BindGroup, // Also read by the debug view
+    blur_vertical_bind_group: wgpu::BindGroup,
+}
+
+/// Screen-space ambient occlusion.
+///
+/// A prepass renders the scene's depth and view-space normals, an SSAO pass compares a
+/// rotated hemisphere of samples around each pixel against that depth, and a separable
+/// depth-aware blur removes the noise before the lit pass reads the result.
+pub struct AmbientOcclusion {
+    settings: AmbientOcclusionSettings,
+    uniforms: AmbientOcclusionUniforms,
+    uniform_buffer: wgpu::Buffer,
+    has_camera: bool,
+    debug_view: bool,
+    camera_bind_group: wgpu::BindGroup,
+    prepass_pipeline: wgpu::RenderPipeline,
+    pass_bind_group_layout: wgpu::BindGroupLayout,
+    ssao_pipeline: wgpu::RenderPipeline,
+    blur_horizontal_pipeline: wgpu::RenderPipeline,
+    blur_vertical_pipeline: wgpu::RenderPipeline,
+    debug_pipeline: wgpu::RenderPipeline,
+    targets: Targets,
+}
+
+impl AmbientOcclusion {
+    /// Create the occlusion pipelines and targets for the given output configuration.
+    /// Occlusion stays off until a camera is provided.
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        object_bind_group_layout: &wgpu::BindGroupLayout,
+        prepass_shader: &wgpu::ShaderModule,
+        shader: &wgpu::ShaderModule,
+    ) -> Self {
+        let settings = AmbientOcclusionSettings::default();
+        let mut uniforms = AmbientOcclusionUniforms::zeroed();
+        uniforms.view = cgmath::Matrix4::identity().into();
+        uniforms.projection = cgmath::Matrix4::identity().into();
+        uniforms.inverse_projection = cgmath::Matrix4::identity().into();
+        Self::apply_settings(&mut uniforms, &settings, false);
+        uniforms.screen_size = [config.width as f32, config.height as f32];
+        uniforms.output_srgb = config.format.is_srgb() as u32;
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Ambient Occlusion Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let uniform_entry = |visibility| wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        // The prepass reads the camera next to each model's transforms
+        let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Ambient Occlusion Camera Bind Group Layout"),
+            entries: &[uniform_entry(wgpu::ShaderStages::VERTEX)],
+        });
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Ambient Occlusion Camera Bind Group"),
+            layout: &camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+        let prepass_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Ambient Occlusion Prepass Pipeline Layout"),
+            bind_group_layouts: &[object_bind_group_layout, &camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let prepass_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Ambient Occlusion Prepass Pipeline"),
+            layout: Some(&prepass_layout),
+            vertex: wgpu::VertexState {
+                module: prepass_shader,
+                entry_point: "vs_main",
+                buffers: &[mesh::Vertex::desc(), Instance::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: prepass_shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: NORMAL_FORMAT,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        // The fullscreen passes read the depth and one more target, by texel
+        let pass_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Ambient Occlusion Pass Bind Group Layout"),
+            entries: &[
+                uniform_entry(wgpu::ShaderStages::FRAGMENT),
+                // Prepass depth
+                texture_entry(1),
+                // Normals, or the occlusion being blurred
+                texture_entry(2),
+            ],
+        });
+        let pass_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Ambient Occlusion Pass Pipeline Layout"),
+            bind_group_layouts: &[&pass_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |entry_point: &str, format, label: &str| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pass_layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let ssao_pipeline = create_pipeline("fs_ssao", OCCLUSION_FORMAT, "SSAO Pipeline");
+        let blur_horizontal_pipeline = create_pipeline("fs_blur_horizontal", OCCLUSION_FORMAT, "SSAO Horizontal Blur Pipeline");
+        let blur_vertical_pipeline = create_pipeline("fs_blur_vertical", OCCLUSION_FORMAT, "SSAO Vertical Blur Pipeline");
+        let debug_pipeline = create_pipeline("fs_debug", config.format, "SSAO Debug Pipeline");
+
+        let targets = Self::create_targets(device, &pass_bind_group_layout, &uniform_buffer, config);
+
+        Self {
+            settings,
+            uniforms,
+            uniform_buffer,
+            has_camera: false,
+            debug_view: false,
+            camera_bind_group,
+            prepass_pipeline,
+            pass_bind_group_layout,
+            ssao_pipeline,
+            blur_horizontal_pipeline,
+            blur_vertical_pipeline,
+            debug_pipeline,
+            targets,
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Targets {
+        let target = |format, label| {
+            let target_config = wgpu::SurfaceConfiguration {
+                format,
+                view_formats: vec![format],
+                ..config.clone()
+            };
+            Texture::create_render_target(device, &target_config, label)
+        };
+        let depth = Texture::create_multisampled_depth_texture(
+            device,
+            config,
+            1,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            "Ambient Occlusion Depth",
+        );
+        let normals = target(NORMAL_FORMAT, "Ambient Occlusion Normals");
+        let raw = target(OCCLUSION_FORMAT, "Raw Ambient Occlusion");
+        let blurred = target(OCCLUSION_FORMAT, "Half Blurred Ambient Occlusion");
+        let occlusion = target(OCCLUSION_FORMAT, "Ambient Occlusion");
+
+        let create_bind_group = |source: &Texture, label| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&depth.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&source.view),
+                    },
+                ],
+            })
+        };
+        let ssao_bind_group = create_bind_group(&normals, "SSAO Bind Group");
+        let blur_horizontal_bind_group = create_bind_group(&raw, "SSAO Horizontal Blur Bind Group");
+        let blur_vertical_bind_group = create_bind_group(&blurred, "SSAO Vertical Blur Bind Group");
+
+        Targets {
+            depth,
+            normals,
+            raw,
+            blurred,
+            occlusion,
+            ssao_bind_group,
+            blur_horizontal_bind_group,
+            blur_vertical_bind_group,
+        }
+    }
+
+    /// Recreate the targets at the new output size, returning the ones they replace. The
+    /// light bind group must be rebound afterwards.
+    pub fn resize(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, config: &wgpu::SurfaceConfiguration) -> Targets {
+        let targets = Self::create_targets(device, &self.pass_bind_group_layout, &self.uniform_buffer, config);
+        self.swap_targets(queue, targets)
+    }
+
+    /// Render into targets kept from an earlier `resize`, returning the current ones. The
+    /// light bind group must be rebound afterwards.
+    pub fn swap_targets(&mut self, queue: &wgpu::Queue, targets: Targets) -> Targets {
+        let size = targets.depth.texture.size();
+        self.uniforms.screen_size = [size.width as f32, size.height as f32];
+        self.write_uniforms(queue);
+        std::mem::replace(&mut self.targets, targets)
+    }
+
+    fn apply_settings(uniforms: &mut AmbientOcclusionUniforms, settings: &AmbientOcclusionSettings, has_camera: bool) {
+        let kernel = sample_kernel(settings.sample_count);
+        uniforms.kernel[..kernel.len()].copy_from_slice(&kernel);
+        uniforms.sample_count = kernel.len() as u32;
+        uniforms.radius = settings.radius.max(0.001);
+        uniforms.bias = settings.bias;
+        uniforms.intensity = settings.intensity.max(0.0);
+        uniforms.enabled = (settings.enabled && has_camera) as u32;
+    }
+
+    fn write_uniforms(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniforms]));
+    }
+
+    /// Returns the current ambient occlusion settings
+    pub fn settings(&self) -> &AmbientOcclusionSettings {
+        &self.settings
+    }
+
+    /// Replace the ambient occlusion settings, regenerating the kernel for the sample count
+    pub fn set_settings(&mut self, queue: &wgpu::Queue, settings: AmbientOcclusionSettings) {
+        self.settings = settings;
+        Self::apply_settings(&mut self.uniforms, &self.settings, self.has_camera);
+        self.write_uniforms(queue);
+    }
+
+    /// Show the raw occlusion buffer instead of the lit scene
+    pub fn set_debug_view(&mut self, enabled: bool) {
+        self.debug_view = enabled;
+    }
+
+    /// Returns true if the raw occlusion buffer is shown
+    pub fn debug_view(&self) -> bool {
+        self.debug_view
+    }
+
+    /// Render the prepass and reconstruct positions with the camera's matrices
+    pub fn update_camera(&mut self, queue: &wgpu::Queue, camera: &Camera) {
+        let projection = camera.get_projection_matrix();
+        self.uniforms.view = camera.get_view_matrix().into();
+        self.uniforms.projection = projection.into();
+        self.uniforms.inverse_projection = projection.invert().unwrap_or(cgmath::Matrix4::identity()).into();
+        self.has_camera = true;
+        Self::apply_settings(&mut self.uniforms, &self.settings, self.has_camera);
+        self.write_uniforms(queue);
+    }
+
+    /// True when the passes run, for the lit shaders or for the debug view
+    fn active(&self) -> bool {
+        self.has_camera && (self.settings.enabled || self.debug_view)
+    }
+
+    /// Layout entries of the occlusion in the light bind group, starting at `AMBIENT_OCCLUSION_FIRST_BINDING`
+    pub fn bind_group_layout_entries() -> [wgpu::BindGroupLayoutEntry; 2] {
+        let binding = |offset| AMBIENT_OCCLUSION_FIRST_BINDING + offset;
+        [
+            // Parameters, for the enabled flag
+            wgpu::BindGroupLayoutEntry {
+                binding: binding(0),
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Blurred occlusion
+            wgpu::BindGroupLayoutEntry {
+                binding: binding(1),
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+        ]
+    }
+
+    /// Bind group entries matching `bind_group_layout_entries`
+    pub fn bind_group_entries(&self) -> [wgpu::BindGroupEntry<'_>; 2] {
+        let binding = |offset| AMBIENT_OCCLUSION_FIRST_BINDING + offset;
+        [
+            wgpu::BindGroupEntry {
+                binding: binding(0),
+                resource: self.uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: binding(1),
+                resource: wgpu::BindingResource::TextureView(&self.targets.occlusion.view),
+            },
+        ]
+    }
+
+    /// Render depth and normals, compute the occlusion and blur it. Does nothing while
+    /// occlusion is off or no camera was provided.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        models: &[Model],
+        instanced_models: &[InstancedModel],
+        single_instance: &InstanceBuffer,
+    ) {
+        if !self.active() {
+            return;
+        }
+
+        {
+            let mut prepass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Ambient Occlusion Prepass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.targets.normals.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.targets.depth.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            prepass.set_pipeline(&self.prepass_pipeline);
+            prepass.set_bind_group(1, &self.camera_bind_group, &[]);
+            prepass.set_vertex_buffer(1, single_instance.slice());
+            for model in models {
+                model.draw_geometry(&mut prepass);
+            }
+            for model in instanced_models {
+                model.draw_geometry(&mut prepass);
+            }
+        }
+
+        let targets = &self.targets;
+        Self::fullscreen_pass(encoder, "SSAO Pass", &targets.raw.view, &self.ssao_pipeline, &targets.ssao_bind_group);
+        Self::fullscreen_pass(
+            encoder,
+            "SSAO Horizontal Blur Pass",
+            &targets.blurred.view,
+            &self.blur_horizontal_pipeline,
+            &targets.blur_horizontal_bind_group,
+        );
+        Self::fullscreen_pass(
+            encoder,
+            "SSAO Vertical Blur Pass",
+            &targets.occlusion.view,
+            &self.blur_vertical_pipeline,
+            &targets.blur_vertical_bind_group,
+        );
+    }
+
+    /// Draw the raw occlusion buffer over `output` when the debug view is on
+    pub fn render_debug(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        if !self.debug_view || !self.active() {
+            return;
+        }
+        Self::fullscreen_pass(encoder, "SSAO Debug Pass", output, &self.debug_pipeline, &self.targets.blur_horizontal_bind_group);
+    }
+
+    fn fullscreen_pass(
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        target: &wgpu::TextureView,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_fills_the_hemisphere() {
+        let kernel = sample_kernel(16);
+        assert_eq!(kernel.len(), 16);
+        for sample in &kernel {
+            let length = Vector3::new(sample[0], sample[1], sample[2]).magnitude();
+            assert!(sample[2] > 0.0, "sample below the surface: {sample:?}");
+            assert!((0.1..=1.0 + 1e-5).contains(&length), "sample outside the hemisphere: {sample:?}");
+        }
+        // Samples move outwards, so the first are the closest to the center
+        let length = |sample: &[f32; 4]| Vector3::new(sample[0], sample[1], sample[2]).magnitude();
+        assert!(length(&kernel[0]) < length(&kernel[15]));
+
+        assert_eq!(sample_kernel(0).len(), 1);
+        assert_eq!(sample_kernel(1000).len(), MAX_AMBIENT_OCCLUSION_SAMPLES as usize);
+    }
+}
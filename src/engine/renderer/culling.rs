@@ -0,0 +1,206 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix, Matrix4, Point3, Transform, Vector3, Vector4};
+
+/// Axis-aligned bounding box
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+#[allow(dead_code)]
+impl Aabb {
+    /// The smallest box containing every point, or an empty box at the origin when there are none
+    pub fn from_points(points: impl IntoIterator<Item = Point3<f32>>) -> Self {
+        let mut points = points.into_iter();
+        let Some(first) = points.next() else {
+            return Self { min: Point3::origin(), max: Point3::origin() };
+        };
+        points.fold(Self { min: first, max: first }, |aabb, point| Self {
+            min: Point3::new(aabb.min.x.min(point.x), aabb.min.y.min(point.y), aabb.min.z.min(point.z)),
+            max: Point3::new(aabb.max.x.max(point.x), aabb.max.y.max(point.y), aabb.max.z.max(point.z)),
+        })
+    }
+
+    /// The smallest box containing both boxes
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self::from_points([self.min, self.max, other.min, other.max])
+    }
+
+    /// Center of the box
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    /// Half the size of the box along each axis
+    pub fn half_extents(&self) -> Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    /// The box containing this one after it is transformed, as in Arvo's "Transforming Axis-Aligned Bounding Boxes"
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Self {
+        let center = matrix.transform_point(self.center());
+        let extents = self.half_extents();
+        let absolute = |column: Vector4<f32>| Vector3::new(column.x.abs(), column.y.abs(), column.z.abs());
+        let half = absolute(matrix.x) * extents.x + absolute(matrix.y) * extents.y + absolute(matrix.z) * extents.z;
+        Self { min: center - half, max: center + half }
+    }
+}
+
+/// Sphere enclosing a mesh, cheaper to test than its box
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+#[allow(dead_code)]
+impl BoundingSphere {
+    /// A sphere around the center of `aabb` reaching the farthest of the points
+    pub fn from_points(points: impl IntoIterator<Item = Point3<f32>>, aabb: &Aabb) -> Self {
+        let center = aabb.center();
+        let radius = points.into_iter()
+            .map(|point| (point - center).magnitude2())
+            .fold(0.0, f32::max)
+            .sqrt();
+        Self { center, radius }
+    }
+
+    /// The sphere containing this one after it is transformed, scaled by the largest axis scale
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Self {
+        let scale = matrix.x.truncate().magnitude()
+            .max(matrix.y.truncate().magnitude())
+            .max(matrix.z.truncate().magnitude());
+        Self {
+            center: matrix.transform_point(self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+/// The six planes bounding what a camera sees, with normals pointing inwards
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6], // Left, right, bottom, top, near, far as (normal, distance)
+}
+
+impl Frustum {
+    /// A frustum that contains everything, for drawing without culling
+    pub const INFINITE: Frustum = Frustum { planes: [Vector4::new(0.0, 0.0, 0.0, 1.0); 6] };
+
+    /// Extract the planes from a view-projection matrix (Gribb and Hartmann). The near plane
+    /// uses the OpenGL -w..w depth range cgmath projections produce, which also contains wgpu's 0..w.
+    pub fn from_matrix(view_proj: Matrix4<f32>) -> Self {
+        let [r0, r1, r2, r3] = [0, 1, 2, 3].map(|i| view_proj.row(i));
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r3 + r2, r3 - r2].map(|plane| {
+            let length = plane.truncate().magnitude();
+            if length > 0.0 { plane / length } else { plane }
+        });
+        Self { planes }
+    }
+
+    /// The planes as (normal, distance), for uploading to shaders
+    pub fn planes(&self) -> [[f32; 4]; 6] {
+        self.planes.map(Into::into)
+    }
+
+    /// Signed distance from a plane, positive inside the frustum
+    fn distance(plane: &Vector4<f32>, point: Point3<f32>) -> f32 {
+        plane.truncate().dot(point.to_vec()) + plane.w
+    }
+
+    /// Returns false if the sphere is entirely outside one of the planes
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes.iter().all(|plane| Self::distance(plane, sphere.center) >= -sphere.radius)
+    }
+
+    /// Returns false if the box is entirely outside one of the planes
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner farthest along the plane normal
+            let corner = Point3::new(
+                if plane.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if plane.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if plane.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            Self::distance(plane, corner) >= 0.0
+        })
+    }
+}
+
+/// How many meshes the last frame drew and how many it skipped as off-screen
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct CullingStats {
+    pub meshes_drawn: u32,
+    pub meshes_culled: u32,
+}
+
+impl CullingStats {
+    /// Count a mesh as drawn or culled
+    pub fn record(&mut self, visible: bool) {
+        if visible {
+            self.meshes_drawn += 1;
+        } else {
+            self.meshes_culled += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{perspective, Deg};
+
+    fn camera_frustum() -> Frustum {
+        // Looking down -Z from the origin
+        let view = Matrix4::look_at_rh(Point3::origin(), Point3::new(0.0, 0.0, -1.0), Vector3::unit_y());
+        Frustum::from_matrix(perspective(Deg(90.0), 1.0, 0.1, 100.0) * view)
+    }
+
+    fn unit_box_at(x: f32, y: f32, z: f32) -> Aabb {
+        Aabb::from_points([Point3::new(x - 0.5, y - 0.5, z - 0.5), Point3::new(x + 0.5, y + 0.5, z + 0.5)])
+    }
+
+    #[test]
+    fn frustum_keeps_visible_bounds_and_rejects_the_rest() {
+        let frustum = camera_frustum();
+        for (aabb, visible) in [
+            (unit_box_at(0.0, 0.0, -5.0), true),     // Straight ahead
+            (unit_box_at(5.4, 0.0, -5.0), true),     // Straddling the right plane
+            (unit_box_at(0.0, 0.0, 5.0), false),     // Behind the camera
+            (unit_box_at(8.0, 0.0, -5.0), false),    // Off to the right
+            (unit_box_at(0.0, -8.0, -5.0), false),   // Below
+            (unit_box_at(0.0, 0.0, -101.0), false),  // Past the far plane
+        ] {
+            assert_eq!(frustum.intersects_aabb(&aabb), visible, "{:?}", aabb);
+            let corners = [aabb.min, aabb.max];
+            let sphere = BoundingSphere::from_points(corners, &aabb);
+            // Spheres are conservative, so they may keep a box the box test rejects but never the reverse
+            if visible {
+                assert!(frustum.intersects_sphere(&sphere), "{:?}", sphere);
+            }
+        }
+    }
+
+    #[test]
+    fn transformed_bounds_contain_transformed_points() {
+        let aabb = unit_box_at(1.0, 2.0, 3.0);
+        let sphere = BoundingSphere::from_points([aabb.min, aabb.max], &aabb);
+        let matrix = Matrix4::from_translation(Vector3::new(-4.0, 0.0, 2.0))
+            * Matrix4::from_angle_y(Deg(30.0))
+            * Matrix4::from_nonuniform_scale(2.0, 1.0, 0.5);
+
+        let transformed = aabb.transform(&matrix);
+        let transformed_sphere = sphere.transform(&matrix);
+        for x in [aabb.min.x, aabb.max.x] {
+            for y in [aabb.min.y, aabb.max.y] {
+                for z in [aabb.min.z, aabb.max.z] {
+                    let point = matrix.transform_point(Point3::new(x, y, z));
+                    for axis in 0..3 {
+                        assert!(point[axis] >= transformed.min[axis] - 1e-4 && point[axis] <= transformed.max[axis] + 1e-4);
+                    }
+                    assert!((point - transformed_sphere.center).magnitude() <= transformed_sphere.radius + 1e-4);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::SquareMatrix;
+use wgpu::util::DeviceExt;
+use crate::engine::renderer::instance::Instance;
+use crate::engine::renderer::mesh;
+use crate::engine::renderer::shader::ShaderManager;
+use crate::engine::renderer::texture::Texture;
+use crate::engine::renderer::tonemap::HDR_FORMAT;
+use crate::engine::scene::{Camera, SunLight};
+
+/// Base color, with the material's ambient occlusion in alpha
+const ALBEDO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+/// World-space normal
+const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// Metallic in red, roughness in green
+const MATERIAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+/// Emitted radiance, which may exceed 1
+const EMISSIVE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// How the renderer lights the scene
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum RenderPath {
+    #[default]
+    Forward,  // Every mesh is lit as it is drawn
+    Deferred, // Opaque meshes fill a G-buffer that one fullscreen pass lights
+}
+
+/// Camera the lighting pass reconstructs positions with, as laid out in the shader
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct DeferredUniforms {
+    inverse_view_proj: [[f32; 4]; 4],
+    screen_size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+/// Surface attributes of the opaque meshes at the output size, with the bind group reading them
+pub struct GBuffer {
+    albedo: Texture,
+    normal: Texture,
+    material: Texture,
+    emissive: Texture,
+    bind_group: wgpu::BindGroup, // Also reads the scene depth
+}
+
+/// Deferred shading.
+///
+/// A geometry pass writes albedo, normal, metallic-roughness and emissive of the opaque
+/// meshes into a G-buffer next to the scene depth. A fullscreen pass then lights every
+/// pixel once with the same shading as the forward path, so the cost of the lights no
+/// longer grows with the overdraw. Transparent meshes are drawn forward afterwards.
+pub struct DeferredRenderer {
+    uniforms: DeferredUniforms,
+    uniform_buffer: wgpu::Buffer,
+    sun_buffer: wgpu::Buffer,             // The one sun every pixel is lit by
+    geometry_pipeline: wgpu::RenderPipeline,
+    lighting_bind_group_layout: wgpu::BindGroupLayout,
+    lighting_pipeline: wgpu::RenderPipeline,
+    gbuffer: GBuffer,
+}
+
+impl DeferredRenderer {
+    /// Create the G-buffer at the output size and the passes filling and lighting it.
+    /// The geometry pass uses the forward pipeline layout, the lighting pass reads
+    /// the shadow and light bind groups at groups 1 and 2 like the forward shader.
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        depth_texture: &Texture,
+        mesh_pipeline_layout: &wgpu::PipelineLayout,
+        shadow_bind_group_layout: &wgpu::BindGroupLayout,
+        light_bind_group_layout: &wgpu::BindGroupLayout,
+        shader_manager: &ShaderManager,
+    ) -> Self {
+        let gbuffer_shader = shader_manager.get("gbuffer").expect("G-buffer shader should be available");
+        let lighting_shader = shader_manager.get("deferred_lighting").expect("Deferred lighting shader should be available");
+        let uniforms = DeferredUniforms {
+            inverse_view_proj: cgmath::Matrix4::identity().into(),
+            screen_size: [config.width as f32, config.height as f32],
+            _padding: [0.0; 2],
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Deferred Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sun_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Deferred Sun Light Buffer"),
+            contents: bytemuck::cast_slice(&[SunLight::new()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let target = |format| {
+            Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })
+        };
+        let geometry_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("G-Buffer Pipeline"),
+            layout: Some(mesh_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: gbuffer_shader,
+                entry_point: "vs_main",
+                buffers: &[mesh::Vertex::desc(), Instance::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: gbuffer_shader,
+                entry_point: "fs_main",
+                targets: &[
+                    target(ALBEDO_FORMAT),
+                    target(NORMAL_FORMAT),
+                    target(MATERIAL_FORMAT),
+                    target(EMISSIVE_FORMAT),
+                ],
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        // Camera and sun take the place of the object bind group, next to the G-buffer read by texel
+        let lighting_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Deferred Lighting Bind Group Layout"),
+            entries: &[
+                uniform_entry(0),
+                uniform_entry(1),
+                texture_entry(2),
+                texture_entry(3),
+                texture_entry(4),
+                texture_entry(5),
+                // Scene depth
+                texture_entry(6),
+            ],
+        });
+        let lighting_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Deferred Lighting Pipeline Layout"),
+            bind_group_layouts: &[&lighting_bind_group_layout, shadow_bind_group_layout, light_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let lighting_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Deferred Lighting Pipeline"),
+            layout: Some(&lighting_layout),
+            vertex: wgpu::VertexState {
+                module: lighting_shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: lighting_shader,
+                entry_point: "fs_main",
+                targets: &[target(HDR_FORMAT)],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let gbuffer = Self::create_gbuffer(
+            device,
+            &lighting_bind_group_layout,
+            &uniform_buffer,
+            &sun_buffer,
+            config,
+            depth_texture,
+        );
+
+        Self {
+            uniforms,
+            uniform_buffer,
+            sun_buffer,
+            geometry_pipeline,
+            lighting_bind_group_layout,
+            lighting_pipeline,
+            gbuffer,
+        }
+    }
+
+    fn create_gbuffer(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        sun_buffer: &wgpu::Buffer,
+        config: &wgpu::SurfaceConfiguration,
+        depth_texture: &Texture,
+    ) -> GBuffer {
+        let target = |format, label| {
+            let target_config = wgpu::SurfaceConfiguration {
+                format,
+                view_formats: vec![format],
+                ..config.clone()
+            };
+            Texture::create_render_target(device, &target_config, label)
+        };
+        let albedo = target(ALBEDO_FORMAT, "G-Buffer Albedo");
+        let normal = target(NORMAL_FORMAT, "G-Buffer Normal");
+        let material = target(MATERIAL_FORMAT, "G-Buffer Material");
+        let emissive = target(EMISSIVE_FORMAT, "G-Buffer Emissive");
+
+        fn view(binding: u32, texture: &Texture) -> wgpu::BindGroupEntry<'_> {
+            wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            }
+        }
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Deferred Lighting Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: sun_buffer.as_entire_binding(),
+                },
+                view(2, &albedo),
+                view(3, &normal),
+                view(4, &material),
+                view(5, &emissive),
+                view(6, depth_texture),
+            ],
+        });
+
+        GBuffer {
+            albedo,
+            normal,
+            material,
+            emissive,
+            bind_group,
+        }
+    }
+
+    /// Recreate the G-buffer for a new output size or scene depth texture, which must be
+    /// single-sampled, and return the one it replaces
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        depth_texture: &Texture,
+    ) -> GBuffer {
+        let gbuffer = Self::create_gbuffer(
+            device,
+            &self.lighting_bind_group_layout,
+            &self.uniform_buffer,
+            &self.sun_buffer,
+            config,
+            depth_texture,
+        );
+        self.swap_gbuffer(queue, gbuffer)
+    }
+
+    /// Draw into a G-buffer kept from an earlier `resize`, along with the depth texture it
+    /// was made for, and return the current one
+    pub fn swap_gbuffer(&mut self, queue: &wgpu::Queue, gbuffer: GBuffer) -> GBuffer {
+        let size = gbuffer.albedo.texture.size();
+        self.uniforms.screen_size = [size.width as f32, size.height as f32];
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniforms]));
+        std::mem::replace(&mut self.gbuffer, gbuffer)
+    }
+
+    /// Reconstruct world-space positions from the depth the camera rendered
+    pub fn update_camera(&mut self, queue: &wgpu::Queue, camera: &Camera) {
+        let view_proj = camera.get_view_projection_matrix();
+        self.uniforms.inverse_view_proj = view_proj.invert().unwrap_or(cgmath::Matrix4::identity()).into();
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniforms]));
+    }
+
+    /// Light every pixel with `sun_light`
+    pub fn update_sun(&self, queue: &wgpu::Queue, sun_light: &SunLight) {
+        queue.write_buffer(&self.sun_buffer, 0, bytemuck::cast_slice(&[*sun_light]));
+    }
+
+    /// Begin the pass writing the G-buffer and the scene depth, with the geometry pipeline set.
+    /// The shadow and light bind groups must be set before drawing like in the forward pass.
+    pub fn begin_geometry_pass<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        depth_view: &'a wgpu::TextureView,
+    ) -> wgpu::RenderPass<'a> {
+        let clear = |view| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("G-Buffer Pass"),
+            color_attachments: &[
+                clear(&self.gbuffer.albedo.view),
+                clear(&self.gbuffer.normal.view),
+                clear(&self.gbuffer.material.view),
+                clear(&self.gbuffer.emissive.view),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.geometry_pipeline);
+        render_pass
+    }
+
+    /// Light the G-buffer into `output`, which is cleared to `clear_color` where nothing was drawn
+    pub fn render_lighting(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        clear_color: wgpu::Color,
+        shadow_bind_group: &wgpu::BindGroup,
+        light_bind_group: &wgpu::BindGroup,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Deferred Lighting Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear_color),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.lighting_pipeline);
+        render_pass.set_bind_group(0, &self.gbuffer.bind_group, &[]);
+        render_pass.set_bind_group(1, shadow_bind_group, &[]);
+        render_pass.set_bind_group(2, light_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
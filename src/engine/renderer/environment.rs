@@ -0,0 +1,760 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::{Matrix, Matrix4, SquareMatrix, Vector4};
+use wgpu::util::DeviceExt;
+use crate::engine::renderer::shader::ShaderManager;
+use crate::engine::renderer::texture::Texture;
+use crate::engine::renderer::tonemap::HDR_FORMAT;
+use crate::engine::scene::Camera;
+
+/// Largest face size of the environment cubemap
+const MAX_ENVIRONMENT_SIZE: u32 = 512;
+/// Smallest face size of the environment cubemap, enough for every prefiltered mip
+const MIN_ENVIRONMENT_SIZE: u32 = 16;
+/// Face size of the diffuse irradiance map
+const IRRADIANCE_SIZE: u32 = 32;
+/// Face size of the top mip of the prefiltered specular map
+const PREFILTERED_SIZE: u32 = 128;
+/// Roughness levels of the prefiltered specular map, from 0 to 1
+pub const PREFILTERED_MIP_COUNT: u32 = 5;
+/// Width and height of the split-sum BRDF lookup table
+const BRDF_LUT_SIZE: u32 = 128;
+/// Radiance of the uniform environment used until one is loaded
+const DEFAULT_AMBIENT: f32 = 0.2;
+
+/// First binding of the environment in the light bind group
+pub const ENVIRONMENT_FIRST_BINDING: u32 = 6;
+
+/// WGSL declarations and split-sum evaluation for the lit shaders.
+/// Expects the environment in the light bind group at group 2.
+pub const ENVIRONMENT_WGSL: &str = r#"
+struct EnvironmentUniforms {
+    camera_position: vec3<f32>,
+    intensity: f32,
+    prefiltered_mip_count: f32,
+    has_camera: u32,
+    _padding: vec2<f32>,
+};
+
+@group(2) @binding(6)
+var<uniform> environment: EnvironmentUniforms;
+@group(2) @binding(7)
+var irradiance_map: texture_cube<f32>;
+@group(2) @binding(8)
+var prefiltered_map: texture_cube<f32>;
+@group(2) @binding(9)
+var brdf_lut: texture_2d<f32>;
+@group(2) @binding(10)
+var environment_sampler: sampler;
+
+// Direction from a surface to the camera; falls back to the per-vertex estimate
+// until the camera position has been provided
+fn view_direction(world_position: vec3<f32>, fallback: vec3<f32>) -> vec3<f32> {
+    if (environment.has_camera == 0u) {
+        return normalize(fallback);
+    }
+    return normalize(environment.camera_position - world_position);
+}
+
+// Split-sum image-based lighting: diffuse irradiance plus prefiltered specular
+fn image_based_lighting(
+    N: vec3<f32>,
+    V: vec3<f32>,
+    albedo: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+) -> vec3<f32> {
+    let NdotV = max(dot(N, V), 0.0);
+    let F0 = mix(vec3<f32>(0.04), albedo, metallic);
+    let F = F0 + (max(vec3<f32>(1.0 - roughness), F0) - F0) * pow(1.0 - NdotV, 5.0);
+    let kD = (vec3<f32>(1.0) - F) * (1.0 - metallic);
+
+    let irradiance = textureSampleLevel(irradiance_map, environment_sampler, N, 0.0).rgb;
+    let R = reflect(-V, N);
+    let lod = roughness * (environment.prefiltered_mip_count - 1.0);
+    let prefiltered = textureSampleLevel(prefiltered_map, environment_sampler, R, lod).rgb;
+    let brdf = textureSampleLevel(brdf_lut, environment_sampler, vec2<f32>(NdotV, roughness), 0.0).rg;
+
+    return (kD * irradiance * albedo + prefiltered * (F * brdf.x + brdf.y)) * environment.intensity;
+}
+"#;
+
+/// WGSL shared by the passes that bake the environment maps: a fullscreen triangle,
+/// cube face directions and GGX importance sampling
+pub const ENVIRONMENT_BAKE_WGSL: &str = r#"
+struct BakeUniforms {
+    face: u32,
+    size: f32,
+    roughness: f32,
+    source_size: f32,
+    source_mip_count: f32,
+    _padding1: f32,
+    _padding2: f32,
+    _padding3: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> bake: BakeUniforms;
+
+const PI: f32 = 3.14159265359;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// Direction through a pixel of a cube face, in the order +X, -X, +Y, -Y, +Z, -Z
+fn cube_direction(face: u32, frag_coord: vec2<f32>) -> vec3<f32> {
+    let st = frag_coord / bake.size * 2.0 - 1.0;
+    var direction: vec3<f32>;
+    switch face {
+        case 0u: { direction = vec3<f32>(1.0, -st.y, -st.x); }
+        case 1u: { direction = vec3<f32>(-1.0, -st.y, st.x); }
+        case 2u: { direction = vec3<f32>(st.x, 1.0, st.y); }
+        case 3u: { direction = vec3<f32>(st.x, -1.0, -st.y); }
+        case 4u: { direction = vec3<f32>(st.x, -st.y, 1.0); }
+        default: { direction = vec3<f32>(-st.x, -st.y, -1.0); }
+    }
+    return normalize(direction);
+}
+
+// Low-discrepancy point i of n
+fn hammersley(i: u32, n: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(n), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+// Orthonormal basis with N as its z axis
+fn tangent_frame(N: vec3<f32>) -> mat3x3<f32> {
+    let up = select(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 0.0, 1.0), abs(N.z) < 0.999);
+    let T = normalize(cross(up, N));
+    let B = cross(N, T);
+    return mat3x3<f32>(T, B, N);
+}
+
+// Half vector distributed by the GGX normal distribution around N
+fn importance_sample_ggx(xi: vec2<f32>, N: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return tangent_frame(N) * vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+}
+
+// Mip of the source cube whose texels cover the solid angle of one sample
+fn sample_lod(pdf: f32, sample_count: u32) -> f32 {
+    let texel_solid_angle = 4.0 * PI / (6.0 * bake.source_size * bake.source_size);
+    let sample_solid_angle = 1.0 / (f32(sample_count) * pdf + 0.0001);
+    return clamp(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0, bake.source_mip_count - 1.0);
+}
+"#;
+
+/// Environment parameters read by the lit shaders
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct EnvironmentUniforms {
+    camera_position: [f32; 3],
+    intensity: f32,
+    prefiltered_mip_count: f32,
+    has_camera: u32,
+    _padding: [f32; 2],
+}
+
+/// Parameters of one bake draw
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct BakeUniforms {
+    face: u32,
+    size: f32,             // Face size of the mip being rendered
+    roughness: f32,        // Roughness of the prefiltered mip being rendered
+    source_size: f32,      // Face size of the top mip of the cube being filtered
+    source_mip_count: f32,
+    _padding: [f32; 3],
+}
+
+/// Camera rotation and lens for drawing the skybox
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct SkyboxUniforms {
+    inverse_view: [[f32; 4]; 4], // Rotates view-space rays into world space
+    tan_half_fovy: f32,
+    aspect: f32,
+    intensity: f32,
+    _padding: f32,
+}
+
+/// Image-based lighting baked from an equirectangular environment: a cubemap for the
+/// skybox, a diffuse irradiance map, a roughness-prefiltered specular map and the
+/// split-sum BRDF lookup table
+pub struct Environment {
+    cube: Texture,
+    irradiance: Texture,
+    prefiltered: Texture,
+    brdf_lut: Texture,
+    sampler: wgpu::Sampler,
+    uniforms: EnvironmentUniforms,
+    uniform_buffer: wgpu::Buffer,
+    skybox_uniforms: SkyboxUniforms,
+    skybox_uniform_buffer: wgpu::Buffer,
+    skybox_bind_group_layout: wgpu::BindGroupLayout,
+    skybox_bind_group: wgpu::BindGroup,
+    skybox_pipeline: wgpu::RenderPipeline,
+    equirect_bind_group_layout: wgpu::BindGroupLayout,
+    cube_bind_group_layout: wgpu::BindGroupLayout,
+    equirect_pipeline: wgpu::RenderPipeline,
+    irradiance_pipeline: wgpu::RenderPipeline,
+    prefilter_pipeline: wgpu::RenderPipeline,
+}
+
+impl Environment {
+    /// Create the bake pipelines, the BRDF lookup table and a uniform grey environment
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, shaders: &ShaderManager) -> Self {
+        let uniform_entry = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let cube_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+
+        // Equirectangular source, read with textureLoad since 32-bit floats are not filterable
+        let equirect_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Environment Equirect Bind Group Layout"),
+            entries: &[
+                uniform_entry(0, wgpu::ShaderStages::FRAGMENT),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        // Cubemap source for the irradiance and prefilter passes
+        let cube_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Environment Cube Bind Group Layout"),
+            entries: &[
+                uniform_entry(0, wgpu::ShaderStages::FRAGMENT),
+                cube_entry(1),
+                sampler_entry(2),
+            ],
+        });
+        let uniform_only_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("BRDF LUT Bind Group Layout"),
+            entries: &[uniform_entry(0, wgpu::ShaderStages::FRAGMENT)],
+        });
+        let skybox_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Skybox Bind Group Layout"),
+            entries: &[
+                uniform_entry(0, wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT),
+                cube_entry(1),
+                sampler_entry(2),
+            ],
+        });
+
+        let shader = |name: &str| shaders.get(name).unwrap_or_else(|| panic!("{} shader should be available", name));
+        let equirect_pipeline = Self::create_bake_pipeline(
+            device,
+            &equirect_bind_group_layout,
+            shader("environment_equirect"),
+            wgpu::TextureFormat::Rgba16Float,
+            "Environment Equirect Pipeline",
+        );
+        let irradiance_pipeline = Self::create_bake_pipeline(
+            device,
+            &cube_bind_group_layout,
+            shader("environment_irradiance"),
+            wgpu::TextureFormat::Rgba16Float,
+            "Environment Irradiance Pipeline",
+        );
+        let prefilter_pipeline = Self::create_bake_pipeline(
+            device,
+            &cube_bind_group_layout,
+            shader("environment_prefilter"),
+            wgpu::TextureFormat::Rgba16Float,
+            "Environment Prefilter Pipeline",
+        );
+        let brdf_lut_pipeline = Self::create_bake_pipeline(
+            device,
+            &uniform_only_bind_group_layout,
+            shader("brdf_lut"),
+            wgpu::TextureFormat::Rgba16Float,
+            "BRDF LUT Pipeline",
+        );
+
+        let skybox_pipeline = Self::create_skybox_pipeline(device, &skybox_bind_group_layout, shader("skybox"), 1);
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Environment Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        // The BRDF lookup table only depends on NdotV and roughness, so it is baked once
+        let brdf_lut = {
+            let config = wgpu::SurfaceConfiguration {
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                format: wgpu::TextureFormat::Rgba16Float,
+                width: BRDF_LUT_SIZE,
+                height: BRDF_LUT_SIZE,
+                present_mode: wgpu::PresentMode::Fifo,
+                alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+                view_formats: vec![],
+            };
+            Texture::create_render_target(device, &config, "BRDF LUT")
+        };
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("BRDF LUT Encoder"),
+        });
+        let lut_bind_group = Self::create_bake_bind_group(
+            device,
+            &uniform_only_bind_group_layout,
+            BakeUniforms {
+                size: BRDF_LUT_SIZE as f32,
+                ..BakeUniforms::zeroed()
+            },
+            &[],
+        );
+        Self::bake_draw(&mut encoder, &brdf_lut_pipeline, &lut_bind_group, &brdf_lut.view);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        // Placeholder maps until the uniform environment below is baked
+        let placeholder = || Texture::create_cube_render_target(device, 1, 1, "Environment Placeholder");
+        let uniforms = EnvironmentUniforms {
+            camera_position: [0.0; 3],
+            intensity: 1.0,
+            prefiltered_mip_count: PREFILTERED_MIP_COUNT as f32,
+            has_camera: 0,
+            _padding: [0.0; 2],
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Environment Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let skybox_uniforms = SkyboxUniforms {
+            inverse_view: Matrix4::identity().into(),
+            tan_half_fovy: 1.0,
+            aspect: 1.0,
+            intensity: 1.0,
+            _padding: 0.0,
+        };
+        let skybox_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skybox Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[skybox_uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let cube = placeholder();
+        let skybox_bind_group = Self::create_skybox_bind_group(
+            device,
+            &skybox_bind_group_layout,
+            &skybox_uniform_buffer,
+            &cube,
+            &sampler,
+        );
+
+        let mut environment = Self {
+            cube,
+            irradiance: placeholder(),
+            prefiltered: placeholder(),
+            brdf_lut,
+            sampler,
+            uniforms,
+            uniform_buffer,
+            skybox_uniforms,
+            skybox_uniform_buffer,
+            skybox_bind_group_layout,
+            skybox_bind_group,
+            skybox_pipeline,
+            equirect_bind_group_layout,
+            cube_bind_group_layout,
+            equirect_pipeline,
+            irradiance_pipeline,
+            prefilter_pipeline,
+        };
+
+        let uniform_sky = image::Rgba32FImage::from_pixel(
+            4,
+            2,
+            image::Rgba([DEFAULT_AMBIENT, DEFAULT_AMBIENT, DEFAULT_AMBIENT, 1.0]),
+        );
+        environment.set_image(device, queue, &uniform_sky);
+        environment
+    }
+
+    /// The skybox is drawn behind everything, where the depth buffer is still cleared
+    fn create_skybox_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        shader: &wgpu::ShaderModule,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox Pipeline Layout"),
+            bind_group_layouts: &[bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Skybox Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        })
+    }
+
+    /// Rebuild the skybox pipeline for scene targets with `sample_count` samples
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, shaders: &ShaderManager, sample_count: u32) {
+        let shader = shaders.get("skybox").expect("Skybox shader should be available");
+        self.skybox_pipeline = Self::create_skybox_pipeline(device, &self.skybox_bind_group_layout, shader, sample_count);
+    }
+
+    fn create_bake_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> wgpu::RenderPipeline {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(label),
+            bind_group_layouts: &[bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    /// Binds the bake uniforms at binding 0 followed by `resources`
+    fn create_bake_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniforms: BakeUniforms,
+        resources: &[wgpu::BindingResource],
+    ) -> wgpu::BindGroup {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Environment Bake Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        }];
+        entries.extend(resources.iter().enumerate().map(|(i, resource)| wgpu::BindGroupEntry {
+            binding: i as u32 + 1,
+            resource: resource.clone(),
+        }));
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Environment Bake Bind Group"),
+            layout,
+            entries: &entries,
+        })
+    }
+
+    /// Render one fullscreen triangle into `target`
+    fn bake_draw(
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+        target: &wgpu::TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Environment Bake Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    fn create_skybox_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        cube: &Texture,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Skybox Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&cube.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    /// Bake the cubemap, irradiance and prefiltered maps from an equirectangular image
+    pub fn set_image(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, image: &image::Rgba32FImage) {
+        let equirect = Texture::from_hdr_image(device, queue, image, "Environment Equirect");
+        let size = (image.width() / 4).next_power_of_two().clamp(MIN_ENVIRONMENT_SIZE, MAX_ENVIRONMENT_SIZE);
+        let mip_count = size.ilog2() + 1;
+        let cube = Texture::create_cube_render_target(device, size, mip_count, "Environment Cubemap");
+        let irradiance_size = IRRADIANCE_SIZE.min(size);
+        let irradiance = Texture::create_cube_render_target(device, irradiance_size, 1, "Environment Irradiance");
+        let prefiltered_size = PREFILTERED_SIZE.min(size);
+        let prefiltered = Texture::create_cube_render_target(
+            device,
+            prefiltered_size,
+            PREFILTERED_MIP_COUNT,
+            "Environment Prefiltered",
+        );
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment Bake Encoder"),
+        });
+        let source = BakeUniforms {
+            source_size: size as f32,
+            source_mip_count: mip_count as f32,
+            ..BakeUniforms::zeroed()
+        };
+
+        // Project the equirectangular image onto every face and mip of the cubemap
+        for mip in 0..mip_count {
+            for face in 0..6 {
+                let bind_group = Self::create_bake_bind_group(
+                    device,
+                    &self.equirect_bind_group_layout,
+                    BakeUniforms { face, size: (size >> mip) as f32, ..source },
+                    &[wgpu::BindingResource::TextureView(&equirect.view)],
+                );
+                let target = cube.create_mip_layer_view(face, mip);
+                Self::bake_draw(&mut encoder, &self.equirect_pipeline, &bind_group, &target);
+            }
+        }
+
+        // Convolve the cubemap into the diffuse irradiance and the roughness-prefiltered specular maps
+        let cube_resources = [
+            wgpu::BindingResource::TextureView(&cube.view),
+            wgpu::BindingResource::Sampler(&self.sampler),
+        ];
+        for face in 0..6 {
+            let bind_group = Self::create_bake_bind_group(
+                device,
+                &self.cube_bind_group_layout,
+                BakeUniforms { face, size: irradiance_size as f32, ..source },
+                &cube_resources,
+            );
+            let target = irradiance.create_mip_layer_view(face, 0);
+            Self::bake_draw(&mut encoder, &self.irradiance_pipeline, &bind_group, &target);
+        }
+        for mip in 0..PREFILTERED_MIP_COUNT {
+            let roughness = mip as f32 / (PREFILTERED_MIP_COUNT - 1) as f32;
+            for face in 0..6 {
+                let bind_group = Self::create_bake_bind_group(
+                    device,
+                    &self.cube_bind_group_layout,
+                    BakeUniforms { face, size: (prefiltered_size >> mip) as f32, roughness, ..source },
+                    &cube_resources,
+                );
+                let target = prefiltered.create_mip_layer_view(face, mip);
+                Self::bake_draw(&mut encoder, &self.prefilter_pipeline, &bind_group, &target);
+            }
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        self.skybox_bind_group = Self::create_skybox_bind_group(
+            device,
+            &self.skybox_bind_group_layout,
+            &self.skybox_uniform_buffer,
+            &cube,
+            &self.sampler,
+        );
+        self.cube = cube;
+        self.irradiance = irradiance;
+        self.prefiltered = prefiltered;
+    }
+
+    /// Layout entries of the environment in the light bind group, starting at `ENVIRONMENT_FIRST_BINDING`
+    pub fn bind_group_layout_entries() -> [wgpu::BindGroupLayoutEntry; 5] {
+        let binding = |offset| ENVIRONMENT_FIRST_BINDING + offset;
+        let texture_entry = |offset, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding: binding(offset),
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+        [
+            // Environment parameters
+            wgpu::BindGroupLayoutEntry {
+                binding: binding(0),
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Diffuse irradiance
+            texture_entry(1, wgpu::TextureViewDimension::Cube),
+            // Prefiltered specular
+            texture_entry(2, wgpu::TextureViewDimension::Cube),
+            // BRDF lookup table
+            texture_entry(3, wgpu::TextureViewDimension::D2),
+            // Sampler shared by the maps
+            wgpu::BindGroupLayoutEntry {
+                binding: binding(4),
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ]
+    }
+
+    /// Bind group entries matching `bind_group_layout_entries`
+    pub fn bind_group_entries(&self) -> [wgpu::BindGroupEntry<'_>; 5] {
+        let binding = |offset| ENVIRONMENT_FIRST_BINDING + offset;
+        [
+            wgpu::BindGroupEntry {
+                binding: binding(0),
+                resource: self.uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: binding(1),
+                resource: wgpu::BindingResource::TextureView(&self.irradiance.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: binding(2),
+                resource: wgpu::BindingResource::TextureView(&self.prefiltered.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: binding(3),
+                resource: wgpu::BindingResource::TextureView(&self.brdf_lut.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: binding(4),
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+        ]
+    }
+
+    /// Scale the light the environment contributes, to both surfaces and the skybox
+    pub fn set_intensity(&mut self, queue: &wgpu::Queue, intensity: f32) {
+        self.uniforms.intensity = intensity;
+        self.skybox_uniforms.intensity = intensity;
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniforms]));
+        queue.write_buffer(&self.skybox_uniform_buffer, 0, bytemuck::cast_slice(&[self.skybox_uniforms]));
+    }
+
+    /// Returns the intensity the environment is scaled by
+    pub fn intensity(&self) -> f32 {
+        self.uniforms.intensity
+    }
+
+    /// Point reflections and the skybox at the camera
+    pub fn update_camera(&mut self, queue: &wgpu::Queue, camera: &Camera) {
+        self.uniforms.camera_position = camera.position.into();
+        self.uniforms.has_camera = 1;
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniforms]));
+
+        // The view matrix without its translation is a pure rotation, so its transpose inverts it
+        let mut rotation = camera.get_view_matrix();
+        rotation.w = Vector4::new(0.0, 0.0, 0.0, 1.0);
+        self.skybox_uniforms.inverse_view = rotation.transpose().into();
+        self.skybox_uniforms.tan_half_fovy = (camera.fovy.0 * 0.5).tan();
+        self.skybox_uniforms.aspect = camera.aspect_ratio;
+        queue.write_buffer(&self.skybox_uniform_buffer, 0, bytemuck::cast_slice(&[self.skybox_uniforms]));
+    }
+
+    /// Draw the environment wherever no geometry was rendered
+    pub fn render_skybox<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.skybox_pipeline);
+        render_pass.set_bind_group(0, &self.skybox_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
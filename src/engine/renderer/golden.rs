@@ -0,0 +1,1288 @@
+//! Golden-image regression tests for the render pipeline.
+//!
+//! Each canonical scene is rendered with a headless renderer and compared against
+//! a reference PNG in `tests/golden`. Run with `UPDATE_GOLDEN=1` to rewrite the
+//! references after an intentional change to the look of a scene. When a scene
+//! does not match, a diff image is written to `target/golden`.
+
+use std::path::{Path, PathBuf};
+use cgmath::{Deg, InnerSpace, Matrix4, Point3, Rad, SquareMatrix, Vector3};
+use image::{Rgba, RgbaImage};
+use crate::engine::renderer::{
+    BlendMode, CullingStats, Instance, Mesh, MeshData, MaterialUniforms, PostEffect, RenderPath, Renderer, SkyMode, Texture,
+    TextureOptions, TonemapOperator, TonemapSettings, TransparencyMode, View, Viewport,
+    MATERIAL_HAS_NORMAL_MAP,
+};
+use crate::engine::scene::{Camera, Model, PointLight, ShadowSettings, SpotLight, SunController, SunLight};
+use super::test_support::{compare_images, create_light_buffer, create_uniform_buffer, headless_renderer, read_texels};
+
+/// Directory holding the checked-in reference images
+const GOLDEN_DIR: &str = "tests/golden";
+/// Directory diff images are written to on a mismatch
+const DIFF_DIR: &str = "target/golden";
+
+/// Size of every golden scene
+const WIDTH: u32 = 128;
+const HEIGHT: u32 = 128;
+
+/// Largest per-channel difference that still counts as a matching pixel
+const CHANNEL_TOLERANCE: u8 = 8;
+/// Fraction of pixels allowed to exceed the tolerance (rasterization differences between adapters)
+const MAX_MISMATCH_RATIO: f64 = 0.005;
+
+/// Compare a rendered scene with `tests/golden/<name>.png`, panicking on a mismatch
+fn assert_matches_golden(name: &str, actual: &RgbaImage) {
+    let reference_path = Path::new(GOLDEN_DIR).join(format!("{}.png", name));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(GOLDEN_DIR).unwrap();
+        actual.save(&reference_path).unwrap();
+        return;
+    }
+
+    let expected = match image::open(&reference_path) {
+        Ok(image) => image.to_rgba8(),
+        Err(e) => panic!(
+            "Missing golden image {:?} ({}); run with UPDATE_GOLDEN=1 to create it",
+            reference_path, e
+        ),
+    };
+
+    let comparison = compare_images(actual, &expected, CHANNEL_TOLERANCE).unwrap();
+    if comparison.mismatch_ratio() > MAX_MISMATCH_RATIO {
+        let diff_dir = PathBuf::from(DIFF_DIR);
+        std::fs::create_dir_all(&diff_dir).unwrap();
+        let actual_path = diff_dir.join(format!("{}.actual.png", name));
+        let diff_path = diff_dir.join(format!("{}.diff.png", name));
+        actual.save(&actual_path).unwrap();
+        comparison.diff.save(&diff_path).unwrap();
+
+        panic!(
+            "Scene '{}' differs from its golden image: {} pixels ({:.2}%) exceed tolerance {}, max difference {}. \
+             Rendered image: {:?}, diff: {:?}",
+            name,
+            comparison.mismatched_pixels,
+            comparison.mismatch_ratio() * 100.0,
+            CHANNEL_TOLERANCE,
+            comparison.max_channel_difference,
+            actual_path,
+            diff_path
+        );
+    }
+}
+
+/// Creates a headless renderer the size of a golden scene
+fn golden_renderer() -> Renderer {
+    headless_renderer(WIDTH, HEIGHT)
+}
+
+/// The camera `Application::new` starts with
+fn application_camera() -> Camera {
+    let mut camera = Camera::new(
+        Point3::new(0.0, 10.0, 10.0),
+        Rad(3.0 * std::f32::consts::PI / 2.0),
+        Rad(-std::f32::consts::PI / 6.0),
+    );
+    camera.set_aspect_ratio(WIDTH, HEIGHT);
+    camera
+}
+
+#[test]
+fn golden_cube_scene() {
+    let mut renderer = golden_renderer();
+    renderer.set_debug_grid_visible(false);
+
+    // Same cube, transform and sun as Application::new
+    let camera = application_camera();
+    renderer.update_environment(&camera);
+    let uniform_buffer = create_uniform_buffer(
+        &renderer,
+        &camera,
+        Matrix4::from_translation(Vector3::new(0.0, 1.0, 0.0)),
+    );
+    let light_buffer = create_light_buffer(&renderer, SunController::new().sun_light);
+    let cube = renderer.create_solid_model(
+        Mesh::create_cube(renderer.device(), 2.0),
+        [1.0, 1.0, 1.0],
+        &uniform_buffer,
+        &light_buffer,
+        "Golden Cube",
+    );
+    renderer.add_model(cube);
+
+    assert_matches_golden("cube", &renderer.capture_frame().unwrap());
+}
+
+#[test]
+fn golden_debug_grid_scene() {
+    let mut renderer = golden_renderer();
+    renderer.update_grid_transform(application_camera().get_view_projection_matrix());
+
+    assert_matches_golden("debug_grid", &renderer.capture_frame().unwrap());
+}
+
+#[test]
+fn golden_sphere_scene() {
+    let mut renderer = golden_renderer();
+    renderer.set_debug_grid_visible(false);
+
+    let mut camera = Camera::new(Point3::new(0.0, 0.0, 5.0), Rad(3.0 * std::f32::consts::PI / 2.0), Rad(0.0));
+    camera.set_aspect_ratio(WIDTH, HEIGHT);
+    renderer.update_environment(&camera);
+    let uniform_buffer = create_uniform_buffer(&renderer, &camera, Matrix4::identity());
+    let mut sun_light = SunLight::default();
+    sun_light.direction = [-0.5, -1.0, -0.3];
+    let light_buffer = create_light_buffer(&renderer, sun_light);
+    let sphere = renderer.create_solid_model(
+        Mesh::create_sphere(renderer.device(), 1.5, 32, 16),
+        [0.8, 0.3, 0.2],
+        &uniform_buffer,
+        &light_buffer,
+        "Golden Sphere",
+    );
+    renderer.add_model(sphere);
+
+    assert_matches_golden("sphere", &renderer.capture_frame().unwrap());
+}
+
+#[test]
+fn golden_shadowed_cube_scene() {
+    let mut renderer = golden_renderer();
+    renderer.set_debug_grid_visible(false);
+
+    let camera = application_camera();
+    renderer.update_environment(&camera);
+    let sun = SunController::new();
+    let light_buffer = create_light_buffer(&renderer, sun.sun_light);
+
+    // Cube floating above a ground plane that receives its shadow
+    let cube_uniforms = create_uniform_buffer(
+        &renderer,
+        &camera,
+        Matrix4::from_translation(Vector3::new(0.0, 1.5, 0.0)),
+    );
+    let cube = renderer.create_solid_model(
+        Mesh::create_cube(renderer.device(), 2.0),
+        [1.0, 1.0, 1.0],
+        &cube_uniforms,
+        &light_buffer,
+        "Golden Cube",
+    );
+    let ground_uniforms = create_uniform_buffer(
+        &renderer,
+        &camera,
+        Matrix4::from_angle_x(Deg(-90.0)),
+    );
+    let ground = renderer.create_solid_model(
+        Mesh::create_quad(renderer.device(), 12.0),
+        [0.6, 0.6, 0.6],
+        &ground_uniforms,
+        &light_buffer,
+        "Golden Ground",
+    );
+    renderer.add_model(cube);
+    renderer.add_model(ground);
+    renderer.update_shadows(&sun.sun_light, &ShadowSettings::default(), &camera);
+
+    assert_matches_golden("shadowed_cube", &renderer.capture_frame().unwrap());
+}
+
+#[test]
+fn golden_shadow_cascade_debug_scene() {
+    let mut renderer = golden_renderer();
+    renderer.set_debug_grid_visible(false);
+
+    // Large ground plane reaching into every cascade
+    let camera = application_camera();
+    renderer.update_environment(&camera);
+    let sun = SunController::new();
+    let light_buffer = create_light_buffer(&renderer, sun.sun_light);
+    let ground_uniforms = create_uniform_buffer(
+        &renderer,
+        &camera,
+        Matrix4::from_angle_x(Deg(-90.0)),
+    );
+    let ground = renderer.create_solid_model(
+        Mesh::create_quad(renderer.device(), 200.0),
+        [0.6, 0.6, 0.6],
+        &ground_uniforms,
+        &light_buffer,
+        "Golden Ground",
+    );
+    renderer.add_model(ground);
+
+    let settings = ShadowSettings {
+        debug_cascades: true,
+        ..ShadowSettings::default()
+    };
+    renderer.update_shadows(&sun.sun_light, &settings, &camera);
+
+    assert_matches_golden("shadow_cascade_debug", &renderer.capture_frame().unwrap());
+}
+
+#[test]
+fn golden_point_and_spot_lights_scene() {
+    let mut renderer = golden_renderer();
+    renderer.set_debug_grid_visible(false);
+
+    // Dim sun so the local lights dominate the ground plane
+    let camera = application_camera();
+    renderer.update_environment(&camera);
+    let mut sun_light = SunLight::default();
+    sun_light.intensity = 0.1;
+    let light_buffer = create_light_buffer(&renderer, sun_light);
+    let ground_uniforms = create_uniform_buffer(
+        &renderer,
+        &camera,
+        Matrix4::from_angle_x(Deg(-90.0)),
+    );
+    let ground = renderer.create_solid_model(
+        Mesh::create_quad(renderer.device(), 16.0),
+        [0.8, 0.8, 0.8],
+        &ground_uniforms,
+        &light_buffer,
+        "Golden Ground",
+    );
+    renderer.add_model(ground);
+
+    // A red and a blue point light on either side and a white spot light in the middle
+    let lights = renderer.lights_mut();
+    lights.add_point_light(PointLight::new(Point3::new(-3.0, 1.0, 0.0), [1.0, 0.2, 0.2], 25.0, 6.0));
+    lights.add_point_light(PointLight::new(Point3::new(3.0, 1.0, 0.0), [0.2, 0.2, 1.0], 25.0, 6.0));
+    lights.add_spot_light(SpotLight::new(
+        Point3::new(0.0, 4.0, 2.0),
+        [0.0, -1.0, -0.3],
+        [1.0, 1.0, 0.9],
+        60.0,
+        12.0,
+        Deg(15.0),
+        Deg(25.0),
+    ));
+
+    assert_matches_golden("point_and_spot_lights", &renderer.capture_frame().unwrap());
+}
+
+/// Ground plane lit by a grid of colored point lights and a few spot lights
+fn add_many_lights_scene(renderer: &mut Renderer, camera: &Camera) {
+    let mut sun_light = SunLight::default();
+    sun_light.intensity = 0.05;
+    let light_buffer = create_light_buffer(renderer, sun_light);
+    let ground_uniforms = create_uniform_buffer(
+        renderer,
+        camera,
+        Matrix4::from_angle_x(Deg(-90.0)),
+    );
+    let ground = renderer.create_solid_model(
+        Mesh::create_quad(renderer.device(), 40.0),
+        [0.8, 0.8, 0.8],
+        &ground_uniforms,
+        &light_buffer,
+        "Golden Ground",
+    );
+    renderer.add_model(ground);
+
+    let lights = renderer.lights_mut();
+    for x in -8..8 {
+        for z in -8..8 {
+            let color = [
+                (x + 8) as f32 / 15.0,
+                0.3,
+                (z + 8) as f32 / 15.0,
+            ];
+            lights.add_point_light(PointLight::new(
+                Point3::new(x as f32 * 1.5, 0.5, z as f32 * 1.5),
+                color,
+                3.0,
+                1.5,
+            ));
+        }
+    }
+    for x in [-4.0, 4.0] {
+        lights.add_spot_light(SpotLight::new(
+            Point3::new(x, 4.0, 0.0),
+            [0.0, -1.0, 0.0],
+            [1.0, 1.0, 1.0],
+            20.0,
+            8.0,
+            Deg(10.0),
+            Deg(20.0),
+        ));
+    }
+}
+
+#[test]
+fn golden_clustered_lights_scene() {
+    let mut renderer = golden_renderer();
+    renderer.set_debug_grid_visible(false);
+
+    let camera = application_camera();
+    renderer.update_environment(&camera);
+    add_many_lights_scene(&mut renderer, &camera);
+    renderer.update_light_clusters(&camera);
+
+    assert_matches_golden("clustered_lights", &renderer.capture_frame().unwrap());
+}
+
+#[test]
+fn clustered_lighting_matches_every_light() {
+    let mut renderer = golden_renderer();
+    renderer.set_debug_grid_visible(false);
+
+    // Lights reach exactly zero at their range, so binning must not change the image
+    let camera = application_camera();
+    renderer.update_environment(&camera);
+    add_many_lights_scene(&mut renderer, &camera);
+    let every_light = renderer.capture_frame().unwrap();
+    renderer.update_light_clusters(&camera);
+    let clustered = renderer.capture_frame().unwrap();
+
+    let comparison = compare_images(&clustered, &every_light, 2).unwrap();
+    assert_eq!(comparison.mismatched_pixels, 0, "max difference {}", comparison.max_channel_difference);
+}
+
+#[test]
+fn golden_light_heatmap_scene() {
+    let mut renderer = golden_renderer();
+    renderer.set_debug_grid_visible(false);
+
+    let camera = application_camera();
+    renderer.update_environment(&camera);
+    add_many_lights_scene(&mut renderer, &camera);
+    renderer.update_light_clusters(&camera);
+    renderer.set_light_heatmap_visible(true);
+
+    assert_matches_golden("light_heatmap", &renderer.capture_frame().unwrap());
+}
+
+#[test]
+fn golden_materials_scene() {
+    let mut renderer = golden_renderer();
+    renderer.set_debug_grid_visible(false);
+
+    let mut camera = Camera::new(Point3::new(0.0, 0.0, 7.0), Rad(3.0 * std::f32::consts::PI / 2.0), Rad(0.0));
+    camera.set_aspect_ratio(WIDTH, HEIGHT);
+    renderer.update_environment(&camera);
+    let mut sun_light = SunLight::default();
+    sun_light.direction = [-0.5, -1.0, -0.6];
+    let light_buffer = create_light_buffer(&renderer, sun_light);
+
+    // Rough plastic, polished gold and a glowing sphere side by side
+    let spheres = [
+        (-2.2, [0.2, 0.4, 0.9], MaterialUniforms { roughness_factor: 0.9, ..MaterialUniforms::default() }),
+        (0.0, [1.0, 0.8, 0.3], MaterialUniforms { metallic_factor: 1.0, roughness_factor: 0.2, ..MaterialUniforms::default() }),
+        (2.2, [0.3, 0.3, 0.3], MaterialUniforms { emissive_factor: [1.0, 0.3, 0.1], ..MaterialUniforms::default() }),
+    ];
+    for (x, color, uniforms) in spheres {
+        let uniform_buffer = create_uniform_buffer(&renderer, &camera, Matrix4::from_translation(Vector3::new(x, 0.8, 0.0)));
+        let mut sphere = renderer.create_solid_model(
+            Mesh::create_sphere(renderer.device(), 1.0, 32, 16),
+            color,
+            &uniform_buffer,
+            &light_buffer,
+            "Golden Sphere",
+        );
+        sphere.materials[0].set_uniforms(renderer.queue(), uniforms);
+        renderer.add_model(sphere);
+    }
+
+    // Wall with a normal map of horizontal ridges below the spheres
+    let normal_map = image::RgbaImage::from_fn(64, 64, |_, y| {
+        let slope = ((y as f32 / 64.0) * std::f32::consts::TAU * 4.0).sin() * 0.7;
+        let normal = Vector3::new(0.0, slope, 1.0).normalize();
+        Rgba([
+            ((normal.x * 0.5 + 0.5) * 255.0) as u8,
+            ((normal.y * 0.5 + 0.5) * 255.0) as u8,
+            ((normal.z * 0.5 + 0.5) * 255.0) as u8,
+            255,
+        ])
+    });
+    let normal_texture = Texture::from_image_with_format(
+        renderer.device(),
+        renderer.queue(),
+        &image::DynamicImage::ImageRgba8(normal_map),
+        Some("Golden Normal Map"),
+        wgpu::TextureFormat::Rgba8Unorm,
+    ).unwrap();
+    let wall_uniforms = create_uniform_buffer(
+        &renderer,
+        &camera,
+        Matrix4::from_translation(Vector3::new(0.0, -1.6, -0.5)) * Matrix4::from_nonuniform_scale(6.0, 1.2, 1.0),
+    );
+    let mut wall = renderer.create_solid_model(
+        Mesh::create_quad(renderer.device(), 2.0),
+        [0.7, 0.7, 0.7],
+        &wall_uniforms,
+        &light_buffer,
+        "Golden Wall",
+    );
+    let albedo = Texture::create_colored_texture(renderer.device(), renderer.queue(), [0.7, 0.7, 0.7], "Golden Wall Albedo");
+    let defaults = renderer.default_material_textures();
+    let textures = crate::engine::renderer::MaterialTextures {
+        normal: &normal_texture,
+        ..defaults.with_albedo(&albedo)
+    };
+    wall.materials[0] = crate::engine::renderer::create_material_instance(
+        renderer.device(),
+        renderer.material_bind_group_layout(),
+        0,
+        MaterialUniforms { flags: MATERIAL_HAS_NORMAL_MAP, ..MaterialUniforms::default() },
+        textures,
+        "Golden Wall Material",
+    );
+    renderer.add_model(wall);
+
+    assert_matches_golden("materials", &renderer.capture_frame().unwrap());
+}
+
+/// Renders a large floor tiled with a fine black and white checkerboard, receding from the camera
+fn render_checkerboard_floor(options: TextureOptions) -> RgbaImage {
+    let mut renderer = golden_renderer();
+    renderer.set_debug_grid_visible(false);
+    let camera = application_camera();
+    renderer.update_environment(&camera);
+    let light_buffer = create_light_buffer(&renderer, SunLight::default());
+
+    let checkerboard = RgbaImage::from_fn(64, 64, |x, y| {
+        let value = if (x / 8 + y / 8) % 2 == 0 { 255 } else { 0 };
+        Rgba([value, value, value, 255])
+    });
+    let albedo = Texture::from_image_with_options(
+        renderer.device(),
+        renderer.queue(),
+        &image::DynamicImage::ImageRgba8(checkerboard),
+        Some("Golden Checkerboard"),
+        &options,
+    ).unwrap();
+    let uniform_buffer = create_uniform_buffer(&renderer, &camera, Matrix4::from_angle_x(Deg(-90.0)));
+    let mut floor = renderer.create_solid_model(
+        Mesh::create_quad(renderer.device(), 60.0),
+        [1.0, 1.0, 1.0],
+        &uniform_buffer,
+        &light_buffer,
+        "Golden Floor",
+    );
+    // Repeat the checkerboard across the floor through the material's own sampler
+    let sampler = options.create_sampler(renderer.device(), Some("Golden Checkerboard Sampler"));
+    let mut uv_scale = MeshData::quad(60.0);
+    for vertex in &mut uv_scale.vertices {
+        vertex.tex_coords = vertex.tex_coords.map(|coordinate| coordinate * 8.0);
+    }
+    floor.meshes[0] = Mesh::new(renderer.device(), &uv_scale);
+    floor.materials[0] = crate::engine::renderer::create_material_instance(
+        renderer.device(),
+        renderer.material_bind_group_layout(),
+        0,
+        MaterialUniforms::default(),
+        crate::engine::renderer::MaterialTextures {
+            sampler: &sampler,
+            ..renderer.default_material_textures().with_albedo(&albedo)
+        },
+        "Golden Floor Material",
+    );
+    renderer.add_model(floor);
+    renderer.capture_frame().unwrap()
+}
+
+/// Sum of the luma differences between horizontally neighboring pixels in the top half,
+/// where the floor is far away and a checkerboard without mipmaps turns to noise
+fn distant_aliasing(image: &RgbaImage) -> u32 {
+    let luma = |x, y| image.get_pixel(x, y).0[1] as i32;
+    (0..HEIGHT / 2)
+        .flat_map(|y| (1..WIDTH).map(move |x| (x, y)))
+        .map(|(x, y)| luma(x, y).abs_diff(luma(x - 1, y)))
+        .sum()
+}
+
+#[test]
+fn golden_mipmapped_checkerboard_floor() {
+    let mipmapped = render_checkerboard_floor(TextureOptions::default());
+    assert_matches_golden("mipmapped_floor", &mipmapped);
+
+    // Without a mip chain the distant checks alias into high-contrast noise
+    let single_level = render_checkerboard_floor(TextureOptions {
+        generate_mipmaps: false,
+        ..TextureOptions::default()
+    });
+    let (mipmapped, single_level) = (distant_aliasing(&mipmapped), distant_aliasing(&single_level));
+    assert!(mipmapped * 2 < single_level, "{} against {} without mipmaps", mipmapped, single_level);
+}
+
+/// A 5x5 grid of tinted cube instances above the origin
+fn cube_grid_instances() -> Vec<Instance> {
+    let mut instances = Vec::new();
+    for x in -2..=2 {
+        for z in -2..=2 {
+            let transform = Matrix4::from_translation(Vector3::new(x as f32 * 2.5, 0.5, z as f32 * 2.5))
+                * Matrix4::from_angle_y(Deg((x * 5 + z) as f32 * 15.0));
+            let tint = [(x + 3) as f32 / 5.0, 0.6, (z + 3) as f32 / 5.0, 1.0];
+            instances.push(Instance::with_tint(transform, tint));
+        }
+    }
+    instances
+}
+
+#[test]
+fn golden_instanced_cubes_scene() {
+    let mut renderer = golden_renderer();
+    renderer.set_debug_grid_visible(false);
+
+    let camera = application_camera();
+    renderer.update_environment(&camera);
+    let uniform_buffer = create_uniform_buffer(&renderer, &camera, Matrix4::identity());
+    let light_buffer = create_light_buffer(&renderer, SunController::new().sun_light);
+    let cube = renderer.create_solid_model(
+        Mesh::create_cube(renderer.device(), 1.0),
+        [1.0, 1.0, 1.0],
+        &uniform_buffer,
+        &light_buffer,
+        "Golden Instanced Cube",
+    );
+    renderer.add_instanced_model(cube, &cube_grid_instances());
+
+    assert_matches_golden("instanced_cubes", &renderer.capture_frame().unwrap());
+}
+
+#[test]
+fn instancing_matches_separate_models() {
+    let mut renderer = golden_renderer();
+    renderer.set_debug_grid_visible(false);
+
+    let camera = application_camera();
+    renderer.update_environment(&camera);
+    let light_buffer = create_light_buffer(&renderer, SunController::new().sun_light);
+    let instances = cube_grid_instances();
+
+    let uniform_buffer = create_uniform_buffer(&renderer, &camera, Matrix4::identity());
+    let cube = renderer.create_solid_model(
+        Mesh::create_cube(renderer.device(), 1.0),
+        [1.0, 1.0, 1.0],
+        &uniform_buffer,
+        &light_buffer,
+        "Golden Instanced Cube",
+    );
+    let batch = renderer.add_instanced_model(cube, &instances);
+    let instanced = renderer.capture_frame().unwrap();
+
+    // The same cubes as one model each, with the tint as the material's base color factor
+    renderer.set_instances(batch, &[]);
+    for instance in &instances {
+        let uniform_buffer = create_uniform_buffer(&renderer, &camera, Matrix4::from(instance.model));
+        let mut cube = renderer.create_solid_model(
+            Mesh::create_cube(renderer.device(), 1.0),
+            [1.0, 1.0, 1.0],
+            &uniform_buffer,
+            &light_buffer,
+            "Golden Separate Cube",
+        );
+        cube.materials[0].set_uniforms(
+            renderer.queue(),
+            MaterialUniforms { base_color_factor: instance.tint, ..MaterialUniforms::default() },
+        );
+        renderer.add_model(cube);
+    }
+    let separate = renderer.capture_frame().unwrap();
+
+    let comparison = compare_images(&instanced, &separate, 2).unwrap();
+    assert_eq!(comparison.mismatched_pixels, 0, "max difference {}", comparison.max_channel_difference);
+}
+
+/// Equirectangular sky: blue overhead, warm at the horizon, dark ground and a bright sun
+fn procedural_environment() -> image::Rgba32FImage {
+    image::Rgba32FImage::from_fn(64, 32, |x, y| {
+        let elevation = 0.5 - (y as f32 + 0.5) / 32.0;
+        let mut color = if elevation > 0.0 {
+            let t = (elevation * 2.0).sqrt();
+            [1.2 - 0.9 * t, 0.9 - 0.4 * t, 0.7 + 0.5 * t]
+        } else {
+            [0.15, 0.12, 0.1]
+        };
+        if (10..13).contains(&x) && (8..11).contains(&y) {
+            color = [40.0, 36.0, 30.0];
+        }
+        Rgba([color[0], color[1], color[2], 1.0])
+    })
+}
+
+#[test]
+fn golden_environment_lighting_scene() {
+    let mut renderer = golden_renderer();
+    renderer.set_debug_grid_visible(false);
+    renderer.set_environment_image(&procedural_environment());
+    renderer.set_sky_mode(SkyMode::Cubemap);
+
+    let mut camera = Camera::new(Point3::new(0.0, 0.5, 7.0), Rad(3.0 * std::f32::consts::PI / 2.0), Rad(-0.1));
+    camera.set_aspect_ratio(WIDTH, HEIGHT);
+    renderer.update_environment(&camera);
+    let mut sun_light = SunLight::default();
+    sun_light.intensity = 0.0;
+    let light_buffer = create_light_buffer(&renderer, sun_light);
+
+    // Mirror, rough metal and rough plastic lit only by the environment
+    let spheres = [
+        (-2.2, [0.95, 0.95, 0.95], 1.0, 0.05),
+        (0.0, [1.0, 0.8, 0.3], 1.0, 0.5),
+        (2.2, [0.8, 0.2, 0.2], 0.0, 0.9),
+    ];
+    for (x, color, metallic_factor, roughness_factor) in spheres {
+        let uniform_buffer = create_uniform_buffer(&renderer, &camera, Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)));
+        let mut sphere = renderer.create_solid_model(
+            Mesh::create_sphere(renderer.device(), 1.0, 32, 16),
+            color,
+            &uniform_buffer,
+            &light_buffer,
+            "Golden Sphere",
+        );
+        sphere.materials[0].set_uniforms(
+            renderer.queue(),
+            MaterialUniforms { metallic_factor, roughness_factor, ..MaterialUniforms::default() },
+        );
+        renderer.add_model(sphere);
+    }
+
+    assert_matches_golden("environment_lighting", &renderer.capture_frame().unwrap());
+}
+
+#[test]
+fn golden_atmosphere_sky() {
+    let mut renderer = golden_renderer();
+    renderer.set_debug_grid_visible(false);
+    renderer.set_sky_mode(SkyMode::Atmosphere);
+
+    // Looking just above the horizon, past a cube the sky must stay behind
+    let mut camera = Camera::new(Point3::new(0.0, 2.0, 6.0), Rad(3.0 * std::f32::consts::PI / 2.0), Rad(0.3));
+    camera.set_aspect_ratio(WIDTH, HEIGHT);
+    renderer.update_environment(&camera);
+    let light_buffer = create_light_buffer(&renderer, SunLight::default());
+    let uniform_buffer = create_uniform_buffer(&renderer, &camera, Matrix4::from_translation(Vector3::new(0.0, 2.0, 0.0)));
+    let cube = renderer.create_solid_model(
+        Mesh::create_cube(renderer.device(), 1.0),
+        [0.5, 0.5, 0.5],
+        &uniform_buffer,
+        &light_buffer,
+        "Golden Cube",
+    );
+    renderer.add_model(cube);
+
+    // High sun behind the camera, then a sun setting straight ahead
+    for (direction, name) in [([0.2, -1.0, -0.4], "sky_noon"), ([0.0, -0.02, 1.0], "sky_sunset")] {
+        let mut sun_light = SunLight::default();
+        sun_light.direction = direction;
+        renderer.update_sky(&camera, &sun_light);
+        assert_matches_golden(name, &renderer.capture_frame().unwrap());
+    }
+}
+
+/// Adds a sphere filling most of the frame, lit by a sun of the given intensity.
+/// Returns the sun's light buffer so the intensity can be changed between frames.
+fn add_hdr_sphere_scene(renderer: &mut Renderer, sun_intensity: f32) -> wgpu::Buffer {
+    renderer.set_debug_grid_visible(false);
+
+    let mut camera = Camera::new(Point3::new(0.0, 0.0, 3.5), Rad(3.0 * std::f32::consts::PI / 2.0), Rad(0.0));
+    camera.set_aspect_ratio(WIDTH, HEIGHT);
+    renderer.update_environment(&camera);
+    let uniform_buffer = create_uniform_buffer(renderer, &camera, Matrix4::identity());
+    let mut sun_light = SunLight::default();
+    sun_light.direction = [-0.5, -1.0, -0.6];
+    sun_light.intensity = sun_intensity;
+    let light_buffer = create_light_buffer(renderer, sun_light);
+    let mut sphere = renderer.create_solid_model(
+        Mesh::create_sphere(renderer.device(), 1.5, 32, 16),
+        [0.9, 0.6, 0.3],
+        &uniform_buffer,
+        &light_buffer,
+        "Golden HDR Sphere",
+    );
+    sphere.materials[0].set_uniforms(
+        renderer.queue(),
+        MaterialUniforms { roughness_factor: 0.3, ..MaterialUniforms::default() },
+    );
+    renderer.add_model(sphere);
+    light_buffer
+}
+
+/// Average Rec. 709 luma of an image, 0-255
+fn average_luma(image: &RgbaImage) -> f32 {
+    let total: f32 = image.pixels()
+        .map(|pixel| 0.2126 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.0722 * pixel[2] as f32)
+        .sum();
+    total / (image.width() * image.height()) as f32
+}
+
+#[test]
+fn golden_tonemap_operators() {
+    let mut renderer = golden_renderer();
+    // Bright enough that highlights go far past 1.0
+    add_hdr_sphere_scene(&mut renderer, 8.0);
+
+    for (operator, name) in [
+        (TonemapOperator::Reinhard, "tonemap_reinhard"),
+        (TonemapOperator::AcesFilmic, "tonemap_aces_filmic"),
+        (TonemapOperator::AgX, "tonemap_agx"),
+    ] {
+        renderer.set_tonemap_settings(TonemapSettings { operator, ..TonemapSettings::default() });
+        assert_matches_golden(name, &renderer.capture_frame().unwrap());
+    }
+}
+
+#[test]
+fn golden_post_process_chain() {
+    let mut renderer = golden_renderer();
+    add_hdr_sphere_scene(&mut renderer, 8.0);
+
+    // A LUT that swaps red and blue, so grading is visible
+    let size = 8;
+    let lut = image::RgbaImage::from_fn(size * size, size, |x, y| {
+        let value = |i: u32| (i * 255 / (size - 1)) as u8;
+        Rgba([value(x / size), value(y), value(x % size), 255])
+    });
+    renderer.set_color_grading_lut(&lut).unwrap();
+
+    let mut settings = renderer.post_process_settings().clone();
+    for effect in PostEffect::ALL {
+        settings.set_enabled(effect, true);
+    }
+    settings.chromatic_aberration.intensity = 0.02;
+    settings.color_grading.contribution = 0.5;
+    renderer.set_post_process_settings(settings.clone());
+    let chained = renderer.capture_frame().unwrap();
+    assert_matches_golden("post_process_chain", &chained);
+
+    // Grading before the vignette is not the same as after it
+    settings.order = vec![PostEffect::Bloom, PostEffect::Fxaa, PostEffect::ChromaticAberration, PostEffect::Vignette, PostEffect::ColorGrading];
+    renderer.set_post_process_settings(settings);
+    let reordered = renderer.capture_frame().unwrap();
+    assert!(compare_images(&chained, &reordered, 0).unwrap().mismatched_pixels > 0);
+}
+
+#[test]
+fn identity_post_effects_keep_the_image() {
+    let mut renderer = golden_renderer();
+    add_hdr_sphere_scene(&mut renderer, 2.0);
+    let plain = renderer.capture_frame().unwrap();
+
+    // Grading through an identity LUT and zero-strength effects only round through the targets
+    let mut settings = renderer.post_process_settings().clone();
+    settings.color_grading.enabled = true;
+    settings.chromatic_aberration.enabled = true;
+    settings.chromatic_aberration.intensity = 0.0;
+    settings.vignette.enabled = true;
+    settings.vignette.intensity = 0.0;
+    renderer.set_post_process_settings(settings.clone());
+    let passed_through = renderer.capture_frame().unwrap();
+    let comparison = compare_images(&plain, &passed_through, 2).unwrap();
+    assert_eq!(comparison.mismatched_pixels, 0, "max difference {}", comparison.max_channel_difference);
+
+    // Bloom brightens the scene around its highlights
+    settings.bloom.enabled = true;
+    settings.bloom.threshold = 0.5;
+    renderer.set_post_process_settings(settings);
+    assert!(average_luma(&renderer.capture_frame().unwrap()) > average_luma(&plain) + 1.0);
+}
+
+#[test]
+fn auto_exposure_compensates_scene_brightness() {
+    let mut renderer = golden_renderer();
+    renderer.set_debug_grid_visible(false);
+
+    // An unlit, black quad filling the frame, so its emissive color is the whole image
+    let mut camera = Camera::new(Point3::new(0.0, 0.0, 1.0), Rad(3.0 * std::f32::consts::PI / 2.0), Rad(0.0));
+    camera.set_aspect_ratio(WIDTH, HEIGHT);
+    renderer.update_environment(&camera);
+    let uniform_buffer = create_uniform_buffer(&renderer, &camera, Matrix4::identity());
+    let mut sun_light = SunLight::default();
+    sun_light.intensity = 0.0;
+    let light_buffer = create_light_buffer(&renderer, sun_light);
+    let quad = renderer.create_solid_model(
+        Mesh::create_quad(renderer.device(), 10.0),
+        [0.0, 0.0, 0.0],
+        &uniform_buffer,
+        &light_buffer,
+        "Golden Emissive Quad",
+    );
+    renderer.add_model(quad);
+
+    let capture_with_emissive = |renderer: &mut Renderer, emissive: f32, auto_exposure: bool| {
+        let uniforms = MaterialUniforms { emissive_factor: [emissive; 3], ..MaterialUniforms::default() };
+        let material = &renderer.get_model(0).unwrap().materials[0];
+        renderer.queue().write_buffer(&material.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+        renderer.set_tonemap_settings(TonemapSettings { auto_exposure, ..TonemapSettings::default() });
+        // Adapt fully each frame so the measurement is not smoothed over time
+        renderer.update_exposure(1000.0);
+        average_luma(&renderer.capture_frame().unwrap())
+    };
+
+    let dim_fixed = capture_with_emissive(&mut renderer, 0.05, false);
+    let bright_fixed = capture_with_emissive(&mut renderer, 4.0, false);
+    let dim_auto = capture_with_emissive(&mut renderer, 0.05, true);
+    let bright_auto = capture_with_emissive(&mut renderer, 4.0, true);
+
+    assert!(bright_fixed - dim_fixed > 100.0, "fixed exposure: {} vs {}", dim_fixed, bright_fixed);
+    assert!((bright_auto - dim_auto).abs() < 8.0, "auto exposure: {} vs {}", dim_auto, bright_auto);
+}
+
+/// Adds a cube resting on a ground plane, lit mostly by the environment
+fn add_contact_scene(renderer: &mut Renderer, camera: &Camera) {
+    renderer.set_debug_grid_visible(false);
+    renderer.update_environment(camera);
+    let mut sun_light = SunController::new().sun_light;
+    sun_light.intensity *= 0.25;
+    let light_buffer = create_light_buffer(renderer, sun_light);
+    let cube_transform = Matrix4::from_translation(Vector3::new(0.0, 1.0, 0.0));
+    let cube_uniforms = create_uniform_buffer(renderer, camera, cube_transform);
+    let mut cube = renderer.create_solid_model(
+        Mesh::create_cube(renderer.device(), 2.0),
+        [0.9, 0.9, 0.9],
+        &cube_uniforms,
+        &light_buffer,
+        "Golden Contact Cube",
+    );
+    cube.set_transform(renderer.queue(), &cube_uniforms, cube_transform);
+    let ground_transform = Matrix4::from_angle_x(Deg(-90.0));
+    let ground_uniforms = create_uniform_buffer(renderer, camera, ground_transform);
+    let mut ground = renderer.create_solid_model(
+        Mesh::create_quad(renderer.device(), 12.0),
+        [0.8, 0.8, 0.8],
+        &ground_uniforms,
+        &light_buffer,
+        "Golden Contact Ground",
+    );
+    ground.set_transform(renderer.queue(), &ground_uniforms, ground_transform);
+    renderer.add_model(cube);
+    renderer.add_model(ground);
+}
+
+/// Pixel a world-space point lands on
+fn project_to_pixel(camera: &Camera, point: Point3<f32>) -> (u32, u32) {
+    let clip = camera.get_view_projection_matrix() * point.to_homogeneous();
+    let x = (clip.x / clip.w * 0.5 + 0.5) * WIDTH as f32;
+    let y = (0.5 - clip.y / clip.w * 0.5) * HEIGHT as f32;
+    (x as u32, y as u32)
+}
+
+fn pixel_luma(image: &RgbaImage, (x, y): (u32, u32)) -> f32 {
+    let pixel = image.get_pixel(x, y);
+    0.2126 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.0722 * pixel[2] as f32
+}
+
+#[test]
+fn golden_ambient_occlusion_scene() {
+    let mut renderer = golden_renderer();
+    let camera = application_camera();
+    add_contact_scene(&mut renderer, &camera);
+    renderer.update_ambient_occlusion(&camera);
+    let unoccluded = renderer.capture_frame().unwrap();
+
+    let mut settings = *renderer.ambient_occlusion_settings();
+    settings.enabled = true;
+    settings.radius = 1.0;
+    renderer.set_ambient_occlusion_settings(settings);
+    let occluded = renderer.capture_frame().unwrap();
+    assert_matches_golden("ambient_occlusion", &occluded);
+
+    // The ground darkens where it meets the cube, but not out in the open
+    let contact = project_to_pixel(&camera, Point3::new(0.0, 0.0, 1.1));
+    let open = project_to_pixel(&camera, Point3::new(4.0, 0.0, 3.0));
+    assert!(
+        pixel_luma(&occluded, contact) < pixel_luma(&unoccluded, contact) - 5.0,
+        "contact: {} vs {}",
+        pixel_luma(&occluded, contact),
+        pixel_luma(&unoccluded, contact),
+    );
+    assert!((pixel_luma(&occluded, open) - pixel_luma(&unoccluded, open)).abs() < 2.0);
+}
+
+#[test]
+fn ambient_occlusion_debug_view_shows_raw_buffer() {
+    let mut renderer = golden_renderer();
+    let camera = application_camera();
+    add_contact_scene(&mut renderer, &camera);
+
+    // Needs a camera to show anything, then works even with occlusion off for lighting
+    let mut settings = *renderer.ambient_occlusion_settings();
+    settings.radius = 1.0;
+    renderer.set_ambient_occlusion_settings(settings);
+    renderer.set_ambient_occlusion_debug_view(true);
+    let without_camera = renderer.capture_frame().unwrap();
+    renderer.update_ambient_occlusion(&camera);
+    let debug = renderer.capture_frame().unwrap();
+    assert!(compare_images(&without_camera, &debug, 0).unwrap().mismatched_pixels > 0);
+
+    // A grayscale image, white in the open and darker in the contact crease
+    assert!(debug.pixels().all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2]));
+    assert_eq!(debug.get_pixel(0, 0)[0], 255);
+    let contact = pixel_luma(&debug, project_to_pixel(&camera, Point3::new(0.0, 0.0, 1.1)));
+    let open = pixel_luma(&debug, project_to_pixel(&camera, Point3::new(4.0, 0.0, 3.0)));
+    assert!(contact < open - 30.0, "contact {} vs open {}", contact, open);
+    assert_matches_golden("ambient_occlusion_debug", &debug);
+}
+
+/// Adds a shadowed cube on a ground plane next to a point light, with a half-transparent
+/// sphere in front, and points the camera-dependent passes at `camera`
+fn add_deferred_scene(renderer: &mut Renderer, camera: &Camera) {
+    renderer.set_debug_grid_visible(false);
+    renderer.set_gpu_culling_enabled(false);
+    let mut sun_light = SunController::new().sun_light;
+    sun_light.use_pbr = 1;
+    let light_buffer = create_light_buffer(renderer, sun_light);
+
+    let cube_transform = Matrix4::from_translation(Vector3::new(0.0, 1.5, 0.0));
+    let cube_uniforms = create_uniform_buffer(renderer, camera, cube_transform);
+    let mut cube = renderer.create_solid_model(
+        Mesh::create_cube(renderer.device(), 2.0),
+        [0.9, 0.9, 0.9],
+        &cube_uniforms,
+        &light_buffer,
+        "Golden Deferred Cube",
+    );
+    cube.set_transform(renderer.queue(), &cube_uniforms, cube_transform);
+    let ground_transform = Matrix4::from_angle_x(Deg(-90.0));
+    let ground_uniforms = create_uniform_buffer(renderer, camera, ground_transform);
+    let mut ground = renderer.create_solid_model(
+        Mesh::create_quad(renderer.device(), 12.0),
+        [0.6, 0.6, 0.6],
+        &ground_uniforms,
+        &light_buffer,
+        "Golden Deferred Ground",
+    );
+    ground.set_transform(renderer.queue(), &ground_uniforms, ground_transform);
+    renderer.add_model(cube);
+    renderer.add_model(ground);
+    let sphere_uniforms = create_uniform_buffer(renderer, camera, Matrix4::from_translation(Vector3::new(2.5, 1.0, 2.0)));
+    let mut sphere = renderer.create_solid_model(
+        Mesh::create_sphere(renderer.device(), 0.8, 32, 16),
+        [0.2, 0.4, 1.0],
+        &sphere_uniforms,
+        &light_buffer,
+        "Golden Deferred Sphere",
+    );
+    sphere.materials[0].set_uniforms(renderer.queue(), MaterialUniforms {
+        base_color_factor: [1.0, 1.0, 1.0, 0.5],
+        blend_mode: BlendMode::AlphaBlend as u32,
+        ..MaterialUniforms::default()
+    });
+    renderer.add_model(sphere);
+    renderer.lights_mut().add_point_light(PointLight::new(Point3::new(-2.5, 0.8, 1.5), [1.0, 0.3, 0.2], 20.0, 5.0));
+
+    update_deferred_scene(renderer, camera, &sun_light);
+}
+
+/// Hands the camera and sun to the renderer, again after switching render paths
+fn update_deferred_scene(renderer: &mut Renderer, camera: &Camera, sun_light: &SunLight) {
+    renderer.update_environment(camera);
+    renderer.update_light_clusters(camera);
+    renderer.update_shadows(sun_light, &ShadowSettings::default(), camera);
+}
+
+#[test]
+fn golden_deferred_scene() {
+    let mut renderer = golden_renderer();
+    renderer.set_render_path(RenderPath::Deferred).unwrap();
+    assert_eq!(renderer.supported_sample_counts(), vec![1]);
+    let camera = application_camera();
+    add_deferred_scene(&mut renderer, &camera);
+
+    let frame = renderer.capture_frame().unwrap();
+    assert_matches_golden("deferred", &frame);
+    // The transparent sphere is drawn by the forward pass, every mesh exactly once
+    assert_eq!(renderer.culling_stats(), CullingStats { meshes_drawn: 3, meshes_culled: 0 });
+}
+
+#[test]
+fn deferred_matches_forward() {
+    let mut renderer = golden_renderer();
+    let camera = application_camera();
+    add_deferred_scene(&mut renderer, &camera);
+    let forward = renderer.capture_frame().unwrap();
+
+    let mut sun_light = SunController::new().sun_light;
+    sun_light.use_pbr = 1;
+    renderer.set_render_path(RenderPath::Deferred).unwrap();
+    update_deferred_scene(&mut renderer, &camera, &sun_light);
+    let deferred = renderer.capture_frame().unwrap();
+
+    // Only the precision of the G-buffer separates the two
+    let comparison = compare_images(&deferred, &forward, 2).unwrap();
+    assert!(
+        comparison.mismatch_ratio() < MAX_MISMATCH_RATIO,
+        "{} pixels differ, max difference {}",
+        comparison.mismatched_pixels,
+        comparison.max_channel_difference,
+    );
+
+    // Switching back restores the forward image
+    renderer.set_render_path(RenderPath::Forward).unwrap();
+    let forward_again = renderer.capture_frame().unwrap();
+    assert_eq!(compare_images(&forward_again, &forward, 0).unwrap().mismatched_pixels, 0);
+}
+
+/// Creates a sphere of `material` at `position`
+fn material_sphere(renderer: &Renderer, camera: &Camera, position: Vector3<f32>, material: MaterialUniforms) -> Model {
+    let light_buffer = create_light_buffer(renderer, SunController::new().sun_light);
+    let transform = Matrix4::from_translation(position);
+    let uniform_buffer = create_uniform_buffer(renderer, camera, transform);
+    let mut sphere = renderer.create_solid_model(
+        Mesh::create_sphere(renderer.device(), 1.0, 32, 16),
+        [1.0, 1.0, 1.0],
+        &uniform_buffer,
+        &light_buffer,
+        "Golden Material Sphere",
+    );
+    sphere.materials[0].set_uniforms(renderer.queue(), material);
+    sphere.set_transform(renderer.queue(), &uniform_buffer, transform);
+    sphere
+}
+
+/// A material of `mode` with the base color `color`
+fn blended_material(color: [f32; 4], mode: BlendMode) -> MaterialUniforms {
+    MaterialUniforms {
+        base_color_factor: color,
+        blend_mode: mode as u32,
+        ..MaterialUniforms::default()
+    }
+}
+
+/// Adds a ground plane and an opaque cube behind three overlapping half-transparent spheres,
+/// an additive sphere, and a pair of alpha-tested cubes, the second tinted below the cutoff
+fn add_transparency_scene(renderer: &mut Renderer, camera: &Camera) {
+    renderer.set_debug_grid_visible(false);
+    renderer.update_environment(camera);
+    renderer.update_culling(camera);
+    let light_buffer = create_light_buffer(renderer, SunController::new().sun_light);
+
+    let ground_transform = Matrix4::from_angle_x(Deg(-90.0));
+    let ground_uniforms = create_uniform_buffer(renderer, camera, ground_transform);
+    let mut ground = renderer.create_solid_model(
+        Mesh::create_quad(renderer.device(), 12.0),
+        [0.6, 0.6, 0.6],
+        &ground_uniforms,
+        &light_buffer,
+        "Golden Transparency Ground",
+    );
+    ground.set_transform(renderer.queue(), &ground_uniforms, ground_transform);
+    renderer.add_model(ground);
+    let cube_transform = Matrix4::from_translation(Vector3::new(0.0, 1.0, -3.0));
+    let cube_uniforms = create_uniform_buffer(renderer, camera, cube_transform);
+    let mut cube = renderer.create_solid_model(
+        Mesh::create_cube(renderer.device(), 2.0),
+        [0.9, 0.9, 0.9],
+        &cube_uniforms,
+        &light_buffer,
+        "Golden Transparency Cube",
+    );
+    cube.set_transform(renderer.queue(), &cube_uniforms, cube_transform);
+    renderer.add_model(cube);
+
+    let spheres = [
+        (Vector3::new(-0.8, 1.0, -0.5), [1.0, 0.1, 0.1, 0.5]),
+        (Vector3::new(0.0, 1.0, 0.5), [0.1, 1.0, 0.1, 0.5]),
+        (Vector3::new(0.8, 1.0, 1.5), [0.1, 0.1, 1.0, 0.5]),
+    ];
+    for (position, color) in spheres {
+        let sphere = material_sphere(renderer, camera, position, blended_material(color, BlendMode::AlphaBlend));
+        renderer.add_model(sphere);
+    }
+    let glow = material_sphere(
+        renderer,
+        camera,
+        Vector3::new(3.0, 1.0, 0.0),
+        blended_material([1.0, 0.5, 0.1, 0.5], BlendMode::Additive),
+    );
+    renderer.add_model(glow);
+
+    let uniform_buffer = create_uniform_buffer(renderer, camera, Matrix4::identity());
+    let mut cutout = renderer.create_solid_model(
+        Mesh::create_cube(renderer.device(), 1.0),
+        [1.0, 1.0, 1.0],
+        &uniform_buffer,
+        &light_buffer,
+        "Golden Cutout Cube",
+    );
+    cutout.materials[0].set_uniforms(renderer.queue(), blended_material([1.0, 1.0, 0.2, 1.0], BlendMode::AlphaTest));
+    renderer.add_instanced_model(cutout, &[
+        Instance::with_tint(Matrix4::from_translation(Vector3::new(-3.0, 0.5, 1.0)), [1.0, 1.0, 1.0, 1.0]),
+        Instance::with_tint(Matrix4::from_translation(Vector3::new(-3.0, 0.5, -1.0)), [1.0, 1.0, 1.0, 0.2]),
+    ]);
+}
+
+#[test]
+fn golden_transparency_scene() {
+    let mut renderer = golden_renderer();
+    let camera = application_camera();
+    add_transparency_scene(&mut renderer, &camera);
+
+    let sorted = renderer.capture_frame().unwrap();
+    assert_eq!(
+        renderer.frame_passes(),
+        ["light culling", "gpu culling", "shadows", "ambient occlusion", "scene", "depth pyramid", "transparent", "post-process"],
+    );
+    assert_matches_golden("transparency_sorted", &sorted);
+
+    renderer.set_transparency_mode(TransparencyMode::WeightedBlended);
+    let weighted = renderer.capture_frame().unwrap();
+    assert!(renderer.frame_passes().contains(&"transparent composite".to_string()));
+    assert_matches_golden("transparency_weighted_blended", &weighted);
+}
+
+#[test]
+fn sorted_transparency_ignores_the_order_meshes_are_added_in() {
+    let camera = application_camera();
+    let near = (Vector3::new(0.0, 1.0, 2.0), [1.0, 0.1, 0.1, 0.6]);
+    let far = (Vector3::new(0.3, 1.0, 0.0), [0.1, 0.1, 1.0, 0.6]);
+    let render = |spheres: [(Vector3<f32>, [f32; 4]); 2], sort: bool| {
+        let mut renderer = golden_renderer();
+        renderer.set_debug_grid_visible(false);
+        renderer.update_environment(&camera);
+        if sort {
+            renderer.update_culling(&camera);
+        }
+        for (position, color) in spheres {
+            let sphere = material_sphere(&renderer, &camera, position, blended_material(color, BlendMode::AlphaBlend));
+            renderer.add_model(sphere);
+        }
+        renderer.capture_frame().unwrap()
+    };
+    let near_first = render([near, far], true);
+    let far_first = render([far, near], true);
+    assert_eq!(compare_images(&near_first, &far_first, 0).unwrap().mismatched_pixels, 0);
+
+    // Without a camera to sort by, the meshes blend in the order they were added
+    let unsorted = render([near, far], false);
+    assert!(compare_images(&unsorted, &far_first, CHANNEL_TOLERANCE).unwrap().mismatched_pixels > 0);
+}
+
+#[test]
+fn weighted_blended_transparency_matches_sorted_without_overlap() {
+    let mut renderer = golden_renderer();
+    renderer.set_debug_grid_visible(false);
+    let camera = application_camera();
+    renderer.update_environment(&camera);
+    renderer.update_culling(&camera);
+    for (x, mode) in [(-2.5, BlendMode::AlphaBlend), (0.0, BlendMode::Additive), (2.5, BlendMode::AlphaBlend)] {
+        let sphere = material_sphere(&renderer, &camera, Vector3::new(x, 1.0, 0.0), blended_material([0.2, 0.6, 1.0, 0.4], mode));
+        renderer.add_model(sphere);
+    }
+    let sorted = renderer.capture_frame().unwrap();
+
+    // A single layer averages to itself, up to the precision of the revealage
+    renderer.set_transparency_mode(TransparencyMode::WeightedBlended);
+    let weighted = renderer.capture_frame().unwrap();
+    let comparison = compare_images(&weighted, &sorted, 2).unwrap();
+    assert_eq!(comparison.mismatched_pixels, 0, "max difference {}", comparison.max_channel_difference);
+
+    // Multisampled accumulation resolves to the same surfaces
+    renderer.set_sample_count(4).unwrap();
+    let multisampled = renderer.capture_frame().unwrap();
+    let comparison = compare_images(&multisampled, &weighted, CHANNEL_TOLERANCE).unwrap();
+    assert!(comparison.mismatch_ratio() < 0.05, "{} pixels differ", comparison.mismatched_pixels);
+}
+
+/// Adds a red cube far off to the side and a white screen in front of the application
+/// camera, returning a camera that looks at the cube head on
+fn add_monitor_scene(renderer: &mut Renderer, screen: &Texture) -> Camera {
+    let camera = application_camera();
+    let light_buffer = create_light_buffer(renderer, SunController::new().sun_light);
+
+    let cube_transform = Matrix4::from_translation(Vector3::new(50.0, 1.0, 0.0));
+    let cube_buffer = create_uniform_buffer(renderer, &camera, cube_transform);
+    let mut cube = renderer.create_solid_model(
+        Mesh::create_cube(renderer.device(), 2.0),
+        [1.0, 0.1, 0.1],
+        &cube_buffer,
+        &light_buffer,
+        "Golden Distant Cube",
+    );
+    cube.set_transform(renderer.queue(), &cube_buffer, cube_transform);
+    renderer.add_model(cube);
+
+    let screen_transform = Matrix4::from_translation(Vector3::new(0.0, 1.5, 0.0));
+    let screen_buffer = create_uniform_buffer(renderer, &camera, screen_transform);
+    let mut monitor = renderer.create_solid_model(
+        Mesh::create_quad(renderer.device(), 6.0),
+        [1.0, 1.0, 1.0],
+        &screen_buffer,
+        &light_buffer,
+        "Golden Monitor",
+    );
+    monitor.materials[0] = crate::engine::renderer::create_material_instance(
+        renderer.device(),
+        renderer.material_bind_group_layout(),
+        0,
+        MaterialUniforms::default(),
+        renderer.default_material_textures().with_albedo(screen),
+        "Golden Monitor Material",
+    );
+    monitor.set_transform(renderer.queue(), &screen_buffer, screen_transform);
+    renderer.add_model(monitor);
+
+    let mut security_camera = Camera::new(Point3::new(44.0, 1.0, 0.0), Rad(0.0), Rad(0.0));
+    security_camera.set_aspect_ratio(64, 64);
+    security_camera
+}
+
+#[test]
+fn golden_render_target_monitor() {
+    let mut renderer = golden_renderer();
+    renderer.set_debug_grid_visible(false);
+    let target = renderer.create_render_target(64, 64, "Golden Security Feed");
+    let security_camera = add_monitor_scene(&mut renderer, target.texture());
+    let camera = application_camera();
+
+    // The target is drawn before the view that samples it
+    let views = [View::with_target(&security_camera, &target), View::new(&camera)];
+    let sun = SunController::new();
+    renderer.render_views(&views, &sun.sun_light, &sun.shadow).unwrap();
+
+    // The feed is the cube filling the middle of the target
+    let feed = read_texels(&renderer, target.texture(), 64, 64);
+    let [r, g, _, _] = feed.get_pixel(32, 32).0;
+    assert!(r > g * 2, "feed center is {:?}", feed.get_pixel(32, 32).0);
+
+    // The main camera never sees the cube, only its picture on the monitor
+    let frame = renderer.read_pixels().unwrap();
+    let (x, y) = project_to_pixel(&camera, Point3::new(0.0, 1.5, 0.0));
+    let [r, g, _, _] = frame.get_pixel(x, y).0;
+    assert!(r > g * 2, "monitor center is {:?}", frame.get_pixel(x, y).0);
+    assert_matches_golden("render_target_monitor", &frame);
+}
+
+#[test]
+fn golden_split_screen() {
+    let mut renderer = golden_renderer();
+    renderer.set_debug_grid_visible(false);
+    let camera = application_camera();
+    let light_buffer = create_light_buffer(&renderer, SunController::new().sun_light);
+    let uniform_buffer = create_uniform_buffer(&renderer, &camera, Matrix4::from_translation(Vector3::new(0.0, 1.0, 0.0)));
+    let mut cube = renderer.create_solid_model(
+        Mesh::create_cube(renderer.device(), 2.0),
+        [1.0, 1.0, 1.0],
+        &uniform_buffer,
+        &light_buffer,
+        "Golden Cube",
+    );
+    cube.set_transform(renderer.queue(), &uniform_buffer, Matrix4::from_translation(Vector3::new(0.0, 1.0, 0.0)));
+    renderer.add_model(cube);
+
+    // Two players looking at the cube from different sides, each with half the width
+    let mut first = Camera::new(Point3::new(0.0, 10.0, 10.0), Rad(3.0 * std::f32::consts::PI / 2.0), Rad(-0.6));
+    let mut second = Camera::new(Point3::new(8.0, 4.0, 0.0), Rad(std::f32::consts::PI), Rad(-0.4));
+    first.set_aspect_ratio(WIDTH / 2, HEIGHT);
+    second.set_aspect_ratio(WIDTH / 2, HEIGHT);
+    let viewports = Viewport::split_screen(2);
+    let views = [View::with_viewport(&first, viewports[0]), View::with_viewport(&second, viewports[1])];
+    let sun = SunController::new();
+    renderer.render_views(&views, &sun.sun_light, &sun.shadow).unwrap();
+    let split = renderer.read_pixels().unwrap();
+    assert_matches_golden("split_screen", &split);
+
+    // The left half is the first camera drawn on its own into a surface of that size
+    renderer.resize(WIDTH / 2, HEIGHT);
+    renderer.render_views(&[View::new(&first)], &sun.sun_light, &sun.shadow).unwrap();
+    let alone = renderer.read_pixels().unwrap();
+    let left = image::imageops::crop_imm(&split, 0, 0, WIDTH / 2, HEIGHT).to_image();
+    let comparison = compare_images(&left, &alone, CHANNEL_TOLERANCE).unwrap();
+    assert!(comparison.mismatch_ratio() < 0.01, "{} pixels differ", comparison.mismatched_pixels);
+
+    // The second camera sees the cube from another side
+    let right = image::imageops::crop_imm(&split, WIDTH / 2, 0, WIDTH / 2, HEIGHT).to_image();
+    assert!(compare_images(&right, &left, CHANNEL_TOLERANCE).unwrap().mismatch_ratio() > 0.05);
+}
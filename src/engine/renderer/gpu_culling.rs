@@ -0,0 +1,815 @@
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use bytemuck::{Pod, Zeroable};
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::util::DeviceExt;
+use crate::engine::renderer::culling::{CullingStats, Frustum};
+use crate::engine::renderer::instance::{Instance, InstancedModel};
+use crate::engine::renderer::lod::LodSelector;
+use crate::engine::renderer::material::MaterialPass;
+use crate::engine::renderer::texture::Texture;
+use crate::engine::scene::Model;
+
+/// Workgroup size of the culling compute shader
+const CULLING_WORKGROUP_SIZE: u32 = 64;
+
+/// Camera and depth pyramid the culling shader tests against
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct CullUniforms {
+    previous_view_proj: [[f32; 4]; 4], // Camera the depth pyramid was rendered with
+    planes: [[f32; 4]; 6],             // Current frustum planes
+    hiz_size: [f32; 2],
+    hiz_mip_count: u32,
+    object_count: u32,
+    occlusion_enabled: u32,            // Zero until a previous frame's depth is available
+    lod_fade_range: f32,               // Zero without LOD cross-fading
+    _padding: [u32; 2],
+    camera_position: [f32; 3],
+    lod_scale: f32,                    // 1 / tan(fovy / 2), zero to draw full detail
+}
+
+/// One instance of one mesh, with its world-space bounds and the draw it belongs to
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct CullObject {
+    instance: Instance,
+    aabb_min: [f32; 3],
+    draw: u32,
+    aabb_max: [f32; 3],
+    lod_count: u32, // Levels of detail, drawn by the commands from `draw` on
+}
+
+/// Indirect draw arguments followed by the offset of the draw's instances in the visible buffer
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct DrawCommand {
+    index_count: u32,
+    instance_count: u32, // Counted up by the culling shader
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+    instance_base: u32,
+    lod_screen_size: f32, // Screen height fraction below which this level is used
+    _padding: u32,
+}
+
+/// Which model a draw comes from
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum DrawSource {
+    Model(usize),
+    Instanced(usize),
+}
+
+/// A mesh drawn indirectly with the instances that survived culling
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct DrawItem {
+    source: DrawSource,
+    mesh: usize,
+    instance_base: u32,
+}
+
+/// Per-object buffers rebuilt whenever the draws change, and rewritten in place when only
+/// instances move
+struct CullScene {
+    draws: Vec<DrawItem>,
+    commands: Vec<DrawCommand>, // Initial commands with no instances, uploaded every frame
+    object_count: u32,
+    draw_buffer: wgpu::Buffer,
+    visible_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    object_buffer: wgpu::Buffer,
+}
+
+/// The depth pyramid of one view, built from its depth buffer after the view is drawn
+struct DepthHistory {
+    depth_sample_count: Option<u32>, // Samples per pixel of the depth buffer level 0 is copied from, none when it cannot be sampled
+    hiz: Texture,
+    levels: Vec<(wgpu::Texture, wgpu::TextureView, wgpu::BindGroup)>, // Each level, rendered from the one above
+    bind_group: wgpu::BindGroup,                                      // The pyramid as the culling shader reads it
+    view_proj: Option<Matrix4<f32>>,                                  // Camera the pyramid was built with, none before it is
+}
+
+/// Frustum and Hi-Z occlusion culling on the GPU. A compute pass tests every mesh instance
+/// against the camera and against a depth pyramid built from the previous frame's depth
+/// buffer, then writes the survivors and their counts into `draw_indexed_indirect`
+/// arguments the main pass draws from. Objects that were hidden last frame and uncovered by
+/// camera or object motion may appear one frame late. Every view of a frame keeps its own
+/// pyramid, so each camera is culled against what it saw itself.
+pub struct GpuCulling {
+    uniform_buffer: wgpu::Buffer,
+    cull_bind_group_layout: wgpu::BindGroupLayout,
+    hiz_bind_group_layout: wgpu::BindGroupLayout,
+    cull_pipeline: wgpu::ComputePipeline,
+    copy_bind_group_layout: wgpu::BindGroupLayout,
+    copy_multisampled_bind_group_layout: wgpu::BindGroupLayout,
+    downsample_bind_group_layout: wgpu::BindGroupLayout,
+    copy_pipeline: wgpu::RenderPipeline,
+    copy_multisampled_pipelines: Vec<(u32, wgpu::RenderPipeline)>, // Level 0 copies for each MSAA sample count
+    downsample_pipeline: wgpu::RenderPipeline,
+    histories: Vec<Option<DepthHistory>>, // Indexed by view, created when the view is first drawn
+    view: usize,                          // View being drawn
+    scene: Option<CullScene>,
+    readback_pending: bool,
+    readback_ready: Arc<AtomicBool>,
+    stats: CullingStats,
+}
+
+impl GpuCulling {
+    /// Returns true if the adapter can run the culling compute pass and draw indirectly
+    pub fn is_supported(adapter: &wgpu::Adapter) -> bool {
+        adapter.get_downlevel_capabilities().flags.contains(
+            wgpu::DownlevelFlags::COMPUTE_SHADERS | wgpu::DownlevelFlags::INDIRECT_EXECUTION
+        )
+    }
+
+    /// Create the culling and depth pyramid pipelines
+    pub fn new(device: &wgpu::Device, culling_shader: &wgpu::ShaderModule, hiz_shader: &wgpu::ShaderModule) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cull Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[CullUniforms::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let texture_entry = |binding, visibility, sample_type| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Texture {
+                sample_type,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let depth_pyramid_sample_type = wgpu::TextureSampleType::Float { filterable: false };
+
+        let cull_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Cull Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Objects
+                storage_entry(1, true),
+                // Draw commands
+                storage_entry(2, false),
+                // Visible instances
+                storage_entry(3, false),
+            ],
+        });
+        // The depth pyramid is bound apart from the objects, as each view has its own
+        let hiz_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Cull Depth Pyramid Bind Group Layout"),
+            entries: &[texture_entry(0, wgpu::ShaderStages::COMPUTE, depth_pyramid_sample_type)],
+        });
+        let cull_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Cull Pipeline Layout"),
+            bind_group_layouts: &[&cull_bind_group_layout, &hiz_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let cull_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Cull Pipeline"),
+            layout: Some(&cull_pipeline_layout),
+            module: culling_shader,
+            entry_point: "cs_main",
+        });
+
+        // Level 0 copies the depth buffer, every further level halves the one above. The depth
+        // buffer is bound as a float texture since GLSL cannot load texels of depth textures.
+        let copy_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Depth Pyramid Copy Bind Group Layout"),
+            entries: &[texture_entry(0, wgpu::ShaderStages::FRAGMENT, depth_pyramid_sample_type)],
+        });
+        let copy_multisampled_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Depth Pyramid Multisampled Copy Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: depth_pyramid_sample_type,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: true,
+                },
+                count: None,
+            }],
+        });
+        let downsample_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Depth Pyramid Downsample Bind Group Layout"),
+            entries: &[texture_entry(1, wgpu::ShaderStages::FRAGMENT, depth_pyramid_sample_type)],
+        });
+        let create_pipeline = |layout: &wgpu::BindGroupLayout, entry_point: &str, label: &str| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: hiz_shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: hiz_shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: wgpu::TextureFormat::R32Float,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let copy_pipeline = create_pipeline(&copy_bind_group_layout, "fs_copy", "Depth Pyramid Copy Pipeline");
+        let copy_multisampled_pipelines = [(2, "fs_copy_2x"), (4, "fs_copy_4x"), (8, "fs_copy_8x")]
+            .into_iter()
+            .map(|(sample_count, entry_point)| {
+                let pipeline = create_pipeline(
+                    &copy_multisampled_bind_group_layout,
+                    entry_point,
+                    "Depth Pyramid Multisampled Copy Pipeline",
+                );
+                (sample_count, pipeline)
+            })
+            .collect();
+        let downsample_pipeline = create_pipeline(
+            &downsample_bind_group_layout,
+            "fs_downsample",
+            "Depth Pyramid Downsample Pipeline",
+        );
+
+        Self {
+            uniform_buffer,
+            cull_bind_group_layout,
+            hiz_bind_group_layout,
+            cull_pipeline,
+            copy_bind_group_layout,
+            copy_multisampled_bind_group_layout,
+            downsample_bind_group_layout,
+            copy_pipeline,
+            copy_multisampled_pipelines,
+            downsample_pipeline,
+            histories: Vec::new(),
+            view: 0,
+            scene: None,
+            readback_pending: false,
+            readback_ready: Arc::new(AtomicBool::new(false)),
+            stats: CullingStats::default(),
+        }
+    }
+
+    fn create_history(&self, device: &wgpu::Device, depth_texture: Option<&Texture>, width: u32, height: u32) -> DepthHistory {
+        let hiz = Texture::create_depth_pyramid(device, width, height, "Depth Pyramid");
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Cull Depth Pyramid Bind Group"),
+            layout: &self.hiz_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&hiz.view),
+            }],
+        });
+        let mut history = DepthHistory {
+            depth_sample_count: depth_texture.map(|depth_texture| depth_texture.texture.sample_count()),
+            hiz,
+            levels: Vec::new(),
+            bind_group,
+            view_proj: None,
+        };
+        let Some(depth_texture) = depth_texture else {
+            // Nothing to build the levels from, the pyramid stays bound but unused
+            return history;
+        };
+        let copy_layout = if depth_texture.texture.sample_count() > 1 {
+            &self.copy_multisampled_bind_group_layout
+        } else {
+            &self.copy_bind_group_layout
+        };
+        for level in 0..history.hiz.texture.mip_level_count() {
+            // Each level is its own render target, copied into the pyramid once it is drawn
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Depth Pyramid Level"),
+                size: wgpu::Extent3d {
+                    width: (width >> level).max(1),
+                    height: (height >> level).max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::R32Float,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            let (layout, binding, source) = match history.levels.last() {
+                None if depth_texture.texture.sample_count() > 1 => (copy_layout, 2, &depth_texture.view),
+                None => (copy_layout, 0, &depth_texture.view),
+                Some((_, source, _)) => (&self.downsample_bind_group_layout, 1, source),
+            };
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Depth Pyramid Bind Group"),
+                layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding,
+                    resource: wgpu::BindingResource::TextureView(source),
+                }],
+            });
+            history.levels.push((texture, view, bind_group));
+        }
+        history
+    }
+
+    /// Cull and build the depth pyramid for the view with the given index from now on,
+    /// drawn into `depth_texture` at the given size. A view whose depth buffer changed size
+    /// or sample count gets a new pyramid, and is culled against the frustum only for a frame.
+    /// Without a depth texture that can be bound, the view is only culled against the frustum.
+    pub fn select_view(&mut self, device: &wgpu::Device, index: usize, depth_texture: Option<&Texture>, width: u32, height: u32) {
+        self.view = index;
+        if self.histories.len() <= index {
+            self.histories.resize_with(index + 1, || None);
+        }
+        let depth_sample_count = depth_texture.map(|depth_texture| depth_texture.texture.sample_count());
+        let current = self.histories[index].as_ref().is_some_and(|history| {
+            let size = history.hiz.texture.size();
+            (size.width, size.height) == (width, height) && history.depth_sample_count == depth_sample_count
+        });
+        if !current {
+            self.histories[index] = Some(self.create_history(device, depth_texture, width, height));
+        }
+    }
+
+    /// Drop the depth pyramids of views past the first `count`
+    pub fn retain_views(&mut self, count: usize) {
+        self.histories.truncate(count);
+    }
+
+    /// Drop the depth pyramid of every view, for depth buffers that were replaced or frames
+    /// culled without them. The next frame of each view is culled against the frustum only.
+    pub fn clear_history(&mut self) {
+        self.histories.clear();
+    }
+
+    fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        object_buffer: &wgpu::Buffer,
+        draw_buffer: &wgpu::Buffer,
+        visible_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Cull Bind Group"),
+            layout: &self.cull_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: object_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: draw_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: visible_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Gather one object per mesh instance and one indirect draw per level of detail of each
+    /// mesh. Called after models are added or instances change. When the draws are the same
+    /// as before, only the objects are uploaded into the existing buffers.
+    pub fn rebuild(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, models: &[Model], instanced_models: &[InstancedModel]) {
+        let mut objects = Vec::new();
+        let mut draws = Vec::new();
+        let mut commands = Vec::new();
+        let mut visible_count = 0;
+
+        // Plain models are drawn as one identity instance, culled with their transform
+        let plain = models.iter().enumerate().map(|(index, model)| {
+            (DrawSource::Model(index), model, vec![(model.transform(), Instance::default())])
+        });
+        let instanced = instanced_models.iter().enumerate().map(|(index, instanced)| {
+            let instances = instanced.instances().iter()
+                .map(|instance| (instanced.model.transform() * Matrix4::from(instance.model), *instance))
+                .collect();
+            (DrawSource::Instanced(index), &instanced.model, instances)
+        });
+        for (source, model, instances) in plain.chain(instanced) {
+            if instances.is_empty() {
+                continue;
+            }
+            for (mesh_index, mesh) in model.meshes.iter().enumerate() {
+                let draw = draws.len() as u32;
+                for (world, instance) in &instances {
+                    let aabb = mesh.aabb.transform(world);
+                    objects.push(CullObject {
+                        instance: *instance,
+                        aabb_min: aabb.min.into(),
+                        draw,
+                        aabb_max: aabb.max.into(),
+                        lod_count: mesh.lods.len() as u32,
+                    });
+                }
+                // Every level has room for all instances, as cross-fading ones are drawn at two
+                for lod in &mesh.lods {
+                    let instance_base = visible_count;
+                    visible_count += instances.len() as u32;
+                    draws.push(DrawItem { source, mesh: mesh_index, instance_base });
+                    commands.push(DrawCommand {
+                        index_count: lod.index_count,
+                        instance_count: 0,
+                        first_index: lod.first_index,
+                        base_vertex: lod.base_vertex,
+                        first_instance: 0,
+                        instance_base,
+                        lod_screen_size: lod.screen_size,
+                        _padding: 0,
+                    });
+                }
+            }
+        }
+
+        if objects.is_empty() {
+            self.scene = None;
+            return;
+        }
+
+        // Moved or retinted instances keep their draws, buffers and pending readback
+        if let Some(scene) = &self.scene {
+            let same_commands = bytemuck::cast_slice::<_, u8>(&scene.commands) == bytemuck::cast_slice::<_, u8>(&commands);
+            if scene.object_count == objects.len() as u32 && scene.draws == draws && same_commands {
+                queue.write_buffer(&scene.object_buffer, 0, bytemuck::cast_slice(&objects));
+                return;
+            }
+        }
+
+        let object_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cull Object Buffer"),
+            contents: bytemuck::cast_slice(&objects),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let draw_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Indirect Draw Buffer"),
+            contents: bytemuck::cast_slice(&commands),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+        let visible_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Visible Instance Buffer"),
+            size: (visible_count as usize * size_of::<Instance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Indirect Draw Readback Buffer"),
+            size: (commands.len() * size_of::<DrawCommand>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = self.create_bind_group(device, &object_buffer, &draw_buffer, &visible_buffer);
+
+        // A readback of the old buffers is dropped with them
+        self.readback_pending = false;
+        self.readback_ready = Arc::new(AtomicBool::new(false));
+        self.scene = Some(CullScene {
+            draws,
+            commands,
+            object_count: objects.len() as u32,
+            draw_buffer,
+            visible_buffer,
+            readback_buffer,
+            bind_group,
+            object_buffer,
+        });
+    }
+
+    /// Returns the counts of the most recent frame whose results reached the CPU. The GPU
+    /// counts each mesh instance, and the counts trail the rendered frame by a frame or two.
+    pub fn stats(&self) -> CullingStats {
+        self.stats
+    }
+
+    /// Record the culling pass of the selected view for a camera with the given
+    /// view-projection matrix, or none to draw everything, against the depth pyramid of the
+    /// view's previous frame. Each surviving instance is drawn at the levels of detail `lods`
+    /// would pick.
+    pub fn cull(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view_proj: Option<Matrix4<f32>>,
+        lods: &LodSelector,
+    ) {
+        self.read_stats(device);
+        let Some(scene) = &self.scene else { return };
+        let Some(history) = self.histories.get(self.view).and_then(Option::as_ref) else { return };
+
+        // Occlusion needs last frame's depth and the camera it was rendered with
+        let previous_view_proj = history.view_proj.filter(|_| view_proj.is_some());
+        let frustum = view_proj.map(Frustum::from_matrix).unwrap_or(Frustum::INFINITE);
+        let hiz_size = history.hiz.texture.size();
+        let uniforms = CullUniforms {
+            previous_view_proj: previous_view_proj.unwrap_or(Matrix4::identity()).into(),
+            planes: frustum.planes(),
+            hiz_size: [hiz_size.width as f32, hiz_size.height as f32],
+            hiz_mip_count: history.hiz.texture.mip_level_count(),
+            object_count: scene.object_count,
+            occlusion_enabled: previous_view_proj.is_some() as u32,
+            lod_fade_range: lods.fade_range(),
+            _padding: [0; 2],
+            camera_position: lods.view().map_or([0.0; 3], |view| view.position.into()),
+            lod_scale: lods.view().map_or(0.0, |view| view.projection_scale),
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+        // Start every draw with no instances
+        queue.write_buffer(&scene.draw_buffer, 0, bytemuck::cast_slice(&scene.commands));
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Object Culling Pass"),
+            });
+            compute_pass.set_pipeline(&self.cull_pipeline);
+            compute_pass.set_bind_group(0, &scene.bind_group, &[]);
+            compute_pass.set_bind_group(1, &history.bind_group, &[]);
+            compute_pass.dispatch_workgroups(scene.object_count.div_ceil(CULLING_WORKGROUP_SIZE), 1, 1);
+        }
+
+        // Copy the counts out unless an earlier copy is still waiting to be read
+        if !self.readback_pending {
+            encoder.copy_buffer_to_buffer(
+                &scene.draw_buffer,
+                0,
+                &scene.readback_buffer,
+                0,
+                scene.readback_buffer.size(),
+            );
+        }
+    }
+
+    /// Build the selected view's depth pyramid from the depth buffer it was just drawn into
+    /// by a camera with the given view-projection matrix, for its next frame to be culled
+    /// against. Without a camera the next frame is culled against the frustum only.
+    pub fn build_depth_pyramid(&mut self, encoder: &mut wgpu::CommandEncoder, view_proj: Option<Matrix4<f32>>) {
+        let Some(history) = self.histories.get_mut(self.view).and_then(Option::as_mut) else { return };
+        history.view_proj = view_proj.filter(|_| !history.levels.is_empty());
+        if history.view_proj.is_none() {
+            return;
+        }
+
+        let history = self.histories[self.view].as_ref().expect("Selected view should have a depth pyramid");
+        let copy_pipeline = self.copy_multisampled_pipelines
+            .iter()
+            .find(|(sample_count, _)| Some(*sample_count) == history.depth_sample_count)
+            .map_or(&self.copy_pipeline, |(_, pipeline)| pipeline);
+        for (level, (texture, view, bind_group)) in history.levels.iter().enumerate() {
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Depth Pyramid Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+                render_pass.set_pipeline(if level == 0 { copy_pipeline } else { &self.downsample_pipeline });
+                render_pass.set_bind_group(0, bind_group, &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+
+            encoder.copy_texture_to_texture(
+                texture.as_image_copy(),
+                wgpu::ImageCopyTexture {
+                    texture: &history.hiz.texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                texture.size(),
+            );
+        }
+    }
+
+    /// Start reading back the draw counts copied by `cull`, once its commands were submitted
+    pub fn request_stats(&mut self) {
+        let Some(scene) = &self.scene else { return };
+        if self.readback_pending {
+            return;
+        }
+        self.readback_pending = true;
+        let ready = self.readback_ready.clone();
+        scene.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            ready.store(result.is_ok(), Ordering::Release);
+        });
+    }
+
+    fn read_stats(&mut self, device: &wgpu::Device) {
+        let Some(scene) = &self.scene else { return };
+        if !self.readback_pending {
+            return;
+        }
+        device.poll(wgpu::Maintain::Poll);
+        if !self.readback_ready.swap(false, Ordering::Acquire) {
+            return;
+        }
+        {
+            let data = scene.readback_buffer.slice(..).get_mapped_range();
+            let commands: &[DrawCommand] = bytemuck::cast_slice(&data);
+            let drawn: u32 = commands.iter().map(|command| command.instance_count).sum();
+            // Instances cross-fading between levels are drawn twice
+            self.stats = CullingStats {
+                meshes_drawn: drawn,
+                meshes_culled: scene.object_count.saturating_sub(drawn),
+            };
+        }
+        scene.readback_buffer.unmap();
+        self.readback_pending = false;
+    }
+
+    /// Draw every mesh in `pass` with the instances that survived culling
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pass: MaterialPass,
+        models: &'a [Model],
+        instanced_models: &'a [InstancedModel],
+    ) {
+        let Some(scene) = &self.scene else { return };
+        for (index, item) in scene.draws.iter().enumerate() {
+            let model = match item.source {
+                DrawSource::Model(index) => &models[index],
+                DrawSource::Instanced(index) => &instanced_models[index].model,
+            };
+            if !model.mesh_in_pass(item.mesh, pass) {
+                continue;
+            }
+            let mesh = &model.meshes[item.mesh];
+            let material = &model.materials[mesh.material_id];
+            let instance_offset = item.instance_base as wgpu::BufferAddress * size_of::<Instance>() as wgpu::BufferAddress;
+
+            render_pass.set_bind_group(0, &model.bind_group, &[]);
+            render_pass.set_bind_group(3, &material.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, scene.visible_buffer.slice(instance_offset..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed_indirect(
+                &scene.draw_buffer,
+                (index * size_of::<DrawCommand>()) as wgpu::BufferAddress,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Point3, Rad, Vector3};
+    use super::*;
+    use crate::engine::renderer::test_support::{
+        compare_images, create_light_buffer, create_uniform_buffer, gpu_culling_available, headless_renderer,
+    };
+    use crate::engine::renderer::Mesh;
+    use crate::engine::scene::{Camera, SunController};
+
+    /// Size of the frames the tests render
+    const WIDTH: u32 = 128;
+    const HEIGHT: u32 = 128;
+
+    #[test]
+    fn gpu_culling_hides_occluded_and_off_screen_meshes() {
+        let mut renderer = headless_renderer(WIDTH, HEIGHT);
+        if !gpu_culling_available(&renderer) {
+            return;
+        }
+        renderer.set_debug_grid_visible(false);
+
+        let mut camera = Camera::new(Point3::new(0.0, 0.0, 6.0), Rad(3.0 * std::f32::consts::PI / 2.0), Rad(0.0));
+        camera.set_aspect_ratio(WIDTH, HEIGHT);
+        renderer.update_environment(&camera);
+        renderer.update_culling(&camera);
+        let light_buffer = create_light_buffer(&renderer, SunController::new().sun_light);
+
+        // A wall filling the view, a cube hidden behind it and a cube behind the camera
+        for (size, position) in [
+            (4.0, Vector3::new(0.0, 0.0, 0.0)),
+            (1.0, Vector3::new(0.0, 0.0, -5.0)),
+            (1.0, Vector3::new(0.0, 0.0, 12.0)),
+        ] {
+            let transform = Matrix4::from_translation(position);
+            let uniform_buffer = create_uniform_buffer(&renderer, &camera, transform);
+            let mut model = renderer.create_solid_model(
+                Mesh::create_cube(renderer.device(), size),
+                [1.0, 1.0, 1.0],
+                &uniform_buffer,
+                &light_buffer,
+                "Test Culled Cube",
+            );
+            model.set_transform(renderer.queue(), &uniform_buffer, transform);
+            renderer.add_model(model);
+        }
+        // One instance in front of the wall and one far off to the side
+        let uniform_buffer = create_uniform_buffer(&renderer, &camera, Matrix4::identity());
+        let cube = renderer.create_solid_model(
+            Mesh::create_cube(renderer.device(), 1.0),
+            [1.0, 0.2, 0.2],
+            &uniform_buffer,
+            &light_buffer,
+            "Test Culled Instance",
+        );
+        let instances = [Vector3::new(0.0, 0.0, 3.0), Vector3::new(20.0, 0.0, 0.0)]
+            .map(|position| Instance::new(Matrix4::from_translation(position)));
+        renderer.add_instanced_model(cube, &instances);
+
+        // The first frame has no depth to test against, so only the frustum culls.
+        // Counts reach the CPU at the start of the following frame.
+        renderer.capture_frame().unwrap();
+        renderer.capture_frame().unwrap();
+        assert_eq!(renderer.culling_stats(), CullingStats { meshes_drawn: 3, meshes_culled: 2 });
+        let gpu_culled = renderer.capture_frame().unwrap();
+        assert_eq!(renderer.culling_stats(), CullingStats { meshes_drawn: 2, meshes_culled: 3 });
+
+        // Culling on the GPU must not change the picture
+        // The CPU counts the batch once and keeps it, since one of its instances is visible
+        renderer.set_gpu_culling_enabled(false);
+        let cpu_culled = renderer.capture_frame().unwrap();
+        assert_eq!(renderer.culling_stats(), CullingStats { meshes_drawn: 3, meshes_culled: 1 });
+        let comparison = compare_images(&gpu_culled, &cpu_culled, 2).unwrap();
+        assert_eq!(comparison.mismatched_pixels, 0, "max difference {}", comparison.max_channel_difference);
+    }
+
+    #[test]
+    fn gpu_culling_counts_instances_moved_every_frame() {
+        let mut renderer = headless_renderer(WIDTH, HEIGHT);
+        if !gpu_culling_available(&renderer) {
+            return;
+        }
+        renderer.set_debug_grid_visible(false);
+
+        let mut camera = Camera::new(Point3::new(0.0, 0.0, 6.0), Rad(3.0 * std::f32::consts::PI / 2.0), Rad(0.0));
+        camera.set_aspect_ratio(WIDTH, HEIGHT);
+        renderer.update_environment(&camera);
+        renderer.update_culling(&camera);
+        let light_buffer = create_light_buffer(&renderer, SunController::new().sun_light);
+        let uniform_buffer = create_uniform_buffer(&renderer, &camera, Matrix4::identity());
+        let cube = renderer.create_solid_model(
+            Mesh::create_cube(renderer.device(), 1.0),
+            [1.0, 0.2, 0.2],
+            &uniform_buffer,
+            &light_buffer,
+            "Test Moving Instance",
+        );
+        let instances_at = |x: f32| [Vector3::new(x, 0.0, 0.0), Vector3::new(20.0, 0.0, 0.0)]
+            .map(|position| Instance::new(Matrix4::from_translation(position)));
+        let batch = renderer.add_instanced_model(cube, &instances_at(0.0));
+        renderer.capture_frame().unwrap();
+
+        // Handing the batch the instances it already has leaves the culling objects alone
+        renderer.set_instances(batch, &instances_at(0.0));
+        assert!(!renderer.culling_scene_changed);
+
+        // Moving the instances every frame rewrites their objects in place, without
+        // dropping the counts on their way back from the GPU
+        for frame in 1..4 {
+            renderer.set_instances(batch, &instances_at(frame as f32 * 0.1));
+            renderer.capture_frame().unwrap();
+        }
+        assert_eq!(renderer.culling_stats(), CullingStats { meshes_drawn: 1, meshes_culled: 1 });
+    }
+}
@@ -0,0 +1,281 @@
+use std::mem::size_of;
+use bytemuck::{Pod, Zeroable};
+use cgmath::{Matrix4, Point3, SquareMatrix};
+use wgpu::util::DeviceExt;
+use crate::engine::renderer::culling::{Aabb, CullingStats, Frustum};
+use crate::engine::renderer::lod::{LodChoice, LodSelector};
+use crate::engine::renderer::material::MaterialPass;
+use crate::engine::scene::Model;
+
+/// WGSL inputs matching `Instance::desc`, for the vertex stage of the mesh pipelines
+pub const INSTANCE_WGSL: &str = r#"
+struct InstanceInput {
+    @location(5) model_0: vec4<f32>,
+    @location(6) model_1: vec4<f32>,
+    @location(7) model_2: vec4<f32>,
+    @location(8) model_3: vec4<f32>,
+    @location(9) tint: vec4<f32>,
+    @location(10) lod_fade: f32,
+};
+
+fn instance_model(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+}
+"#;
+
+/// Per-instance transform and color, read from the second vertex buffer
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+pub struct Instance {
+    pub model: [[f32; 4]; 4], // Applied before the model's own transform
+    pub tint: [f32; 4],       // Multiplies the material's base color
+    pub lod_fade: f32,        // Fraction of pixels dithered away while cross-fading LODs, negative for the complement
+    _padding: [f32; 3],
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self::new(Matrix4::identity())
+    }
+}
+
+#[allow(dead_code)]
+impl Instance {
+    /// Create an untinted instance
+    pub fn new(model: Matrix4<f32>) -> Self {
+        Self::with_tint(model, [1.0; 4])
+    }
+
+    /// Create an instance whose base color is multiplied by `tint`
+    pub fn with_tint(model: Matrix4<f32>, tint: [f32; 4]) -> Self {
+        Self {
+            model: model.into(),
+            tint,
+            lod_fade: 0.0,
+            _padding: [0.0; 3],
+        }
+    }
+
+    /// The same instance dithered for a LOD cross-fade
+    pub fn with_lod_fade(self, lod_fade: f32) -> Self {
+        Self { lod_fade, ..self }
+    }
+
+    /// Creates an instance buffer layout for the GPU
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 6] = wgpu::vertex_attr_array![
+            5 => Float32x4,
+            6 => Float32x4,
+            7 => Float32x4,
+            8 => Float32x4,
+            9 => Float32x4,
+            10 => Float32,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: size_of::<Instance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+/// Vertex buffer of instances that grows as more are uploaded
+pub struct InstanceBuffer {
+    buffer: wgpu::Buffer,
+    capacity: usize,
+    count: u32,
+}
+
+impl InstanceBuffer {
+    /// Create a buffer holding `instances`
+    pub fn new(device: &wgpu::Device, instances: &[Instance], label: &str) -> Self {
+        // Keep room for one instance so the buffer is never empty
+        let capacity = instances.len().max(1);
+        let mut contents = instances.to_vec();
+        contents.resize(capacity, Instance::default());
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents: bytemuck::cast_slice(&contents),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+
+        Self {
+            buffer,
+            capacity,
+            count: instances.len() as u32,
+        }
+    }
+
+    /// Replace the instances, reallocating when they no longer fit
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, instances: &[Instance]) {
+        if instances.len() > self.capacity {
+            self.capacity = instances.len().next_power_of_two();
+            self.buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Instance Buffer"),
+                size: (self.capacity * size_of::<Instance>()) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+        }
+        if !instances.is_empty() {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(instances));
+        }
+        self.count = instances.len() as u32;
+    }
+
+    /// Number of instances drawn
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// The whole buffer, for binding as vertex buffer 1
+    pub fn slice(&self) -> wgpu::BufferSlice<'_> {
+        self.buffer.slice(..)
+    }
+
+    /// The buffer from instance `first` on, so drawing instance 0 reads it
+    pub fn slice_from(&self, first: u32) -> wgpu::BufferSlice<'_> {
+        self.buffer.slice(first as wgpu::BufferAddress * size_of::<Instance>() as wgpu::BufferAddress..)
+    }
+}
+
+/// A model drawn once per instance with a single draw call per mesh
+pub struct InstancedModel {
+    pub model: Model,
+    instances: Vec<Instance>,
+    buffer: InstanceBuffer,
+    mesh_bounds: Vec<Aabb>, // World-space box around every instance of each mesh
+}
+
+impl InstancedModel {
+    /// Create an instanced model drawn with `instances`
+    pub fn new(device: &wgpu::Device, model: Model, instances: &[Instance]) -> Self {
+        let mesh_bounds = Self::compute_mesh_bounds(&model, instances);
+        Self {
+            model,
+            instances: instances.to_vec(),
+            buffer: InstanceBuffer::new(device, instances, "Instance Buffer"),
+            mesh_bounds,
+        }
+    }
+
+    /// Replace the instances and the bounds they cover
+    pub fn set_instances(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, instances: &[Instance]) {
+        self.mesh_bounds = Self::compute_mesh_bounds(&self.model, instances);
+        self.buffer.update(device, queue, instances);
+        self.instances = instances.to_vec();
+    }
+
+    /// The transforms and tints the model is drawn with
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    fn compute_mesh_bounds(model: &Model, instances: &[Instance]) -> Vec<Aabb> {
+        model.meshes.iter().map(|mesh| {
+            instances.iter()
+                .map(|instance| mesh.aabb.transform(&(model.transform() * Matrix4::from(instance.model))))
+                .reduce(|a, b| a.union(&b))
+                .unwrap_or(mesh.aabb)
+        }).collect()
+    }
+
+    /// Draw every instance of the meshes in `pass` whose instances are not all outside the frustum.
+    /// The whole batch uses one level of detail, picked for the bounds around every instance.
+    pub fn draw_culled<'a, 'b>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'b>,
+        pass: MaterialPass,
+        frustum: &Frustum,
+        lods: &LodSelector,
+        stats: &mut CullingStats,
+    ) where 'a: 'b {
+        if self.buffer.count() == 0 {
+            return;
+        }
+        render_pass.set_vertex_buffer(1, self.buffer.slice());
+        self.model.draw_meshes(render_pass, 0..self.buffer.count(), |index| {
+            if !self.model.mesh_in_pass(index, pass) {
+                return None;
+            }
+            let bounds = &self.mesh_bounds[index];
+            let visible = frustum.intersects_aabb(bounds);
+            stats.record(visible);
+            visible.then(|| match lods.select(&self.model.meshes[index].lods, bounds) {
+                LodChoice::Level(level) | LodChoice::CrossFade { from: level, .. } => level,
+            })
+        });
+    }
+
+    /// Draw every instance of the mesh at `index` unless the bounds around them lie outside the frustum
+    pub fn draw_mesh_culled<'a, 'b>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'b>,
+        index: usize,
+        frustum: &Frustum,
+        lods: &LodSelector,
+        stats: &mut CullingStats,
+    ) where 'a: 'b {
+        if self.buffer.count() == 0 {
+            return;
+        }
+        let bounds = &self.mesh_bounds[index];
+        let visible = frustum.intersects_aabb(bounds);
+        stats.record(visible);
+        if !visible {
+            return;
+        }
+        let level = match lods.select(&self.model.meshes[index].lods, bounds) {
+            LodChoice::Level(level) | LodChoice::CrossFade { from: level, .. } => level,
+        };
+        render_pass.set_vertex_buffer(1, self.buffer.slice());
+        render_pass.set_bind_group(0, &self.model.bind_group, &[]);
+        self.model.draw_mesh(render_pass, index, level, 0..self.buffer.count());
+    }
+
+    /// Returns the world-space center of the bounds around every instance of the mesh at `index`
+    pub fn mesh_center(&self, index: usize) -> Point3<f32> {
+        self.mesh_bounds[index].center()
+    }
+
+    #[allow(dead_code)]
+    /// Draw every instance of the model
+    pub fn draw<'a, 'b>(&'a self, render_pass: &mut wgpu::RenderPass<'b>)
+    where 'a: 'b {
+        if self.buffer.count() == 0 {
+            return;
+        }
+        render_pass.set_vertex_buffer(1, self.buffer.slice());
+        self.model.draw_instanced(render_pass, 0..self.buffer.count());
+    }
+
+    /// Draw every instance without binding materials, for depth-only passes
+    pub fn draw_geometry<'a, 'b>(&'a self, render_pass: &mut wgpu::RenderPass<'b>)
+    where 'a: 'b {
+        if self.buffer.count() == 0 {
+            return;
+        }
+        render_pass.set_vertex_buffer(1, self.buffer.slice());
+        self.model.draw_geometry_instanced(render_pass, 0..self.buffer.count());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Vector3;
+
+    #[test]
+    fn instance_layout_matches_struct() {
+        let layout = Instance::desc();
+        assert_eq!(layout.array_stride as usize, size_of::<Instance>());
+        let last = layout.attributes.last().unwrap();
+        assert_eq!(last.shader_location, 10);
+        assert_eq!(last.offset as usize, size_of::<[[f32; 4]; 4]>() + size_of::<[f32; 4]>());
+
+        // Columns in cgmath order, translation in the last one
+        let instance = Instance::new(Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0)));
+        assert_eq!(instance.model[3], [1.0, 2.0, 3.0, 1.0]);
+        assert_eq!(instance.tint, [1.0; 4]);
+    }
+}
@@ -0,0 +1,602 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+use crate::engine::renderer::ambient_occlusion::AmbientOcclusion;
+use crate::engine::renderer::environment::Environment;
+use crate::engine::renderer::view::ViewBuffer;
+use crate::engine::scene::{Camera, PointLight, SceneLights, SpotLight};
+
+/// Number of clusters the view frustum is divided into along x, y and depth
+pub const CLUSTER_GRID_SIZE: [u32; 3] = [16, 9, 24];
+
+/// Most lights a single cluster can reference; further lights are dropped
+pub const MAX_LIGHTS_PER_CLUSTER: u32 = 128;
+
+/// Closest depth the exponential cluster slices start from. Everything nearer
+/// falls into the first slice.
+const MIN_CLUSTER_NEAR: f32 = 0.1;
+
+/// Workgroup size of the light culling compute shader
+const CULLING_WORKGROUP_SIZE: u32 = 64;
+
+/// WGSL structs shared by the lit shaders and the light culling compute shader
+pub const LIGHT_TYPES_WGSL: &str = r#"
+struct LightCounts {
+    point_count: u32,
+    spot_count: u32,
+    _padding1: u32,
+    _padding2: u32,
+};
+
+struct PointLight {
+    position: vec3<f32>,
+    _padding: u32,
+    color: vec3<f32>,
+    intensity: f32,
+    radius: f32,
+    falloff: f32,
+    _padding2: vec2<f32>,
+};
+
+struct SpotLight {
+    position: vec3<f32>,
+    range: f32,
+    direction: vec3<f32>,
+    intensity: f32,
+    color: vec3<f32>,
+    inner_cone_cos: f32,
+    outer_cone_cos: f32,
+    _padding1: f32,
+    _padding2: f32,
+    _padding3: f32,
+};
+
+struct ClusterUniforms {
+    view: mat4x4<f32>,
+    grid_size: vec3<u32>,
+    max_lights: u32,
+    screen_size: vec2<f32>,
+    near: f32,
+    far: f32,
+    tan_half_fovy: f32,
+    aspect: f32,
+    slice_scale: f32,
+    enabled: u32,
+    debug_heatmap: u32,
+    _padding1: u32,
+    _padding2: u32,
+    _padding3: u32,
+};
+
+// Number of point and spot lights binned into one cluster
+struct ClusterLights {
+    point_count: u32,
+    spot_count: u32,
+};
+"#;
+
+/// WGSL declarations and attenuation helpers for the scene light list.
+/// Expects the light bind group at group 2.
+pub const LIGHTS_WGSL: &str = r#"
+@group(2) @binding(0)
+var<uniform> light_counts: LightCounts;
+@group(2) @binding(1)
+var<storage, read> point_lights: array<PointLight>;
+@group(2) @binding(2)
+var<storage, read> spot_lights: array<SpotLight>;
+@group(2) @binding(3)
+var<uniform> clusters: ClusterUniforms;
+@group(2) @binding(4)
+var<storage, read> cluster_lights: array<ClusterLights>;
+@group(2) @binding(5)
+var<storage, read> cluster_light_indices: array<u32>;
+
+// Inverse square falloff, windowed so the light reaches exactly zero at its range
+fn distance_attenuation(distance: f32, range: f32, falloff: f32) -> f32 {
+    let ratio = distance / max(range, 0.0001);
+    let window = clamp(1.0 - pow(ratio, 4.0), 0.0, 1.0);
+    return pow(window * window, falloff) / (distance * distance + 1.0);
+}
+
+// Smooth falloff between the inner and outer cone of a spot light
+fn spot_attenuation(light: SpotLight, light_dir: vec3<f32>) -> f32 {
+    let cos_angle = dot(normalize(light.direction), -light_dir);
+    return smoothstep(light.outer_cone_cos, light.inner_cone_cos, cos_angle);
+}
+
+// Lights that can affect a fragment: its cluster's list, or every light when clustering is off
+struct LightRange {
+    point_count: u32,
+    spot_count: u32,
+    offset: u32,
+    clustered: bool,
+};
+
+fn cluster_index(frag_coord: vec2<f32>, world_position: vec3<f32>) -> u32 {
+    let tile = vec2<u32>(clamp(
+        frag_coord / clusters.screen_size * vec2<f32>(clusters.grid_size.xy),
+        vec2<f32>(0.0),
+        vec2<f32>(clusters.grid_size.xy - vec2<u32>(1u)),
+    ));
+    let view_depth = -(clusters.view * vec4<f32>(world_position, 1.0)).z;
+    let slice = u32(clamp(
+        log(max(view_depth, clusters.near) / clusters.near) * clusters.slice_scale,
+        0.0,
+        f32(clusters.grid_size.z - 1u),
+    ));
+    return tile.x + tile.y * clusters.grid_size.x + slice * clusters.grid_size.x * clusters.grid_size.y;
+}
+
+fn light_range(frag_coord: vec2<f32>, world_position: vec3<f32>) -> LightRange {
+    var range: LightRange;
+    if (clusters.enabled == 0u) {
+        range.point_count = light_counts.point_count;
+        range.spot_count = light_counts.spot_count;
+        range.offset = 0u;
+        range.clustered = false;
+        return range;
+    }
+    let cluster = cluster_index(frag_coord, world_position);
+    let counts = cluster_lights[cluster];
+    range.point_count = counts.point_count;
+    range.spot_count = counts.spot_count;
+    range.offset = cluster * clusters.max_lights;
+    range.clustered = true;
+    return range;
+}
+
+fn point_light_index(range: LightRange, i: u32) -> u32 {
+    if (range.clustered) {
+        return cluster_light_indices[range.offset + i];
+    }
+    return i;
+}
+
+fn spot_light_index(range: LightRange, i: u32) -> u32 {
+    if (range.clustered) {
+        return cluster_light_indices[range.offset + range.point_count + i];
+    }
+    return i;
+}
+
+// Blend a heatmap of the lights in the fragment's cluster over the shaded color
+fn light_heatmap(color: vec3<f32>, range: LightRange) -> vec3<f32> {
+    if (clusters.debug_heatmap == 0u) {
+        return color;
+    }
+    let t = clamp(f32(range.point_count + range.spot_count) / 32.0, 0.0, 1.0);
+    let heat = clamp(vec3<f32>(
+        1.5 - abs(4.0 * t - 3.0),
+        1.5 - abs(4.0 * t - 2.0),
+        1.5 - abs(4.0 * t - 1.0),
+    ), vec3<f32>(0.0), vec3<f32>(1.0));
+    return mix(color, heat, 0.6);
+}
+"#;
+
+/// View and grid parameters used to build and look up light clusters
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct ClusterUniforms {
+    view: [[f32; 4]; 4],
+    grid_size: [u32; 3],
+    max_lights: u32,
+    screen_size: [f32; 2],
+    near: f32,
+    far: f32,
+    tan_half_fovy: f32,
+    aspect: f32,
+    slice_scale: f32, // Slices per unit of ln(depth / near)
+    enabled: u32,
+    debug_heatmap: u32,
+    _padding: [u32; 3],
+}
+
+/// Light counts of one cluster, written by the culling shader
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct ClusterLights {
+    point_count: u32,
+    spot_count: u32,
+}
+
+/// Total number of clusters in the grid
+pub fn cluster_count() -> u32 {
+    CLUSTER_GRID_SIZE[0] * CLUSTER_GRID_SIZE[1] * CLUSTER_GRID_SIZE[2]
+}
+
+/// Number of lights in each storage buffer
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct LightCounts {
+    point_count: u32,
+    spot_count: u32,
+    _padding: [u32; 2],
+}
+
+/// Lit bind group reading one set of occlusion targets, kept while other targets are bound
+pub struct LitBindGroup {
+    bind_group: wgpu::BindGroup,
+    generation: u64, // Of the light buffers and environment it binds
+}
+
+/// GPU copy of the scene's point and spot lights, binned into view-space clusters
+pub struct LightBuffers {
+    count_buffer: wgpu::Buffer,
+    point_buffer: wgpu::Buffer,
+    spot_buffer: wgpu::Buffer,
+    point_capacity: usize,
+    spot_capacity: usize,
+    cluster_uniforms: ClusterUniforms,
+    cluster_uniform_buffer: wgpu::Buffer,
+    cluster_lights_buffer: wgpu::Buffer,
+    cluster_index_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    generation: u64, // Counts the times the storage buffers or the environment were replaced
+    culling_bind_group_layout: wgpu::BindGroupLayout,
+    culling_bind_group: wgpu::BindGroup,
+    culling_pipeline: wgpu::ComputePipeline,
+}
+
+impl LightBuffers {
+    /// Create empty light buffers, the layout the lit pipelines bind them with and
+    /// the compute pipeline that bins lights into clusters. The lit bind group also
+    /// carries the image-based lighting of `environment`, the screen-space
+    /// `ambient_occlusion` and the camera of the `view`.
+    pub fn new(
+        device: &wgpu::Device,
+        culling_shader: &wgpu::ShaderModule,
+        environment: &Environment,
+        ambient_occlusion: &AmbientOcclusion,
+        view: &ViewBuffer,
+    ) -> Self {
+        let count_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Count Buffer"),
+            contents: bytemuck::cast_slice(&[LightCounts::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let point_buffer = Self::create_storage_buffer::<PointLight>(device, 1, "Point Light Buffer");
+        let spot_buffer = Self::create_storage_buffer::<SpotLight>(device, 1, "Spot Light Buffer");
+
+        // Clustering stays off until a camera is provided
+        let cluster_uniforms = ClusterUniforms::zeroed();
+        let cluster_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cluster Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[cluster_uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let cluster_lights_buffer = Self::create_storage_buffer::<ClusterLights>(
+            device,
+            cluster_count() as usize,
+            "Cluster Lights Buffer",
+        );
+        let cluster_index_buffer = Self::create_storage_buffer::<u32>(
+            device,
+            (cluster_count() * MAX_LIGHTS_PER_CLUSTER) as usize,
+            "Cluster Light Index Buffer",
+        );
+
+        let uniform_entry = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let storage_entry = |binding, visibility, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let mut lit_entries = vec![
+            // Light counts
+            uniform_entry(0, wgpu::ShaderStages::FRAGMENT),
+            // Point lights
+            storage_entry(1, wgpu::ShaderStages::FRAGMENT, true),
+            // Spot lights
+            storage_entry(2, wgpu::ShaderStages::FRAGMENT, true),
+            // Cluster grid
+            uniform_entry(3, wgpu::ShaderStages::FRAGMENT),
+            // Lights per cluster
+            storage_entry(4, wgpu::ShaderStages::FRAGMENT, true),
+            // Light indices per cluster
+            storage_entry(5, wgpu::ShaderStages::FRAGMENT, true),
+        ];
+        lit_entries.extend(Environment::bind_group_layout_entries());
+        lit_entries.extend(AmbientOcclusion::bind_group_layout_entries());
+        lit_entries.push(ViewBuffer::bind_group_layout_entry());
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Light Bind Group Layout"),
+            entries: &lit_entries,
+        });
+        // Same bindings for the culling shader, which writes the cluster lists
+        let culling_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Light Culling Bind Group Layout"),
+            entries: &[
+                uniform_entry(0, wgpu::ShaderStages::COMPUTE),
+                storage_entry(1, wgpu::ShaderStages::COMPUTE, true),
+                storage_entry(2, wgpu::ShaderStages::COMPUTE, true),
+                uniform_entry(3, wgpu::ShaderStages::COMPUTE),
+                storage_entry(4, wgpu::ShaderStages::COMPUTE, false),
+                storage_entry(5, wgpu::ShaderStages::COMPUTE, false),
+            ],
+        });
+
+        let culling_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Light Culling Pipeline Layout"),
+            bind_group_layouts: &[&culling_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let culling_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Light Culling Pipeline"),
+            layout: Some(&culling_pipeline_layout),
+            module: culling_shader,
+            entry_point: "cs_main",
+        });
+
+        let buffers = [
+            &count_buffer,
+            &point_buffer,
+            &spot_buffer,
+            &cluster_uniform_buffer,
+            &cluster_lights_buffer,
+            &cluster_index_buffer,
+        ];
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            "Light Bind Group",
+            buffers,
+            &Self::lit_entries(environment, ambient_occlusion, view),
+        );
+        let culling_bind_group = Self::create_bind_group(
+            device,
+            &culling_bind_group_layout,
+            "Light Culling Bind Group",
+            buffers,
+            &[],
+        );
+
+        Self {
+            count_buffer,
+            point_buffer,
+            spot_buffer,
+            point_capacity: 1,
+            spot_capacity: 1,
+            cluster_uniforms,
+            cluster_uniform_buffer,
+            cluster_lights_buffer,
+            cluster_index_buffer,
+            bind_group_layout,
+            bind_group,
+            generation: 0,
+            culling_bind_group_layout,
+            culling_bind_group,
+            culling_pipeline,
+        }
+    }
+
+    fn create_storage_buffer<T>(device: &wgpu::Device, capacity: usize, label: &str) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (capacity * std::mem::size_of::<T>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Binds the buffers in order: light counts, point lights, spot lights, cluster grid,
+    /// lights per cluster and light indices, followed by `extra_entries`
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        label: &str,
+        buffers: [&wgpu::Buffer; 6],
+        extra_entries: &[wgpu::BindGroupEntry],
+    ) -> wgpu::BindGroup {
+        let mut entries: Vec<_> = buffers.iter()
+            .enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect();
+        entries.extend_from_slice(extra_entries);
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(label),
+            layout,
+            entries: &entries,
+        })
+    }
+
+    /// Bindings of the lit bind group after the light buffers
+    fn lit_entries<'a>(
+        environment: &'a Environment,
+        ambient_occlusion: &'a AmbientOcclusion,
+        view: &'a ViewBuffer,
+    ) -> Vec<wgpu::BindGroupEntry<'a>> {
+        let mut entries = environment.bind_group_entries().to_vec();
+        entries.extend(ambient_occlusion.bind_group_entries());
+        entries.push(view.bind_group_entry());
+        entries
+    }
+
+    fn buffers(&self) -> [&wgpu::Buffer; 6] {
+        [
+            &self.count_buffer,
+            &self.point_buffer,
+            &self.spot_buffer,
+            &self.cluster_uniform_buffer,
+            &self.cluster_lights_buffer,
+            &self.cluster_index_buffer,
+        ]
+    }
+
+    fn create_lit_bind_group(
+        &self,
+        device: &wgpu::Device,
+        environment: &Environment,
+        ambient_occlusion: &AmbientOcclusion,
+        view: &ViewBuffer,
+    ) -> wgpu::BindGroup {
+        Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            "Light Bind Group",
+            self.buffers(),
+            &Self::lit_entries(environment, ambient_occlusion, view),
+        )
+    }
+
+    /// Recreate both bind groups after a storage buffer or the environment was replaced,
+    /// which stales the lit bind groups kept for other occlusion targets
+    fn recreate_bind_groups(
+        &mut self,
+        device: &wgpu::Device,
+        environment: &Environment,
+        ambient_occlusion: &AmbientOcclusion,
+        view: &ViewBuffer,
+    ) {
+        self.generation += 1;
+        self.bind_group = self.create_lit_bind_group(device, environment, ambient_occlusion, view);
+        self.culling_bind_group = Self::create_bind_group(
+            device,
+            &self.culling_bind_group_layout,
+            "Light Culling Bind Group",
+            self.buffers(),
+            &[],
+        );
+    }
+
+    /// Layout of the bind group that lit pipelines use to read the lights
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    /// Bind group that lit pipelines use to read the lights
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Rebind the environment maps after they were recreated
+    pub fn rebind(&mut self, device: &wgpu::Device, environment: &Environment, ambient_occlusion: &AmbientOcclusion, view: &ViewBuffer) {
+        self.recreate_bind_groups(device, environment, ambient_occlusion, view);
+    }
+
+    /// Bind the occlusion targets `ambient_occlusion` renders into now, reusing `cached` when
+    /// it was made for them and nothing else changed since. Returns the bind group of the
+    /// targets bound until now, to hand back here when they are bound again.
+    pub fn bind_occlusion(
+        &mut self,
+        device: &wgpu::Device,
+        cached: Option<LitBindGroup>,
+        environment: &Environment,
+        ambient_occlusion: &AmbientOcclusion,
+        view: &ViewBuffer,
+    ) -> LitBindGroup {
+        let bind_group = match cached.filter(|cached| cached.generation == self.generation) {
+            Some(cached) => cached.bind_group,
+            None => self.create_lit_bind_group(device, environment, ambient_occlusion, view),
+        };
+        LitBindGroup {
+            bind_group: std::mem::replace(&mut self.bind_group, bind_group),
+            generation: self.generation,
+        }
+    }
+
+    /// Upload the scene lights, growing the storage buffers when they no longer fit
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lights: &SceneLights,
+        environment: &Environment,
+        ambient_occlusion: &AmbientOcclusion,
+        view: &ViewBuffer,
+    ) {
+        let point_lights: Vec<PointLight> = lights.point_lights().copied().collect();
+        let spot_lights: Vec<SpotLight> = lights.spot_lights().copied().collect();
+
+        let mut resized = false;
+        if point_lights.len() > self.point_capacity {
+            self.point_capacity = point_lights.len().next_power_of_two();
+            self.point_buffer = Self::create_storage_buffer::<PointLight>(device, self.point_capacity, "Point Light Buffer");
+            resized = true;
+        }
+        if spot_lights.len() > self.spot_capacity {
+            self.spot_capacity = spot_lights.len().next_power_of_two();
+            self.spot_buffer = Self::create_storage_buffer::<SpotLight>(device, self.spot_capacity, "Spot Light Buffer");
+            resized = true;
+        }
+        if resized {
+            self.recreate_bind_groups(device, environment, ambient_occlusion, view);
+        }
+
+        if !point_lights.is_empty() {
+            queue.write_buffer(&self.point_buffer, 0, bytemuck::cast_slice(&point_lights));
+        }
+        if !spot_lights.is_empty() {
+            queue.write_buffer(&self.spot_buffer, 0, bytemuck::cast_slice(&spot_lights));
+        }
+        let counts = LightCounts {
+            point_count: point_lights.len() as u32,
+            spot_count: spot_lights.len() as u32,
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.count_buffer, 0, bytemuck::cast_slice(&[counts]));
+    }
+
+    /// Fit the cluster grid to the camera. Until this is called every fragment
+    /// evaluates every light.
+    pub fn update_clusters(&mut self, queue: &wgpu::Queue, camera: &Camera, width: u32, height: u32) {
+        let near = camera.znear.max(MIN_CLUSTER_NEAR);
+        let far = camera.zfar.max(near * 2.0);
+        self.cluster_uniforms = ClusterUniforms {
+            view: camera.get_view_matrix().into(),
+            grid_size: CLUSTER_GRID_SIZE,
+            max_lights: MAX_LIGHTS_PER_CLUSTER,
+            screen_size: [width as f32, height as f32],
+            near,
+            far,
+            tan_half_fovy: (camera.fovy.0 * 0.5).tan(),
+            aspect: camera.aspect_ratio,
+            slice_scale: CLUSTER_GRID_SIZE[2] as f32 / (far / near).ln(),
+            enabled: 1,
+            ..self.cluster_uniforms
+        };
+        queue.write_buffer(&self.cluster_uniform_buffer, 0, bytemuck::cast_slice(&[self.cluster_uniforms]));
+    }
+
+    /// Blend a heatmap of the lights per cluster over the lit scene
+    pub fn set_heatmap_visible(&mut self, queue: &wgpu::Queue, visible: bool) {
+        self.cluster_uniforms.debug_heatmap = visible as u32;
+        queue.write_buffer(&self.cluster_uniform_buffer, 0, bytemuck::cast_slice(&[self.cluster_uniforms]));
+    }
+
+    /// Returns true if the per-cluster heatmap is shown
+    pub fn heatmap_visible(&self) -> bool {
+        self.cluster_uniforms.debug_heatmap != 0
+    }
+
+    /// Bin the lights into clusters. Does nothing until the grid has been fit to a camera.
+    pub fn cull(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.cluster_uniforms.enabled == 0 {
+            return;
+        }
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Light Culling Pass"),
+        });
+        compute_pass.set_pipeline(&self.culling_pipeline);
+        compute_pass.set_bind_group(0, &self.culling_bind_group, &[]);
+        compute_pass.dispatch_workgroups(cluster_count().div_ceil(CULLING_WORKGROUP_SIZE), 1, 1);
+    }
+}
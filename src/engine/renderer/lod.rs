@@ -0,0 +1,564 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::ops::Add;
+use cgmath::{InnerSpace, Point3, Vector3};
+use crate::engine::renderer::culling::Aabb;
+use crate::engine::renderer::instance::{Instance, InstanceBuffer};
+use crate::engine::renderer::mesh::{MeshData, MeshLod};
+use crate::engine::scene::Camera;
+
+/// Steps of the dithered cross-fade, one per threshold of the 4x4 ordered dither
+pub const LOD_FADE_STEPS: u32 = 16;
+
+/// WGSL test for the pixels a cross-fading LOD leaves to the other level, matching `Instance::lod_fade`
+pub const LOD_FADE_WGSL: &str = r#"
+fn lod_faded_out(position: vec2<f32>, fade: f32) -> bool {
+    var bayer = array<f32, 16>(0.0, 8.0, 2.0, 10.0, 12.0, 4.0, 14.0, 6.0, 3.0, 11.0, 1.0, 9.0, 15.0, 7.0, 13.0, 5.0);
+    let pixel = vec2<u32>(position) % 4u;
+    let threshold = (bayer[pixel.y * 4u + pixel.x] + 0.5) / 16.0;
+    return select(threshold < fade, threshold >= -fade, fade < 0.0);
+}
+"#;
+
+/// How a mesh's chain of simplified levels is built and when each level takes over
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LodSettings {
+    pub level_count: usize,   // Including the full mesh
+    pub triangle_ratio: f32,  // Triangles each level keeps of the one before
+    pub min_triangles: usize, // Levels are not simplified below this
+    pub screen_size: f32,     // Screen height fraction below which level 1 is used, halved for each further level
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            level_count: 4,
+            triangle_ratio: 0.5,
+            min_triangles: 64,
+            screen_size: 0.5,
+        }
+    }
+}
+
+impl LodSettings {
+    /// The screen height fraction below which `level` replaces the one before it
+    pub fn screen_size(&self, level: usize) -> f32 {
+        if level == 0 {
+            f32::MAX
+        } else {
+            self.screen_size * 0.5f32.powi(level as i32 - 1)
+        }
+    }
+}
+
+/// Error of a position against a set of planes, as the upper triangle of a symmetric 4x4 matrix
+#[derive(Debug, Copy, Clone, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    /// Squared distance to the plane `normal . p + d = 0`, scaled by `weight`
+    fn from_plane(normal: Vector3<f64>, d: f64, weight: f64) -> Self {
+        let [a, b, c] = [normal.x, normal.y, normal.z];
+        Self([a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d].map(|value| value * weight))
+    }
+
+    fn error(&self, p: Vector3<f64>) -> f64 {
+        let [aa, ab, ac, ad, bb, bc, bd, cc, cd, dd] = self.0;
+        let (x, y, z) = (p.x, p.y, p.z);
+        aa * x * x + bb * y * y + cc * z * z + dd
+            + 2.0 * (ab * x * y + ac * x * z + bc * y * z + ad * x + bd * y + cd * z)
+    }
+}
+
+impl Add for Quadric {
+    type Output = Quadric;
+
+    fn add(self, other: Quadric) -> Quadric {
+        let mut sum = self.0;
+        for (value, other) in sum.iter_mut().zip(other.0) {
+            *value += other;
+        }
+        Quadric(sum)
+    }
+}
+
+/// A candidate collapse of `from` onto `to`, valid while neither vertex changed since
+#[derive(Debug, Copy, Clone)]
+struct Collapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    // Cheapest first out of the max-heap, ties broken by vertex order to stay deterministic
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+            .then_with(|| other.from.cmp(&self.from))
+            .then_with(|| other.to.cmp(&self.to))
+    }
+}
+
+/// Mesh connectivity the simplifier edits in place
+struct Simplifier {
+    positions: Vec<Vector3<f64>>,
+    triangles: Vec<[u32; 3]>,
+    removed: Vec<bool>,                // Per triangle
+    vertex_triangles: Vec<Vec<u32>>,
+    quadrics: Vec<Quadric>,
+    locked: Vec<bool>,                 // Seam and border vertices, which never move
+    versions: Vec<u32>,
+    heap: BinaryHeap<Collapse>,
+}
+
+impl Simplifier {
+    fn new(mesh: &MeshData) -> Self {
+        let positions: Vec<Vector3<f64>> = mesh.vertices.iter()
+            .map(|vertex| Vector3::from(vertex.position).cast().unwrap())
+            .collect();
+        let triangles: Vec<[u32; 3]> = mesh.indices.chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .filter(|[a, b, c]| a != b && b != c && a != c)
+            .collect();
+
+        let mut vertex_triangles = vec![Vec::new(); positions.len()];
+        let mut quadrics = vec![Quadric::default(); positions.len()];
+        let mut edges: HashMap<(u32, u32), u32> = HashMap::new();
+        for (index, triangle) in triangles.iter().enumerate() {
+            let [p0, p1, p2] = triangle.map(|vertex| positions[vertex as usize]);
+            let cross = (p1 - p0).cross(p2 - p0);
+            let length = cross.magnitude();
+            for corner in 0..3 {
+                let vertex = triangle[corner];
+                vertex_triangles[vertex as usize].push(index as u32);
+                if length > 0.0 {
+                    let normal = cross / length;
+                    let plane = Quadric::from_plane(normal, -normal.dot(p0), length * 0.5);
+                    quadrics[vertex as usize] = quadrics[vertex as usize] + plane;
+                }
+                let next = triangle[(corner + 1) % 3];
+                *edges.entry((vertex.min(next), vertex.max(next))).or_default() += 1;
+            }
+        }
+
+        // Vertices split for UV or normal seams share a position with another vertex, and
+        // moving only one copy would tear the surface; border vertices would shrink holes' edges
+        let mut copies: HashMap<[u32; 3], u32> = HashMap::new();
+        for vertex in &mesh.vertices {
+            *copies.entry(vertex.position.map(f32::to_bits)).or_default() += 1;
+        }
+        let mut locked: Vec<bool> = mesh.vertices.iter()
+            .map(|vertex| copies[&vertex.position.map(f32::to_bits)] > 1)
+            .collect();
+        for (&(a, b), &count) in &edges {
+            if count != 2 {
+                locked[a as usize] = true;
+                locked[b as usize] = true;
+            }
+        }
+
+        let vertex_count = positions.len();
+        let mut simplifier = Self {
+            positions,
+            removed: vec![false; triangles.len()],
+            triangles,
+            vertex_triangles,
+            quadrics,
+            locked,
+            versions: vec![0; vertex_count],
+            heap: BinaryHeap::new(),
+        };
+        for vertex in 0..vertex_count as u32 {
+            simplifier.push_collapses(vertex);
+        }
+        simplifier
+    }
+
+    fn neighbors(&self, vertex: u32) -> Vec<u32> {
+        let mut neighbors: Vec<u32> = self.vertex_triangles[vertex as usize].iter()
+            .flat_map(|&triangle| self.triangles[triangle as usize])
+            .filter(|&other| other != vertex)
+            .collect();
+        neighbors.sort_unstable();
+        neighbors.dedup();
+        neighbors
+    }
+
+    /// Queue collapsing `from` onto each of its neighbors
+    fn push_collapses(&mut self, from: u32) {
+        if self.locked[from as usize] || self.vertex_triangles[from as usize].is_empty() {
+            return;
+        }
+        for to in self.neighbors(from) {
+            let quadric = self.quadrics[from as usize] + self.quadrics[to as usize];
+            self.heap.push(Collapse {
+                cost: quadric.error(self.positions[to as usize]),
+                from,
+                to,
+                versions: (self.versions[from as usize], self.versions[to as usize]),
+            });
+        }
+    }
+
+    /// Returns false if collapsing would pinch the surface or fold a triangle over
+    fn can_collapse(&self, from: u32, to: u32) -> bool {
+        // Only the two vertices across the edge may be shared neighbors, or the surface pinches
+        let shared_triangles = self.vertex_triangles[from as usize].iter()
+            .filter(|&&triangle| self.triangles[triangle as usize].contains(&to))
+            .count();
+        let to_neighbors = self.neighbors(to);
+        let shared_neighbors = self.neighbors(from).iter()
+            .filter(|vertex| to_neighbors.binary_search(vertex).is_ok())
+            .count();
+        if shared_triangles != 2 || shared_neighbors != 2 {
+            return false;
+        }
+
+        self.vertex_triangles[from as usize].iter().all(|&triangle| {
+            let corners = self.triangles[triangle as usize];
+            if corners.contains(&to) {
+                return true;
+            }
+            let [p0, p1, p2] = corners.map(|vertex| self.positions[vertex as usize]);
+            let before = (p1 - p0).cross(p2 - p0);
+            let [q0, q1, q2] = corners.map(|vertex| {
+                self.positions[if vertex == from { to } else { vertex } as usize]
+            });
+            let after = (q1 - q0).cross(q2 - q0);
+            before.magnitude2() == 0.0 || before.dot(after) > 0.0
+        })
+    }
+
+    /// Move `from` onto `to`, dropping the triangles between them
+    fn collapse(&mut self, from: u32, to: u32) -> usize {
+        let mut dropped = 0;
+        for triangle in std::mem::take(&mut self.vertex_triangles[from as usize]) {
+            let corners = &mut self.triangles[triangle as usize];
+            if corners.contains(&to) {
+                self.removed[triangle as usize] = true;
+                dropped += 1;
+                for vertex in *corners {
+                    self.vertex_triangles[vertex as usize].retain(|&other| other != triangle);
+                }
+            } else {
+                for vertex in corners.iter_mut() {
+                    if *vertex == from {
+                        *vertex = to;
+                    }
+                }
+                self.vertex_triangles[to as usize].push(triangle);
+            }
+        }
+        self.quadrics[to as usize] = self.quadrics[to as usize] + self.quadrics[from as usize];
+
+        // Collapses around the moved vertex have new costs and validity
+        let neighbors = self.neighbors(to);
+        for &vertex in neighbors.iter().chain(std::iter::once(&to)) {
+            self.versions[vertex as usize] += 1;
+        }
+        for &vertex in neighbors.iter().chain(std::iter::once(&to)) {
+            self.push_collapses(vertex);
+        }
+        dropped
+    }
+
+    /// Collapse the cheapest edges until at most `target` triangles are left
+    fn run(&mut self, target: usize) {
+        let mut live = self.triangles.len();
+        while live > target {
+            let Some(candidate) = self.heap.pop() else { break };
+            let (from, to) = (candidate.from, candidate.to);
+            let current = (self.versions[from as usize], self.versions[to as usize]);
+            if candidate.versions != current || !self.can_collapse(from, to) {
+                continue;
+            }
+            live -= self.collapse(from, to);
+        }
+    }
+}
+
+/// Simplify a mesh to about `target_triangles` with quadric error metrics (Garland and
+/// Heckbert), collapsing vertices onto their neighbors so every attribute and the bounds
+/// stay those of the source. Seams and borders are kept, so meshes made mostly of them,
+/// like the primitive cube, may not reach the target.
+pub fn simplify(mesh: &MeshData, target_triangles: usize) -> MeshData {
+    let mut simplifier = Simplifier::new(mesh);
+    simplifier.run(target_triangles);
+
+    // Keep only the vertices still referenced, in their original order
+    let mut remap = vec![u32::MAX; mesh.vertices.len()];
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for (triangle, removed) in simplifier.triangles.iter().zip(&simplifier.removed) {
+        if !removed {
+            indices.extend_from_slice(triangle);
+        }
+    }
+    let mut used: Vec<u32> = indices.clone();
+    used.sort_unstable();
+    used.dedup();
+    for vertex in used {
+        remap[vertex as usize] = vertices.len() as u32;
+        vertices.push(mesh.vertices[vertex as usize]);
+    }
+    for index in &mut indices {
+        *index = remap[*index as usize];
+    }
+
+    MeshData {
+        vertices,
+        indices,
+        material_id: mesh.material_id,
+    }
+}
+
+/// What LOD selection needs to know about the camera
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LodView {
+    pub position: Point3<f32>,
+    pub projection_scale: f32, // 1 / tan(fovy / 2)
+}
+
+impl LodView {
+    /// Capture the camera's position and field of view
+    pub fn from_camera(camera: &Camera) -> Self {
+        Self {
+            position: camera.position,
+            projection_scale: 1.0 / (camera.fovy.0 * 0.5).tan(),
+        }
+    }
+
+    /// Fraction of the screen height covered by the sphere around a world-space box
+    pub fn screen_size(&self, aabb: &Aabb) -> f32 {
+        let radius = aabb.half_extents().magnitude();
+        let distance = (aabb.center() - self.position).magnitude();
+        if distance <= radius {
+            return f32::MAX;
+        }
+        radius * self.projection_scale / distance
+    }
+}
+
+/// Which levels of a mesh to draw
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LodChoice {
+    Level(usize),
+    /// Both levels dithered, `fade` of the pixels already drawn by `to`
+    CrossFade { from: usize, to: usize, fade: f32 },
+}
+
+/// Picks mesh levels of detail by their size on screen
+pub struct LodSelector {
+    view: Option<LodView>,
+    cross_fade: bool,
+    fade_range: f32,              // How far above a level's screen size the fade to it starts, relative to it
+    fade_instances: InstanceBuffer, // Identity instances at every fade step, bound while cross-fading plain models
+}
+
+impl LodSelector {
+    /// Create a selector that draws full detail until a camera is set
+    pub fn new(device: &wgpu::Device) -> Self {
+        // Zero first, then the steps fading out, then those fading in
+        let fades = std::iter::once(0.0)
+            .chain((1..LOD_FADE_STEPS).map(|step| step as f32 / LOD_FADE_STEPS as f32))
+            .chain((1..LOD_FADE_STEPS).map(|step| -(step as f32) / LOD_FADE_STEPS as f32));
+        let instances: Vec<Instance> = fades.map(|fade| Instance::default().with_lod_fade(fade)).collect();
+        Self {
+            view: None,
+            cross_fade: true,
+            fade_range: 0.25,
+            fade_instances: InstanceBuffer::new(device, &instances, "LOD Fade Instance Buffer"),
+        }
+    }
+
+    /// Pick levels for the given camera, or full detail for none
+    pub fn set_view(&mut self, view: Option<LodView>) {
+        self.view = view;
+    }
+
+    /// The camera levels are picked for
+    pub fn view(&self) -> Option<LodView> {
+        self.view
+    }
+
+    /// Dither between neighboring levels near their switch instead of popping
+    pub fn set_cross_fade(&mut self, enabled: bool) {
+        self.cross_fade = enabled;
+    }
+
+    /// Returns the fraction above a switch at which fading starts, or zero without cross-fading
+    pub fn fade_range(&self) -> f32 {
+        if self.cross_fade { self.fade_range } else { 0.0 }
+    }
+
+    /// Pick the levels of a mesh whose world-space bounds are `aabb`
+    pub fn select(&self, lods: &[MeshLod], aabb: &Aabb) -> LodChoice {
+        let Some(view) = &self.view else { return LodChoice::Level(0) };
+        select_lod(lods, view.screen_size(aabb), self.fade_range())
+    }
+
+    /// Identity instance carrying `fade`, for binding as vertex buffer 1
+    pub fn fade_instance(&self, fade: f32) -> wgpu::BufferSlice<'_> {
+        let step = (fade.abs() * LOD_FADE_STEPS as f32).round() as u32;
+        let index = match step {
+            0 => 0,
+            _ if fade > 0.0 => step,
+            _ => step + LOD_FADE_STEPS - 1,
+        };
+        self.fade_instances.slice_from(index)
+    }
+}
+
+/// Pick the levels for a mesh covering `screen_size` of the screen height. The GPU culling
+/// shader makes the same choice.
+pub fn select_lod(lods: &[MeshLod], screen_size: f32, fade_range: f32) -> LodChoice {
+    let level = lods.iter().rposition(|lod| screen_size < lod.screen_size).unwrap_or(0);
+    let Some(next) = lods.get(level + 1) else { return LodChoice::Level(level) };
+
+    // Fade towards the next level over the range just above its switch
+    let start = next.screen_size * (1.0 + fade_range);
+    if fade_range <= 0.0 || screen_size >= start {
+        return LodChoice::Level(level);
+    }
+    let fade = ((start - screen_size) / (start - next.screen_size) * LOD_FADE_STEPS as f32).round() as u32;
+    match fade {
+        0 => LodChoice::Level(level),
+        LOD_FADE_STEPS => LodChoice::Level(level + 1),
+        _ => LodChoice::CrossFade { from: level, to: level + 1, fade: fade as f32 / LOD_FADE_STEPS as f32 },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Matrix4, Rad};
+    use super::*;
+    use crate::engine::renderer::test_support::{
+        compare_images, create_light_buffer, create_uniform_buffer, gpu_culling_available, headless_renderer,
+    };
+    use crate::engine::renderer::Mesh;
+    use crate::engine::scene::SunController;
+
+    /// Size of the frames the tests render
+    const WIDTH: u32 = 128;
+    const HEIGHT: u32 = 128;
+
+    fn triangle_count(mesh: &MeshData) -> usize {
+        mesh.indices.len() / 3
+    }
+
+    #[test]
+    fn lod_chain_reduces_triangles_and_keeps_bounds() {
+        let mesh = MeshData::sphere(1.0, 64, 32);
+        let chain = mesh.lod_chain(&LodSettings::default());
+        assert_eq!(chain.len(), 4);
+
+        let (source_bounds, _) = mesh.bounds();
+        for pair in chain.windows(2) {
+            assert!(triangle_count(&pair[1]) < triangle_count(&pair[0]), "{} then {}", triangle_count(&pair[0]), triangle_count(&pair[1]));
+        }
+        for level in &chain[1..] {
+            let (bounds, _) = level.bounds();
+            for axis in 0..3 {
+                // Vertices only move onto others, so the box can shrink but never grow
+                assert!(bounds.min[axis] >= source_bounds.min[axis] && bounds.max[axis] <= source_bounds.max[axis]);
+                let extent = bounds.max[axis] - bounds.min[axis];
+                assert!(extent > 0.95 * (source_bounds.max[axis] - source_bounds.min[axis]), "{:?}", bounds);
+            }
+            assert!(level.indices.iter().all(|&index| (index as usize) < level.vertices.len()));
+        }
+    }
+
+    #[test]
+    fn simplify_stops_at_seams() {
+        // Every cube vertex is on a face seam
+        let cube = MeshData::cube(1.0);
+        assert_eq!(simplify(&cube, 2).indices, cube.indices);
+        assert_eq!(cube.lod_chain(&LodSettings { min_triangles: 1, ..LodSettings::default() }).len(), 1);
+    }
+
+    #[test]
+    fn selection_follows_screen_size() {
+        let settings = LodSettings::default();
+        let lods: Vec<MeshLod> = (0..3).map(|level| MeshLod {
+            first_index: 0,
+            index_count: 3,
+            base_vertex: 0,
+            screen_size: settings.screen_size(level),
+        }).collect();
+
+        assert_eq!(select_lod(&lods, 2.0, 0.0), LodChoice::Level(0));
+        assert_eq!(select_lod(&lods, 0.4, 0.0), LodChoice::Level(1));
+        assert_eq!(select_lod(&lods, 0.01, 0.0), LodChoice::Level(2));
+        // Halfway through the fade above the switch to level 1
+        match select_lod(&lods, 0.5625, 0.25) {
+            LodChoice::CrossFade { from: 0, to: 1, fade } => assert!((fade - 0.5).abs() < 1e-6),
+            choice => panic!("expected a cross-fade, got {:?}", choice),
+        }
+        assert_eq!(select_lod(&lods, 0.7, 0.25), LodChoice::Level(0));
+    }
+
+    #[test]
+    fn gpu_lod_selection_matches_cpu() {
+        let mut renderer = headless_renderer(WIDTH, HEIGHT);
+        if !gpu_culling_available(&renderer) {
+            return;
+        }
+        renderer.set_debug_grid_visible(false);
+
+        let mut camera = Camera::new(Point3::new(0.0, 0.0, 6.0), Rad(3.0 * std::f32::consts::PI / 2.0), Rad(0.0));
+        camera.set_aspect_ratio(WIDTH, HEIGHT);
+        renderer.update_environment(&camera);
+        renderer.update_culling(&camera);
+        let light_buffer = create_light_buffer(&renderer, SunController::new().sun_light);
+
+        // Spheres at full detail, cross-fading to level 1, at level 1 and at level 2
+        let settings = LodSettings::default();
+        let levels = MeshData::sphere(1.0, 64, 32).lod_chain(&settings);
+        assert_eq!(levels.len(), 4);
+        for position in [
+            Vector3::new(-1.5, 0.0, 3.0),
+            Vector3::new(1.2, 0.0, 1.6),
+            Vector3::new(-2.0, 0.5, -1.0),
+            Vector3::new(3.0, 1.0, -8.0),
+        ] {
+            let transform = Matrix4::from_translation(position);
+            let uniform_buffer = create_uniform_buffer(&renderer, &camera, transform);
+            let mut model = renderer.create_solid_model(
+                Mesh::with_lods(renderer.device(), &levels, &settings),
+                [0.8, 0.8, 0.8],
+                &uniform_buffer,
+                &light_buffer,
+                "Test LOD Sphere",
+            );
+            model.set_transform(renderer.queue(), &uniform_buffer, transform);
+            renderer.add_model(model);
+        }
+
+        let gpu = renderer.capture_frame().unwrap();
+        renderer.set_gpu_culling_enabled(false);
+        let cpu = renderer.capture_frame().unwrap();
+        let comparison = compare_images(&gpu, &cpu, 2).unwrap();
+        assert_eq!(comparison.mismatched_pixels, 0, "max difference {}", comparison.max_channel_difference);
+
+        // Switching levels at once changes only the cross-fading sphere
+        renderer.set_lod_cross_fade(false);
+        let popped = renderer.capture_frame().unwrap();
+        let comparison = compare_images(&cpu, &popped, 2).unwrap();
+        assert!(comparison.mismatched_pixels > 0);
+    }
+}
@@ -0,0 +1,288 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+use crate::engine::renderer::texture::{Texture, TextureOptions};
+use crate::engine::scene::MaterialInstance;
+
+/// Set in `MaterialUniforms::flags` when the material has a tangent-space normal map
+pub const MATERIAL_HAS_NORMAL_MAP: u32 = 1;
+
+/// WGSL declarations for the metallic-roughness material bind group.
+/// Expects the material bind group at group 3.
+pub const MATERIAL_WGSL: &str = r#"
+struct MaterialUniforms {
+    base_color_factor: vec4<f32>,
+    emissive_factor: vec3<f32>,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    flags: u32,
+    alpha_cutoff: f32,
+    blend_mode: u32,
+};
+
+@group(3) @binding(0)
+var<uniform> material: MaterialUniforms;
+@group(3) @binding(1)
+var t_albedo: texture_2d<f32>;
+@group(3) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(3) @binding(3)
+var t_metallic_roughness: texture_2d<f32>;
+@group(3) @binding(4)
+var t_occlusion: texture_2d<f32>;
+@group(3) @binding(5)
+var t_emissive: texture_2d<f32>;
+@group(3) @binding(6)
+var s_material: sampler;
+
+const MATERIAL_HAS_NORMAL_MAP: u32 = 1u;
+
+const BLEND_MODE_OPAQUE: u32 = 0u;
+const BLEND_MODE_ALPHA_TEST: u32 = 1u;
+const BLEND_MODE_ALPHA_BLEND: u32 = 2u;
+const BLEND_MODE_ADDITIVE: u32 = 3u;
+"#;
+
+/// Per-material factors, multiplied with the material's textures in the shader
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct MaterialUniforms {
+    pub base_color_factor: [f32; 4],
+    pub emissive_factor: [f32; 3],
+    pub metallic_factor: f32,    // Scales the blue channel of the metallic-roughness map
+    pub roughness_factor: f32,   // Scales the green channel of the metallic-roughness map
+    pub normal_scale: f32,       // Strength of the normal map's X and Y
+    pub occlusion_strength: f32, // 0 ignores the occlusion map, 1 applies it fully
+    pub flags: u32,
+    pub alpha_cutoff: f32,       // Alpha below which alpha-tested materials are cut out
+    pub blend_mode: u32,         // A `BlendMode`
+    pub _padding: [u32; 2],
+}
+
+impl Default for MaterialUniforms {
+    fn default() -> Self {
+        Self {
+            base_color_factor: [1.0, 1.0, 1.0, 1.0],
+            emissive_factor: [0.0, 0.0, 0.0],
+            metallic_factor: 0.0,
+            roughness_factor: 0.5,
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            flags: 0,
+            alpha_cutoff: 0.5,
+            blend_mode: BlendMode::Opaque as u32,
+            _padding: [0; 2],
+        }
+    }
+}
+
+/// How a material's surface combines with what is behind it. The alpha is the base
+/// color's, including the albedo texture and the instance tint.
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    #[default]
+    Opaque,     // Alpha is ignored
+    AlphaTest,  // Cut out where alpha is below `alpha_cutoff`, opaque elsewhere
+    AlphaBlend, // Blended over the scene by alpha, without writing depth
+    Additive,   // Color scaled by alpha is added to the scene, without writing depth
+}
+
+impl MaterialUniforms {
+    /// Returns how the material is blended, opaque for unknown values
+    pub fn blend_mode(&self) -> BlendMode {
+        match self.blend_mode {
+            1 => BlendMode::AlphaTest,
+            2 => BlendMode::AlphaBlend,
+            3 => BlendMode::Additive,
+            _ => BlendMode::Opaque,
+        }
+    }
+
+    /// True if the material lets what is behind the surface show through
+    pub fn is_transparent(&self) -> bool {
+        matches!(self.blend_mode(), BlendMode::AlphaBlend | BlendMode::Additive)
+    }
+}
+
+/// Which meshes a pass draws, by their material's blend mode
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MaterialPass {
+    Opaque,      // Opaque and alpha-tested, writing depth
+    Transparent, // Alpha-blended and additive, drawn after the opaque meshes
+    AlphaBlend,  // Accumulated by order-independent transparency
+    Additive,    // Order-independent on its own
+}
+
+impl MaterialPass {
+    /// True if meshes with `material` are drawn in this pass
+    pub fn includes(self, material: &MaterialUniforms) -> bool {
+        match self {
+            MaterialPass::Opaque => !material.is_transparent(),
+            MaterialPass::Transparent => material.is_transparent(),
+            MaterialPass::AlphaBlend => material.blend_mode() == BlendMode::AlphaBlend,
+            MaterialPass::Additive => material.blend_mode() == BlendMode::Additive,
+        }
+    }
+}
+
+/// Textures bound by a material. Unused maps should be one of `DefaultMaterialTextures`.
+pub struct MaterialTextures<'a> {
+    pub albedo: &'a Texture,
+    pub normal: &'a Texture,
+    pub metallic_roughness: &'a Texture,
+    pub occlusion: &'a Texture,
+    pub emissive: &'a Texture,
+    pub sampler: &'a wgpu::Sampler,
+}
+
+/// Neutral textures for materials that do not provide every map
+pub struct DefaultMaterialTextures {
+    pub white: Texture,         // sRGB white, for albedo and emissive
+    pub white_linear: Texture,  // Linear white, for metallic-roughness and occlusion
+    pub flat_normal: Texture,   // Normal pointing straight out of the surface
+    pub sampler: wgpu::Sampler, // Repeating anisotropic sampler shared by every map
+}
+
+impl DefaultMaterialTextures {
+    /// Create the default textures
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        Self {
+            white: Texture::create_colored_texture(device, queue, [1.0, 1.0, 1.0], "Default White Texture"),
+            white_linear: Texture::create_solid_texture(
+                device,
+                queue,
+                [255, 255, 255, 255],
+                wgpu::TextureFormat::Rgba8Unorm,
+                "Default Linear White Texture",
+            ),
+            flat_normal: Texture::create_solid_texture(
+                device,
+                queue,
+                [128, 128, 255, 255],
+                wgpu::TextureFormat::Rgba8Unorm,
+                "Default Normal Texture",
+            ),
+            sampler: TextureOptions::default().create_sampler(device, Some("Material Sampler")),
+        }
+    }
+
+    /// Textures for a material with only an albedo map
+    pub fn with_albedo<'a>(&'a self, albedo: &'a Texture) -> MaterialTextures<'a> {
+        MaterialTextures {
+            albedo,
+            normal: &self.flat_normal,
+            metallic_roughness: &self.white_linear,
+            occlusion: &self.white_linear,
+            emissive: &self.white,
+            sampler: &self.sampler,
+        }
+    }
+}
+
+/// Create the layout of the material bind group
+pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    };
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Material Bind Group Layout"),
+        entries: &[
+            // Material factors
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Albedo
+            texture_entry(1),
+            // Normal
+            texture_entry(2),
+            // Metallic (blue) and roughness (green)
+            texture_entry(3),
+            // Occlusion (red)
+            texture_entry(4),
+            // Emissive
+            texture_entry(5),
+            // Sampler shared by every map
+            wgpu::BindGroupLayoutEntry {
+                binding: 6,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+    })
+}
+
+/// Upload a material's factors and bind it with its textures
+pub fn create_material_instance(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    material_id: usize,
+    uniforms: MaterialUniforms,
+    textures: MaterialTextures,
+    label: &str,
+) -> MaterialInstance {
+    let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{} Uniform Buffer", label)),
+        contents: bytemuck::cast_slice(&[uniforms]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    });
+
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(&format!("{} Bind Group", label)),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&textures.albedo.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&textures.normal.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&textures.metallic_roughness.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&textures.occlusion.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(&textures.emissive.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::Sampler(textures.sampler),
+            },
+        ],
+    });
+
+    MaterialInstance {
+        material_id,
+        bind_group,
+        uniform_buffer,
+        uniforms,
+    }
+}
@@ -4,6 +4,10 @@ use bytemuck::{Pod, Zeroable, cast_slice};
 #[allow(unused_imports)]
 use std::ops::Range;
 use wgpu::util::DeviceExt;
+use std::collections::HashMap;
+use cgmath::{InnerSpace, Point3, Vector2, Vector3, Zero};
+use crate::engine::renderer::culling::{Aabb, BoundingSphere};
+use crate::engine::renderer::lod::{self, LodSettings};
 
 /// Vertex structure for 3D models
 #[repr(C)]
@@ -13,6 +17,7 @@ pub struct Vertex {
     pub normal: [f32; 3],
     pub tex_coords: [f32; 2],
     pub color: [f32; 3],
+    pub tangent: [f32; 4], // Tangent along +U in xyz, bitangent sign in w
 }
 
 impl Vertex {
@@ -42,6 +47,11 @@ impl Vertex {
                     shader_location: 3,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: size_of::<[f32; 11]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
@@ -55,108 +65,70 @@ pub struct MeshData {
     pub material_id: usize,
 }
 
-/// Mesh with GPU buffers
-#[allow(unused)]
-pub struct Mesh {
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
-    pub num_vertices: u32,
-    pub num_indices: u32,
-    pub material_id: usize,
-}
-
-impl Mesh {
-    /// Creates a new mesh from mesh data
-    pub fn new(device: &wgpu::Device, mesh_data: &MeshData) -> Self {
-        let vertex_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Vertex Buffer"),
-                contents: cast_slice(&mesh_data.vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            }
-        );
-        
-        let index_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Index Buffer"),
-                contents: cast_slice(&mesh_data.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            }
-        );
-        
-        Self {
-            vertex_buffer,
-            index_buffer,
-            num_vertices: mesh_data.vertices.len() as u32,
-            num_indices: mesh_data.indices.len() as u32,
-            material_id: mesh_data.material_id,
-        }
-    }
-    
-    #[allow(dead_code)]
-    /// Creates a simple quad mesh
-    pub fn create_quad(device: &wgpu::Device, size: f32) -> Self {
+impl MeshData {
+    /// Builds a quad facing +Z
+    pub fn quad(size: f32) -> Self {
         let half_size = size / 2.0;
         
         let vertices = vec![
-            Vertex { position: [-half_size, -half_size, 0.0], normal: [0.0, 0.0, 1.0], tex_coords: [0.0, 1.0], color: [1.0, 1.0, 1.0] },
-            Vertex { position: [half_size, -half_size, 0.0], normal: [0.0, 0.0, 1.0], tex_coords: [1.0, 1.0], color: [1.0, 1.0, 1.0] },
-            Vertex { position: [half_size, half_size, 0.0], normal: [0.0, 0.0, 1.0], tex_coords: [1.0, 0.0], color: [1.0, 1.0, 1.0] },
-            Vertex { position: [-half_size, half_size, 0.0], normal: [0.0, 0.0, 1.0], tex_coords: [0.0, 0.0], color: [1.0, 1.0, 1.0] },
+            Vertex { position: [-half_size, -half_size, 0.0], normal: [0.0, 0.0, 1.0], tex_coords: [0.0, 1.0], color: [1.0, 1.0, 1.0], tangent: [0.0; 4] },
+            Vertex { position: [half_size, -half_size, 0.0], normal: [0.0, 0.0, 1.0], tex_coords: [1.0, 1.0], color: [1.0, 1.0, 1.0], tangent: [0.0; 4] },
+            Vertex { position: [half_size, half_size, 0.0], normal: [0.0, 0.0, 1.0], tex_coords: [1.0, 0.0], color: [1.0, 1.0, 1.0], tangent: [0.0; 4] },
+            Vertex { position: [-half_size, half_size, 0.0], normal: [0.0, 0.0, 1.0], tex_coords: [0.0, 0.0], color: [1.0, 1.0, 1.0], tangent: [0.0; 4] },
         ];
         
         let indices = vec![0, 1, 2, 0, 2, 3];
         
-        let mesh_data = MeshData {
+        let mut mesh_data = MeshData {
             vertices,
             indices,
             material_id: 0,
         };
-        
-        Self::new(device, &mesh_data)
+        mesh_data.generate_tangents();
+        mesh_data
     }
     
-    /// Creates a simple cube mesh
-    pub fn create_cube(device: &wgpu::Device, size: f32) -> Self {
+    /// Builds a cube with one UV square per face
+    pub fn cube(size: f32) -> Self {
         let half_size = size / 2.0;
         
         #[rustfmt::skip]
         let vertices = vec![
             // Front face
-            Vertex { position: [-half_size, -half_size, half_size], normal: [0.0, 0.0, 1.0], tex_coords: [0.0, 1.0], color: [1.0, 0.0, 0.0] },
-            Vertex { position: [half_size, -half_size, half_size], normal: [0.0, 0.0, 1.0], tex_coords: [1.0, 1.0], color: [1.0, 0.0, 0.0] },
-            Vertex { position: [half_size, half_size, half_size], normal: [0.0, 0.0, 1.0], tex_coords: [1.0, 0.0], color: [1.0, 0.0, 0.0] },
-            Vertex { position: [-half_size, half_size, half_size], normal: [0.0, 0.0, 1.0], tex_coords: [0.0, 0.0], color: [1.0, 0.0, 0.0] },
+            Vertex { position: [-half_size, -half_size, half_size], normal: [0.0, 0.0, 1.0], tex_coords: [0.0, 1.0], color: [1.0, 0.0, 0.0], tangent: [0.0; 4] },
+            Vertex { position: [half_size, -half_size, half_size], normal: [0.0, 0.0, 1.0], tex_coords: [1.0, 1.0], color: [1.0, 0.0, 0.0], tangent: [0.0; 4] },
+            Vertex { position: [half_size, half_size, half_size], normal: [0.0, 0.0, 1.0], tex_coords: [1.0, 0.0], color: [1.0, 0.0, 0.0], tangent: [0.0; 4] },
+            Vertex { position: [-half_size, half_size, half_size], normal: [0.0, 0.0, 1.0], tex_coords: [0.0, 0.0], color: [1.0, 0.0, 0.0], tangent: [0.0; 4] },
             
             // Back face
-            Vertex { position: [half_size, -half_size, -half_size], normal: [0.0, 0.0, -1.0], tex_coords: [0.0, 1.0], color: [0.0, 1.0, 0.0] },
-            Vertex { position: [-half_size, -half_size, -half_size], normal: [0.0, 0.0, -1.0], tex_coords: [1.0, 1.0], color: [0.0, 1.0, 0.0] },
-            Vertex { position: [-half_size, half_size, -half_size], normal: [0.0, 0.0, -1.0], tex_coords: [1.0, 0.0], color: [0.0, 1.0, 0.0] },
-            Vertex { position: [half_size, half_size, -half_size], normal: [0.0, 0.0, -1.0], tex_coords: [0.0, 0.0], color: [0.0, 1.0, 0.0] },
+            Vertex { position: [half_size, -half_size, -half_size], normal: [0.0, 0.0, -1.0], tex_coords: [0.0, 1.0], color: [0.0, 1.0, 0.0], tangent: [0.0; 4] },
+            Vertex { position: [-half_size, -half_size, -half_size], normal: [0.0, 0.0, -1.0], tex_coords: [1.0, 1.0], color: [0.0, 1.0, 0.0], tangent: [0.0; 4] },
+            Vertex { position: [-half_size, half_size, -half_size], normal: [0.0, 0.0, -1.0], tex_coords: [1.0, 0.0], color: [0.0, 1.0, 0.0], tangent: [0.0; 4] },
+            Vertex { position: [half_size, half_size, -half_size], normal: [0.0, 0.0, -1.0], tex_coords: [0.0, 0.0], color: [0.0, 1.0, 0.0], tangent: [0.0; 4] },
             
             // Top face
-            Vertex { position: [-half_size, half_size, half_size], normal: [0.0, 1.0, 0.0], tex_coords: [0.0, 1.0], color: [0.0, 0.0, 1.0] },
-            Vertex { position: [half_size, half_size, half_size], normal: [0.0, 1.0, 0.0], tex_coords: [1.0, 1.0], color: [0.0, 0.0, 1.0] },
-            Vertex { position: [half_size, half_size, -half_size], normal: [0.0, 1.0, 0.0], tex_coords: [1.0, 0.0], color: [0.0, 0.0, 1.0] },
-            Vertex { position: [-half_size, half_size, -half_size], normal: [0.0, 1.0, 0.0], tex_coords: [0.0, 0.0], color: [0.0, 0.0, 1.0] },
+            Vertex { position: [-half_size, half_size, half_size], normal: [0.0, 1.0, 0.0], tex_coords: [0.0, 1.0], color: [0.0, 0.0, 1.0], tangent: [0.0; 4] },
+            Vertex { position: [half_size, half_size, half_size], normal: [0.0, 1.0, 0.0], tex_coords: [1.0, 1.0], color: [0.0, 0.0, 1.0], tangent: [0.0; 4] },
+            Vertex { position: [half_size, half_size, -half_size], normal: [0.0, 1.0, 0.0], tex_coords: [1.0, 0.0], color: [0.0, 0.0, 1.0], tangent: [0.0; 4] },
+            Vertex { position: [-half_size, half_size, -half_size], normal: [0.0, 1.0, 0.0], tex_coords: [0.0, 0.0], color: [0.0, 0.0, 1.0], tangent: [0.0; 4] },
             
             // Bottom face
-            Vertex { position: [-half_size, -half_size, -half_size], normal: [0.0, -1.0, 0.0], tex_coords: [0.0, 1.0], color: [1.0, 1.0, 0.0] },
-            Vertex { position: [half_size, -half_size, -half_size], normal: [0.0, -1.0, 0.0], tex_coords: [1.0, 1.0], color: [1.0, 1.0, 0.0] },
-            Vertex { position: [half_size, -half_size, half_size], normal: [0.0, -1.0, 0.0], tex_coords: [1.0, 0.0], color: [1.0, 1.0, 0.0] },
-            Vertex { position: [-half_size, -half_size, half_size], normal: [0.0, -1.0, 0.0], tex_coords: [0.0, 0.0], color: [1.0, 1.0, 0.0] },
+            Vertex { position: [-half_size, -half_size, -half_size], normal: [0.0, -1.0, 0.0], tex_coords: [0.0, 1.0], color: [1.0, 1.0, 0.0], tangent: [0.0; 4] },
+            Vertex { position: [half_size, -half_size, -half_size], normal: [0.0, -1.0, 0.0], tex_coords: [1.0, 1.0], color: [1.0, 1.0, 0.0], tangent: [0.0; 4] },
+            Vertex { position: [half_size, -half_size, half_size], normal: [0.0, -1.0, 0.0], tex_coords: [1.0, 0.0], color: [1.0, 1.0, 0.0], tangent: [0.0; 4] },
+            Vertex { position: [-half_size, -half_size, half_size], normal: [0.0, -1.0, 0.0], tex_coords: [0.0, 0.0], color: [1.0, 1.0, 0.0], tangent: [0.0; 4] },
             
             // Right face
-            Vertex { position: [half_size, -half_size, half_size], normal: [1.0, 0.0, 0.0], tex_coords: [0.0, 1.0], color: [1.0, 0.0, 1.0] },
-            Vertex { position: [half_size, -half_size, -half_size], normal: [1.0, 0.0, 0.0], tex_coords: [1.0, 1.0], color: [1.0, 0.0, 1.0] },
-            Vertex { position: [half_size, half_size, -half_size], normal: [1.0, 0.0, 0.0], tex_coords: [1.0, 0.0], color: [1.0, 0.0, 1.0] },
-            Vertex { position: [half_size, half_size, half_size], normal: [1.0, 0.0, 0.0], tex_coords: [0.0, 0.0], color: [1.0, 0.0, 1.0] },
+            Vertex { position: [half_size, -half_size, half_size], normal: [1.0, 0.0, 0.0], tex_coords: [0.0, 1.0], color: [1.0, 0.0, 1.0], tangent: [0.0; 4] },
+            Vertex { position: [half_size, -half_size, -half_size], normal: [1.0, 0.0, 0.0], tex_coords: [1.0, 1.0], color: [1.0, 0.0, 1.0], tangent: [0.0; 4] },
+            Vertex { position: [half_size, half_size, -half_size], normal: [1.0, 0.0, 0.0], tex_coords: [1.0, 0.0], color: [1.0, 0.0, 1.0], tangent: [0.0; 4] },
+            Vertex { position: [half_size, half_size, half_size], normal: [1.0, 0.0, 0.0], tex_coords: [0.0, 0.0], color: [1.0, 0.0, 1.0], tangent: [0.0; 4] },
             
             // Left face
-            Vertex { position: [-half_size, -half_size, -half_size], normal: [-1.0, 0.0, 0.0], tex_coords: [0.0, 1.0], color: [0.0, 1.0, 1.0] },
-            Vertex { position: [-half_size, -half_size, half_size], normal: [-1.0, 0.0, 0.0], tex_coords: [1.0, 1.0], color: [0.0, 1.0, 1.0] },
-            Vertex { position: [-half_size, half_size, half_size], normal: [-1.0, 0.0, 0.0], tex_coords: [1.0, 0.0], color: [0.0, 1.0, 1.0] },
-            Vertex { position: [-half_size, half_size, -half_size], normal: [-1.0, 0.0, 0.0], tex_coords: [0.0, 0.0], color: [0.0, 1.0, 1.0] },
+            Vertex { position: [-half_size, -half_size, -half_size], normal: [-1.0, 0.0, 0.0], tex_coords: [0.0, 1.0], color: [0.0, 1.0, 1.0], tangent: [0.0; 4] },
+            Vertex { position: [-half_size, -half_size, half_size], normal: [-1.0, 0.0, 0.0], tex_coords: [1.0, 1.0], color: [0.0, 1.0, 1.0], tangent: [0.0; 4] },
+            Vertex { position: [-half_size, half_size, half_size], normal: [-1.0, 0.0, 0.0], tex_coords: [1.0, 0.0], color: [0.0, 1.0, 1.0], tangent: [0.0; 4] },
+            Vertex { position: [-half_size, half_size, -half_size], normal: [-1.0, 0.0, 0.0], tex_coords: [0.0, 0.0], color: [0.0, 1.0, 1.0], tangent: [0.0; 4] },
         ];
         
         let indices = vec![
@@ -168,18 +140,17 @@ impl Mesh {
             20, 21, 22, 20, 22, 23, // Left face
         ];
         
-        let mesh_data = MeshData {
+        let mut mesh_data = MeshData {
             vertices,
             indices,
             material_id: 0,
         };
-        
-        Self::new(device, &mesh_data)
+        mesh_data.generate_tangents();
+        mesh_data
     }
     
-    #[allow(dead_code)]
-    /// Creates a sphere mesh
-    pub fn create_sphere(device: &wgpu::Device, radius: f32, segments: u32, rings: u32) -> Self {
+    /// Builds a UV sphere
+    pub fn sphere(radius: f32, segments: u32, rings: u32) -> Self {
         let mut vertices = Vec::new();
         let mut indices = Vec::new();
         
@@ -204,6 +175,7 @@ impl Mesh {
                     normal,
                     tex_coords: tex_coord,
                     color: [1.0, 1.0, 1.0],
+                    tangent: [0.0; 4],
                 });
             }
         }
@@ -214,23 +186,228 @@ impl Mesh {
                 let first = ring * (segments + 1) + segment;
                 let second = first + segments + 1;
                 
-                indices.push(first as u32);
-                indices.push(second as u32);
-                indices.push((first + 1) as u32);
+                // Counter-clockwise seen from outside the sphere
+                indices.push(first);
+                indices.push(first + 1);
+                indices.push(second);
                 
-                indices.push(second as u32);
-                indices.push((second + 1) as u32);
-                indices.push((first + 1) as u32);
+                indices.push(second);
+                indices.push(first + 1);
+                indices.push(second + 1);
             }
         }
         
-        let mesh_data = MeshData {
+        let mut mesh_data = MeshData {
             vertices,
             indices,
             material_id: 0,
         };
+        mesh_data.generate_tangents();
+        mesh_data
+    }
+
+    /// Axis-aligned box and sphere enclosing every vertex
+    pub fn bounds(&self) -> (Aabb, BoundingSphere) {
+        let positions = self.vertices.iter().map(|vertex| Point3::from(vertex.position));
+        let aabb = Aabb::from_points(positions.clone());
+        (aabb, BoundingSphere::from_points(positions, &aabb))
+    }
+
+    /// The mesh followed by successively simplified copies, each with fewer triangles than
+    /// the one before. The chain ends early once a level cannot be simplified further.
+    pub fn lod_chain(&self, settings: &LodSettings) -> Vec<MeshData> {
+        let mut chain = vec![self.clone()];
+        while chain.len() < settings.level_count {
+            let previous = chain.last().unwrap();
+            let triangles = previous.indices.len() / 3;
+            let target = (triangles as f32 * settings.triangle_ratio) as usize;
+            if target < settings.min_triangles {
+                break;
+            }
+            let level = lod::simplify(previous, target);
+            if level.indices.len() >= previous.indices.len() {
+                break;
+            }
+            chain.push(level);
+        }
+        chain
+    }
+
+    /// Generate per-vertex tangents following MikkTSpace conventions: each corner's
+    /// tangent is projected onto the vertex normal and weighted by the corner angle,
+    /// and vertices shared by triangles of opposite UV winding are split so each copy
+    /// carries a single bitangent sign. The bitangent (`cross(normal, tangent) * w`)
+    /// points along decreasing V, i.e. up the image, matching green-up normal maps.
+    pub fn generate_tangents(&mut self) {
+        let mut signs: Vec<Option<f32>> = vec![None; self.vertices.len()];
+        let mut mirrored: HashMap<u32, u32> = HashMap::new();
+        let mut tangents: Vec<Vector3<f32>> = vec![Vector3::zero(); self.vertices.len()];
+
+        for triangle in self.indices.chunks_exact_mut(3) {
+            let [p0, p1, p2] = [0, 1, 2].map(|i| Vector3::from(self.vertices[triangle[i] as usize].position));
+            let [uv0, uv1, uv2] = [0, 1, 2].map(|i| {
+                let [u, v] = self.vertices[triangle[i] as usize].tex_coords;
+                Vector2::new(u, -v)
+            });
+
+            let (dp1, dp2) = (p1 - p0, p2 - p0);
+            let (duv1, duv2) = (uv1 - uv0, uv2 - uv0);
+            let det = duv1.x * duv2.y - duv2.x * duv1.y;
+            let degenerate = det.abs() <= f32::EPSILON;
+            let (face_tangent, face_bitangent) = if !degenerate {
+                ((dp1 * duv2.y - dp2 * duv1.y) / det, (dp2 * duv1.x - dp1 * duv2.x) / det)
+            } else {
+                // Degenerate UVs contribute nothing; the vertex falls back to any tangent
+                (Vector3::zero(), Vector3::zero())
+            };
+
+            for (corner, slot) in triangle.iter_mut().enumerate() {
+                let mut index = *slot;
+                let normal = Vector3::from(self.vertices[index as usize].normal);
+                let sign = if degenerate {
+                    signs[index as usize].unwrap_or(1.0)
+                } else if normal.cross(face_tangent).dot(face_bitangent) < 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+
+                // Give each bitangent sign its own copy of the vertex
+                match signs[index as usize] {
+                    None => signs[index as usize] = Some(sign),
+                    Some(existing) if existing != sign => {
+                        index = *mirrored.entry(index).or_insert_with(|| {
+                            self.vertices.push(self.vertices[index as usize]);
+                            signs.push(Some(sign));
+                            tangents.push(Vector3::zero());
+                            self.vertices.len() as u32 - 1
+                        });
+                        *slot = index;
+                    }
+                    Some(_) => {}
+                }
+
+                let projected = face_tangent - normal * normal.dot(face_tangent);
+                let (a, b) = match corner {
+                    0 => (p1 - p0, p2 - p0),
+                    1 => (p2 - p1, p0 - p1),
+                    _ => (p0 - p2, p1 - p2),
+                };
+                let weight = if a.magnitude2() > 0.0 && b.magnitude2() > 0.0 {
+                    a.angle(b).0
+                } else {
+                    0.0
+                };
+                if projected.magnitude2() > 0.0 {
+                    tangents[index as usize] += projected.normalize() * weight;
+                }
+            }
+        }
+
+        for (i, vertex) in self.vertices.iter_mut().enumerate() {
+            let normal = Vector3::from(vertex.normal);
+            let mut tangent = tangents[i] - normal * normal.dot(tangents[i]);
+            if tangent.magnitude2() < 1e-12 {
+                // Any direction perpendicular to the normal
+                let axis = if normal.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+                tangent = axis - normal * normal.dot(axis);
+            }
+            let tangent = tangent.normalize();
+            vertex.tangent = [tangent.x, tangent.y, tangent.z, signs[i].unwrap_or(1.0)];
+        }
+    }
+}
+
+/// One level of detail within a mesh's buffers
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MeshLod {
+    pub first_index: u32,
+    pub index_count: u32,
+    pub base_vertex: i32,
+    pub screen_size: f32, // Used once the mesh covers less of the screen height than this
+}
+
+/// Mesh with GPU buffers
+#[allow(unused)]
+pub struct Mesh {
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub num_vertices: u32,              // Of the full detail level
+    pub num_indices: u32,
+    pub material_id: usize,
+    pub aabb: Aabb,                     // Model-space bounds, for culling
+    pub bounding_sphere: BoundingSphere,
+    pub lods: Vec<MeshLod>,             // Level 0 is the full mesh at the start of the buffers
+}
+
+impl Mesh {
+    /// Creates a new mesh from mesh data
+    pub fn new(device: &wgpu::Device, mesh_data: &MeshData) -> Self {
+        Self::with_lods(device, std::slice::from_ref(mesh_data), &LodSettings::default())
+    }
+
+    /// Creates a mesh from a chain of levels of detail, the first being the full mesh, with
+    /// every level stored one after the other in the same buffers
+    pub fn with_lods(device: &wgpu::Device, levels: &[MeshData], settings: &LodSettings) -> Self {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut lods = Vec::new();
+        for (level, mesh_data) in levels.iter().enumerate() {
+            lods.push(MeshLod {
+                first_index: indices.len() as u32,
+                index_count: mesh_data.indices.len() as u32,
+                base_vertex: vertices.len() as i32,
+                screen_size: settings.screen_size(level),
+            });
+            vertices.extend_from_slice(&mesh_data.vertices);
+            indices.extend_from_slice(&mesh_data.indices);
+        }
+
+        let vertex_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Vertex Buffer"),
+                contents: cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            }
+        );
         
-        Self::new(device, &mesh_data)
+        let index_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Index Buffer"),
+                contents: cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            }
+        );
+        
+        // Every level is culled with the full mesh's bounds
+        let (aabb, bounding_sphere) = levels[0].bounds();
+        Self {
+            vertex_buffer,
+            index_buffer,
+            num_vertices: levels[0].vertices.len() as u32,
+            num_indices: levels[0].indices.len() as u32,
+            material_id: levels[0].material_id,
+            aabb,
+            bounding_sphere,
+            lods,
+        }
+    }
+    
+    #[allow(dead_code)]
+    /// Creates a simple quad mesh
+    pub fn create_quad(device: &wgpu::Device, size: f32) -> Self {
+        Self::new(device, &MeshData::quad(size))
+    }
+    
+    /// Creates a simple cube mesh
+    pub fn create_cube(device: &wgpu::Device, size: f32) -> Self {
+        Self::new(device, &MeshData::cube(size))
+    }
+    
+    #[allow(dead_code)]
+    /// Creates a sphere mesh
+    pub fn create_sphere(device: &wgpu::Device, radius: f32, segments: u32, rings: u32) -> Self {
+        Self::new(device, &MeshData::sphere(radius, segments, rings))
     }
 
     #[allow(unused)]
@@ -238,4 +415,87 @@ impl Mesh {
         
     }
 
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_tangents_orthogonal(mesh: &MeshData) {
+        for vertex in &mesh.vertices {
+            let normal = Vector3::from(vertex.normal);
+            let tangent = Vector3::new(vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]);
+            assert!((tangent.magnitude() - 1.0).abs() < 1e-4, "tangent not unit length: {:?}", vertex);
+            assert!(normal.dot(tangent).abs() < 1e-4, "tangent not orthogonal to normal: {:?}", vertex);
+            assert!(vertex.tangent[3] == 1.0 || vertex.tangent[3] == -1.0, "invalid bitangent sign: {:?}", vertex);
+        }
+    }
+
+    /// Counter-clockwise triangles, the front face the pipelines keep, face along their normals
+    fn assert_front_faces_outward(mesh: &MeshData) {
+        for triangle in mesh.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| &mesh.vertices[triangle[i] as usize]);
+            let [pa, pb, pc] = [a, b, c].map(|v| Vector3::from(v.position));
+            let face_normal = (pb - pa).cross(pc - pa);
+            if face_normal.magnitude2() < 1e-12 {
+                continue;
+            }
+            let vertex_normal = Vector3::from(a.normal) + Vector3::from(b.normal) + Vector3::from(c.normal);
+            assert!(face_normal.dot(vertex_normal) > 0.0, "triangle {:?} faces inward", triangle);
+        }
+    }
+
+    #[test]
+    fn primitive_triangles_face_outward() {
+        assert_front_faces_outward(&MeshData::quad(2.0));
+        assert_front_faces_outward(&MeshData::cube(2.0));
+        assert_front_faces_outward(&MeshData::sphere(1.0, 16, 8));
+    }
+
+    #[test]
+    fn primitive_tangents_are_orthogonal() {
+        assert_tangents_orthogonal(&MeshData::quad(2.0));
+        assert_tangents_orthogonal(&MeshData::cube(1.0));
+        assert_tangents_orthogonal(&MeshData::sphere(1.0, 32, 16));
+    }
+
+    #[test]
+    fn quad_tangent_follows_uv_axes() {
+        for vertex in MeshData::quad(2.0).vertices {
+            let normal = Vector3::from(vertex.normal);
+            let tangent = Vector3::new(vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]);
+            let bitangent = normal.cross(tangent) * vertex.tangent[3];
+            assert!((tangent - Vector3::unit_x()).magnitude() < 1e-5);
+            assert!((bitangent - Vector3::unit_y()).magnitude() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn mirrored_uvs_split_shared_vertices() {
+        // Two triangles sharing an edge, the second with U mirrored
+        let vertex = |position: [f32; 3], tex_coords: [f32; 2]| Vertex {
+            position,
+            normal: [0.0, 0.0, 1.0],
+            tex_coords,
+            color: [1.0, 1.0, 1.0],
+            tangent: [0.0; 4],
+        };
+        let mut mesh = MeshData {
+            vertices: vec![
+                vertex([0.0, 0.0, 0.0], [0.0, 1.0]),
+                vertex([1.0, 0.0, 0.0], [1.0, 1.0]),
+                vertex([0.0, 1.0, 0.0], [0.0, 0.0]),
+                vertex([-1.0, 0.0, 0.0], [1.0, 1.0]),
+            ],
+            indices: vec![0, 1, 2, 0, 2, 3],
+            material_id: 0,
+        };
+        mesh.generate_tangents();
+
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_tangents_orthogonal(&mesh);
+        let sign = |index: u32| mesh.vertices[index as usize].tangent[3];
+        assert_eq!(sign(mesh.indices[0]), sign(mesh.indices[1]));
+        assert_ne!(sign(mesh.indices[0]), sign(mesh.indices[3]));
+        assert_eq!(sign(mesh.indices[3]), sign(mesh.indices[5]));
+    }
+}
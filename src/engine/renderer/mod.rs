@@ -2,78 +2,132 @@ pub mod texture;
 pub mod mesh;
 mod pipeline;
 mod shader;
+mod shadow;
+mod lights;
+mod material;
+mod tonemap;
+mod post_process;
+mod environment;
+mod ambient_occlusion;
+mod deferred;
+mod render_graph;
+mod transparency;
+mod sky;
+mod instance;
+mod culling;
+mod gpu_culling;
+mod lod;
+mod view;
+#[cfg(test)]
+mod golden;
+#[cfg(test)]
+pub(crate) mod test_support;
 
+use std::collections::HashMap;
 use wgpu;
 use wgpu::util::DeviceExt;
 use winit::window::Window;
-use crate::engine::scene::Model;
-use cgmath::SquareMatrix;
+use crate::engine::scene::{Camera, Model, SceneLights, ShadowSettings, SunLight};
+use cgmath::{MetricSpace, Point3, SquareMatrix};
+use transparency::{TransparencyPipelines, ACCUMULATION_CLEAR, WEIGHT_CLEAR};
 
 // Re-export key structs
-pub use texture::Texture;
+pub use texture::{ColorSpace, Texture, TextureOptions, TextureRole};
 pub use mesh::{Mesh, Vertex, MeshData};
 #[allow(unused_imports)]
 pub use shader::ShaderManager;
+#[allow(unused_imports)]
+pub use tonemap::{TonemapOperator, TonemapSettings};
+#[allow(unused_imports)]
+pub use post_process::{
+    BloomSettings, ChromaticAberrationSettings, ColorGradingSettings, FxaaSettings, PostEffect,
+    PostProcessSettings, VignetteSettings,
+};
+#[allow(unused_imports)]
+pub use ambient_occlusion::AmbientOcclusionSettings;
+#[allow(unused_imports)]
+pub use deferred::RenderPath;
+#[allow(unused_imports)]
+pub use render_graph::{
+    CustomPass, FrameResources, PassBuilder, PassContext, PassStage, RenderGraph, ResourceId, Schedule,
+    TransientTextureDesc, TransientTextures,
+};
+#[allow(unused_imports)]
+pub use sky::{AtmosphereSettings, SkyMode};
+#[allow(unused_imports)]
+pub use transparency::TransparencyMode;
+#[allow(unused_imports)]
+pub use instance::{Instance, InstancedModel};
+#[allow(unused_imports)]
+pub use culling::{Aabb, BoundingSphere, CullingStats, Frustum};
+#[allow(unused_imports)]
+pub use lod::{LodChoice, LodSelector, LodSettings, LodView};
+#[allow(unused_imports)]
+pub use view::{RenderTarget, View, ViewTarget, Viewport};
+#[allow(unused_imports)]
+pub use material::{
+    create_material_instance, BlendMode, DefaultMaterialTextures, MaterialPass, MaterialTextures, MaterialUniforms,
+    MATERIAL_HAS_NORMAL_MAP,
+};
 
-#[allow(dead_code)]
-/// Create a debugging material for grids and gizmos
-fn create_debug_material(
+/// Color the HDR target is cleared to before the sky is drawn
+const CLEAR_COLOR: wgpu::Color = wgpu::Color {
+    r: 0.1,
+    g: 0.2,
+    b: 0.3,
+    a: 1.0,
+};
+
+/// Device features used when the adapter has them: every MSAA sample count the adapter
+/// supports for our formats, and the compressed texture formats
+const OPTIONAL_FEATURES: wgpu::Features = wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+    .union(wgpu::Features::TEXTURE_COMPRESSION_BC)
+    .union(wgpu::Features::TEXTURE_COMPRESSION_ETC2)
+    .union(wgpu::Features::TEXTURE_COMPRESSION_ASTC);
+
+/// Create the bind group holding a model's transforms and the sun light
+pub fn create_object_bind_group(
     device: &wgpu::Device,
-    queue: &wgpu::Queue,
-    bind_group_layout: &wgpu::BindGroupLayout,
-) -> crate::engine::scene::MaterialInstance {
-    // Create a white texture for the debug material
-    let white_texture = texture::Texture::create_colored_texture(
-        device,
-        queue,
-        [1.0, 1.0, 1.0],
-        "Debug Material Texture"
-    );
-    
-    // Create a uniform buffer for transform matrices
-    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: Some("Debug Uniform Buffer"),
-        size: 128, // Space for matrices
-        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-    });
-    
-    // Create a light buffer (unused but required by the shader)
-    let light_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: Some("Debug Light Buffer"),
-        size: 64, // Space for light data
-        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-    });
-    
-    // Create the bind group for this material
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: Some("Debug Bind Group"),
-        layout: bind_group_layout,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    light_buffer: &wgpu::Buffer,
+    label: &str,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(label),
+        layout,
         entries: &[
             wgpu::BindGroupEntry {
                 binding: 0,
-                resource: buffer.as_entire_binding(),
+                resource: uniform_buffer.as_entire_binding(),
             },
             wgpu::BindGroupEntry {
                 binding: 1,
-                resource: wgpu::BindingResource::TextureView(&white_texture.view),
-            },
-            wgpu::BindGroupEntry {
-                binding: 2,
-                resource: wgpu::BindingResource::Sampler(&white_texture.sampler),
-            },
-            wgpu::BindGroupEntry {
-                binding: 3,
                 resource: light_buffer.as_entire_binding(),
             },
         ],
-    });
-    
-    crate::engine::scene::MaterialInstance {
-        material_id: 0,
-        diffuse_bind_group: bind_group,
-    }
+    })
+}
+
+/// A model drawn once per instance for every object that shares it
+struct ModelBatch {
+    instanced_model: usize,       // Index of the instanced model
+    uniform_buffer: wgpu::Buffer, // Camera transform shared by all instances
+}
+
+/// Every target the scene is drawn into at one size, kept while views of other sizes are
+/// drawn so that alternating between them does not recreate anything
+struct SceneTargets {
+    width: u32,
+    height: u32,
+    hdr: tonemap::HdrTarget,
+    post_process: post_process::Targets,
+    ambient_occlusion: ambient_occlusion::Targets,
+    light_bind_group: lights::LitBindGroup, // Reads these occlusion targets
+    multisampled_framebuffer: Option<wgpu::TextureView>,
+    depth_texture: texture::Texture,
+    gbuffer: Option<deferred::GBuffer>,     // None on the forward path
+    transient_textures: TransientTextures,
 }
 
 #[allow(dead_code)]
@@ -81,18 +135,52 @@ fn create_debug_material(
 pub struct Renderer {
     device: wgpu::Device,
     queue: wgpu::Queue,
-    surface: wgpu::Surface,
+    surface: Option<wgpu::Surface>,       // None when rendering headless
+    offscreen_target: Option<texture::Texture>, // Color target used instead of the swapchain
     config: wgpu::SurfaceConfiguration,
+    scene_config: wgpu::SurfaceConfiguration, // Size of the scene targets: the surface's, or that of the view being drawn
     adapter: wgpu::Adapter,
     depth_texture: texture::Texture,
     models: Vec<Model>,
+    instanced_models: Vec<instance::InstancedModel>, // Models drawn once per instance
+    model_batches: HashMap<String, ModelBatch>,      // Instanced models shared by callers, keyed by model
+    single_instance: instance::InstanceBuffer,       // Identity instance bound while drawing plain models
     grid_model: Option<Model>,  // Separate grid model
-    bind_group_layout: wgpu::BindGroupLayout,
+    grid_uniform_buffer: Option<wgpu::Buffer>,  // Transform for the grid model
+    show_debug_grid: bool,
+    bind_group_layout: wgpu::BindGroupLayout,          // Per-model transforms and sun light
+    material_bind_group_layout: wgpu::BindGroupLayout, // Per-material factors and textures
+    default_material_textures: material::DefaultMaterialTextures,
+    pipeline_layout: wgpu::PipelineLayout,        // Kept to rebuild the pipelines when the sample count changes
+    grid_pipeline_layout: wgpu::PipelineLayout,
     render_pipeline: wgpu::RenderPipeline,
     grid_pipeline: Option<wgpu::RenderPipeline>,  // Specialized pipeline for grid
+    transparency: transparency::TransparencyPipelines, // Draw the alpha-blended and additive meshes
+    transparency_mode: TransparencyMode,
     shader_manager: shader::ShaderManager,
-    multisampled_framebuffer: Option<wgpu::TextureView>,
-    sample_count: u32,
+    shadow_map: shadow::ShadowMap,
+    lights: SceneLights,              // Point and spot lights besides the sun
+    light_buffers: lights::LightBuffers,
+    view_buffer: view::ViewBuffer,    // Camera the lit meshes are drawn from
+    viewport_blit: view::ViewportBlit, // Copies views drawn into part of the surface into place
+    tonemapper: tonemap::Tonemapper,  // HDR target and the pass that maps it to the output
+    post_process: post_process::PostProcess, // Bloom before tonemapping, display effects after
+    environment: environment::Environment, // Image-based lighting and skybox
+    ambient_occlusion: ambient_occlusion::AmbientOcclusion, // Darkens the ambient term in creases and contacts
+    sky: sky::Sky,                    // Background drawn behind the scene
+    culling_view_proj: Option<cgmath::Matrix4<f32>>, // Camera meshes are culled against, None draws everything
+    culling_stats: std::cell::Cell<CullingStats>, // Meshes drawn and culled in the last frame on the CPU path
+    gpu_culling: Option<gpu_culling::GpuCulling>, // None where compute or indirect draws are unavailable
+    gpu_culling_enabled: bool,
+    culling_scene_changed: bool,      // Models or instances changed since the GPU culling objects were built
+    lods: lod::LodSelector,           // Picks mesh levels of detail for the culling camera
+    multisampled_framebuffer: Option<wgpu::TextureView>, // Scene color target resolved into the HDR target, None without MSAA
+    sample_count: u32,                // Samples per pixel of the scene color and depth targets
+    deferred: Option<deferred::DeferredRenderer>, // G-buffer and lighting pass, None on the forward path
+    custom_passes: Vec<Box<dyn CustomPass>>, // Passes added to the frame graph from outside the renderer
+    transient_textures: TransientTextures,   // Textures backing the frame graph's transient resources
+    scene_target_cache: Vec<SceneTargets>,   // Targets of the other sizes views were drawn at
+    frame_passes: Vec<String>,               // Passes the last frame ran, in order
 }
 
 impl Renderer {
@@ -120,7 +208,7 @@ impl Renderer {
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor {
                 label: None,
-                features: wgpu::Features::empty(),
+                features: adapter.features() & OPTIONAL_FEATURES,
                 limits: wgpu::Limits::default(),
             },
             None,
@@ -146,12 +234,83 @@ impl Renderer {
 
         surface.configure(&device, &config);
         
+        Self::from_device(adapter, device, queue, Some(surface), config)
+    }
+    
+    #[allow(dead_code)]
+    /// Creates a renderer without a window that draws into an offscreen texture.
+    ///
+    /// Any available adapter is accepted, falling back to a software adapter when
+    /// no hardware one is present. The backend can be narrowed with `WGPU_BACKEND`.
+    pub async fn new_headless(width: u32, height: u32) -> anyhow::Result<Self> {
+        // Instance
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::all()),
+            dx12_shader_compiler: Default::default(),
+        });
+        
+        // Adapter, preferring real hardware over the fallback adapter
+        let mut adapter = None;
+        for force_fallback_adapter in [false, true] {
+            adapter = instance.request_adapter(
+                &wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    force_fallback_adapter,
+                    compatible_surface: None,
+                }
+            ).await;
+            if adapter.is_some() {
+                break;
+            }
+        }
+        let adapter = adapter.ok_or_else(|| anyhow::anyhow!("No suitable adapter found for headless rendering"))?;
+
+        // Device and queue
+        let (device, queue) = adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("Headless Device"),
+                features: adapter.features() & OPTIONAL_FEATURES,
+                limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
+            },
+            None,
+        ).await?;
+
+        // Offscreen target configuration; never passed to a surface
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format,
+            width: width.max(1),
+            height: height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![format],
+        };
+        
+        Ok(Self::from_device(adapter, device, queue, None, config))
+    }
+    
+    /// Builds the renderer state shared by the windowed and headless constructors
+    fn from_device(
+        adapter: wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        surface: Option<wgpu::Surface>,
+        config: wgpu::SurfaceConfiguration,
+    ) -> Self {
+        // Create the offscreen color target when there is no surface to present to
+        let offscreen_target = if surface.is_none() {
+            Some(texture::Texture::create_render_target(&device, &config, "Offscreen Color Target"))
+        } else {
+            None
+        };
+        
         // Create depth texture
         let depth_texture = texture::Texture::create_depth_texture(&device, &config, "Depth Texture");
         
-        // Create a combined bind group layout for model rendering
+        // Create the per-model bind group layout
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Combined Bind Group Layout"),
+            label: Some("Object Bind Group Layout"),
             entries: &[
                 // Uniform buffer (matrix transforms)
                 wgpu::BindGroupLayoutEntry {
@@ -164,27 +323,9 @@ impl Renderer {
                     },
                     count: None,
                 },
-                // Texture
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
-                    },
-                    count: None,
-                },
-                // Sampler
-                wgpu::BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
                 // Light buffer (for sun light)
                 wgpu::BindGroupLayoutEntry {
-                    binding: 3,
+                    binding: 1,
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
@@ -195,36 +336,239 @@ impl Renderer {
                 },
             ],
         });
+        
+        // Create the material bind group layout and the textures for missing maps
+        let material_bind_group_layout = material::create_bind_group_layout(&device);
+        let default_material_textures = material::DefaultMaterialTextures::new(&device, &queue);
 
         // Create the shader manager
-        let shader_manager = shader::ShaderManager::new(&device);
+        let mut shader_manager = shader::ShaderManager::new(&device);
+        
+        // Create a specialized shader for the grid
+        let grid_shader_src = r#"
+            struct Uniforms {
+                view_proj: mat4x4<f32>,
+                model: mat4x4<f32>,
+            };
+
+            @group(0) @binding(0)
+            var<uniform> uniforms: Uniforms;
+
+            struct VertexInput {
+                @location(0) position: vec3<f32>,
+                @location(1) normal: vec3<f32>,
+                @location(2) tex_coords: vec2<f32>,
+                @location(3) color: vec3<f32>,
+            };
+
+            struct VertexOutput {
+                @builtin(position) clip_position: vec4<f32>,
+                @location(0) color: vec3<f32>,
+                @location(1) position: vec3<f32>,
+            };
+
+            @vertex
+            fn vs_main(in: VertexInput) -> VertexOutput {
+                var out: VertexOutput;
+                out.clip_position = uniforms.view_proj * uniforms.model * vec4<f32>(in.position, 1.0);
+                out.color = in.color;
+                out.position = (uniforms.model * vec4<f32>(in.position, 1.0)).xyz;
+                return out;
+            }
+
+            @fragment
+            fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+                // Darken grid lines that lie in the sun's shadow
+                let shadow_factor = sample_shadow(in.position, vec3<f32>(0.0, 1.0, 0.0));
+                let color = in.color * mix(0.35, 1.0, shadow_factor) * cascade_debug_tint(in.position);
+                return vec4<f32>(color, 1.0);
+            }
+        "#;
+        let grid_shader_src = [shadow::SHADOW_SAMPLING_WGSL, grid_shader_src].concat();
+        shader_manager.load_from_string(&device, "grid", &grid_shader_src);
+        
+        // Create the sun's shadow map
+        let shadow_map = shadow::ShadowMap::new(
+            &device,
+            &bind_group_layout,
+            shader_manager.get("shadow").expect("Shadow shader should be available"),
+        );
+        
+        // Bake the default environment used for image-based lighting
+        let environment = environment::Environment::new(&device, &queue, &shader_manager);
+        
+        // Create the background pass
+        let sky = sky::Sky::new(
+            &device,
+            shader_manager.get("atmosphere").expect("Atmosphere shader should be available"),
+        );
+        
+        // Create the screen-space ambient occlusion passes, off until a camera is set
+        let ambient_occlusion = ambient_occlusion::AmbientOcclusion::new(
+            &device,
+            &config,
+            &bind_group_layout,
+            shader_manager.get("ambient_occlusion_prepass").expect("Ambient occlusion prepass shader should be available"),
+            shader_manager.get("ambient_occlusion").expect("Ambient occlusion shader should be available"),
+        );
+        
+        // Create the storage buffers for point and spot lights, bound with the camera the meshes are drawn from
+        let view_buffer = view::ViewBuffer::new(&device);
+        let light_buffers = lights::LightBuffers::new(
+            &device,
+            shader_manager.get("light_culling").expect("Light culling shader should be available"),
+            &environment,
+            &ambient_occlusion,
+            &view_buffer,
+        );
         
-        // Get default shader module for rendering
-        let shader_module = shader_manager.get("pbr").expect("PBR shader should be available");
+        // Create the HDR target the scene is lit into and its tonemap pass
+        let tonemapper = tonemap::Tonemapper::new(
+            &device,
+            &config,
+            shader_manager.get("tonemap").expect("Tonemap shader should be available"),
+            shader_manager.get("exposure_histogram").expect("Exposure histogram shader should be available"),
+        );
+        
+        // Create the post-process chain around the tonemap pass
+        let post_process = post_process::PostProcess::new(
+            &device,
+            &queue,
+            &config,
+            tonemapper.hdr_view(),
+            shader_manager.get("bloom").expect("Bloom shader should be available"),
+            shader_manager.get("post_process").expect("Post-process shader should be available"),
+        );
+        
+        // Pick levels of detail, full detail until a camera is set
+        let lods = lod::LodSelector::new(&device);
+
+        // Cull and draw indirectly on the GPU where the adapter allows it
+        let gpu_culling = gpu_culling::GpuCulling::is_supported(&adapter).then(|| {
+            gpu_culling::GpuCulling::new(
+                &device,
+                shader_manager.get("gpu_culling").expect("GPU culling shader should be available"),
+                shader_manager.get("hiz").expect("Depth pyramid shader should be available"),
+            )
+        });
+        
+        // Identity instance for models that are not instanced
+        let single_instance = instance::InstanceBuffer::new(&device, &[Instance::default()], "Single Instance Buffer");
         
         // Create the pipeline layout
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
             bind_group_layouts: &[
                 &bind_group_layout,
+                shadow_map.bind_group_layout(),
+                light_buffers.bind_group_layout(),
+                &material_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        // The grid only reads its transform and the shadow map
+        let grid_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Grid Pipeline Layout"),
+            bind_group_layouts: &[
+                &bind_group_layout,
+                shadow_map.bind_group_layout(),
             ],
             push_constant_ranges: &[],
         });
+        
+        // Create the scene pipelines, single-sampled until MSAA is turned on
+        let render_pipeline = Self::create_render_pipeline(
+            &device,
+            &pipeline_layout,
+            shader_manager.get("pbr").expect("PBR shader should be available"),
+            1,
+        );
+        let grid_pipeline = Self::create_grid_pipeline(
+            &device,
+            &grid_pipeline_layout,
+            shader_manager.get("grid").expect("Grid shader should be available"),
+            1,
+        );
+        let transparency = transparency::TransparencyPipelines::new(&device, &pipeline_layout, &shader_manager, 1);
+        let viewport_blit = view::ViewportBlit::new(&device, &shader_manager, config.format);
 
-        // Create the render pipeline
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        let mut renderer = Self {
+            device,
+            queue,
+            surface,
+            offscreen_target,
+            scene_config: config.clone(),
+            config,
+            adapter,
+            depth_texture,
+            models: Vec::new(),
+            instanced_models: Vec::new(),
+            model_batches: HashMap::new(),
+            single_instance,
+            grid_model: None,
+            grid_uniform_buffer: None,
+            show_debug_grid: true,
+            bind_group_layout,
+            material_bind_group_layout,
+            default_material_textures,
+            pipeline_layout,
+            grid_pipeline_layout,
+            render_pipeline,
+            grid_pipeline: Some(grid_pipeline),
+            transparency,
+            transparency_mode: TransparencyMode::default(),
+            shader_manager,
+            shadow_map,
+            lights: SceneLights::new(),
+            light_buffers,
+            view_buffer,
+            viewport_blit,
+            tonemapper,
+            post_process,
+            environment,
+            ambient_occlusion,
+            sky,
+            culling_view_proj: None,
+            culling_stats: std::cell::Cell::new(CullingStats::default()),
+            gpu_culling,
+            gpu_culling_enabled: true,
+            culling_scene_changed: true,
+            lods,
+            multisampled_framebuffer: None,
+            sample_count: 1,
+            deferred: None,
+            custom_passes: Vec::new(),
+            transient_textures: TransientTextures::default(),
+            frame_passes: Vec::new(),
+            scene_target_cache: Vec::new(),
+        };
+        
+        // Initialize debug visualization (grid and axis gizmos)
+        renderer.init_debug_visualization();
+        
+        renderer
+    }
+    /// Creates the PBR pipeline drawing into the HDR target with `sample_count` samples
+    fn create_render_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Render Pipeline"),
-            layout: Some(&pipeline_layout),
+            layout: Some(layout),
             vertex: wgpu::VertexState {
-                module: shader_module,
+                module: shader,
                 entry_point: "vs_main",
-                buffers: &[mesh::Vertex::desc()],
+                buffers: &[mesh::Vertex::desc(), Instance::desc()],
             },
             fragment: Some(wgpu::FragmentState {
-                module: shader_module,
+                module: shader,
                 entry_point: "fs_main",
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
+                    format: tonemap::HDR_FORMAT,
                     blend: Some(wgpu::BlendState::REPLACE),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
@@ -246,69 +590,34 @@ impl Renderer {
                 bias: wgpu::DepthBiasState::default(),
             }),
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
             multiview: None,
-        });
-
-        // Create a specialized shader for the grid
-        let grid_shader_src = r#"
-            struct Uniforms {
-                view_proj: mat4x4<f32>,
-                model: mat4x4<f32>,
-            };
-
-            @group(0) @binding(0)
-            var<uniform> uniforms: Uniforms;
-
-            struct VertexInput {
-                @location(0) position: vec3<f32>,
-                @location(1) normal: vec3<f32>,
-                @location(2) tex_coords: vec2<f32>,
-                @location(3) color: vec3<f32>,
-            };
-
-            struct VertexOutput {
-                @builtin(position) clip_position: vec4<f32>,
-                @location(0) color: vec3<f32>,
-            };
-
-            @vertex
-            fn vs_main(in: VertexInput) -> VertexOutput {
-                var out: VertexOutput;
-                out.clip_position = uniforms.view_proj * uniforms.model * vec4<f32>(in.position, 1.0);
-                out.color = in.color;
-                return out;
-            }
+        })
+    }
 
-            @fragment
-            fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-                return vec4<f32>(in.color, 1.0);
-            }
-        "#;
-        
-        // Create grid shader module
-        let grid_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Grid Shader"),
-            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(grid_shader_src)),
-        });
-        
-        // Create grid pipeline
-        let grid_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+    /// Creates the debug grid's line pipeline drawing into the HDR target with `sample_count` samples
+    fn create_grid_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Grid Pipeline"),
-            layout: Some(&pipeline_layout),
+            layout: Some(layout),
             vertex: wgpu::VertexState {
-                module: &grid_shader,
+                module: shader,
                 entry_point: "vs_main",
                 buffers: &[mesh::Vertex::desc()],
             },
             fragment: Some(wgpu::FragmentState {
-                module: &grid_shader,
+                module: shader,
                 entry_point: "fs_main",
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
+                    format: tonemap::HDR_FORMAT,
                     blend: Some(wgpu::BlendState::REPLACE),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
@@ -330,34 +639,12 @@ impl Renderer {
                 bias: wgpu::DepthBiasState::default(),
             }),
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
             multiview: None,
-        });
-
-        let mut renderer = Self {
-            device,
-            queue,
-            surface,
-            config,
-            adapter,
-            depth_texture,
-            models: Vec::new(),
-            grid_model: None,
-            bind_group_layout,
-            render_pipeline,
-            grid_pipeline: Some(grid_pipeline),
-            shader_manager,
-            multisampled_framebuffer: None,
-            sample_count: 1,
-        };
-        
-        // Initialize debug visualization (grid and axis gizmos)
-        renderer.init_debug_visualization();
-        
-        renderer
+        })
     }
     
     /// Resizes the renderer surface
@@ -367,111 +654,1420 @@ impl Renderer {
             self.config.width = width;
             self.config.height = height;
             
-            // Configure the surface with our updated config
-            self.surface.configure(&self.device, &self.config);
+            // Configure the surface with our updated config, or recreate the offscreen target
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.config);
+            } else {
+                self.offscreen_target = Some(texture::Texture::create_render_target(
+                    &self.device,
+                    &self.config,
+                    "Offscreen Color Target"
+                ));
+            }
             
-            // Create a new depth texture with the updated dimensions
-            self.depth_texture = texture::Texture::create_depth_texture(
-                &self.device, 
-                &self.config, 
-                "Depth Texture"
-            );
+            self.resize_scene_targets(width, height);
         }
     }
     
-    /// Returns a reference to the device
-    pub fn device(&self) -> &wgpu::Device {
-        &self.device
+    /// Recreates every target the scene is drawn into at the given size, dropping those
+    /// kept for other sizes
+    fn resize_scene_targets(&mut self, width: u32, height: u32) {
+        self.clear_scene_target_cache();
+        self.replace_scene_targets(width, height);
     }
     
-    /// Returns a reference to the adapter
-    #[allow(unused)]
-    pub fn adapter(&self) -> &wgpu::Adapter {
-        &self.adapter
+    /// Switches the scene targets to those of a view of the given size, unless they already
+    /// have it. The targets of every size views were drawn at are kept for the next switch.
+    fn fit_scene_targets(&mut self, width: u32, height: u32) {
+        if (self.scene_config.width, self.scene_config.height) == (width, height) {
+            return;
+        }
+        let cached = self.scene_target_cache.iter().position(|targets| (targets.width, targets.height) == (width, height));
+        let previous = match cached {
+            Some(index) => {
+                let targets = self.scene_target_cache.swap_remove(index);
+                self.swap_scene_targets(targets)
+            }
+            None => self.replace_scene_targets(width, height),
+        };
+        self.scene_target_cache.push(previous);
     }
     
-    /// Returns a reference to the queue
-    pub fn queue(&self) -> &wgpu::Queue {
-        &self.queue
+    /// Drops the targets kept for other sizes, after something they were made with changed.
+    /// Depth textures made later may share their sizes, so the depth pyramids go as well.
+    fn clear_scene_target_cache(&mut self) {
+        self.scene_target_cache.clear();
+        if let Some(gpu_culling) = &mut self.gpu_culling {
+            gpu_culling.clear_history();
+        }
     }
     
-    /// Returns a reference to the bind group layout
-    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
-        &self.bind_group_layout
+    /// Creates every target the scene is drawn into at the given size, returning the ones
+    /// they replace
+    fn replace_scene_targets(&mut self, width: u32, height: u32) -> SceneTargets {
+        let (previous_width, previous_height) = (self.scene_config.width, self.scene_config.height);
+        self.scene_config.width = width;
+        self.scene_config.height = height;
+        
+        // Recreate the HDR and post-process targets to match
+        let hdr = self.tonemapper.resize(&self.device, &self.scene_config);
+        let post_process = self.post_process.resize(&self.device, &self.scene_config, self.tonemapper.hdr_view());
+        
+        // Recreate the occlusion targets and bind the one the lit pass reads
+        let ambient_occlusion = self.ambient_occlusion.resize(&self.device, &self.queue, &self.scene_config);
+        let light_bind_group = self.light_buffers.bind_occlusion(
+            &self.device,
+            None,
+            &self.environment,
+            &self.ambient_occlusion,
+            &self.view_buffer,
+        );
+        
+        // Create new scene color and depth targets with the updated dimensions
+        let (multisampled_framebuffer, depth_texture, gbuffer) = self.create_scene_targets();
+        SceneTargets {
+            width: previous_width,
+            height: previous_height,
+            hdr,
+            post_process,
+            ambient_occlusion,
+            light_bind_group,
+            multisampled_framebuffer,
+            depth_texture,
+            gbuffer,
+            transient_textures: std::mem::take(&mut self.transient_textures),
+        }
     }
     
-    #[allow(dead_code)]
-    /// Returns a reference to the depth texture
-    pub fn depth_texture(&self) -> &texture::Texture {
-        &self.depth_texture
+    /// Draws the scene into targets kept from an earlier size, returning the current ones
+    fn swap_scene_targets(&mut self, targets: SceneTargets) -> SceneTargets {
+        let (previous_width, previous_height) = (self.scene_config.width, self.scene_config.height);
+        self.scene_config.width = targets.width;
+        self.scene_config.height = targets.height;
+        
+        let hdr = self.tonemapper.swap_hdr_target(targets.hdr);
+        let post_process = self.post_process.swap_targets(targets.post_process);
+        let ambient_occlusion = self.ambient_occlusion.swap_targets(&self.queue, targets.ambient_occlusion);
+        let light_bind_group = self.light_buffers.bind_occlusion(
+            &self.device,
+            Some(targets.light_bind_group),
+            &self.environment,
+            &self.ambient_occlusion,
+            &self.view_buffer,
+        );
+        
+        // The cache is cleared when the path changes, so kept targets have a G-buffer exactly
+        // when the deferred path is on
+        let gbuffer = match (&mut self.deferred, targets.gbuffer) {
+            (Some(deferred), Some(gbuffer)) => Some(deferred.swap_gbuffer(&self.queue, gbuffer)),
+            _ => None,
+        };
+        SceneTargets {
+            width: previous_width,
+            height: previous_height,
+            hdr,
+            post_process,
+            ambient_occlusion,
+            light_bind_group,
+            multisampled_framebuffer: std::mem::replace(&mut self.multisampled_framebuffer, targets.multisampled_framebuffer),
+            depth_texture: std::mem::replace(&mut self.depth_texture, targets.depth_texture),
+            gbuffer,
+            transient_textures: std::mem::replace(&mut self.transient_textures, targets.transient_textures),
+        }
     }
     
-    /// Get a reference to a model by index
-    pub fn get_model(&self, index: usize) -> Option<&Model> {
-        self.models.get(index)
+    /// Recreates the multisampled color target, the depth texture and the G-buffer for the
+    /// current size and sample count, returning the ones they replace
+    fn create_scene_targets(&mut self) -> (Option<wgpu::TextureView>, texture::Texture, Option<deferred::GBuffer>) {
+        let multisampled_framebuffer = std::mem::replace(
+            &mut self.multisampled_framebuffer,
+            Self::create_multisampled_framebuffer(&self.device, &self.scene_config, self.sample_count),
+        );
+        
+        // The GL backend fails to create multisampled textures that can also be sampled, so
+        // there a multisampled depth buffer is attachment-only and occlusion culling is off
+        let depth_sampled = self.sample_count == 1 || self.adapter.get_info().backend != wgpu::Backend::Gl;
+        let mut depth_usage = wgpu::TextureUsages::RENDER_ATTACHMENT;
+        if depth_sampled {
+            depth_usage |= wgpu::TextureUsages::TEXTURE_BINDING;
+        }
+        let depth_texture = std::mem::replace(
+            &mut self.depth_texture,
+            texture::Texture::create_multisampled_depth_texture(
+                &self.device,
+                &self.scene_config,
+                self.sample_count,
+                depth_usage,
+                "Depth Texture",
+            ),
+        );
+        
+        // Resize the G-buffer and rebind the depth the lighting pass reads
+        let gbuffer = self.deferred
+            .as_mut()
+            .map(|deferred| deferred.resize(&self.device, &self.queue, &self.scene_config, &self.depth_texture));
+        (multisampled_framebuffer, depth_texture, gbuffer)
+    }
+    
+    /// Creates the HDR color target the scene is drawn into with MSAA, or None for a single sample
+    fn create_multisampled_framebuffer(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> Option<wgpu::TextureView> {
+        if sample_count <= 1 {
+            return None;
+        }
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Multisampled HDR Color Target"),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: tonemap::HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+    }
+    
+    /// Returns the number of samples per pixel the scene is rendered with
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+    
+    /// Returns the MSAA sample counts out of 1, 2, 4 and 8 the adapter can render and resolve
+    /// the HDR color and depth targets with. The deferred path only renders single-sampled.
+    pub fn supported_sample_counts(&self) -> Vec<u32> {
+        if self.deferred.is_some() {
+            return vec![1];
+        }
+        let color = self.format_features(tonemap::HDR_FORMAT);
+        let depth = self.format_features(wgpu::TextureFormat::Depth32Float);
+        let resolvable = color.flags.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE);
+        [1, 2, 4, 8]
+            .into_iter()
+            .filter(|&count| {
+                count == 1
+                    || (resolvable
+                        && color.flags.sample_count_supported(count)
+                        && depth.flags.sample_count_supported(count))
+            })
+            .collect()
+    }
+    
+    /// Returns the features the device validates textures of `format` against. Like wgpu,
+    /// adapter-specific features only apply if they were requested or the adapter is downlevel.
+    fn format_features(&self, format: wgpu::TextureFormat) -> wgpu::TextureFormatFeatures {
+        let adapter_specific = self.device.features().contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+            || !self.adapter.get_downlevel_capabilities().is_webgpu_compliant();
+        if adapter_specific {
+            self.adapter.get_texture_format_features(format)
+        } else {
+            format.guaranteed_format_features(self.device.features())
+        }
+    }
+    
+    /// Sets the MSAA sample count, recreating the scene targets and every pipeline drawing
+    /// into them. Fails without changing anything if the adapter does not support the count.
+    pub fn set_sample_count(&mut self, sample_count: u32) -> anyhow::Result<()> {
+        let supported = self.supported_sample_counts();
+        if !supported.contains(&sample_count) {
+            anyhow::bail!("{sample_count}x MSAA is not supported by this adapter, supported counts are {supported:?}");
+        }
+        if sample_count == self.sample_count {
+            return Ok(());
+        }
+        self.sample_count = sample_count;
+        self.clear_scene_target_cache();
+        self.create_scene_targets();
+        
+        // Rebuild every pipeline of the main pass to match the targets
+        self.render_pipeline = Self::create_render_pipeline(
+            &self.device,
+            &self.pipeline_layout,
+            self.shader_manager.get("pbr").expect("PBR shader should be available"),
+            sample_count,
+        );
+        self.grid_pipeline = Some(Self::create_grid_pipeline(
+            &self.device,
+            &self.grid_pipeline_layout,
+            self.shader_manager.get("grid").expect("Grid shader should be available"),
+            sample_count,
+        ));
+        self.transparency = transparency::TransparencyPipelines::new(
+            &self.device,
+            &self.pipeline_layout,
+            &self.shader_manager,
+            sample_count,
+        );
+        self.sky.set_sample_count(
+            &self.device,
+            self.shader_manager.get("atmosphere").expect("Atmosphere shader should be available"),
+            sample_count,
+        );
+        self.environment.set_sample_count(&self.device, &self.shader_manager, sample_count);
+        Ok(())
+    }
+    
+    /// Returns whether the scene is lit forward or deferred
+    pub fn render_path(&self) -> RenderPath {
+        if self.deferred.is_some() {
+            RenderPath::Deferred
+        } else {
+            RenderPath::Forward
+        }
+    }
+    
+    /// Choose between lighting every mesh as it is drawn and lighting a G-buffer of the opaque
+    /// meshes in one fullscreen pass. The deferred path turns MSAA off, and lights with the
+    /// camera and sun passed to `update_environment` and `update_shadows` from then on.
+    pub fn set_render_path(&mut self, path: RenderPath) -> anyhow::Result<()> {
+        if path == self.render_path() {
+            return Ok(());
+        }
+        // Targets kept for other sizes have a G-buffer only on the deferred path
+        self.clear_scene_target_cache();
+        match path {
+            RenderPath::Forward => self.deferred = None,
+            RenderPath::Deferred => {
+                self.set_sample_count(1)?;
+                self.deferred = Some(deferred::DeferredRenderer::new(
+                    &self.device,
+                    &self.scene_config,
+                    &self.depth_texture,
+                    &self.pipeline_layout,
+                    self.shadow_map.bind_group_layout(),
+                    self.light_buffers.bind_group_layout(),
+                    &self.shader_manager,
+                ));
+            }
+        }
+        Ok(())
+    }
+    
+    #[allow(dead_code)]
+    /// Returns how alpha-blended meshes are composited
+    pub fn transparency_mode(&self) -> TransparencyMode {
+        self.transparency_mode
+    }
+    
+    /// Draw alpha-blended meshes sorted back to front, or accumulate them in any order with
+    /// weighted blended order-independent transparency, which copes better with heavy overlap
+    pub fn set_transparency_mode(&mut self, mode: TransparencyMode) {
+        self.transparency_mode = mode;
+    }
+    
+    /// Returns a reference to the device
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+    
+    /// Returns a reference to the adapter
+    #[allow(unused)]
+    pub fn adapter(&self) -> &wgpu::Adapter {
+        &self.adapter
+    }
+    
+    /// Returns a reference to the queue
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+    
+    /// Returns the layout of the per-model bind group (transforms and sun light)
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+    
+    /// Returns the layout of the per-material bind group
+    pub fn material_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.material_bind_group_layout
+    }
+    
+    #[allow(dead_code)]
+    /// Returns neutral textures for materials that leave maps out
+    pub fn default_material_textures(&self) -> &material::DefaultMaterialTextures {
+        &self.default_material_textures
+    }
+    
+    #[allow(dead_code)]
+    /// Returns a reference to the depth texture
+    pub fn depth_texture(&self) -> &texture::Texture {
+        &self.depth_texture
+    }
+    
+    /// Update the view-projection matrix used to draw the debug grid
+    pub fn update_grid_transform(&self, view_proj: cgmath::Matrix4<f32>) {
+        if let Some(buffer) = &self.grid_uniform_buffer {
+            let uniforms = crate::engine::core::application::Uniforms {
+                view_proj: view_proj.into(),
+                model: cgmath::Matrix4::identity().into(),
+            };
+            self.queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[uniforms]));
+        }
+    }
+    
+    /// Update the view-projection matrix the batched models are drawn with
+    fn update_batch_transforms(&self, view_proj: cgmath::Matrix4<f32>) {
+        let view_proj: [[f32; 4]; 4] = view_proj.into();
+        for batch in self.model_batches.values() {
+            self.queue.write_buffer(&batch.uniform_buffer, 0, bytemuck::cast_slice(&[view_proj]));
+        }
+    }
+    
+    /// Update the sun's shadow map for the current light direction and camera
+    pub fn update_shadows(&mut self, sun_light: &SunLight, settings: &ShadowSettings, camera: &Camera) {
+        self.shadow_map.update(&self.device, &self.queue, sun_light.direction, settings, camera);
+        if let Some(deferred) = &self.deferred {
+            deferred.update_sun(&self.queue, sun_light);
+        }
+    }
+    
+    #[allow(dead_code)]
+    /// Returns the point and spot lights in the scene
+    pub fn lights(&self) -> &SceneLights {
+        &self.lights
+    }
+    
+    /// Returns the scene lights for adding, removing or changing lights
+    pub fn lights_mut(&mut self) -> &mut SceneLights {
+        &mut self.lights
+    }
+    
+    /// Fit the light clusters to the camera so each fragment only evaluates nearby lights
+    pub fn update_light_clusters(&mut self, camera: &Camera) {
+        self.light_buffers.update_clusters(&self.queue, camera, self.scene_config.width, self.scene_config.height);
+    }
+    
+    /// Show or hide the heatmap of lights per cluster
+    pub fn set_light_heatmap_visible(&mut self, visible: bool) {
+        self.light_buffers.set_heatmap_visible(&self.queue, visible);
+    }
+    
+    /// Returns true if the heatmap of lights per cluster is shown
+    pub fn light_heatmap_visible(&self) -> bool {
+        self.light_buffers.heatmap_visible()
+    }
+    
+    /// Returns the tonemapping and exposure settings
+    pub fn tonemap_settings(&self) -> &TonemapSettings {
+        self.tonemapper.settings()
+    }
+    
+    /// Change the tonemapping operator and exposure
+    pub fn set_tonemap_settings(&mut self, settings: TonemapSettings) {
+        self.tonemapper.set_settings(&self.queue, settings);
+    }
+    
+    /// Returns the current post-process settings
+    pub fn post_process_settings(&self) -> &PostProcessSettings {
+        self.post_process.settings()
+    }
+    
+    /// Replace the post-process settings, turning effects on or off and reordering the chain
+    pub fn set_post_process_settings(&mut self, settings: PostProcessSettings) {
+        self.post_process.set_settings(&self.queue, settings);
+    }
+    
+    #[allow(dead_code)]
+    /// Load a color grading LUT image laid out as a strip of square slices, such as 256x16
+    pub fn load_color_grading_lut(&mut self, path: &std::path::Path) -> anyhow::Result<()> {
+        let image = image::open(path)
+            .map_err(|e| anyhow::anyhow!("Failed to load color grading LUT {}: {}", path.display(), e))?
+            .into_rgba8();
+        self.set_color_grading_lut(&image)
+    }
+    
+    #[allow(dead_code)]
+    /// Replace the color grading LUT with a strip of square slices, one per blue value
+    pub fn set_color_grading_lut(&mut self, strip: &image::RgbaImage) -> anyhow::Result<()> {
+        self.post_process.set_color_grading_lut(&self.device, &self.queue, &self.scene_config, strip)?;
+        
+        // Post-process targets kept for other sizes read the old LUT
+        self.clear_scene_target_cache();
+        Ok(())
+    }
+    
+    /// Let auto exposure adapt for the time elapsed since the last frame
+    pub fn update_exposure(&mut self, dt: f32) {
+        self.tonemapper.update(&self.queue, dt);
+    }
+    
+    /// Load an equirectangular `.hdr` or `.exr` image as the environment for image-based lighting
+    pub fn load_environment(&mut self, path: &std::path::Path) -> anyhow::Result<()> {
+        let image = image::open(path)
+            .map_err(|e| anyhow::anyhow!("Failed to load environment {}: {}", path.display(), e))?
+            .into_rgba32f();
+        self.set_environment_image(&image);
+        Ok(())
+    }
+    
+    /// Bake an equirectangular HDR image into the environment for image-based lighting
+    pub fn set_environment_image(&mut self, image: &image::Rgba32FImage) {
+        self.environment.set_image(&self.device, &self.queue, image);
+        self.light_buffers.rebind(&self.device, &self.environment, &self.ambient_occlusion, &self.view_buffer);
+    }
+    
+    #[allow(dead_code)]
+    /// Returns the intensity the environment lighting and skybox are scaled by
+    pub fn environment_intensity(&self) -> f32 {
+        self.environment.intensity()
+    }
+    
+    #[allow(dead_code)]
+    /// Scale the environment lighting and skybox
+    pub fn set_environment_intensity(&mut self, intensity: f32) {
+        self.environment.set_intensity(&self.queue, intensity);
+    }
+    
+    /// Point the environment reflections, the skybox and the deferred lighting at the camera
+    pub fn update_environment(&mut self, camera: &Camera) {
+        self.environment.update_camera(&self.queue, camera);
+        if let Some(deferred) = &mut self.deferred {
+            deferred.update_camera(&self.queue, camera);
+        }
+    }
+    
+    /// Render ambient occlusion from the camera's point of view. Until this is called
+    /// the occlusion passes do not run.
+    pub fn update_ambient_occlusion(&mut self, camera: &Camera) {
+        self.ambient_occlusion.update_camera(&self.queue, camera);
+    }
+    
+    /// Returns the screen-space ambient occlusion settings
+    pub fn ambient_occlusion_settings(&self) -> &AmbientOcclusionSettings {
+        self.ambient_occlusion.settings()
+    }
+    
+    /// Turn screen-space ambient occlusion on or off and change its radius, intensity and sample count
+    pub fn set_ambient_occlusion_settings(&mut self, settings: AmbientOcclusionSettings) {
+        self.ambient_occlusion.set_settings(&self.queue, settings);
+    }
+    
+    /// Show the raw, unblurred ambient occlusion buffer instead of the lit scene
+    pub fn set_ambient_occlusion_debug_view(&mut self, enabled: bool) {
+        self.ambient_occlusion.set_debug_view(enabled);
+    }
+    
+    /// Returns true if the raw ambient occlusion buffer is shown
+    pub fn ambient_occlusion_debug_view(&self) -> bool {
+        self.ambient_occlusion.debug_view()
+    }
+    
+    /// Cull meshes against the camera's frustum and pick their levels of detail by their
+    /// size on its screen from the next frame on
+    pub fn update_culling(&mut self, camera: &Camera) {
+        self.culling_view_proj = Some(camera.get_view_projection_matrix());
+        self.lods.set_view(Some(LodView::from_camera(camera)));
+    }
+    
+    #[allow(dead_code)]
+    /// Dither between a mesh's levels of detail near the screen size where they switch
+    /// instead of popping. Instanced batches on the CPU path always switch at once.
+    pub fn set_lod_cross_fade(&mut self, enabled: bool) {
+        self.lods.set_cross_fade(enabled);
+    }
+    
+    /// Returns how many meshes the last frame drew and culled. With GPU culling every
+    /// instance counts as a mesh, and the counts arrive a frame or two late.
+    pub fn culling_stats(&self) -> CullingStats {
+        match &self.gpu_culling {
+            Some(gpu_culling) if self.gpu_culling_enabled => gpu_culling.stats(),
+            _ => self.culling_stats.get(),
+        }
+    }
+    
+    #[allow(dead_code)]
+    /// Choose between culling on the GPU with indirect draws and culling on the CPU.
+    /// GPU culling is used by default wherever the adapter supports it.
+    pub fn set_gpu_culling_enabled(&mut self, enabled: bool) {
+        self.gpu_culling_enabled = enabled;
+        
+        // Depth pyramids are not built while culling on the CPU, so they would be stale
+        if let Some(gpu_culling) = self.gpu_culling.as_mut().filter(|_| !enabled) {
+            gpu_culling.clear_history();
+        }
+    }
+    
+    #[allow(dead_code)]
+    /// Returns true if meshes are culled on the GPU and drawn indirectly
+    pub fn gpu_culling_enabled(&self) -> bool {
+        self.gpu_culling.is_some() && self.gpu_culling_enabled
+    }
+    
+    /// Returns what is drawn behind the scene
+    pub fn sky_mode(&self) -> SkyMode {
+        self.sky.mode()
+    }
+    
+    /// Choose between the clear color, the environment cubemap and the procedural atmosphere
+    pub fn set_sky_mode(&mut self, mode: SkyMode) {
+        self.sky.set_mode(mode);
+    }
+    
+    #[allow(dead_code)]
+    /// Returns the parameters of the procedural atmosphere
+    pub fn atmosphere_settings(&self) -> &AtmosphereSettings {
+        self.sky.atmosphere()
+    }
+    
+    #[allow(dead_code)]
+    /// Change the parameters of the procedural atmosphere
+    pub fn set_atmosphere_settings(&mut self, settings: AtmosphereSettings) {
+        self.sky.set_atmosphere(&self.queue, settings);
+    }
+    
+    /// Point the sky at the camera and light the atmosphere from the sun
+    pub fn update_sky(&mut self, camera: &Camera, sun_light: &SunLight) {
+        self.sky.update(&self.queue, camera, sun_light);
+    }
+    
+    #[allow(dead_code)]
+    /// Show or hide the debug grid and axis gizmos
+    pub fn set_debug_grid_visible(&mut self, visible: bool) {
+        self.show_debug_grid = visible;
+    }
+    
+    /// Create a single-material model that shades a mesh with a solid color
+    pub fn create_solid_model(
+        &self,
+        mesh: Mesh,
+        color: [f32; 3],
+        uniform_buffer: &wgpu::Buffer,
+        light_buffer: &wgpu::Buffer,
+        label: &str,
+    ) -> Model {
+        let texture = texture::Texture::create_colored_texture(
+            &self.device,
+            &self.queue,
+            color,
+            &format!("{} Texture", label)
+        );
+        
+        let material = material::create_material_instance(
+            &self.device,
+            &self.material_bind_group_layout,
+            0,
+            MaterialUniforms::default(),
+            self.default_material_textures.with_albedo(&texture),
+            &format!("{} Material", label),
+        );
+        
+        Model::with_meshes(
+            vec![mesh],
+            vec![material],
+            create_object_bind_group(
+                &self.device,
+                &self.bind_group_layout,
+                uniform_buffer,
+                light_buffer,
+                &format!("{} Bind Group", label),
+            ),
+        )
+    }
+    
+    #[allow(dead_code)]
+    /// Get a reference to a model by index
+    pub fn get_model(&self, index: usize) -> Option<&Model> {
+        self.models.get(index)
     }
     
     /// Add a model to the renderer
     pub fn add_model(&mut self, model: Model) {
         self.models.push(model);
+        self.culling_scene_changed = true;
+    }
+    
+    /// Add a model that is drawn once per instance, with one draw call per mesh.
+    /// Returns the index to update its instances with.
+    pub fn add_instanced_model(&mut self, model: Model, instances: &[Instance]) -> usize {
+        self.instanced_models.push(instance::InstancedModel::new(&self.device, model, instances));
+        self.culling_scene_changed = true;
+        self.instanced_models.len() - 1
+    }
+    
+    #[allow(dead_code)]
+    /// Get a reference to an instanced model by index
+    pub fn get_instanced_model(&self, index: usize) -> Option<&instance::InstancedModel> {
+        self.instanced_models.get(index)
+    }
+    
+    /// Replace the transforms and tints an instanced model is drawn with. Instances equal
+    /// to the current ones are neither uploaded again nor re-gathered for GPU culling.
+    pub fn set_instances(&mut self, index: usize, instances: &[Instance]) {
+        if let Some(model) = self.instanced_models.get_mut(index) {
+            if model.instances() == instances {
+                return;
+            }
+            model.set_instances(&self.device, &self.queue, instances);
+            self.culling_scene_changed = true;
+        }
+    }
+    
+    /// Create the uniform buffer to load a model that will be batched with. It places the
+    /// model at the origin, so each instance's transform alone positions a copy.
+    pub fn create_batch_uniform_buffer(&self, key: &str) -> wgpu::Buffer {
+        let uniforms = crate::engine::core::application::Uniforms {
+            view_proj: cgmath::Matrix4::identity().into(),
+            model: cgmath::Matrix4::identity().into(),
+        };
+        self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("Uniform Buffer for {}", key)),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+    
+    /// Returns true if a model is batched under `key`
+    pub fn has_model_batch(&self, key: &str) -> bool {
+        self.model_batches.contains_key(key)
+    }
+    
+    /// Batch `model`, loaded with `uniform_buffer`, under `key`. It is drawn once per
+    /// instance given for the key, with one draw call per mesh.
+    pub fn add_model_batch(&mut self, key: &str, model: Model, uniform_buffer: wgpu::Buffer) {
+        let instanced_model = self.add_instanced_model(model, &[]);
+        self.model_batches.insert(key.to_string(), ModelBatch { instanced_model, uniform_buffer });
+    }
+    
+    /// Group the instances by the key of their model and replace the instances of every
+    /// batch with its group. Batches without instances are not drawn, and instances of
+    /// keys nothing is batched under are ignored.
+    pub fn set_batched_instances<'a>(&mut self, instances: impl IntoIterator<Item = (&'a str, Instance)>) {
+        let mut groups: HashMap<&str, Vec<Instance>> = HashMap::new();
+        for (key, instance) in instances {
+            groups.entry(key).or_default().push(instance);
+        }
+        let batches: Vec<(usize, Vec<Instance>)> = self.model_batches.iter()
+            .map(|(key, batch)| (batch.instanced_model, groups.remove(key.as_str()).unwrap_or_default()))
+            .collect();
+        for (index, batch_instances) in batches {
+            self.set_instances(index, &batch_instances);
+        }
     }
     
     /// Render the scene with the current camera
     pub fn render_scene(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let frame = self.surface
-            .get_current_texture()
+        let frame = self.acquire_frame()?;
+        let output = self.output_view(frame.as_ref());
+        
+        // The camera sees the whole surface
+        self.fit_scene_targets(self.config.width, self.config.height);
+        self.render_view(0, output, None);
+        if let Some(frame) = frame {
+            frame.present();
+        }
+
+        Ok(())
+    }
+    
+    /// Render the scene once per view, in order, from the view's camera into its target.
+    /// Views drawing into render targets that later views sample should come first. Each
+    /// view is drawn at its own size, so its camera's aspect ratio should match it. Scene
+    /// targets are created once for every distinct view size and kept until the window is
+    /// resized, so views of different sizes alternate without recreating them. The sun and
+    /// its shadow settings are the same for every view.
+    pub fn render_views(
+        &mut self,
+        views: &[View],
+        sun_light: &SunLight,
+        shadow_settings: &ShadowSettings,
+    ) -> Result<(), wgpu::SurfaceError> {
+        // Only take a swapchain image when some view draws into it
+        let draws_to_surface = views.iter().any(|view| matches!(view.target, ViewTarget::Surface(_)));
+        let frame = if draws_to_surface { self.acquire_frame()? } else { None };
+        
+        // Each view is culled against the depth pyramid of its own previous frame
+        if let Some(gpu_culling) = &mut self.gpu_culling {
+            gpu_culling.retain_views(views.len());
+        }
+        
+        let mut surface_drawn = false;
+        for (index, view) in views.iter().enumerate() {
+            match view.target {
+                ViewTarget::Texture(target) => {
+                    self.fit_scene_targets(target.width(), target.height());
+                    self.set_camera(view.camera, sun_light, shadow_settings);
+                    let output = target.texture().texture.create_view(&wgpu::TextureViewDescriptor::default());
+                    self.render_view(index, output, None);
+                }
+                ViewTarget::Surface(viewport) => {
+                    let pixels = viewport.pixels(self.config.width, self.config.height);
+                    let (_, _, width, height) = pixels;
+                    self.fit_scene_targets(width, height);
+                    self.set_camera(view.camera, sun_light, shadow_settings);
+                    
+                    // The first view drawing into part of the surface clears the rest of it
+                    let output = self.output_view(frame.as_ref());
+                    let placement = (!viewport.is_full(self.config.width, self.config.height)).then_some((pixels, !surface_drawn));
+                    self.render_view(index, output, placement);
+                    surface_drawn = true;
+                }
+            }
+        }
+        
+        if let Some(frame) = frame {
+            frame.present();
+        }
+        Ok(())
+    }
+    
+    /// Point everything that follows the camera at it: the meshes, the batched models and
+    /// the debug grid, the shadow cascades, the light clusters, culling, ambient occlusion,
+    /// the environment and the sky. Lit meshes are drawn from the last camera set here rather than with
+    /// the view-projection in their own transforms.
+    pub fn set_camera(&mut self, camera: &Camera, sun_light: &SunLight, shadow_settings: &ShadowSettings) {
+        self.view_buffer.update_camera(&self.queue, camera);
+        self.update_grid_transform(camera.get_view_projection_matrix());
+        self.update_batch_transforms(camera.get_view_projection_matrix());
+        self.update_shadows(sun_light, shadow_settings, camera);
+        self.update_light_clusters(camera);
+        self.update_culling(camera);
+        self.update_ambient_occlusion(camera);
+        self.update_environment(camera);
+        self.update_sky(camera, sun_light);
+    }
+    
+    #[allow(dead_code)]
+    /// Create an offscreen target of the given size that cameras can render into and
+    /// materials can sample
+    pub fn create_render_target(&self, width: u32, height: u32, label: &str) -> RenderTarget {
+        RenderTarget::new(&self.device, &self.config, width, height, label)
+    }
+    
+    /// Takes the next swapchain image, or none when headless
+    fn acquire_frame(&mut self) -> Result<Option<wgpu::SurfaceTexture>, wgpu::SurfaceError> {
+        self.surface
+            .as_ref()
+            .map(|surface| surface.get_current_texture())
+            .transpose()
             .map_err(|e| {
                 // If we lost the surface, try to reconfigure it
                 if e == wgpu::SurfaceError::Lost {
                     self.resize(self.config.width, self.config.height);
                 }
                 e
-            })?;
-
-        let view = frame.texture.create_view(&wgpu::TextureViewDescriptor::default());
+            })
+    }
+    
+    /// The swapchain image, or the offscreen target when headless
+    fn output_view(&self, frame: Option<&wgpu::SurfaceTexture>) -> wgpu::TextureView {
+        match frame {
+            Some(frame) => frame.texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            None => self.offscreen_target
+                .as_ref()
+                .expect("Headless renderer should own an offscreen target")
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor::default()),
+        }
+    }
+    
+    /// Render the scene at the size of the scene targets into `output`, or with a
+    /// `placement` into the pixel rectangle of `output` it gives, clearing the rest of
+    /// `output` first when its flag is set. `view` is the index of the view in the frame,
+    /// whose depth pyramid from the previous frame GPU culling tests against.
+    fn render_view(&mut self, view: usize, output: wgpu::TextureView, placement: Option<((u32, u32, u32, u32), bool)>) {
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
         });
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
-            });
+        // Upload the scene lights if any were added, removed or changed
+        if self.lights.take_changed() {
+            self.light_buffers.update(&self.device, &self.queue, &self.lights, &self.environment, &self.ambient_occlusion, &self.view_buffer);
+        }
+
+        // Rebuild the GPU culling objects if models or instances changed
+        let gpu_culling_enabled = self.gpu_culling.is_some() && self.gpu_culling_enabled;
+        if let Some(gpu_culling) = self.gpu_culling.as_mut().filter(|_| gpu_culling_enabled && self.culling_scene_changed) {
+            gpu_culling.rebuild(&self.device, &self.queue, &self.models, &self.instanced_models);
+            self.culling_scene_changed = false;
+        }
+        if let Some(gpu_culling) = self.gpu_culling.as_mut().filter(|_| gpu_culling_enabled) {
+            // The GL backend cannot sample a multisampled depth buffer, see `create_scene_targets`
+            let depth_sampled = self.depth_texture.texture.usage().contains(wgpu::TextureUsages::TEXTURE_BINDING);
+            let depth_texture = depth_sampled.then_some(&self.depth_texture);
+            gpu_culling.select_view(&self.device, view, depth_texture, self.scene_config.width, self.scene_config.height);
+        }
 
-            render_pass.set_pipeline(&self.render_pipeline);
+        // Describe the frame's passes and what they read and write, then record them
+        let graph = self.build_frame_graph(output, placement, gpu_culling_enabled);
+        // Custom passes using resources of other graphs were left out as they were added
+        let schedule = graph.compile().expect("Frame graph passes should only use the frame's resources");
+        self.transient_textures.allocate(&self.device, &schedule);
+        let transient_textures = std::mem::take(&mut self.transient_textures);
+        self.frame_passes = graph.execute(&schedule, &mut encoder, &transient_textures, self);
+        self.transient_textures = transient_textures;
 
-            // Draw all models
-            for model in self.models.iter() {
-                model.draw(&mut render_pass);
+        // Submit the work, so the camera of the next view can be uploaded, then fetch the
+        // GPU culling counts once it is done
+        self.queue.submit(std::iter::once(encoder.finish()));
+        if let Some(gpu_culling) = self.gpu_culling.as_mut().filter(|_| gpu_culling_enabled) {
+            gpu_culling.request_stats();
+        }
+    }
+    
+    /// Builds the graph of the frame's passes into `output`, with the custom passes at their
+    /// stages. With a `placement` the frame is drawn into an image of the scene's size and
+    /// copied into its rectangle of `output` at the end.
+    fn build_frame_graph(
+        &mut self,
+        output: wgpu::TextureView,
+        placement: Option<((u32, u32, u32, u32), bool)>,
+        gpu_culling_enabled: bool,
+    ) -> RenderGraph<'static, Renderer> {
+        let mut graph = RenderGraph::new();
+        let default_view = wgpu::TextureViewDescriptor::default();
+        let (output, surface) = match placement {
+            Some(_) => {
+                let desc = TransientTextureDesc::new(self.scene_config.width, self.scene_config.height, self.config.format);
+                (graph.create_texture("view output", desc), Some(graph.import_texture("surface", output)))
             }
+            None => (graph.import_texture("output", output), None),
+        };
+        let frame = FrameResources {
+            output,
+            hdr: graph.import_texture("hdr", self.tonemapper.hdr_texture().texture.create_view(&default_view)),
+            depth: graph.import_texture("depth", self.depth_texture.texture.create_view(&default_view)),
+            shadow_map: graph.import("shadow map"),
+            ambient_occlusion: graph.import("ambient occlusion"),
+            width: self.scene_config.width,
+            height: self.scene_config.height,
+            output_format: self.config.format,
+            sample_count: self.sample_count,
+        };
+        let light_clusters = graph.import("light clusters");
+        let visible_draws = graph.import("visible draws");
+        let scene_inputs = [visible_draws, light_clusters, frame.shadow_map, frame.ambient_occlusion];
+
+        // Bin the lights into clusters for the lit passes
+        graph
+            .add_pass("light culling", |renderer: &mut Renderer, context| renderer.light_buffers.cull(context.encoder))
+            .write(light_clusters);
+        
+        // Cull the meshes into indirect draws on the GPU, against the depth of the view's previous frame
+        if gpu_culling_enabled {
+            graph
+                .add_pass("gpu culling", |renderer: &mut Renderer, context| {
+                    let Renderer { gpu_culling, device, queue, culling_view_proj, lods, .. } = renderer;
+                    if let Some(gpu_culling) = gpu_culling {
+                        gpu_culling.cull(device, queue, context.encoder, *culling_view_proj, lods);
+                    }
+                })
+                .write(visible_draws);
         }
 
-        // Submit the work
-        self.queue.submit(std::iter::once(encoder.finish()));
-        frame.present();
+        // Render shadow casters from the sun's point of view
+        graph
+            .add_pass("shadows", |renderer: &mut Renderer, context| {
+                renderer.shadow_map.render(context.encoder, &renderer.models, &renderer.instanced_models, &renderer.single_instance);
+            })
+            .write(frame.shadow_map);
+        
+        // Compute and blur the ambient occlusion the lit passes read
+        graph
+            .add_pass("ambient occlusion", |renderer: &mut Renderer, context| {
+                renderer.ambient_occlusion.render(context.encoder, &renderer.models, &renderer.instanced_models, &renderer.single_instance);
+            })
+            .write(frame.ambient_occlusion);
+        
+        self.add_custom_passes(&mut graph, PassStage::BeforeScene, &frame);
 
-        Ok(())
+        let (hdr, depth) = (frame.hdr, frame.depth);
+        if self.deferred.is_some() {
+            let gbuffer = graph.import("g-buffer");
+            
+            // Fill the G-buffer with the opaque meshes
+            graph
+                .add_pass("g-buffer", move |renderer: &mut Renderer, context| {
+                    let renderer = &*renderer;
+                    let deferred = renderer.deferred.as_ref().expect("Deferred path should have a G-buffer");
+                    let mut stats = CullingStats::default();
+                    {
+                        let mut render_pass = deferred.begin_geometry_pass(context.encoder, context.texture_view(depth));
+                        render_pass.set_bind_group(1, renderer.shadow_map.bind_group(), &[]);
+                        render_pass.set_bind_group(2, renderer.light_buffers.bind_group(), &[]);
+                        renderer.draw_meshes(&mut render_pass, MaterialPass::Opaque, &mut stats);
+                    }
+                    renderer.culling_stats.set(stats);
+                })
+                .read(visible_draws)
+                .write(gbuffer)
+                .write(depth);
+            
+            // Light the G-buffer into the HDR target
+            graph
+                .add_pass("deferred lighting", move |renderer: &mut Renderer, context| {
+                    let deferred = renderer.deferred.as_ref().expect("Deferred path should have a G-buffer");
+                    deferred.render_lighting(
+                        context.encoder,
+                        context.texture_view(hdr),
+                        CLEAR_COLOR,
+                        renderer.shadow_map.bind_group(),
+                        renderer.light_buffers.bind_group(),
+                    );
+                })
+                .read(gbuffer)
+                .read(depth)
+                .read(light_clusters)
+                .read(frame.shadow_map)
+                .read(frame.ambient_occlusion)
+                .write(hdr);
+            
+            // Fill the unlit background with the sky, then draw the debug grid
+            graph
+                .add_pass("sky", move |renderer: &mut Renderer, context| {
+                    let renderer = &*renderer;
+                    let (hdr, depth) = (context.texture_view(hdr), context.texture_view(depth));
+                    let mut render_pass = renderer.begin_scene_pass(context.encoder, "Sky Pass", hdr, depth, false);
+                    renderer.draw_background(&mut render_pass);
+                })
+                .read(depth)
+                .write(hdr);
+        } else {
+            // Draw and light the opaque meshes, then the sky and the debug grid
+            let mut pass = graph.add_pass("scene", move |renderer: &mut Renderer, context| {
+                let renderer = &*renderer;
+                let mut stats = CullingStats::default();
+                {
+                    let (hdr, depth) = (context.texture_view(hdr), context.texture_view(depth));
+                    let mut render_pass = renderer.begin_scene_pass(context.encoder, "Render Pass", hdr, depth, true);
+                    render_pass.set_pipeline(&renderer.render_pipeline);
+                    render_pass.set_bind_group(1, renderer.shadow_map.bind_group(), &[]);
+                    render_pass.set_bind_group(2, renderer.light_buffers.bind_group(), &[]);
+                    renderer.draw_meshes(&mut render_pass, MaterialPass::Opaque, &mut stats);
+                    renderer.draw_background(&mut render_pass);
+                }
+                renderer.culling_stats.set(stats);
+            });
+            for input in scene_inputs {
+                pass.read(input);
+            }
+            pass.write(hdr).write(depth);
+        }
+        
+        // Keep the opaque depth of this view as the pyramid its next frame is culled against
+        if gpu_culling_enabled {
+            let depth_pyramid = graph.import("depth pyramid");
+            graph
+                .add_pass("depth pyramid", |renderer: &mut Renderer, context| {
+                    let Renderer { gpu_culling, culling_view_proj, .. } = renderer;
+                    if let Some(gpu_culling) = gpu_culling {
+                        gpu_culling.build_depth_pyramid(context.encoder, *culling_view_proj);
+                    }
+                })
+                .read(depth)
+                .write(depth_pyramid);
+        }
+
+        // Blend the transparent meshes over the lit scene, skipped while there are none
+        let has_transparent_meshes = self.models
+            .iter()
+            .chain(self.instanced_models.iter().map(|model| &model.model))
+            .any(|model| (0..model.meshes.len()).any(|index| model.mesh_in_pass(index, MaterialPass::Transparent)));
+        if has_transparent_meshes {
+            self.add_transparency_passes(&mut graph, &frame, scene_inputs);
+        }
+        
+        self.add_custom_passes(&mut graph, PassStage::AfterScene, &frame);
+
+        // Expose and tonemap the HDR scene into the output, with post effects around it
+        let output = frame.output;
+        graph
+            .add_pass("post-process", move |renderer: &mut Renderer, context| {
+                renderer.post_process.render(context.encoder, &renderer.tonemapper, context.texture_view(output));
+            })
+            .read(hdr)
+            .write(output);
+        
+        // Replace the image with the raw ambient occlusion when its debug view is on
+        if self.ambient_occlusion.debug_view() {
+            graph
+                .add_pass("ambient occlusion debug", move |renderer: &mut Renderer, context| {
+                    renderer.ambient_occlusion.render_debug(context.encoder, context.texture_view(output));
+                })
+                .read(frame.ambient_occlusion)
+                .write(output);
+        }
+        
+        self.add_custom_passes(&mut graph, PassStage::Overlay, &frame);
+        
+        // Copy the view into its rectangle of the surface
+        if let (Some(surface), Some((pixels, clear))) = (surface, placement) {
+            graph
+                .add_pass("viewport blit", move |renderer: &mut Renderer, context| {
+                    renderer.viewport_blit.render(
+                        &renderer.device,
+                        context.encoder,
+                        context.texture_view(output),
+                        context.texture_view(surface),
+                        pixels,
+                        clear,
+                    );
+                })
+                .read(output)
+                .write(surface);
+        }
+        graph
+    }
+    
+    /// Adds the passes drawing the alpha-blended and additive meshes over the scene, tested
+    /// against its depth
+    fn add_transparency_passes(&self, graph: &mut RenderGraph<'static, Renderer>, frame: &FrameResources, scene_inputs: [ResourceId; 4]) {
+        let (hdr, depth) = (frame.hdr, frame.depth);
+        match self.transparency_mode {
+            TransparencyMode::Sorted => {
+                // Blend each mesh over what is behind it, the farthest first
+                let mut pass = graph.add_pass("transparent", move |renderer: &mut Renderer, context| {
+                    let renderer = &*renderer;
+                    let mut stats = renderer.culling_stats.get();
+                    {
+                        let (hdr, depth) = (context.texture_view(hdr), context.texture_view(depth));
+                        let mut render_pass = renderer.begin_scene_pass(context.encoder, "Transparent Pass", hdr, depth, false);
+                        renderer.draw_sorted(
+                            &mut render_pass,
+                            MaterialPass::Transparent,
+                            |mode| renderer.transparency.blend_pipeline(mode),
+                            &mut stats,
+                        );
+                    }
+                    renderer.culling_stats.set(stats);
+                });
+                for input in scene_inputs {
+                    pass.read(input);
+                }
+                pass.read(depth).write(hdr);
+            }
+            TransparencyMode::WeightedBlended => {
+                // Resolved targets the composite reads, rendered through multisampled ones with MSAA
+                let [accumulation_desc, weight_desc] = TransparencyPipelines::target_descs(frame.width, frame.height);
+                let accumulation = graph.create_texture("oit accumulation", accumulation_desc);
+                let weight = graph.create_texture("oit weight", weight_desc);
+                let samples = (frame.sample_count > 1).then(|| {
+                    let [accumulation_desc, weight_desc] =
+                        TransparencyPipelines::multisampled_target_descs(frame.width, frame.height, frame.sample_count);
+                    (
+                        graph.create_texture("oit accumulation samples", accumulation_desc),
+                        graph.create_texture("oit weight samples", weight_desc),
+                    )
+                });
+                
+                // Sum the weighted alpha-blended meshes in any order
+                let mut pass = graph.add_pass("transparent accumulation", move |renderer: &mut Renderer, context| {
+                    let renderer = &*renderer;
+                    let mut stats = renderer.culling_stats.get();
+                    {
+                        let (accumulation, weight) = (context.texture_view(accumulation), context.texture_view(weight));
+                        let target = |view, resolve_target, clear| Some(wgpu::RenderPassColorAttachment {
+                            view,
+                            resolve_target,
+                            ops: wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(clear),
+                                store: true,
+                            },
+                        });
+                        let color_attachments = match samples {
+                            Some((accumulation_samples, weight_samples)) => [
+                                target(context.texture_view(accumulation_samples), Some(accumulation), ACCUMULATION_CLEAR),
+                                target(context.texture_view(weight_samples), Some(weight), WEIGHT_CLEAR),
+                            ],
+                            None => [
+                                target(accumulation, None, ACCUMULATION_CLEAR),
+                                target(weight, None, WEIGHT_CLEAR),
+                            ],
+                        };
+                        let mut render_pass = context.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                            label: Some("OIT Accumulation Pass"),
+                            color_attachments: &color_attachments,
+                            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                                view: context.texture_view(depth),
+                                depth_ops: Some(wgpu::Operations {
+                                    load: wgpu::LoadOp::Load,
+                                    store: true,
+                                }),
+                                stencil_ops: None,
+                            }),
+                        });
+                        renderer.draw_sorted(
+                            &mut render_pass,
+                            MaterialPass::AlphaBlend,
+                            |_| renderer.transparency.accumulation_pipeline(),
+                            &mut stats,
+                        );
+                    }
+                    renderer.culling_stats.set(stats);
+                });
+                for input in scene_inputs {
+                    pass.read(input);
+                }
+                pass.read(depth).write(accumulation).write(weight);
+                if let Some((accumulation_samples, weight_samples)) = samples {
+                    pass.write(accumulation_samples).write(weight_samples);
+                }
+                
+                // Blend their average over the scene, then add the additive meshes
+                let mut pass = graph.add_pass("transparent composite", move |renderer: &mut Renderer, context| {
+                    let renderer = &*renderer;
+                    let bind_group = renderer.transparency.composite_bind_group(
+                        &renderer.device,
+                        context.texture_view(accumulation),
+                        context.texture_view(weight),
+                    );
+                    let mut stats = renderer.culling_stats.get();
+                    {
+                        let (hdr, depth) = (context.texture_view(hdr), context.texture_view(depth));
+                        let mut render_pass = renderer.begin_scene_pass(context.encoder, "OIT Composite Pass", hdr, depth, false);
+                        renderer.transparency.composite(&mut render_pass, &bind_group);
+                        renderer.draw_sorted(
+                            &mut render_pass,
+                            MaterialPass::Additive,
+                            |mode| renderer.transparency.blend_pipeline(mode),
+                            &mut stats,
+                        );
+                    }
+                    renderer.culling_stats.set(stats);
+                });
+                for input in scene_inputs {
+                    pass.read(input);
+                }
+                pass.read(accumulation).read(weight).read(depth).write(hdr);
+            }
+        }
+    }
+    
+    /// Adds the custom passes of `stage` to the frame's graph. Passes declaring resources
+    /// kept from an earlier frame's graph are left out of this frame.
+    fn add_custom_passes(&mut self, graph: &mut RenderGraph<'static, Renderer>, stage: PassStage, frame: &FrameResources) {
+        for (index, pass) in self.custom_passes.iter_mut().enumerate() {
+            if pass.stage() != stage {
+                continue;
+            }
+            let mut builder = graph.add_pass(pass.name(), move |renderer: &mut Renderer, context| {
+                let Renderer { custom_passes, device, queue, .. } = renderer;
+                custom_passes[index].execute(device, queue, context);
+            });
+            pass.setup(&mut builder, frame);
+            if let Err(e) = graph.check_last_pass() {
+                eprintln!("Skipping custom pass for this frame: {:#}", e);
+                graph.remove_last_pass();
+            }
+        }
+    }
+    
+    /// Add a pass to every frame at `pass.stage()`, after the passes of that stage added before it
+    pub fn add_custom_pass(&mut self, pass: impl CustomPass + 'static) {
+        self.custom_passes.push(Box::new(pass));
+    }
+    
+    #[allow(dead_code)]
+    /// Returns the names of the passes the last frame ran, in order
+    pub fn frame_passes(&self) -> &[String] {
+        &self.frame_passes
+    }
+    
+    /// Begin a render pass on the scene color and depth, resolving the samples into the HDR
+    /// target with MSAA. Clears both targets, or keeps what earlier passes drew.
+    fn begin_scene_pass<'e>(
+        &'e self,
+        encoder: &'e mut wgpu::CommandEncoder,
+        label: &str,
+        hdr: &'e wgpu::TextureView,
+        depth: &'e wgpu::TextureView,
+        clear: bool,
+    ) -> wgpu::RenderPass<'e> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                // With MSAA the samples are resolved into the HDR target at the end of the pass
+                view: self.multisampled_framebuffer.as_ref().unwrap_or(hdr),
+                resolve_target: self.multisampled_framebuffer.as_ref().map(|_| hdr),
+                ops: wgpu::Operations {
+                    load: if clear { wgpu::LoadOp::Clear(CLEAR_COLOR) } else { wgpu::LoadOp::Load },
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: if clear { wgpu::LoadOp::Clear(1.0) } else { wgpu::LoadOp::Load },
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        })
+    }
+    
+    /// Fill the background with the sky where nothing was drawn, then draw the debug grid on top
+    fn draw_background<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        self.sky.render(render_pass, &self.environment);
+        
+        // Draw the debug grid and axis gizmos on top of the scene
+        if self.show_debug_grid {
+            if let (Some(grid_pipeline), Some(grid_model)) = (&self.grid_pipeline, &self.grid_model) {
+                render_pass.set_pipeline(grid_pipeline);
+                grid_model.draw_geometry(render_pass);
+            }
+        }
+    }
+    
+    /// Draw the meshes in `pass` that lie inside the camera frustum back to front by the
+    /// distance of their bounds' centers to the culling camera, each with the pipeline
+    /// `pipeline` returns for its blend mode. Instanced batches sort as a whole. Culled on
+    /// the CPU even with GPU culling on, since the indirect draws cannot be reordered.
+    fn draw_sorted<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pass: MaterialPass,
+        pipeline: impl Fn(BlendMode) -> &'a wgpu::RenderPipeline,
+        stats: &mut CullingStats,
+    ) {
+        enum Draw<'m> {
+            Model(&'m Model, usize),
+            Instanced(&'m InstancedModel, usize),
+        }
+        let mut draws: Vec<(f32, Draw)> = Vec::new();
+        let distance = |center: Point3<f32>| self.lods.view().map_or(0.0, |view| view.position.distance2(center));
+        for model in self.models.iter() {
+            for index in (0..model.meshes.len()).filter(|&index| model.mesh_in_pass(index, pass)) {
+                draws.push((distance(model.mesh_center(index)), Draw::Model(model, index)));
+            }
+        }
+        for model in self.instanced_models.iter() {
+            for index in (0..model.model.meshes.len()).filter(|&index| model.model.mesh_in_pass(index, pass)) {
+                draws.push((distance(model.mesh_center(index)), Draw::Instanced(model, index)));
+            }
+        }
+        // Stable, so without a camera the meshes keep the order they were added in
+        draws.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        
+        render_pass.set_bind_group(1, self.shadow_map.bind_group(), &[]);
+        render_pass.set_bind_group(2, self.light_buffers.bind_group(), &[]);
+        let frustum = self.culling_view_proj.map(Frustum::from_matrix).unwrap_or(Frustum::INFINITE);
+        for (_, draw) in draws {
+            match draw {
+                Draw::Model(model, index) => {
+                    render_pass.set_pipeline(pipeline(model.mesh_blend_mode(index)));
+                    render_pass.set_bind_group(0, &model.bind_group, &[]);
+                    render_pass.set_vertex_buffer(1, self.single_instance.slice());
+                    model.draw_mesh_culled(render_pass, index, &frustum, &self.lods, stats);
+                }
+                Draw::Instanced(model, index) => {
+                    render_pass.set_pipeline(pipeline(model.model.mesh_blend_mode(index)));
+                    model.draw_mesh_culled(render_pass, index, &frustum, &self.lods, stats);
+                }
+            }
+        }
+    }
+    
+    /// Draw the meshes in `pass` with the pipeline already set, either those that survived GPU
+    /// culling or those inside the camera frustum, counting the latter in `stats`
+    fn draw_meshes<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, pass: MaterialPass, stats: &mut CullingStats) {
+        match &self.gpu_culling {
+            // Draw what survived GPU culling
+            Some(gpu_culling) if self.gpu_culling_enabled => {
+                gpu_culling.draw(render_pass, pass, &self.models, &self.instanced_models);
+            }
+            // Draw the meshes inside the camera frustum, plain models as a single identity instance
+            _ => {
+                let frustum = self.culling_view_proj.map(Frustum::from_matrix).unwrap_or(Frustum::INFINITE);
+                render_pass.set_vertex_buffer(1, self.single_instance.slice());
+                for model in self.models.iter() {
+                    model.draw_culled(render_pass, pass, &frustum, &self.lods, stats);
+                }
+                for model in self.instanced_models.iter() {
+                    model.draw_culled(render_pass, pass, &frustum, &self.lods, stats);
+                }
+            }
+        }
+    }
+    
+    #[allow(dead_code)]
+    /// Returns true if the renderer draws into an offscreen target instead of a window
+    pub fn is_headless(&self) -> bool {
+        self.surface.is_none()
+    }
+    
+    #[allow(dead_code)]
+    /// Copies the offscreen color target back to the CPU as an RGBA image.
+    ///
+    /// Only available on headless renderers; the swapchain image cannot be read back.
+    pub fn read_pixels(&self) -> anyhow::Result<image::RgbaImage> {
+        let target = self.offscreen_target
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("read_pixels requires a headless renderer"))?;
+        
+        let width = self.config.width;
+        let height = self.config.height;
+        
+        // Rows in the staging buffer must be padded to the copy alignment
+        let unpadded_bytes_per_row = 4 * width;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+        
+        let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &target.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &staging_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+        
+        // Wait for the copy to finish and map the buffer
+        let slice = staging_buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+        
+        // Strip the row padding
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+            }
+        }
+        staging_buffer.unmap();
+        
+        image::RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| anyhow::anyhow!("Readback size does not match {}x{}", width, height))
+    }
+    
+    #[allow(dead_code)]
+    /// Renders the scene and reads the result back as an RGBA image
+    pub fn capture_frame(&mut self) -> anyhow::Result<image::RgbaImage> {
+        self.render_scene()?;
+        self.read_pixels()
     }
     
     /// Initialize grid and axis gizmos (helper objects)
@@ -500,6 +2096,7 @@ impl Renderer {
                 normal: [0.0, 1.0, 0.0],
                 tex_coords: [0.0, 0.0],
                 color,
+                tangent: [1.0, 0.0, 0.0, 1.0],
             });
             
             vertices.push(Vertex {
@@ -507,6 +2104,7 @@ impl Renderer {
                 normal: [0.0, 1.0, 0.0],
                 tex_coords: [1.0, 0.0],
                 color,
+                tangent: [1.0, 0.0, 0.0, 1.0],
             });
             
             indices.push(start_idx);
@@ -530,6 +2128,7 @@ impl Renderer {
                 normal: [0.0, 1.0, 0.0],
                 tex_coords: [0.0, 0.0],
                 color,
+                tangent: [1.0, 0.0, 0.0, 1.0],
             });
             
             vertices.push(Vertex {
@@ -537,6 +2136,7 @@ impl Renderer {
                 normal: [0.0, 1.0, 0.0],
                 tex_coords: [1.0, 0.0],
                 color,
+                tangent: [1.0, 0.0, 0.0, 1.0],
             });
             
             indices.push(start_idx);
@@ -551,6 +2151,7 @@ impl Renderer {
             normal: [1.0, 0.0, 0.0],
             tex_coords: [0.0, 0.0],
             color: [1.0, 0.0, 0.0], // Red for X
+            tangent: [1.0, 0.0, 0.0, 1.0],
         });
         
         vertices.push(Vertex {
@@ -558,6 +2159,7 @@ impl Renderer {
             normal: [1.0, 0.0, 0.0],
             tex_coords: [1.0, 0.0],
             color: [1.0, 0.0, 0.0], // Red for X
+            tangent: [1.0, 0.0, 0.0, 1.0],
         });
         
         indices.push(start_idx);
@@ -570,6 +2172,7 @@ impl Renderer {
             normal: [0.0, 1.0, 0.0],
             tex_coords: [0.0, 0.0],
             color: [0.0, 1.0, 0.0], // Green for Y
+            tangent: [1.0, 0.0, 0.0, 1.0],
         });
         
         vertices.push(Vertex {
@@ -577,6 +2180,7 @@ impl Renderer {
             normal: [0.0, 1.0, 0.0],
             tex_coords: [1.0, 0.0],
             color: [0.0, 1.0, 0.0], // Green for Y
+            tangent: [1.0, 0.0, 0.0, 1.0],
         });
         
         indices.push(start_idx);
@@ -589,6 +2193,7 @@ impl Renderer {
             normal: [0.0, 0.0, 1.0],
             tex_coords: [0.0, 0.0],
             color: [0.0, 0.0, 1.0], // Blue for Z
+            tangent: [1.0, 0.0, 0.0, 1.0],
         });
         
         vertices.push(Vertex {
@@ -596,6 +2201,7 @@ impl Renderer {
             normal: [0.0, 0.0, 1.0],
             tex_coords: [1.0, 0.0],
             color: [0.0, 0.0, 1.0], // Blue for Z
+            tangent: [1.0, 0.0, 0.0, 1.0],
         });
         
         indices.push(start_idx);
@@ -639,51 +2245,340 @@ impl Renderer {
             }
         );
         
-        // Create a white texture for the grid material
-        let white_texture = texture::Texture::create_colored_texture(
-            self.device(),
-            self.queue(),
-            [1.0, 1.0, 1.0],
-            "Grid Material Texture"
+        // Create the grid model
+        let grid_model = Model::with_meshes(
+            vec![grid_mesh],
+            Vec::new(),
+            create_object_bind_group(
+                self.device(),
+                &self.bind_group_layout,
+                &uniform_buffer,
+                &light_buffer,
+                "Grid Bind Group",
+            ),
         );
         
-        // Create bind group
-        let bind_group = self.device().create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Grid Bind Group"),
-            layout: &self.bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: uniform_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&white_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::Sampler(&white_texture.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: light_buffer.as_entire_binding(),
-                },
-            ],
+        // Store the grid model separately
+        self.grid_model = Some(grid_model);
+        self.grid_uniform_buffer = Some(uniform_buffer);
+    }
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::test_support::{compare_images, create_light_buffer, create_uniform_buffer, headless_renderer};
+    use crate::engine::scene::{Camera, SunController, SunLight};
+    use crate::engine::core::application::Uniforms;
+    use crate::engine::resources::ModelLoader;
+    use cgmath::{Matrix4, Point3, Rad, Vector3};
+    use image::RgbaImage;
+
+    #[test]
+    fn headless_frame_matches_target_size_and_clear_color() {
+        let mut renderer = headless_renderer(64, 64);
+        assert!(renderer.is_headless());
+
+        let frame = renderer.capture_frame().unwrap();
+        assert_eq!(frame.dimensions(), (64, 64));
+        // Clear color (0.1, 0.2, 0.3) through the default ACES tonemap, encoded as sRGB
+        assert_eq!(frame.get_pixel(32, 32).0, [60, 98, 124, 255]);
+    }
+
+    #[test]
+    fn headless_renders_cube_obj() {
+        let mut renderer = headless_renderer(64, 64);
+
+        let mut camera = Camera::new(Point3::new(0.0, 0.0, 6.0), Rad(-std::f32::consts::FRAC_PI_2), Rad(0.0));
+        camera.set_aspect_ratio(64, 64);
+        let uniforms = Uniforms {
+            view_proj: camera.get_view_projection_matrix().into(),
+            model: cgmath::Matrix4::identity().into(),
+        };
+        let uniform_buffer = renderer.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Test Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
-        
-        // Create material instance
-        let material = crate::engine::scene::model::MaterialInstance {
-            material_id: 0,
-            diffuse_bind_group: bind_group,
+        let light_buffer = renderer.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Test Light Buffer"),
+            contents: bytemuck::cast_slice(&[SunLight::new()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let model = {
+            let loader = ModelLoader::new(
+                renderer.device(),
+                renderer.queue(),
+                renderer.bind_group_layout(),
+                renderer.material_bind_group_layout(),
+            );
+            pollster::block_on(loader.load_obj(
+                std::path::Path::new("models/cube/cube.obj"),
+                &uniform_buffer,
+                Some(&light_buffer),
+            )).unwrap()
         };
-        
-        // Create the grid model
-        let grid_model = Model {
-            meshes: vec![grid_mesh],
-            materials: vec![material],
+        assert!(model.meshes.iter().any(|mesh| mesh.num_indices > 0));
+        renderer.add_model(model);
+
+        let frame = renderer.capture_frame().unwrap();
+        // The cube covers the center of the frame, the corners still show the clear color
+        assert_ne!(frame.get_pixel(32, 32).0, [60, 98, 124, 255]);
+        assert_eq!(frame.get_pixel(0, 0).0, [60, 98, 124, 255]);
+    }
+
+    #[test]
+    fn meshes_outside_the_frustum_are_culled() {
+        let mut renderer = headless_renderer(64, 64);
+        renderer.set_gpu_culling_enabled(false);
+
+        let mut camera = Camera::new(Point3::new(0.0, 0.0, 6.0), Rad(-std::f32::consts::FRAC_PI_2), Rad(0.0));
+        camera.set_aspect_ratio(64, 64);
+        let light_buffer = renderer.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Test Light Buffer"),
+            contents: bytemuck::cast_slice(&[SunLight::new()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // One cube in front of the camera and one behind it, plus a batch entirely off to the side
+        for (x, z) in [(0.0, 0.0), (0.0, 12.0)] {
+            let transform = cgmath::Matrix4::from_translation(cgmath::Vector3::new(x, 0.0, z));
+            let uniform_buffer = renderer.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Test Uniform Buffer"),
+                contents: bytemuck::cast_slice(&[Uniforms {
+                    view_proj: camera.get_view_projection_matrix().into(),
+                    model: transform.into(),
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+            let mesh = Mesh::create_cube(renderer.device(), 1.0);
+            let mut model = renderer.create_solid_model(mesh, [1.0; 3], &uniform_buffer, &light_buffer, "Test Cube");
+            model.set_transform(renderer.queue(), &uniform_buffer, transform);
+            renderer.add_model(model);
+        }
+        let uniform_buffer = renderer.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Test Instanced Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[Uniforms {
+                view_proj: camera.get_view_projection_matrix().into(),
+                model: cgmath::Matrix4::identity().into(),
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let mesh = Mesh::create_cube(renderer.device(), 1.0);
+        let model = renderer.create_solid_model(mesh, [1.0; 3], &uniform_buffer, &light_buffer, "Test Batch");
+        let instances = [20.0, 25.0].map(|x| Instance::new(cgmath::Matrix4::from_translation(cgmath::Vector3::new(x, 0.0, 0.0))));
+        renderer.add_instanced_model(model, &instances);
+
+        // Nothing is culled until the renderer knows the camera
+        renderer.capture_frame().unwrap();
+        assert_eq!(renderer.culling_stats(), CullingStats { meshes_drawn: 3, meshes_culled: 0 });
+
+        renderer.update_culling(&camera);
+        let frame = renderer.capture_frame().unwrap();
+        assert_eq!(renderer.culling_stats(), CullingStats { meshes_drawn: 1, meshes_culled: 2 });
+        assert_ne!(frame.get_pixel(32, 32).0, [60, 98, 124, 255]);
+    }
+
+    #[test]
+    fn batched_instances_are_grouped_by_their_model() {
+        let mut renderer = headless_renderer(64, 64);
+        let light_buffer = create_light_buffer(&renderer, SunController::new().sun_light);
+        for key in ["cube", "sphere"] {
+            let uniform_buffer = renderer.create_batch_uniform_buffer(key);
+            let mesh = Mesh::create_cube(renderer.device(), 1.0);
+            let model = renderer.create_solid_model(mesh, [1.0; 3], &uniform_buffer, &light_buffer, "Test Batch");
+            renderer.add_model_batch(key, model, uniform_buffer);
+        }
+        assert!(renderer.has_model_batch("cube"));
+        assert!(!renderer.has_model_batch("cone"));
+
+        let at = |x: f32| Instance::new(Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)));
+        renderer.set_batched_instances([("cube", at(0.0)), ("sphere", at(1.0)), ("cone", at(2.0)), ("cube", at(3.0))]);
+        let instances_of = |renderer: &Renderer, key: &str| {
+            renderer.instanced_models[renderer.model_batches[key].instanced_model].instances().to_vec()
         };
-        
-        // Store the grid model separately
-        self.grid_model = Some(grid_model);
+        assert_eq!(instances_of(&renderer, "cube"), [at(0.0), at(3.0)]);
+        assert_eq!(instances_of(&renderer, "sphere"), [at(1.0)]);
+
+        // A batch nothing is given for this frame is not drawn
+        renderer.set_batched_instances([("sphere", at(4.0))]);
+        assert!(instances_of(&renderer, "cube").is_empty());
+        assert_eq!(instances_of(&renderer, "sphere"), [at(4.0)]);
+        renderer.capture_frame().unwrap();
     }
-} 
\ No newline at end of file
+
+    /// Number of distinct colors in an image, which anti-aliasing raises along edges
+    fn distinct_colors(image: &RgbaImage) -> usize {
+        image.pixels().map(|pixel| pixel.0).collect::<std::collections::HashSet<_>>().len()
+    }
+
+    #[test]
+    fn msaa_smooths_grid_edges() {
+        let mut renderer = headless_renderer(128, 128);
+        assert_eq!(renderer.sample_count(), 1);
+        assert!(renderer.set_sample_count(3).is_err());
+        if !renderer.supported_sample_counts().contains(&4) {
+            eprintln!("Skipping MSAA test: 4x MSAA unsupported");
+            return;
+        }
+
+        let mut camera = Camera::new(Point3::new(0.0, 10.0, 10.0), Rad(3.0 * std::f32::consts::PI / 2.0), Rad(-std::f32::consts::PI / 6.0));
+        camera.set_aspect_ratio(128, 128);
+        renderer.update_environment(&camera);
+        renderer.update_culling(&camera);
+        renderer.update_grid_transform(camera.get_view_projection_matrix());
+        let transform = Matrix4::from_translation(Vector3::new(0.0, 1.0, 0.0));
+        let uniform_buffer = create_uniform_buffer(&renderer, &camera, transform);
+        let light_buffer = create_light_buffer(&renderer, SunController::new().sun_light);
+        let mut cube = renderer.create_solid_model(
+            Mesh::create_cube(renderer.device(), 2.0),
+            [1.0, 1.0, 1.0],
+            &uniform_buffer,
+            &light_buffer,
+            "Test MSAA Cube",
+        );
+        cube.set_transform(renderer.queue(), &uniform_buffer, transform);
+        renderer.add_model(cube);
+        let aliased = renderer.capture_frame().unwrap();
+
+        // Culling keeps working with a multisampled depth buffer
+        renderer.set_sample_count(4).unwrap();
+        renderer.capture_frame().unwrap();
+        let smoothed = renderer.capture_frame().unwrap();
+        assert_eq!(renderer.sample_count(), 4);
+        assert_eq!(renderer.culling_stats().meshes_drawn, 1);
+        assert!(distinct_colors(&smoothed) > distinct_colors(&aliased));
+
+        // Going back to a single sample restores the original picture
+        renderer.set_sample_count(1).unwrap();
+        let restored = renderer.capture_frame().unwrap();
+        let comparison = compare_images(&aliased, &restored, 0).unwrap();
+        assert_eq!(comparison.mismatched_pixels, 0);
+    }
+
+    /// Clears the HDR target after the scene is drawn
+    struct ClearHdrPass {
+        color: wgpu::Color,
+        hdr: Option<ResourceId>,
+    }
+
+    impl CustomPass for ClearHdrPass {
+        fn name(&self) -> &str {
+            "clear hdr"
+        }
+
+        fn stage(&self) -> PassStage {
+            PassStage::AfterScene
+        }
+
+        fn setup(&mut self, builder: &mut PassBuilder<'_>, frame: &FrameResources) {
+            builder.write(frame.hdr);
+            self.hdr = Some(frame.hdr);
+        }
+
+        fn execute(&mut self, _device: &wgpu::Device, _queue: &wgpu::Queue, context: &mut PassContext<'_>) {
+            let view = context.texture_view(self.hdr.unwrap());
+            context.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Clear HDR Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.color),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+        }
+    }
+
+    /// Renders into a texture of its own that nothing reads
+    struct UnusedPass;
+
+    impl CustomPass for UnusedPass {
+        fn name(&self) -> &str {
+            "unused"
+        }
+
+        fn stage(&self) -> PassStage {
+            PassStage::BeforeScene
+        }
+
+        fn setup(&mut self, builder: &mut PassBuilder<'_>, frame: &FrameResources) {
+            builder.create_texture("scratch", TransientTextureDesc::new(frame.width, frame.height, wgpu::TextureFormat::Rgba16Float));
+        }
+
+        fn execute(&mut self, _device: &wgpu::Device, _queue: &wgpu::Queue, _context: &mut PassContext<'_>) {
+            panic!("A pass nothing reads should be culled");
+        }
+    }
+
+    /// Writes the HDR target of the first frame it is added to in every later frame
+    struct StalePass {
+        hdr: Option<ResourceId>,
+    }
+
+    impl CustomPass for StalePass {
+        fn name(&self) -> &str {
+            "stale"
+        }
+
+        fn stage(&self) -> PassStage {
+            PassStage::AfterScene
+        }
+
+        fn setup(&mut self, builder: &mut PassBuilder<'_>, frame: &FrameResources) {
+            builder.write(*self.hdr.get_or_insert(frame.hdr));
+        }
+
+        fn execute(&mut self, _device: &wgpu::Device, _queue: &wgpu::Queue, _context: &mut PassContext<'_>) {}
+    }
+
+    #[test]
+    fn custom_passes_keeping_resources_of_an_earlier_frame_are_skipped() {
+        let mut renderer = headless_renderer(64, 64);
+        renderer.add_custom_pass(StalePass { hdr: None });
+        renderer.capture_frame().unwrap();
+        assert!(renderer.frame_passes().contains(&"stale".to_string()));
+
+        // The kept id is in range of the next frame's graph but stands for nothing in it
+        let frame = renderer.capture_frame().unwrap();
+        assert!(!renderer.frame_passes().contains(&"stale".to_string()));
+        assert_eq!(frame.get_pixel(32, 32).0, [60, 98, 124, 255]);
+    }
+
+    #[test]
+    fn custom_passes_run_at_their_stage() {
+        let mut renderer = headless_renderer(128, 128);
+        renderer.set_debug_grid_visible(false);
+        renderer.set_gpu_culling_enabled(false);
+        let mut camera = Camera::new(Point3::new(0.0, 10.0, 10.0), Rad(3.0 * std::f32::consts::PI / 2.0), Rad(-std::f32::consts::PI / 6.0));
+        camera.set_aspect_ratio(128, 128);
+        renderer.update_environment(&camera);
+        let uniform_buffer = create_uniform_buffer(&renderer, &camera, Matrix4::from_translation(Vector3::new(0.0, 1.0, 0.0)));
+        let light_buffer = create_light_buffer(&renderer, SunController::new().sun_light);
+        let cube = renderer.create_solid_model(
+            Mesh::create_cube(renderer.device(), 2.0),
+            [1.0, 1.0, 1.0],
+            &uniform_buffer,
+            &light_buffer,
+            "Test Cube",
+        );
+        renderer.add_model(cube);
+        let scene = renderer.capture_frame().unwrap();
+
+        renderer.add_custom_pass(UnusedPass);
+        renderer.add_custom_pass(ClearHdrPass { color: wgpu::Color::BLACK, hdr: None });
+        let cleared = renderer.capture_frame().unwrap();
+        assert_eq!(
+            renderer.frame_passes(),
+            ["light culling", "shadows", "ambient occlusion", "scene", "clear hdr", "post-process"],
+        );
+
+        // The clear replaces the whole scene before it is tonemapped
+        assert!(compare_images(&cleared, &scene, 0).unwrap().mismatched_pixels > 0);
+        assert!(cleared.pixels().all(|pixel| pixel.0 == [0, 0, 0, 255]));
+    }
+}
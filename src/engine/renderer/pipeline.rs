@@ -94,10 +94,12 @@ pub fn create_pbr_pipeline(
     shader: wgpu::ShaderModule,
     format: wgpu::TextureFormat,
 ) -> wgpu::RenderPipeline {
-    let mut config = PipelineConfig::default();
-    config.layout = Some(layout);
-    config.shader = Some(shader);
-    config.color_format = format;
+    let config = PipelineConfig {
+        layout: Some(layout),
+        shader: Some(shader),
+        color_format: format,
+        ..Default::default()
+    };
     
     create_render_pipeline(device, &config)
 }
@@ -110,12 +112,14 @@ pub fn create_grid_pipeline(
     shader: wgpu::ShaderModule,
     format: wgpu::TextureFormat,
 ) -> wgpu::RenderPipeline {
-    let mut config = PipelineConfig::default();
-    config.layout = Some(layout);
-    config.shader = Some(shader);
-    config.color_format = format;
-    config.primitive_topology = wgpu::PrimitiveTopology::LineList;
-    config.cull_mode = None;
+    let config = PipelineConfig {
+        layout: Some(layout),
+        shader: Some(shader),
+        color_format: format,
+        primitive_topology: wgpu::PrimitiveTopology::LineList,
+        cull_mode: None,
+        ..Default::default()
+    };
     
     create_render_pipeline(device, &config)
 } 
\ No newline at end of file
@@ -0,0 +1,806 @@
+use anyhow::Result;
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+use crate::engine::renderer::texture::Texture;
+use crate::engine::renderer::tonemap::{Tonemapper, HDR_FORMAT};
+
+/// Most levels the bloom blur goes down, each half the size of the one above
+pub const MAX_BLOOM_LEVELS: u32 = 8;
+
+/// Width, height and depth of the identity color grading LUT
+const IDENTITY_LUT_SIZE: u32 = 16;
+
+/// WGSL structs shared by the bloom and post-process shaders
+pub const POST_PROCESS_TYPES_WGSL: &str = r#"
+struct PostProcessUniforms {
+    bloom_threshold: f32,
+    bloom_knee: f32,
+    bloom_intensity: f32,
+    fxaa_edge_threshold: f32,
+    fxaa_edge_threshold_min: f32,
+    fxaa_subpixel: f32,
+    chromatic_aberration: f32,
+    vignette_intensity: f32,
+    vignette_radius: f32,
+    vignette_smoothness: f32,
+    grading_contribution: f32,
+    lut_size: f32,
+    display_encoded: u32,
+    padding0: u32,
+    padding1: u32,
+    padding2: u32,
+};
+
+struct FullscreenOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// Single triangle covering the whole target
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> FullscreenOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: FullscreenOutput;
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+"#;
+
+/// A step of the post-process chain
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PostEffect {
+    /// Glow around bright areas. Reads the HDR scene, so it always runs before tonemapping.
+    Bloom,
+    /// Fast approximate anti-aliasing of the tonemapped image
+    Fxaa,
+    /// Splits the color channels towards the screen edges
+    ChromaticAberration,
+    /// Darkens the screen edges
+    Vignette,
+    /// Remaps colors through a 3D lookup table
+    ColorGrading,
+}
+
+impl PostEffect {
+    /// Every effect, in the default chain order
+    pub const ALL: [PostEffect; 5] = [
+        PostEffect::Bloom,
+        PostEffect::Fxaa,
+        PostEffect::ChromaticAberration,
+        PostEffect::ColorGrading,
+        PostEffect::Vignette,
+    ];
+
+    /// True for effects applied to the HDR scene before tonemapping
+    pub fn is_hdr(self) -> bool {
+        self == PostEffect::Bloom
+    }
+
+    /// Fragment entry point of effects that run on the tonemapped image
+    fn entry_point(self) -> Option<&'static str> {
+        match self {
+            PostEffect::Bloom => None,
+            PostEffect::Fxaa => Some("fs_fxaa"),
+            PostEffect::ChromaticAberration => Some("fs_chromatic_aberration"),
+            PostEffect::Vignette => Some("fs_vignette"),
+            PostEffect::ColorGrading => Some("fs_color_grading"),
+        }
+    }
+}
+
+/// Settings of the bloom effect
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BloomSettings {
+    pub enabled: bool,
+    pub threshold: f32, // Brightness above which pixels bloom
+    pub knee: f32,      // Width of the soft transition around the threshold
+    pub intensity: f32, // How much of the blurred highlights is added to the scene
+    pub levels: u32,    // Blur levels, up to MAX_BLOOM_LEVELS; more levels spread the glow further
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.3,
+            levels: 5,
+        }
+    }
+}
+
+/// Settings of the FXAA effect
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FxaaSettings {
+    pub enabled: bool,
+    pub edge_threshold: f32,     // Smallest local contrast, relative to the brightest pixel, treated as an edge
+    pub edge_threshold_min: f32, // Contrast below which dark areas are left alone
+    pub subpixel: f32,           // Amount of subpixel aliasing removed, from 0 to 1
+}
+
+impl Default for FxaaSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            edge_threshold: 0.125,
+            edge_threshold_min: 0.0312,
+            subpixel: 0.75,
+        }
+    }
+}
+
+/// Settings of the chromatic aberration effect
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ChromaticAberrationSettings {
+    pub enabled: bool,
+    pub intensity: f32, // Red and blue offset at the screen corners, as a fraction of the screen
+}
+
+impl Default for ChromaticAberrationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            intensity: 0.005,
+        }
+    }
+}
+
+/// Settings of the vignette effect
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VignetteSettings {
+    pub enabled: bool,
+    pub intensity: f32,  // How dark the corners get, from 0 to 1
+    pub radius: f32,     // Distance from the center where darkening starts; 1 is a corner
+    pub smoothness: f32, // Distance over which it reaches full strength
+}
+
+impl Default for VignetteSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            intensity: 0.4,
+            radius: 0.5,
+            smoothness: 0.5,
+        }
+    }
+}
+
+/// Settings of the color grading effect
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ColorGradingSettings {
+    pub enabled: bool,
+    pub contribution: f32, // Blend from the original colors at 0 to the graded ones at 1
+}
+
+impl Default for ColorGradingSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            contribution: 1.0,
+        }
+    }
+}
+
+/// Which post effects run, in what order, and with which parameters
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostProcessSettings {
+    pub order: Vec<PostEffect>, // Effects left out never run
+    pub bloom: BloomSettings,
+    pub fxaa: FxaaSettings,
+    pub chromatic_aberration: ChromaticAberrationSettings,
+    pub vignette: VignetteSettings,
+    pub color_grading: ColorGradingSettings,
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        Self {
+            order: PostEffect::ALL.to_vec(),
+            bloom: BloomSettings::default(),
+            fxaa: FxaaSettings::default(),
+            chromatic_aberration: ChromaticAberrationSettings::default(),
+            vignette: VignetteSettings::default(),
+            color_grading: ColorGradingSettings::default(),
+        }
+    }
+}
+
+impl PostProcessSettings {
+    /// Returns true if the effect is turned on
+    pub fn is_enabled(&self, effect: PostEffect) -> bool {
+        match effect {
+            PostEffect::Bloom => self.bloom.enabled,
+            PostEffect::Fxaa => self.fxaa.enabled,
+            PostEffect::ChromaticAberration => self.chromatic_aberration.enabled,
+            PostEffect::Vignette => self.vignette.enabled,
+            PostEffect::ColorGrading => self.color_grading.enabled,
+        }
+    }
+
+    /// Turn an effect on or off
+    pub fn set_enabled(&mut self, effect: PostEffect, enabled: bool) {
+        match effect {
+            PostEffect::Bloom => self.bloom.enabled = enabled,
+            PostEffect::Fxaa => self.fxaa.enabled = enabled,
+            PostEffect::ChromaticAberration => self.chromatic_aberration.enabled = enabled,
+            PostEffect::Vignette => self.vignette.enabled = enabled,
+            PostEffect::ColorGrading => self.color_grading.enabled = enabled,
+        }
+    }
+
+    /// The enabled effects that run on the tonemapped image, in chain order
+    pub fn display_effects(&self) -> impl Iterator<Item = PostEffect> + '_ {
+        self.order.iter().copied().filter(|&effect| !effect.is_hdr() && self.is_enabled(effect))
+    }
+}
+
+/// Post-process parameters as laid out in the shaders
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct PostProcessUniforms {
+    bloom_threshold: f32,
+    bloom_knee: f32,
+    bloom_intensity: f32,
+    fxaa_edge_threshold: f32,
+    fxaa_edge_threshold_min: f32,
+    fxaa_subpixel: f32,
+    chromatic_aberration: f32,
+    vignette_intensity: f32,
+    vignette_radius: f32,
+    vignette_smoothness: f32,
+    grading_contribution: f32,
+    lut_size: f32,
+    display_encoded: u32, // Set when the tonemapped targets hold sRGB-encoded values rather than decoding them
+    _padding: [u32; 3],
+}
+
+impl PostProcessUniforms {
+    fn new(settings: &PostProcessSettings, lut_size: u32, output_format: wgpu::TextureFormat) -> Self {
+        Self {
+            bloom_threshold: settings.bloom.threshold,
+            bloom_knee: settings.bloom.knee,
+            bloom_intensity: settings.bloom.intensity,
+            fxaa_edge_threshold: settings.fxaa.edge_threshold,
+            fxaa_edge_threshold_min: settings.fxaa.edge_threshold_min,
+            fxaa_subpixel: settings.fxaa.subpixel,
+            chromatic_aberration: settings.chromatic_aberration.intensity,
+            vignette_intensity: settings.vignette.intensity,
+            vignette_radius: settings.vignette.radius,
+            vignette_smoothness: settings.vignette.smoothness,
+            grading_contribution: settings.color_grading.contribution,
+            lut_size: lut_size as f32,
+            display_encoded: (!output_format.is_srgb()) as u32,
+            _padding: [0; 3],
+        }
+    }
+}
+
+/// Converts a color grading LUT laid out as a horizontal strip of `size` square slices, one per
+/// blue value, into the texels of a `size`³ volume. Returns the size and the texels.
+pub fn lut_volume_from_strip(strip: &image::RgbaImage) -> Result<(u32, Vec<u8>)> {
+    let size = strip.height();
+    if size < 2 || strip.width() != size * size {
+        anyhow::bail!(
+            "Color grading LUT must be a strip of square slices, {}x{} given",
+            strip.width(),
+            strip.height()
+        );
+    }
+    let mut texels = Vec::with_capacity((size * size * size * 4) as usize);
+    for blue in 0..size {
+        for green in 0..size {
+            for red in 0..size {
+                texels.extend_from_slice(&strip.get_pixel(blue * size + red, green).0);
+            }
+        }
+    }
+    Ok((size, texels))
+}
+
+/// A LUT strip that leaves colors unchanged
+pub fn identity_lut_strip(size: u32) -> image::RgbaImage {
+    let value = |i: u32| ((i * 255 + (size - 1) / 2) / (size - 1)) as u8;
+    image::RgbaImage::from_fn(size * size, size, |x, y| {
+        image::Rgba([value(x % size), value(y), value(x / size), 255])
+    })
+}
+
+/// Bloom levels and effect targets at one output size, with the bind groups reading them
+pub struct Targets {
+    hdr_bind_group: wgpu::BindGroup,               // Reads the HDR scene into the first level
+    bloom_levels: Vec<(Texture, wgpu::BindGroup)>, // Each level and the bind group reading it
+    effects: [(Texture, wgpu::BindGroup); 2],      // Tonemapped image ping-pong targets
+}
+
+/// Bloom, anti-aliasing and grading passes around the tonemapper.
+///
+/// Bloom thresholds the HDR scene, blurs it with a dual-filter down and up chain and adds it
+/// back before tonemapping. The remaining effects run on the tonemapped image in the order of
+/// the settings, ping-ponging between two targets of the output format, the last writing to
+/// the output.
+pub struct PostProcess {
+    settings: PostProcessSettings,
+    output_format: wgpu::TextureFormat,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    bloom_bind_group_layout: wgpu::BindGroupLayout,
+    bloom_prefilter_pipeline: wgpu::RenderPipeline,
+    bloom_downsample_pipeline: wgpu::RenderPipeline,
+    bloom_upsample_pipeline: wgpu::RenderPipeline,
+    bloom_composite_pipeline: wgpu::RenderPipeline,
+    effect_bind_group_layout: wgpu::BindGroupLayout,
+    effect_pipelines: Vec<(PostEffect, wgpu::RenderPipeline)>,
+    lut: Texture,
+    lut_size: u32,
+    targets: Targets,
+}
+
+impl PostProcess {
+    /// Create the post-process pipelines and targets for the given output configuration
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        hdr_view: &wgpu::TextureView,
+        bloom_shader: &wgpu::ShaderModule,
+        post_process_shader: &wgpu::ShaderModule,
+    ) -> Self {
+        let settings = PostProcessSettings::default();
+        let uniforms = PostProcessUniforms::new(&settings, IDENTITY_LUT_SIZE, config.format);
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post Process Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Process Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let uniform_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let texture_entry = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let sampler_entry = wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+
+        let bloom_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bloom Bind Group Layout"),
+            entries: &[uniform_entry, texture_entry(1, wgpu::TextureViewDimension::D2), sampler_entry],
+        });
+        let effect_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Post Effect Bind Group Layout"),
+            entries: &[
+                uniform_entry,
+                texture_entry(1, wgpu::TextureViewDimension::D2),
+                sampler_entry,
+                // Color grading LUT
+                texture_entry(3, wgpu::TextureViewDimension::D3),
+            ],
+        });
+
+        let create_pipeline = |layout: &wgpu::BindGroupLayout,
+                               shader: &wgpu::ShaderModule,
+                               entry_point: &str,
+                               format: wgpu::TextureFormat,
+                               blend: wgpu::BlendState,
+                               label: &str| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let bloom_pipeline = |entry_point, blend, label| {
+            create_pipeline(&bloom_bind_group_layout, bloom_shader, entry_point, HDR_FORMAT, blend, label)
+        };
+        let bloom_prefilter_pipeline = bloom_pipeline("fs_prefilter", wgpu::BlendState::REPLACE, "Bloom Prefilter Pipeline");
+        let bloom_downsample_pipeline = bloom_pipeline("fs_downsample", wgpu::BlendState::REPLACE, "Bloom Downsample Pipeline");
+        let bloom_upsample_pipeline = bloom_pipeline("fs_upsample", wgpu::BlendState::REPLACE, "Bloom Upsample Pipeline");
+        // Adds the blurred highlights onto the scene
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::OVER,
+        };
+        let bloom_composite_pipeline = bloom_pipeline("fs_composite", additive, "Bloom Composite Pipeline");
+
+        let effect_pipelines = PostEffect::ALL
+            .into_iter()
+            .filter_map(|effect| {
+                let entry_point = effect.entry_point()?;
+                let pipeline = create_pipeline(
+                    &effect_bind_group_layout,
+                    post_process_shader,
+                    entry_point,
+                    config.format,
+                    wgpu::BlendState::REPLACE,
+                    "Post Effect Pipeline",
+                );
+                Some((effect, pipeline))
+            })
+            .collect();
+
+        let (lut_size, lut_texels) = lut_volume_from_strip(&identity_lut_strip(IDENTITY_LUT_SIZE))
+            .expect("Identity LUT should be a valid strip");
+        let lut = Self::create_lut(device, queue, lut_size, &lut_texels);
+
+        let targets = Targets {
+            hdr_bind_group: Self::create_bloom_bind_group(device, &bloom_bind_group_layout, &uniform_buffer, hdr_view, &sampler),
+            bloom_levels: Self::create_bloom_levels(device, &bloom_bind_group_layout, &uniform_buffer, &sampler, config),
+            effects: Self::create_effect_targets(device, &effect_bind_group_layout, &uniform_buffer, &sampler, &lut, config),
+        };
+
+        Self {
+            settings,
+            output_format: config.format,
+            uniform_buffer,
+            sampler,
+            bloom_bind_group_layout,
+            bloom_prefilter_pipeline,
+            bloom_downsample_pipeline,
+            bloom_upsample_pipeline,
+            bloom_composite_pipeline,
+            effect_bind_group_layout,
+            effect_pipelines,
+            lut,
+            lut_size,
+            targets,
+        }
+    }
+
+    fn create_lut(device: &wgpu::Device, queue: &wgpu::Queue, size: u32, texels: &[u8]) -> Texture {
+        let extent = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: size,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Color Grading LUT"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            texture.as_image_copy(),
+            texels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * size),
+                rows_per_image: Some(size),
+            },
+            extent,
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Color Grading LUT Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Texture { texture, view, sampler }
+    }
+
+    fn create_bloom_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        source: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bloom Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    /// Each level is its own texture, since per-mip views are not sampled reliably on GL
+    fn create_bloom_levels(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        sampler: &wgpu::Sampler,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Vec<(Texture, wgpu::BindGroup)> {
+        (1..=MAX_BLOOM_LEVELS)
+            .take_while(|&level| (config.width >> level) > 0 && (config.height >> level) > 0)
+            .map(|level| {
+                let level_config = wgpu::SurfaceConfiguration {
+                    format: HDR_FORMAT,
+                    width: config.width >> level,
+                    height: config.height >> level,
+                    view_formats: vec![HDR_FORMAT],
+                    ..config.clone()
+                };
+                let texture = Texture::create_render_target(device, &level_config, "Bloom Level");
+                let bind_group = Self::create_bloom_bind_group(device, layout, uniform_buffer, &texture.view, sampler);
+                (texture, bind_group)
+            })
+            .collect()
+    }
+
+    fn create_effect_targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        sampler: &wgpu::Sampler,
+        lut: &Texture,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> [(Texture, wgpu::BindGroup); 2] {
+        let create_target = || {
+            let texture = Texture::create_render_target(device, config, "Post Process Target");
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Post Effect Bind Group"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&lut.view),
+                    },
+                ],
+            });
+            (texture, bind_group)
+        };
+        [create_target(), create_target()]
+    }
+
+    /// Recreate the targets at the new output size, reading the resized HDR target, and
+    /// return the ones they replace
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, hdr_view: &wgpu::TextureView) -> Targets {
+        let targets = Targets {
+            hdr_bind_group: Self::create_bloom_bind_group(
+                device,
+                &self.bloom_bind_group_layout,
+                &self.uniform_buffer,
+                hdr_view,
+                &self.sampler,
+            ),
+            bloom_levels: Self::create_bloom_levels(
+                device,
+                &self.bloom_bind_group_layout,
+                &self.uniform_buffer,
+                &self.sampler,
+                config,
+            ),
+            effects: Self::create_effect_targets(
+                device,
+                &self.effect_bind_group_layout,
+                &self.uniform_buffer,
+                &self.sampler,
+                &self.lut,
+                config,
+            ),
+        };
+        self.swap_targets(targets)
+    }
+
+    /// Render into targets kept from an earlier `resize`, returning the current ones. The
+    /// targets must read the HDR target the tonemapper renders into, and be made since the
+    /// color grading LUT last changed.
+    pub fn swap_targets(&mut self, targets: Targets) -> Targets {
+        std::mem::replace(&mut self.targets, targets)
+    }
+
+    /// Returns the current post-process settings
+    pub fn settings(&self) -> &PostProcessSettings {
+        &self.settings
+    }
+
+    /// Replace the post-process settings
+    pub fn set_settings(&mut self, queue: &wgpu::Queue, settings: PostProcessSettings) {
+        self.settings = settings;
+        self.write_uniforms(queue);
+    }
+
+    fn write_uniforms(&self, queue: &wgpu::Queue) {
+        let uniforms = PostProcessUniforms::new(&self.settings, self.lut_size, self.output_format);
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+    }
+
+    /// Replace the color grading LUT with one laid out as a strip of square slices, one per
+    /// blue value, such as a 256x16 image for a 16³ LUT
+    pub fn set_color_grading_lut(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        strip: &image::RgbaImage,
+    ) -> Result<()> {
+        let (size, texels) = lut_volume_from_strip(strip)?;
+        self.lut = Self::create_lut(device, queue, size, &texels);
+        self.lut_size = size;
+        self.targets.effects = Self::create_effect_targets(
+            device,
+            &self.effect_bind_group_layout,
+            &self.uniform_buffer,
+            &self.sampler,
+            &self.lut,
+            config,
+        );
+        self.write_uniforms(queue);
+        Ok(())
+    }
+
+    /// Add bloom to the HDR scene, tonemap it, then run the display effects into `output`
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, tonemapper: &Tonemapper, output: &wgpu::TextureView) {
+        if self.settings.bloom.enabled {
+            self.render_bloom(encoder, tonemapper.hdr_view());
+        }
+
+        // Tonemap straight into the output when no effect follows
+        let effects: Vec<PostEffect> = self.settings.display_effects().collect();
+        if effects.is_empty() {
+            tonemapper.render(encoder, output);
+            return;
+        }
+        tonemapper.render(encoder, &self.targets.effects[0].0.view);
+
+        for (index, effect) in effects.iter().enumerate() {
+            let (_, source) = &self.targets.effects[index % 2];
+            let target = if index + 1 == effects.len() {
+                output
+            } else {
+                &self.targets.effects[(index + 1) % 2].0.view
+            };
+            let pipeline = self.effect_pipelines
+                .iter()
+                .find(|(candidate, _)| candidate == effect)
+                .map(|(_, pipeline)| pipeline)
+                .expect("Every display effect should have a pipeline");
+            Self::fullscreen_pass(encoder, "Post Effect Pass", target, wgpu::LoadOp::Clear(wgpu::Color::BLACK), pipeline, source);
+        }
+    }
+
+    fn render_bloom(&self, encoder: &mut wgpu::CommandEncoder, hdr_view: &wgpu::TextureView) {
+        let level_count = (self.settings.bloom.levels as usize).clamp(1, self.targets.bloom_levels.len().max(1));
+        let Some(levels) = self.targets.bloom_levels.get(..level_count) else { return };
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+
+        // Keep what is above the threshold at half size, then halve it down the chain
+        Self::fullscreen_pass(encoder, "Bloom Prefilter Pass", &levels[0].0.view, clear, &self.bloom_prefilter_pipeline, &self.targets.hdr_bind_group);
+        for pair in levels.windows(2) {
+            Self::fullscreen_pass(encoder, "Bloom Downsample Pass", &pair[1].0.view, clear, &self.bloom_downsample_pipeline, &pair[0].1);
+        }
+        // Blur back up to the first level, widening the glow at each step
+        for pair in levels.windows(2).rev() {
+            Self::fullscreen_pass(encoder, "Bloom Upsample Pass", &pair[0].0.view, clear, &self.bloom_upsample_pipeline, &pair[1].1);
+        }
+        Self::fullscreen_pass(encoder, "Bloom Composite Pass", hdr_view, wgpu::LoadOp::Load, &self.bloom_composite_pipeline, &levels[0].1);
+    }
+
+    fn fullscreen_pass(
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        target: &wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations { load, store: true },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lut_strip_unpacks_into_volume() {
+        let (size, texels) = lut_volume_from_strip(&identity_lut_strip(4)).unwrap();
+        assert_eq!(size, 4);
+        assert_eq!(texels.len(), 4 * 4 * 4 * 4);
+        // Texel (red 3, green 1, blue 2) of the identity LUT holds its own coordinates
+        let index = ((2 * 4 + 1) * 4 + 3) * 4;
+        assert_eq!(&texels[index..index + 4], &[255, 85, 170, 255]);
+
+        assert!(lut_volume_from_strip(&image::RgbaImage::new(16, 16)).is_err());
+        assert!(lut_volume_from_strip(&image::RgbaImage::new(1, 1)).is_err());
+    }
+
+    #[test]
+    fn display_effects_follow_order() {
+        let mut settings = PostProcessSettings::default();
+        assert_eq!(settings.display_effects().count(), 0);
+        for effect in PostEffect::ALL {
+            settings.set_enabled(effect, true);
+            assert!(settings.is_enabled(effect));
+        }
+        settings.order = vec![PostEffect::Vignette, PostEffect::Bloom, PostEffect::Fxaa];
+        let effects: Vec<PostEffect> = settings.display_effects().collect();
+        assert_eq!(effects, vec![PostEffect::Vignette, PostEffect::Fxaa]);
+    }
+}
@@ -0,0 +1,566 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Number of render graphs created so far, which tags the resources of each
+static GRAPH_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Handle to a texture or buffer that the passes of a `RenderGraph` read and write. Only
+/// valid in the graph that returned it, which is rebuilt every frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ResourceId {
+    graph: u64,   // Graph the resource belongs to
+    index: usize, // Position among the graph's resources
+}
+
+/// Size, format and usage of a transient texture the graph allocates
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TransientTextureDesc {
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+    pub sample_count: u32,
+}
+
+impl TransientTextureDesc {
+    /// A texture that can be rendered to and then sampled
+    pub fn new(width: u32, height: u32, format: wgpu::TextureFormat) -> Self {
+        Self {
+            width,
+            height,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            sample_count: 1,
+        }
+    }
+
+    /// A multisampled texture that can only be rendered to and resolved, since the GL
+    /// backend cannot sample multisampled textures
+    pub fn multisampled(width: u32, height: u32, format: wgpu::TextureFormat, sample_count: u32) -> Self {
+        Self {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            sample_count,
+            ..Self::new(width, height, format)
+        }
+    }
+}
+
+/// Where in the frame the renderer adds passes from outside its core
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PassStage {
+    BeforeScene, // After culling, shadows and ambient occlusion, before the scene is drawn
+    AfterScene,  // Once the scene and sky are in the HDR target, before post-processing
+    Overlay,     // On the output, after post-processing
+}
+
+#[allow(dead_code)]
+/// Resources of the renderer's graph that custom passes can read and write
+#[derive(Debug, Copy, Clone)]
+pub struct FrameResources {
+    pub output: ResourceId,            // Swapchain image or offscreen target, in `output_format`
+    pub hdr: ResourceId,               // Linear HDR scene color, single-sampled
+    pub depth: ResourceId,             // Scene depth, with `sample_count` samples
+    pub shadow_map: ResourceId,        // Sun shadow cascades, written by the renderer only
+    pub ambient_occlusion: ResourceId, // Blurred occlusion, written by the renderer only
+    pub width: u32,
+    pub height: u32,
+    pub output_format: wgpu::TextureFormat,
+    pub sample_count: u32,
+}
+
+/// A pass added to the renderer's graph every frame, such as an effect or overlay from game code
+pub trait CustomPass {
+    /// Name of the pass in the frame's pass list and in GPU debuggers
+    fn name(&self) -> &str;
+
+    /// Where in the frame the pass is added
+    fn stage(&self) -> PassStage;
+
+    /// Declare what the pass reads and writes this frame, and create its transient textures
+    fn setup(&mut self, builder: &mut PassBuilder<'_>, frame: &FrameResources);
+
+    /// Record the pass's commands
+    fn execute(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, context: &mut PassContext<'_>);
+}
+
+enum ResourceKind {
+    Imported(Option<wgpu::TextureView>), // Owned outside the graph, with a view when passes may attach it
+    Transient(TransientTextureDesc),     // Allocated by the graph for the frame
+}
+
+struct Resource {
+    name: String,
+    kind: ResourceKind,
+}
+
+/// Resources a pass declared it reads and writes
+struct PassDeclaration {
+    name: String,
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+}
+
+type ExecuteFn<'a, C> = Box<dyn FnOnce(&mut C, &mut PassContext<'_>) + 'a>;
+
+struct PassNode<'a, C> {
+    declaration: PassDeclaration,
+    execute: ExecuteFn<'a, C>,
+}
+
+#[allow(dead_code)]
+/// Declares the resources a pass reads and writes
+pub struct PassBuilder<'g> {
+    graph: u64,
+    resources: &'g mut Vec<Resource>,
+    declaration: &'g mut PassDeclaration,
+}
+
+impl PassBuilder<'_> {
+    /// The pass reads `resource` as the passes added before it left it
+    pub fn read(&mut self, resource: ResourceId) -> &mut Self {
+        self.declaration.reads.push(resource);
+        self
+    }
+
+    /// The pass writes `resource`, after every earlier pass reading or writing it
+    pub fn write(&mut self, resource: ResourceId) -> &mut Self {
+        self.declaration.writes.push(resource);
+        self
+    }
+
+    #[allow(dead_code)]
+    /// Create a texture that only lives for this frame, written by this pass
+    pub fn create_texture(&mut self, name: &str, desc: TransientTextureDesc) -> ResourceId {
+        let id = ResourceId {
+            graph: self.graph,
+            index: self.resources.len(),
+        };
+        self.resources.push(Resource {
+            name: name.to_string(),
+            kind: ResourceKind::Transient(desc),
+        });
+        self.write(id);
+        id
+    }
+}
+
+/// Encoder and resource views a pass records its commands with
+pub struct PassContext<'e> {
+    pub encoder: &'e mut wgpu::CommandEncoder,
+    graph: u64,
+    views: &'e [Option<&'e wgpu::TextureView>],
+}
+
+impl<'e> PassContext<'e> {
+    /// View of a transient texture or of an imported texture. Panics for resources without
+    /// one and for resources of another graph.
+    pub fn texture_view(&self, resource: ResourceId) -> &'e wgpu::TextureView {
+        assert_eq!(resource.graph, self.graph, "Render graph resource should belong to this frame's graph");
+        self.views[resource.index].expect("Render graph resource should be a texture with a view")
+    }
+}
+
+/// Order the live passes of a graph run in, and the memory their transient textures share
+#[derive(Debug, Default, PartialEq)]
+pub struct Schedule {
+    pub order: Vec<usize>,                           // Indices of the passes to run, in order
+    pub culled: Vec<usize>,                          // Passes nothing needs, which never run
+    slots: Vec<Option<usize>>,                       // Texture of its description each transient uses
+    slot_counts: HashMap<TransientTextureDesc, usize>, // Textures needed of each description
+}
+
+#[allow(dead_code)]
+impl Schedule {
+    /// Index among the textures of its description that a transient resource is placed in,
+    /// None for imported resources and transients only culled passes use
+    pub fn slot(&self, resource: ResourceId) -> Option<usize> {
+        self.slots[resource.index]
+    }
+
+    /// Number of textures allocated for transients of `desc`
+    pub fn texture_count(&self, desc: &TransientTextureDesc) -> usize {
+        self.slot_counts.get(desc).copied().unwrap_or(0)
+    }
+}
+
+/// Graph of the GPU passes of a frame.
+///
+/// Passes declare the resources they read and write. Every read sees the writes of the
+/// passes added before it, and a write waits for the earlier passes using the resource,
+/// so passes run in the order they were added. From these dependencies the graph culls
+/// passes whose results nothing reads and packs transient textures whose lifetimes do
+/// not overlap into the same memory. wgpu tracks how each texture and buffer is used and
+/// inserts the transitions between passes itself.
+///
+/// `C` is the state passes are executed with, such as the renderer owning the pipelines.
+pub struct RenderGraph<'a, C> {
+    id: u64, // Tags the graph's resources, so ids kept from another graph are caught
+    resources: Vec<Resource>,
+    passes: Vec<PassNode<'a, C>>,
+}
+
+impl<'a, C> RenderGraph<'a, C> {
+    /// Create an empty graph
+    pub fn new() -> Self {
+        Self {
+            id: GRAPH_COUNT.fetch_add(1, Ordering::Relaxed),
+            resources: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    /// Import a texture owned outside the graph that passes can attach by its view.
+    /// Passes writing imported resources are never culled.
+    pub fn import_texture(&mut self, name: &str, view: wgpu::TextureView) -> ResourceId {
+        self.add_resource(name, ResourceKind::Imported(Some(view)))
+    }
+
+    /// Import a resource owned outside the graph that passes only refer to, such as a
+    /// buffer or the targets a renderer module keeps to itself
+    pub fn import(&mut self, name: &str) -> ResourceId {
+        self.add_resource(name, ResourceKind::Imported(None))
+    }
+
+    /// Create a texture that only lives for this frame, for passes to declare before it
+    /// exists in their closures. The first pass writing it defines its contents.
+    pub fn create_texture(&mut self, name: &str, desc: TransientTextureDesc) -> ResourceId {
+        self.add_resource(name, ResourceKind::Transient(desc))
+    }
+
+    fn add_resource(&mut self, name: &str, kind: ResourceKind) -> ResourceId {
+        self.resources.push(Resource {
+            name: name.to_string(),
+            kind,
+        });
+        ResourceId {
+            graph: self.id,
+            index: self.resources.len() - 1,
+        }
+    }
+
+    /// Add a pass recorded by `execute`, returning the builder to declare its resources with
+    pub fn add_pass(
+        &mut self,
+        name: &str,
+        execute: impl FnOnce(&mut C, &mut PassContext<'_>) + 'a,
+    ) -> PassBuilder<'_> {
+        self.passes.push(PassNode {
+            declaration: PassDeclaration {
+                name: name.to_string(),
+                reads: Vec::new(),
+                writes: Vec::new(),
+            },
+            execute: Box::new(execute),
+        });
+        PassBuilder {
+            graph: self.id,
+            resources: &mut self.resources,
+            declaration: &mut self.passes.last_mut().expect("Pass was just added").declaration,
+        }
+    }
+
+    #[allow(dead_code)]
+    /// Name of the resource `resource` was imported or created as
+    pub fn resource_name(&self, resource: ResourceId) -> &str {
+        &self.resources[resource.index].name
+    }
+
+    #[allow(dead_code)]
+    /// Name of the pass at `index`
+    pub fn pass_name(&self, index: usize) -> &str {
+        &self.passes[index].declaration.name
+    }
+
+    /// Fails if the pass added last uses a resource of another graph, such as an id kept
+    /// from an earlier frame, which could otherwise stand for a different resource here
+    pub fn check_last_pass(&self) -> anyhow::Result<()> {
+        match self.passes.last() {
+            Some(pass) => self.check_declaration(&pass.declaration),
+            None => Ok(()),
+        }
+    }
+
+    /// Remove the pass added last, such as one `check_last_pass` rejected. Transient
+    /// textures it created stay in the graph unused.
+    pub fn remove_last_pass(&mut self) {
+        self.passes.pop();
+    }
+
+    fn check_declaration(&self, declaration: &PassDeclaration) -> anyhow::Result<()> {
+        for resource in declaration.reads.iter().chain(&declaration.writes) {
+            if resource.graph != self.id || resource.index >= self.resources.len() {
+                anyhow::bail!("Pass '{}' uses a resource of another graph", declaration.name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Work out which passes run and where their transient textures live. Fails if a pass
+    /// uses a resource of another graph.
+    pub fn compile(&self) -> anyhow::Result<Schedule> {
+        let pass_count = self.passes.len();
+        for pass in &self.passes {
+            self.check_declaration(&pass.declaration)?;
+        }
+
+        // Passes each pass needs the results of: the last writer of every resource it reads,
+        // and of every resource it writes, since a write may keep part of the old contents
+        let mut last_writer: Vec<Option<usize>> = vec![None; self.resources.len()];
+        let mut producers: Vec<Vec<usize>> = vec![Vec::new(); pass_count];
+        for (index, pass) in self.passes.iter().enumerate() {
+            let declaration = &pass.declaration;
+            for resource in declaration.reads.iter().chain(&declaration.writes) {
+                if let Some(writer) = last_writer[resource.index] {
+                    producers[index].push(writer);
+                }
+            }
+            for resource in &declaration.writes {
+                last_writer[resource.index] = Some(index);
+            }
+        }
+
+        // Keep passes with effects outside the graph and, walking back, the passes they need
+        let mut live = vec![false; pass_count];
+        for index in (0..pass_count).rev() {
+            let writes = &self.passes[index].declaration.writes;
+            let external = writes.is_empty()
+                || writes.iter().any(|resource| matches!(self.resources[resource.index].kind, ResourceKind::Imported(_)));
+            if external {
+                live[index] = true;
+            }
+            if live[index] {
+                for &producer in &producers[index] {
+                    live[producer] = true;
+                }
+            }
+        }
+        let (order, culled): (Vec<usize>, Vec<usize>) = (0..pass_count).partition(|&index| live[index]);
+
+        // First and last position in the order each transient is used at
+        let mut lifetimes: Vec<Option<(usize, usize)>> = vec![None; self.resources.len()];
+        for (position, &index) in order.iter().enumerate() {
+            let declaration = &self.passes[index].declaration;
+            for resource in declaration.reads.iter().chain(&declaration.writes) {
+                if let ResourceKind::Transient(_) = self.resources[resource.index].kind {
+                    let lifetime = lifetimes[resource.index].get_or_insert((position, position));
+                    lifetime.1 = position;
+                }
+            }
+        }
+
+        // Hand each transient a texture of its description no other live transient uses at the same time
+        let mut slots = vec![None; self.resources.len()];
+        let mut slot_counts: HashMap<TransientTextureDesc, usize> = HashMap::new();
+        let mut free: HashMap<TransientTextureDesc, Vec<usize>> = HashMap::new();
+        for position in 0..order.len() {
+            for (resource, lifetime) in lifetimes.iter().enumerate() {
+                if let (Some((first, _)), ResourceKind::Transient(desc)) = (lifetime, &self.resources[resource].kind) {
+                    if *first == position {
+                        let slot = free.get_mut(desc).and_then(|slots| slots.pop()).unwrap_or_else(|| {
+                            let count = slot_counts.entry(*desc).or_insert(0);
+                            *count += 1;
+                            *count - 1
+                        });
+                        slots[resource] = Some(slot);
+                    }
+                }
+            }
+            for (resource, lifetime) in lifetimes.iter().enumerate() {
+                if let (Some((_, last)), ResourceKind::Transient(desc)) = (lifetime, &self.resources[resource].kind) {
+                    if *last == position {
+                        free.entry(*desc).or_default().push(slots[resource].expect("Transient was placed"));
+                    }
+                }
+            }
+        }
+
+        Ok(Schedule {
+            order,
+            culled,
+            slots,
+            slot_counts,
+        })
+    }
+
+    /// Record the live passes of `schedule`, compiled from this graph, into `encoder`, each in
+    /// a debug group named after it. `transients` must have been allocated for the schedule.
+    /// Returns the names of the passes that ran.
+    pub fn execute(
+        self,
+        schedule: &Schedule,
+        encoder: &mut wgpu::CommandEncoder,
+        transients: &TransientTextures,
+        state: &mut C,
+    ) -> Vec<String> {
+        let RenderGraph { id, resources, passes } = self;
+        let views: Vec<Option<&wgpu::TextureView>> = resources
+            .iter()
+            .enumerate()
+            .map(|(index, resource)| match &resource.kind {
+                ResourceKind::Imported(view) => view.as_ref(),
+                ResourceKind::Transient(desc) => schedule.slots[index].map(|slot| transients.view(desc, slot)),
+            })
+            .collect();
+
+        let mut passes: Vec<Option<PassNode<'a, C>>> = passes.into_iter().map(Some).collect();
+        let mut executed = Vec::with_capacity(schedule.order.len());
+        for &index in &schedule.order {
+            let pass = passes[index].take().expect("Each pass runs once");
+            encoder.push_debug_group(&pass.declaration.name);
+            let mut context = PassContext {
+                encoder: &mut *encoder,
+                graph: id,
+                views: &views,
+            };
+            (pass.execute)(state, &mut context);
+            encoder.pop_debug_group();
+            executed.push(pass.declaration.name);
+        }
+        executed
+    }
+}
+
+impl<C> Default for RenderGraph<'_, C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Textures backing the transient resources of render graphs, kept from frame to frame
+#[derive(Default)]
+pub struct TransientTextures {
+    textures: HashMap<TransientTextureDesc, Vec<(wgpu::Texture, wgpu::TextureView)>>,
+}
+
+impl TransientTextures {
+    /// Make exactly the textures `schedule` needs available, reusing the last frame's
+    pub fn allocate(&mut self, device: &wgpu::Device, schedule: &Schedule) {
+        let counts = &schedule.slot_counts;
+        self.textures.retain(|desc, _| counts.contains_key(desc));
+        for (desc, &count) in counts {
+            let textures = self.textures.entry(*desc).or_default();
+            textures.truncate(count);
+            while textures.len() < count {
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("Render Graph Transient Texture"),
+                    size: wgpu::Extent3d {
+                        width: desc.width.max(1),
+                        height: desc.height.max(1),
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: desc.sample_count,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: desc.format,
+                    usage: desc.usage,
+                    view_formats: &[],
+                });
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                textures.push((texture, view));
+            }
+        }
+    }
+
+    fn view(&self, desc: &TransientTextureDesc, slot: usize) -> &wgpu::TextureView {
+        &self.textures[desc][slot].1
+    }
+
+    #[allow(dead_code)]
+    /// Number of textures currently allocated
+    pub fn texture_count(&self) -> usize {
+        self.textures.values().map(Vec::len).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desc() -> TransientTextureDesc {
+        TransientTextureDesc::new(64, 64, wgpu::TextureFormat::Rgba8Unorm)
+    }
+
+    #[test]
+    fn passes_nothing_reads_are_culled() {
+        let mut graph: RenderGraph<()> = RenderGraph::new();
+        let output = graph.import("output");
+        let scratch = graph.add_pass("unused", |_, _| {}).create_texture("scratch", desc());
+        let lit = graph.add_pass("lighting", |_, _| {}).create_texture("lit", desc());
+        graph.add_pass("composite", |_, _| {}).read(lit).write(output);
+        graph.add_pass("readback", |_, _| {}).read(output);
+
+        // Passes writing nothing are kept for their side effects
+        let schedule = graph.compile().unwrap();
+        assert_eq!(graph.resource_name(lit), "lit");
+        assert_eq!(schedule.order, vec![1, 2, 3]);
+        assert_eq!(schedule.culled, vec![0]);
+        assert_eq!(schedule.slot(scratch), None);
+        assert_eq!(schedule.slot(lit), Some(0));
+    }
+
+    #[test]
+    fn writes_keep_the_passes_they_build_on() {
+        let mut graph: RenderGraph<()> = RenderGraph::new();
+        let output = graph.import("output");
+        let texture = graph.add_pass("clear", |_, _| {}).create_texture("texture", desc());
+        graph.add_pass("draw", |_, _| {}).write(texture);
+        graph.add_pass("composite", |_, _| {}).read(texture).write(output);
+        graph.add_pass("late draw", |_, _| {}).write(texture);
+
+        let schedule = graph.compile().unwrap();
+        assert_eq!(schedule.order, vec![0, 1, 2]);
+        assert_eq!(schedule.culled, vec![3]);
+        assert_eq!(graph.pass_name(schedule.culled[0]), "late draw");
+    }
+
+    #[test]
+    fn transients_with_disjoint_lifetimes_share_a_texture() {
+        let mut graph: RenderGraph<()> = RenderGraph::new();
+        let output = graph.import("output");
+        let a = graph.add_pass("a", |_, _| {}).create_texture("a", desc());
+        let b = graph.add_pass("b", |_, _| {}).create_texture("b", desc());
+        graph.add_pass("a + b", |_, _| {}).read(a).read(b).write(output);
+        let c = graph.add_pass("c", |_, _| {}).create_texture("c", desc());
+        let half = TransientTextureDesc::new(32, 32, wgpu::TextureFormat::Rgba8Unorm);
+        let d = graph.add_pass("d", |_, _| {}).create_texture("d", half);
+        graph.add_pass("c + d", |_, _| {}).read(c).read(d).write(output);
+
+        // a and b overlap, c starts once both are done, d differs in size
+        let schedule = graph.compile().unwrap();
+        assert_eq!(schedule.order, vec![0, 1, 2, 3, 4, 5]);
+        assert_ne!(schedule.slot(a), schedule.slot(b));
+        assert!(schedule.slot(c) == schedule.slot(a) || schedule.slot(c) == schedule.slot(b));
+        assert_eq!(schedule.texture_count(&desc()), 2);
+        assert_eq!(schedule.texture_count(&half), 1);
+    }
+
+    #[test]
+    fn resources_of_another_graph_are_rejected() {
+        let mut other: RenderGraph<()> = RenderGraph::new();
+        other.import("first");
+        let foreign = other.import("second");
+
+        let mut graph: RenderGraph<()> = RenderGraph::new();
+        graph.import("output");
+        graph.add_pass("reader", |_, _| {}).read(foreign);
+        assert!(graph.compile().is_err());
+    }
+
+    #[test]
+    fn ids_kept_from_an_earlier_graph_are_rejected_even_in_range() {
+        let mut earlier: RenderGraph<()> = RenderGraph::new();
+        let kept = earlier.import("hdr");
+
+        // The same index names a different resource in the next frame's graph
+        let mut graph: RenderGraph<()> = RenderGraph::new();
+        let output = graph.import("output");
+        graph.add_pass("composite", |_, _| {}).write(output);
+        graph.check_last_pass().unwrap();
+        graph.add_pass("stale", |_, _| {}).write(kept);
+        assert!(graph.check_last_pass().is_err());
+        assert!(graph.compile().is_err());
+
+        graph.remove_last_pass();
+        assert_eq!(graph.compile().unwrap().order, vec![0]);
+    }
+}
@@ -4,6 +4,278 @@ use anyhow::Result;
 use std::collections::HashMap;
 use std::path::Path;
 use std::fs;
+use crate::engine::renderer::ambient_occlusion::{AMBIENT_OCCLUSION_TYPES_WGSL, AMBIENT_OCCLUSION_WGSL};
+use crate::engine::renderer::environment::{ENVIRONMENT_BAKE_WGSL, ENVIRONMENT_WGSL};
+use crate::engine::renderer::instance::INSTANCE_WGSL;
+use crate::engine::renderer::lod::LOD_FADE_WGSL;
+use crate::engine::renderer::lights::{LIGHTS_WGSL, LIGHT_TYPES_WGSL};
+use crate::engine::renderer::material::MATERIAL_WGSL;
+use crate::engine::renderer::post_process::POST_PROCESS_TYPES_WGSL;
+use crate::engine::renderer::shadow::SHADOW_SAMPLING_WGSL;
+use crate::engine::renderer::tonemap::TONEMAP_TYPES_WGSL;
+use crate::engine::renderer::view::VIEW_WGSL;
+
+/// WGSL description of a lit point, as the forward shader computes it from the material
+/// and the deferred lighting pass reads it back from the G-buffer
+const SURFACE_TYPES_WGSL: &str = r#"
+struct Surface {
+    position: vec3<f32>, // World space
+    normal: vec3<f32>,   // World space, with the normal map applied
+    albedo: vec3<f32>,
+    alpha: f32,
+    metallic: f32,
+    roughness: f32,
+    occlusion: f32,      // Material ambient occlusion, scaled by its strength
+    emissive: vec3<f32>,
+};
+"#;
+
+/// WGSL vertex stage of the lit mesh shaders and the material sampling they share.
+/// Expects the material, instance and view declarations and `SURFACE_TYPES_WGSL`.
+const MESH_SURFACE_WGSL: &str = r#"
+struct Uniforms {
+    view_proj: mat4x4<f32>,
+    model: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> uniforms: Uniforms;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
+    @location(4) tangent: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
+    @location(3) view_dir: vec3<f32>,
+    @location(4) tangent: vec4<f32>,
+    @location(5) tint: vec4<f32>,
+    @location(6) @interpolate(flat) lod_fade: f32,
+};
+
+@vertex
+fn vs_main(
+    in: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    
+    // Transform position and normal to world space, the instance transform applies first
+    let model_matrix = uniforms.model * instance_model(instance);
+    out.clip_position = view_projection(uniforms.view_proj) * model_matrix * vec4<f32>(in.position, 1.0);
+    out.position = (model_matrix * vec4<f32>(in.position, 1.0)).xyz;
+    out.normal = normalize((model_matrix * vec4<f32>(in.normal, 0.0)).xyz);
+    out.tangent = vec4<f32>(normalize((model_matrix * vec4<f32>(in.tangent.xyz, 0.0)).xyz), in.tangent.w);
+    
+    // Calculate view direction (from position to camera)
+    out.view_dir = normalize(-out.position);
+    out.tex_coords = in.tex_coords;
+    out.tint = instance.tint;
+    out.lod_fade = instance.lod_fade;
+    
+    return out;
+}
+
+// Apply a tangent-space normal map using the interpolated vertex tangent frame.
+// The tangent is re-orthogonalized against N since interpolation skews it.
+fn perturb_normal(N: vec3<f32>, tangent: vec4<f32>, map_normal: vec3<f32>) -> vec3<f32> {
+    let T = normalize(tangent.xyz - N * dot(N, tangent.xyz));
+    let B = cross(N, T) * tangent.w;
+    return normalize(mat3x3<f32>(T, B, N) * map_normal);
+}
+
+// Sample the material's maps for the fragment. Takes every sample up front, so callers
+// may discard afterwards.
+fn material_surface(in: VertexOutput) -> Surface {
+    let base_color = textureSample(t_albedo, s_material, in.tex_coords) * material.base_color_factor * in.tint;
+    let metallic_roughness = textureSample(t_metallic_roughness, s_material, in.tex_coords);
+    let occlusion = textureSample(t_occlusion, s_material, in.tex_coords).r;
+    let emissive = textureSample(t_emissive, s_material, in.tex_coords).rgb * material.emissive_factor;
+    var map_normal = textureSample(t_normal, s_material, in.tex_coords).xyz * 2.0 - 1.0;
+    map_normal = vec3<f32>(map_normal.xy * material.normal_scale, map_normal.z);
+    
+    let geometric_normal = normalize(in.normal);
+    let mapped_normal = perturb_normal(geometric_normal, in.tangent, map_normal);
+    
+    var surface: Surface;
+    surface.position = in.position;
+    surface.normal = select(geometric_normal, mapped_normal, (material.flags & MATERIAL_HAS_NORMAL_MAP) != 0u);
+    surface.albedo = base_color.rgb;
+    surface.alpha = base_color.a;
+    surface.metallic = clamp(material.metallic_factor * metallic_roughness.b, 0.0, 1.0);
+    surface.roughness = clamp(material.roughness_factor * metallic_roughness.g, 0.04, 1.0);
+    surface.occlusion = mix(1.0, occlusion, material.occlusion_strength);
+    surface.emissive = emissive;
+    return surface;
+}
+
+// True where an alpha-tested material cuts the surface out
+fn alpha_clipped(surface: Surface) -> bool {
+    return material.blend_mode == BLEND_MODE_ALPHA_TEST && surface.alpha < material.alpha_cutoff;
+}
+"#;
+
+/// WGSL lighting of a `Surface` by the sun, the clustered lights and the environment,
+/// shared by the forward shader and the deferred lighting pass. Expects the shadow,
+/// light, environment and ambient occlusion declarations and `SURFACE_TYPES_WGSL`.
+const PBR_SHADING_WGSL: &str = r#"
+struct SunLight {
+    direction: vec3<f32>,
+    _padding1: u32,
+    color: vec3<f32>,
+    intensity: f32,
+    use_pbr: u32,
+    _padding2: u32,
+    _padding3: u32,
+    _padding4: u32,
+};
+
+@group(0) @binding(1)
+var<uniform> sun_light: SunLight;
+
+// PBR helper functions
+fn distributionGGX(N: vec3<f32>, H: vec3<f32>, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let NdotH = max(dot(N, H), 0.0);
+    let NdotH2 = NdotH * NdotH;
+    
+    let num = a2;
+    let denom = (NdotH2 * (a2 - 1.0) + 1.0);
+    return num / (3.14159265359 * denom * denom);
+}
+
+fn geometrySchlickGGX(NdotV: f32, roughness: f32) -> f32 {
+    let r = (roughness + 1.0);
+    let k = (r * r) / 8.0;
+    
+    return NdotV / (NdotV * (1.0 - k) + k);
+}
+
+fn geometrySmith(N: vec3<f32>, V: vec3<f32>, L: vec3<f32>, roughness: f32) -> f32 {
+    let NdotV = max(dot(N, V), 0.0);
+    let NdotL = max(dot(N, L), 0.0);
+    let ggx2 = geometrySchlickGGX(NdotV, roughness);
+    let ggx1 = geometrySchlickGGX(NdotL, roughness);
+    
+    return ggx1 * ggx2;
+}
+
+fn fresnelSchlick(cosTheta: f32, F0: vec3<f32>) -> vec3<f32> {
+    return F0 + (1.0 - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+// Cook-Torrance BRDF for one light arriving from light_dir with the given radiance
+fn evaluate_light(
+    N: vec3<f32>,
+    V: vec3<f32>,
+    light_dir: vec3<f32>,
+    radiance: vec3<f32>,
+    albedo: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+) -> vec3<f32> {
+    let NdotL = max(dot(N, light_dir), 0.0);
+    if (NdotL <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+    
+    // Reflectance at normal incidence (Fresnel F0)
+    let F0 = mix(vec3<f32>(0.04), albedo, metallic);
+    let H = normalize(V + light_dir);
+    
+    let NDF = distributionGGX(N, H, roughness);
+    let G = geometrySmith(N, V, light_dir, roughness);
+    let F = fresnelSchlick(max(dot(H, V), 0.0), F0);
+    
+    let kS = F; // Specular contribution
+    let kD = (vec3<f32>(1.0) - kS) * (1.0 - metallic); // Diffuse contribution
+    
+    // Specular component
+    let numerator = NDF * G * F;
+    let denominator = 4.0 * max(dot(N, V), 0.0) * NdotL + 0.0001;
+    let specular = numerator / denominator;
+    
+    return (kD * albedo / 3.14159265359 + specular) * radiance * NdotL;
+}
+
+// Linear HDR radiance leaving the surface towards V, for the pixel at frag_coord
+fn shade_surface(frag_coord: vec2<f32>, surface: Surface, V: vec3<f32>) -> vec3<f32> {
+    let N = surface.normal;
+    let albedo = surface.albedo;
+    let metallic = surface.metallic;
+    let roughness = surface.roughness;
+    
+    // Fraction of the sun that reaches this fragment
+    let shadow_factor = sample_shadow(surface.position, N);
+    
+    // Direct lighting from the sun
+    let light_dir = normalize(-sun_light.direction);
+    let light_color = sun_light.color * sun_light.intensity;
+    var Lo = evaluate_light(N, V, light_dir, light_color * shadow_factor, albedo, metallic, roughness);
+    
+    // Lambert term of every light, used when PBR is disabled
+    var diffuse = max(dot(N, light_dir), 0.0) * shadow_factor * light_color;
+    
+    // Point and spot lights in this fragment's cluster
+    let lights = light_range(frag_coord, surface.position);
+    for (var i = 0u; i < lights.point_count; i = i + 1u) {
+        let light = point_lights[point_light_index(lights, i)];
+        let to_light = light.position - surface.position;
+        let distance = length(to_light);
+        let L = to_light / max(distance, 0.0001);
+        let radiance = light.color * light.intensity * distance_attenuation(distance, light.radius, light.falloff);
+        Lo += evaluate_light(N, V, L, radiance, albedo, metallic, roughness);
+        diffuse += max(dot(N, L), 0.0) * radiance;
+    }
+    
+    for (var i = 0u; i < lights.spot_count; i = i + 1u) {
+        let light = spot_lights[spot_light_index(lights, i)];
+        let to_light = light.position - surface.position;
+        let distance = length(to_light);
+        let L = to_light / max(distance, 0.0001);
+        let radiance = light.color * light.intensity
+            * distance_attenuation(distance, light.range, 1.0)
+            * spot_attenuation(light, L);
+        Lo += evaluate_light(N, V, L, radiance, albedo, metallic, roughness);
+        diffuse += max(dot(N, L), 0.0) * radiance;
+    }
+    
+    // Ambient lighting from the environment, darkened where nearby geometry blocks it
+    let ambient_occlusion = surface.occlusion * screen_space_occlusion(frag_coord);
+    let ambient = image_based_lighting(N, V, albedo, metallic, roughness) * ambient_occlusion;
+    
+    // Final color
+    var final_color = vec3<f32>(0.0);
+    
+    // Debug visualization modes (based on material properties)
+    let debug_mode = 0;  // 0 = normal rendering, 1 = metallic visualization, 2 = roughness visualization
+    
+    if (debug_mode == 1) {
+        final_color = vec3<f32>(metallic);
+    } else if (debug_mode == 2) {
+        final_color = vec3<f32>(roughness);
+    } else if (sun_light.use_pbr != 0u) {
+        final_color = ambient + Lo + surface.emissive;
+    } else {
+        // Use simple diffuse/ambient lighting
+        let ambient = 0.4 * ambient_occlusion;
+        final_color = albedo * (ambient + diffuse) + surface.emissive;
+    }
+    
+    // Tint by shadow cascade when the debug view is on
+    final_color *= cascade_debug_tint(surface.position);
+    
+    // Show the number of lights per cluster when the heatmap is on
+    return light_heatmap(final_color, lights);
+}
+"#;
 
 /// Shader manager for handling shader modules
 pub struct ShaderManager {
@@ -21,6 +293,26 @@ impl ShaderManager {
         // Initialize with default shaders
         manager.create_basic_3d_shader(device);
         manager.create_pbr_shader(device);
+        manager.create_gbuffer_shader(device);
+        manager.create_deferred_lighting_shader(device);
+        manager.create_oit_composite_shader(device);
+        manager.create_viewport_blit_shader(device);
+        manager.create_shadow_shader(device);
+        manager.create_ambient_occlusion_prepass_shader(device);
+        manager.create_ambient_occlusion_shader(device);
+        manager.create_light_culling_shader(device);
+        manager.create_tonemap_shader(device);
+        manager.create_exposure_histogram_shader(device);
+        manager.create_bloom_shader(device);
+        manager.create_post_process_shader(device);
+        manager.create_environment_equirect_shader(device);
+        manager.create_environment_irradiance_shader(device);
+        manager.create_environment_prefilter_shader(device);
+        manager.create_brdf_lut_shader(device);
+        manager.create_skybox_shader(device);
+        manager.create_atmosphere_shader(device);
+        manager.create_hiz_shader(device);
+        manager.create_gpu_culling_shader(device);
         
         manager
     }
@@ -109,6 +401,252 @@ impl ShaderManager {
     
     /// Create a PBR shader
     pub fn create_pbr_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        @fragment
+        fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+            let surface = material_surface(in);
+            
+            // Leave the pixels of a cross-fade to the other level of detail, once the
+            // samples needing uniform control flow are taken
+            if (lod_faded_out(in.clip_position.xy, in.lod_fade) || alpha_clipped(surface)) {
+                discard;
+            }
+            
+            let V = view_direction(in.position, in.view_dir);
+            
+            // Output stays linear HDR, the tonemap pass maps it to the display. Alpha
+            // only matters to the blended pipelines.
+            let alpha = select(1.0, surface.alpha, material.blend_mode >= BLEND_MODE_ALPHA_BLEND);
+            return vec4<f32>(shade_surface(in.clip_position.xy, surface, V), alpha);
+        }
+        
+        struct OitOutput {
+            @location(0) accumulation: vec4<f32>, // Weighted premultiplied color, and alpha for the revealage
+            @location(1) weight: vec4<f32>,       // Weighted alpha
+        };
+        
+        // Weighted blended order-independent transparency (McGuire and Bavoil 2013). The
+        // weight favors surfaces near the camera, so the nearest layer dominates the average.
+        @fragment
+        fn fs_oit(in: VertexOutput) -> OitOutput {
+            let surface = material_surface(in);
+            if (lod_faded_out(in.clip_position.xy, in.lod_fade)) {
+                discard;
+            }
+            
+            let V = view_direction(in.position, in.view_dir);
+            let color = shade_surface(in.clip_position.xy, surface, V);
+            let alpha = surface.alpha;
+            let weight = alpha * clamp(3000.0 * pow(1.0 - in.clip_position.z, 3.0), 0.01, 3000.0);
+            
+            var out: OitOutput;
+            out.accumulation = vec4<f32>(color * alpha * weight, alpha);
+            out.weight = vec4<f32>(alpha * weight);
+            return out;
+        }
+        "#;
+        
+        let source = [
+            SHADOW_SAMPLING_WGSL,
+            LIGHT_TYPES_WGSL,
+            LIGHTS_WGSL,
+            ENVIRONMENT_WGSL,
+            AMBIENT_OCCLUSION_TYPES_WGSL,
+            AMBIENT_OCCLUSION_WGSL,
+            VIEW_WGSL,
+            MATERIAL_WGSL,
+            INSTANCE_WGSL,
+            LOD_FADE_WGSL,
+            SURFACE_TYPES_WGSL,
+            MESH_SURFACE_WGSL,
+            PBR_SHADING_WGSL,
+            source,
+        ].concat();
+        self.load_from_string(device, "pbr", &source)
+    }
+    
+    /// Create the shader that writes the surfaces of opaque meshes into the G-buffer
+    pub fn create_gbuffer_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        struct GBufferOutput {
+            @location(0) albedo: vec4<f32>,   // Base color, material occlusion in alpha
+            @location(1) normal: vec4<f32>,   // World-space normal
+            @location(2) material: vec4<f32>, // Metallic and roughness
+            @location(3) emissive: vec4<f32>,
+        };
+
+        @fragment
+        fn fs_main(in: VertexOutput) -> GBufferOutput {
+            let surface = material_surface(in);
+            if (lod_faded_out(in.clip_position.xy, in.lod_fade) || alpha_clipped(surface)) {
+                discard;
+            }
+            
+            var out: GBufferOutput;
+            out.albedo = vec4<f32>(surface.albedo, surface.occlusion);
+            out.normal = vec4<f32>(surface.normal, 0.0);
+            out.material = vec4<f32>(surface.metallic, surface.roughness, 0.0, 0.0);
+            out.emissive = vec4<f32>(surface.emissive, 0.0);
+            return out;
+        }
+        "#;
+        
+        let source = [
+            VIEW_WGSL,
+            MATERIAL_WGSL,
+            INSTANCE_WGSL,
+            LOD_FADE_WGSL,
+            SURFACE_TYPES_WGSL,
+            MESH_SURFACE_WGSL,
+            source,
+        ].concat();
+        self.load_from_string(device, "gbuffer", &source)
+    }
+    
+    /// Create the fullscreen pass that lights the G-buffer with the same shading as the PBR shader
+    pub fn create_deferred_lighting_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        struct DeferredUniforms {
+            inverse_view_proj: mat4x4<f32>,
+            screen_size: vec2<f32>,
+        };
+
+        @group(0) @binding(0)
+        var<uniform> deferred: DeferredUniforms;
+        @group(0) @binding(2)
+        var g_albedo: texture_2d<f32>;
+        @group(0) @binding(3)
+        var g_normal: texture_2d<f32>;
+        @group(0) @binding(4)
+        var g_material: texture_2d<f32>;
+        @group(0) @binding(5)
+        var g_emissive: texture_2d<f32>;
+        @group(0) @binding(6)
+        var g_depth: texture_2d<f32>;
+
+        // Single triangle covering the whole target
+        @vertex
+        fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+            let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+            return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+        }
+
+        @fragment
+        fn fs_main(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+            let pixel = vec2<i32>(frag_coord.xy);
+            let depth = textureLoad(g_depth, pixel, 0).r;
+            // Leave the background to the sky
+            if (depth >= 1.0) {
+                discard;
+            }
+            
+            // World-space position from the depth buffer
+            let uv = frag_coord.xy / deferred.screen_size;
+            let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+            let world = deferred.inverse_view_proj * ndc;
+            let position = world.xyz / world.w;
+            
+            let albedo = textureLoad(g_albedo, pixel, 0);
+            let material = textureLoad(g_material, pixel, 0);
+            var surface: Surface;
+            surface.position = position;
+            surface.normal = normalize(textureLoad(g_normal, pixel, 0).xyz);
+            surface.albedo = albedo.rgb;
+            surface.alpha = 1.0;
+            surface.occlusion = albedo.a;
+            surface.metallic = material.r;
+            surface.roughness = material.g;
+            surface.emissive = textureLoad(g_emissive, pixel, 0).rgb;
+            
+            let V = view_direction(position, -position);
+            return vec4<f32>(shade_surface(frag_coord.xy, surface, V), 1.0);
+        }
+        "#;
+        
+        let source = [
+            SHADOW_SAMPLING_WGSL,
+            LIGHT_TYPES_WGSL,
+            LIGHTS_WGSL,
+            ENVIRONMENT_WGSL,
+            AMBIENT_OCCLUSION_TYPES_WGSL,
+            AMBIENT_OCCLUSION_WGSL,
+            SURFACE_TYPES_WGSL,
+            PBR_SHADING_WGSL,
+            source,
+        ].concat();
+        self.load_from_string(device, "deferred_lighting", &source)
+    }
+    
+    /// Create the fullscreen pass that blends the average of the accumulated transparent
+    /// surfaces over the scene
+    pub fn create_oit_composite_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        @group(0) @binding(0)
+        var t_accumulation: texture_2d<f32>;
+        @group(0) @binding(1)
+        var t_weight: texture_2d<f32>;
+
+        // Single triangle covering the whole target
+        @vertex
+        fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+            let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+            return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+        }
+
+        @fragment
+        fn fs_main(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+            let pixel = vec2<i32>(frag_coord.xy);
+            let accumulation = textureLoad(t_accumulation, pixel, 0);
+            // How much of the scene still shows through every surface
+            let revealage = accumulation.a;
+            if (revealage >= 1.0) {
+                discard;
+            }
+            
+            let weight = textureLoad(t_weight, pixel, 0).r;
+            let color = accumulation.rgb / clamp(weight, 1e-4, 5e4);
+            return vec4<f32>(color, 1.0 - revealage);
+        }
+        "#;
+        
+        self.load_from_string(device, "oit_composite", source)
+    }
+    
+    /// Create the pass that copies a view's image into its viewport of the surface
+    pub fn create_viewport_blit_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        @group(0) @binding(0)
+        var t_view: texture_2d<f32>;
+
+        struct VertexOutput {
+            @builtin(position) clip_position: vec4<f32>,
+            @location(0) uv: vec2<f32>,
+        };
+
+        // Single triangle covering the viewport
+        @vertex
+        fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+            let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+            var out: VertexOutput;
+            out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+            out.uv = uv;
+            return out;
+        }
+
+        @fragment
+        fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+            // The view's image has the viewport's size, so each pixel reads one texel
+            let size = vec2<f32>(textureDimensions(t_view));
+            let texel = min(vec2<i32>(in.uv * size), vec2<i32>(size) - 1);
+            return textureLoad(t_view, texel, 0);
+        }
+        "#;
+        
+        self.load_from_string(device, "viewport_blit", source)
+    }
+    
+    /// Create the depth-only shader used to render shadow maps
+    pub fn create_shadow_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
         let source = r#"
         struct Uniforms {
             view_proj: mat4x4<f32>,
@@ -118,166 +656,1329 @@ impl ShaderManager {
         @group(0) @binding(0)
         var<uniform> uniforms: Uniforms;
 
-        @group(0) @binding(1)
-        var t_diffuse: texture_2d<f32>;
-        @group(0) @binding(2)
-        var s_diffuse: sampler;
-
-        struct SunLight {
-            direction: vec3<f32>,
-            _padding1: u32,
-            color: vec3<f32>,
-            intensity: f32,
-            use_pbr: u32,
-            metallic_factor: f32,
-            roughness_factor: f32,
-            _padding2: f32,
+        struct ShadowPass {
+            light_view_proj: mat4x4<f32>,
         };
 
-        @group(0) @binding(3)
-        var<uniform> sun_light: SunLight;
+        @group(1) @binding(0)
+        var<uniform> shadow_pass: ShadowPass;
 
-        struct VertexInput {
-            @location(0) position: vec3<f32>,
-            @location(1) normal: vec3<f32>,
-            @location(2) tex_coords: vec2<f32>,
+        @vertex
+        fn vs_main(@location(0) position: vec3<f32>, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+            return shadow_pass.light_view_proj * uniforms.model * instance_model(instance) * vec4<f32>(position, 1.0);
+        }
+        "#;
+        
+        let source = [INSTANCE_WGSL, source].concat();
+        self.load_from_string(device, "shadow", &source)
+    }
+
+    /// Create the prepass that renders depth and view-space normals for ambient occlusion
+    pub fn create_ambient_occlusion_prepass_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        struct Uniforms {
+            view_proj: mat4x4<f32>,
+            model: mat4x4<f32>,
         };
 
+        @group(0) @binding(0)
+        var<uniform> uniforms: Uniforms;
+        @group(1) @binding(0)
+        var<uniform> ambient_occlusion: AmbientOcclusionUniforms;
+
         struct VertexOutput {
             @builtin(position) clip_position: vec4<f32>,
-            @location(0) position: vec3<f32>,
-            @location(1) normal: vec3<f32>,
-            @location(2) tex_coords: vec2<f32>,
-            @location(3) view_dir: vec3<f32>,
+            @location(0) normal: vec3<f32>,
         };
 
         @vertex
         fn vs_main(
-            in: VertexInput,
+            @location(0) position: vec3<f32>,
+            @location(1) normal: vec3<f32>,
+            instance: InstanceInput,
         ) -> VertexOutput {
+            let model_view = ambient_occlusion.view * uniforms.model * instance_model(instance);
             var out: VertexOutput;
-            out.clip_position = uniforms.view_proj * uniforms.model * vec4<f32>(in.position, 1.0);
-            
-            // Transform position and normal to world space
-            let model_matrix = uniforms.model;
-            out.position = (model_matrix * vec4<f32>(in.position, 1.0)).xyz;
-            out.normal = normalize((model_matrix * vec4<f32>(in.normal, 0.0)).xyz);
-            
-            // Calculate view direction (from position to camera)
-            out.view_dir = normalize(-out.position);
-            out.tex_coords = in.tex_coords;
-            
+            out.clip_position = ambient_occlusion.projection * model_view * vec4<f32>(position, 1.0);
+            out.normal = (model_view * vec4<f32>(normal, 0.0)).xyz;
             return out;
         }
 
-        // PBR helper functions
-        fn distributionGGX(N: vec3<f32>, H: vec3<f32>, roughness: f32) -> f32 {
-            let a = roughness * roughness;
-            let a2 = a * a;
-            let NdotH = max(dot(N, H), 0.0);
-            let NdotH2 = NdotH * NdotH;
-            
-            let num = a2;
-            let denom = (NdotH2 * (a2 - 1.0) + 1.0);
-            return num / (3.14159265359 * denom * denom);
+        @fragment
+        fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+            return vec4<f32>(normalize(in.normal), 1.0);
         }
-        
-        fn geometrySchlickGGX(NdotV: f32, roughness: f32) -> f32 {
-            let r = (roughness + 1.0);
-            let k = (r * r) / 8.0;
-            
-            return NdotV / (NdotV * (1.0 - k) + k);
+        "#;
+
+        let source = [AMBIENT_OCCLUSION_TYPES_WGSL, INSTANCE_WGSL, source].concat();
+        self.load_from_string(device, "ambient_occlusion_prepass", &source)
+    }
+
+    /// Create the SSAO pass, the depth-aware blur applied to its result along rows and
+    /// columns, and the debug view of the raw occlusion
+    pub fn create_ambient_occlusion_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        @group(0) @binding(0)
+        var<uniform> ambient_occlusion: AmbientOcclusionUniforms;
+        @group(0) @binding(1)
+        var depth_texture: texture_2d<f32>;
+        @group(0) @binding(2)
+        var source_texture: texture_2d<f32>;
+
+        // Taps on each side of the blurred pixel
+        const BLUR_RADIUS: i32 = 4;
+        // How fast blur weights fall off with the relative depth difference
+        const BLUR_DEPTH_SHARPNESS: f32 = 100.0;
+
+        // Single triangle covering the whole target
+        @vertex
+        fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+            let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+            return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
         }
-        
-        fn geometrySmith(N: vec3<f32>, V: vec3<f32>, L: vec3<f32>, roughness: f32) -> f32 {
-            let NdotV = max(dot(N, V), 0.0);
-            let NdotL = max(dot(N, L), 0.0);
-            let ggx2 = geometrySchlickGGX(NdotV, roughness);
-            let ggx1 = geometrySchlickGGX(NdotL, roughness);
-            
-            return ggx1 * ggx2;
+
+        fn load_depth(pixel: vec2<i32>) -> f32 {
+            let size = vec2<i32>(ambient_occlusion.screen_size);
+            return textureLoad(depth_texture, clamp(pixel, vec2<i32>(0), size - 1), 0).r;
         }
-        
-        fn fresnelSchlick(cosTheta: f32, F0: vec3<f32>) -> vec3<f32> {
-            return F0 + (1.0 - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+
+        // View-space position of the point at `depth` behind a position on the screen
+        fn view_position(frag_coord: vec2<f32>, depth: f32) -> vec3<f32> {
+            let uv = frag_coord / ambient_occlusion.screen_size;
+            let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+            let position = ambient_occlusion.inverse_projection * ndc;
+            return position.xyz / position.w;
+        }
+
+        fn linear_depth(pixel: vec2<i32>) -> f32 {
+            return -view_position(vec2<f32>(pixel) + 0.5, load_depth(pixel)).z;
+        }
+
+        // Per-pixel noise that averages out well under a small blur
+        fn interleaved_gradient_noise(frag_coord: vec2<f32>) -> f32 {
+            return fract(52.9829189 * fract(dot(frag_coord, vec2<f32>(0.06711056, 0.00583715))));
         }
 
         @fragment
-        fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-            // Sample the texture
-            let albedo = textureSample(t_diffuse, s_diffuse, in.tex_coords).rgb;
-            
-            // Normal and view vectors
-            let N = normalize(in.normal);
-            let V = normalize(in.view_dir);
-            
-            // Material properties
-            let metallic = sun_light.metallic_factor;
-            let roughness = sun_light.roughness_factor;
-            let ao = 1.0; // Ambient occlusion default
-            
-            // Reflectance at normal incidence (Fresnel F0)
-            let F0 = mix(vec3<f32>(0.04), albedo, metallic);
-            
-            // Direct lighting calculation
-            let light_dir = normalize(-sun_light.direction);
-            let light_color = sun_light.color * sun_light.intensity;
-            
-            // Calculate the light parameters for PBR
-            let H = normalize(V + light_dir);
-            let NdotL = max(dot(N, light_dir), 0.0);
-            
-            // Cook-Torrance BRDF
-            let NDF = distributionGGX(N, H, roughness);
-            let G = geometrySmith(N, V, light_dir, roughness);
-            let F = fresnelSchlick(max(dot(H, V), 0.0), F0);
-            
-            let kS = F; // Specular contribution
-            let kD = (vec3<f32>(1.0) - kS) * (1.0 - metallic); // Diffuse contribution
-            
-            // Specular component
-            let numerator = NDF * G * F;
-            let denominator = 4.0 * max(dot(N, V), 0.0) * NdotL + 0.0001;
-            let specular = numerator / denominator;
-            
-            // Combine diffuse and specular
-            var Lo = vec3<f32>(0.0);
-            if (NdotL > 0.0) {
-                Lo += (kD * albedo / 3.14159265359 + specular) * light_color * NdotL;
+        fn fs_ssao(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+            let pixel = vec2<i32>(frag_coord.xy);
+            let depth = load_depth(pixel);
+            if (depth >= 1.0) {
+                return vec4<f32>(1.0);
             }
-            
-            // Ambient lighting
-            let ambient = vec3<f32>(0.2) * albedo * ao;
-            
-            // Final color
-            var final_color = vec3<f32>(0.0);
-            
-            // Debug visualization modes (based on material properties)
-            let debug_mode = 0;  // 0 = normal rendering, 1 = metallic visualization, 2 = roughness visualization
-            
-            if (debug_mode == 1) {
-                final_color = vec3<f32>(metallic);
-            } else if (debug_mode == 2) {
-                final_color = vec3<f32>(roughness);
-            } else if (sun_light.use_pbr != 0u) {
-                final_color = ambient + Lo;
-            } else {
-                // Use simple diffuse/ambient lighting
-                let diffuse = max(dot(N, light_dir), 0.0);
-                let ambient = 0.4;
-                final_color = albedo * (ambient + diffuse * sun_light.intensity * sun_light.color);
+            let P = view_position(frag_coord.xy, depth);
+            let N = normalize(textureLoad(source_texture, pixel, 0).xyz);
+
+            // Rotate the kernel around the normal by a different angle at each pixel
+            let angle = interleaved_gradient_noise(frag_coord.xy) * 6.28318530718;
+            let seed = vec3<f32>(cos(angle), sin(angle), 0.0);
+            var T = seed - N * dot(seed, N);
+            if (dot(T, T) < 0.0001) {
+                T = vec3<f32>(0.0, 0.0, 1.0) - N * N.z;
             }
-            
-            // Apply tone mapping (HDR -> LDR)
-            final_color = final_color / (final_color + vec3<f32>(1.0));
-            
-            // Apply gamma correction
-            final_color = pow(final_color, vec3<f32>(1.0/2.2));
-            
-            return vec4<f32>(final_color, 1.0);
+            T = normalize(T);
+            let tbn = mat3x3<f32>(T, cross(N, T), N);
+
+            var occlusion = 0.0;
+            for (var i = 0u; i < ambient_occlusion.sample_count; i = i + 1u) {
+                let sample_position = P + tbn * ambient_occlusion.kernel[i].xyz * ambient_occlusion.radius;
+                let clip = ambient_occlusion.projection * vec4<f32>(sample_position, 1.0);
+                let ndc = clip.xy / clip.w;
+                let sample_coord = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5) * ambient_occlusion.screen_size;
+                if (any(sample_coord < vec2<f32>(0.0)) || any(sample_coord >= ambient_occlusion.screen_size)) {
+                    continue;
+                }
+                let scene_z = view_position(sample_coord, load_depth(vec2<i32>(sample_coord))).z;
+                // Geometry far in front of the pixel does not occlude it
+                let range = smoothstep(0.0, 1.0, ambient_occlusion.radius / max(abs(P.z - scene_z), 0.0001));
+                occlusion += select(0.0, 1.0, scene_z >= sample_position.z + ambient_occlusion.bias) * range;
+            }
+
+            let visibility = 1.0 - occlusion / f32(max(ambient_occlusion.sample_count, 1u));
+            return vec4<f32>(pow(max(visibility, 0.0001), ambient_occlusion.intensity), 0.0, 0.0, 1.0);
+        }
+
+        // Gaussian blur along `direction` that skips taps across depth edges
+        fn bilateral_blur(frag_coord: vec2<f32>, direction: vec2<i32>) -> f32 {
+            let center = vec2<i32>(frag_coord);
+            let size = vec2<i32>(ambient_occlusion.screen_size);
+            let center_depth = linear_depth(center);
+            var sum = 0.0;
+            var weight_sum = 0.0;
+            for (var i = -BLUR_RADIUS; i <= BLUR_RADIUS; i = i + 1) {
+                let pixel = clamp(center + direction * i, vec2<i32>(0), size - 1);
+                let depth_difference = abs(linear_depth(pixel) - center_depth) / max(center_depth, 0.0001);
+                let weight = exp(-f32(i * i) / 8.0) * exp(-depth_difference * BLUR_DEPTH_SHARPNESS);
+                sum += textureLoad(source_texture, pixel, 0).r * weight;
+                weight_sum += weight;
+            }
+            return sum / weight_sum;
+        }
+
+        @fragment
+        fn fs_blur_horizontal(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+            return vec4<f32>(bilateral_blur(frag_coord.xy, vec2<i32>(1, 0)), 0.0, 0.0, 1.0);
+        }
+
+        @fragment
+        fn fs_blur_vertical(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+            return vec4<f32>(bilateral_blur(frag_coord.xy, vec2<i32>(0, 1)), 0.0, 0.0, 1.0);
+        }
+
+        @fragment
+        fn fs_debug(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+            var occlusion = textureLoad(source_texture, vec2<i32>(frag_coord.xy), 0).r;
+            // Show the stored values as they are, undoing the encoding an sRGB target applies
+            if (ambient_occlusion.output_srgb != 0u) {
+                occlusion = select(pow((occlusion + 0.055) / 1.055, 2.4), occlusion / 12.92, occlusion <= 0.04045);
+            }
+            return vec4<f32>(vec3<f32>(occlusion), 1.0);
         }
         "#;
-        
-        self.load_from_string(device, "pbr", source)
+
+        let source = [AMBIENT_OCCLUSION_TYPES_WGSL, source].concat();
+        self.load_from_string(device, "ambient_occlusion", &source)
     }
-} 
\ No newline at end of file
+
+    /// Create the compute shader that bins point and spot lights into view-space clusters
+    pub fn create_light_culling_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        @group(0) @binding(0)
+        var<uniform> light_counts: LightCounts;
+        @group(0) @binding(1)
+        var<storage, read> point_lights: array<PointLight>;
+        @group(0) @binding(2)
+        var<storage, read> spot_lights: array<SpotLight>;
+        @group(0) @binding(3)
+        var<uniform> clusters: ClusterUniforms;
+        @group(0) @binding(4)
+        var<storage, read_write> cluster_lights: array<ClusterLights>;
+        @group(0) @binding(5)
+        var<storage, read_write> cluster_light_indices: array<u32>;
+
+        // Squared distance from a point to a box, zero inside it
+        fn distance_to_aabb_squared(point: vec3<f32>, aabb_min: vec3<f32>, aabb_max: vec3<f32>) -> f32 {
+            let closest = clamp(point, aabb_min, aabb_max);
+            let offset = point - closest;
+            return dot(offset, offset);
+        }
+
+        // View depth of the boundary between two slices, slices are spaced exponentially
+        fn slice_depth(slice: u32) -> f32 {
+            if (slice == 0u) {
+                return 0.0;
+            }
+            return clusters.near * pow(clusters.far / clusters.near, f32(slice) / f32(clusters.grid_size.z));
+        }
+
+        @compute @workgroup_size(64)
+        fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+            let grid = clusters.grid_size;
+            let cluster = id.x;
+            if (cluster >= grid.x * grid.y * grid.z) {
+                return;
+            }
+            let x = cluster % grid.x;
+            let y = (cluster / grid.x) % grid.y;
+            let z = cluster / (grid.x * grid.y);
+
+            // Tile bounds in NDC, rows count down from the top of the screen
+            let ndc_min = vec2<f32>(
+                -1.0 + 2.0 * f32(x) / f32(grid.x),
+                1.0 - 2.0 * f32(y + 1u) / f32(grid.y),
+            );
+            let ndc_max = vec2<f32>(
+                -1.0 + 2.0 * f32(x + 1u) / f32(grid.x),
+                1.0 - 2.0 * f32(y) / f32(grid.y),
+            );
+
+            // View-space box around the tile between the slice's near and far depth
+            let near_depth = slice_depth(z);
+            let far_depth = slice_depth(z + 1u);
+            let scale = vec2<f32>(clusters.tan_half_fovy * clusters.aspect, clusters.tan_half_fovy);
+            let near_min = ndc_min * scale * near_depth;
+            let near_max = ndc_max * scale * near_depth;
+            let far_min = ndc_min * scale * far_depth;
+            let far_max = ndc_max * scale * far_depth;
+            let aabb_min = vec3<f32>(min(near_min, far_min), -far_depth);
+            let aabb_max = vec3<f32>(max(near_max, far_max), -near_depth);
+
+            let offset = cluster * clusters.max_lights;
+            var count = 0u;
+
+            // Point lights whose range sphere touches the cluster
+            var point_count = 0u;
+            for (var i = 0u; i < light_counts.point_count && count < clusters.max_lights; i = i + 1u) {
+                let light = point_lights[i];
+                let center = (clusters.view * vec4<f32>(light.position, 1.0)).xyz;
+                if (distance_to_aabb_squared(center, aabb_min, aabb_max) <= light.radius * light.radius) {
+                    cluster_light_indices[offset + count] = i;
+                    count = count + 1u;
+                    point_count = point_count + 1u;
+                }
+            }
+
+            // Spot lights, bounded by the sphere of their range
+            var spot_count = 0u;
+            for (var i = 0u; i < light_counts.spot_count && count < clusters.max_lights; i = i + 1u) {
+                let light = spot_lights[i];
+                let center = (clusters.view * vec4<f32>(light.position, 1.0)).xyz;
+                if (distance_to_aabb_squared(center, aabb_min, aabb_max) <= light.range * light.range) {
+                    cluster_light_indices[offset + count] = i;
+                    count = count + 1u;
+                    spot_count = spot_count + 1u;
+                }
+            }
+
+            cluster_lights[cluster] = ClusterLights(point_count, spot_count);
+        }
+        "#;
+        
+        let source = [LIGHT_TYPES_WGSL, source].concat();
+        self.load_from_string(device, "light_culling", &source)
+    }
+
+    /// Create the fullscreen pass that exposes and tonemaps the HDR target
+    pub fn create_tonemap_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        @group(0) @binding(0)
+        var<uniform> tonemap: TonemapUniforms;
+        @group(0) @binding(1)
+        var hdr_texture: texture_2d<f32>;
+        @group(0) @binding(2)
+        var<storage, read> exposure: Exposure;
+
+        // Single triangle covering the whole screen
+        @vertex
+        fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+            let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+            return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+        }
+
+        fn tonemap_reinhard(color: vec3<f32>) -> vec3<f32> {
+            return color / (color + vec3<f32>(1.0));
+        }
+
+        // Stephen Hill's fit of the ACES reference rendering and output transforms
+        fn tonemap_aces_filmic(color: vec3<f32>) -> vec3<f32> {
+            let input_matrix = mat3x3<f32>(
+                vec3<f32>(0.59719, 0.07600, 0.02840),
+                vec3<f32>(0.35458, 0.90834, 0.13383),
+                vec3<f32>(0.04823, 0.01566, 0.83777),
+            );
+            let output_matrix = mat3x3<f32>(
+                vec3<f32>(1.60475, -0.10208, -0.00327),
+                vec3<f32>(-0.53108, 1.10813, -0.07276),
+                vec3<f32>(-0.07367, -0.00605, 1.07602),
+            );
+            let v = input_matrix * color;
+            let a = v * (v + 0.0245786) - 0.000090537;
+            let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+            return clamp(output_matrix * (a / b), vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+
+        // AgX base look using the polynomial fit of its sigmoid, converted back to linear
+        fn tonemap_agx(color: vec3<f32>) -> vec3<f32> {
+            let inset = mat3x3<f32>(
+                vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+                vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+                vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+            );
+            let outset = mat3x3<f32>(
+                vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+                vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+                vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+            );
+            let min_ev = -12.47393;
+            let max_ev = 4.026069;
+            
+            var x = clamp(log2(max(inset * color, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+            x = (x - min_ev) / (max_ev - min_ev);
+            let x2 = x * x;
+            let x4 = x2 * x2;
+            x = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+            return pow(max(outset * x, vec3<f32>(0.0)), vec3<f32>(2.2));
+        }
+
+        fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+            let low = color * 12.92;
+            let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+            return select(high, low, color <= vec3<f32>(0.0031308));
+        }
+
+        @fragment
+        fn fs_main(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+            let hdr = textureLoad(hdr_texture, vec2<i32>(frag_coord.xy), 0).rgb;
+            
+            // Manual exposure, or compensation on top of the measured scene luminance
+            var scale = exp2(tonemap.exposure);
+            if (tonemap.auto_exposure != 0u) {
+                scale *= tonemap.middle_grey / max(exposure.luminance, 1e-4);
+            }
+            let color = max(hdr * scale, vec3<f32>(0.0));
+            
+            var mapped: vec3<f32>;
+            if (tonemap.tonemap_operator == TONEMAP_REINHARD) {
+                mapped = tonemap_reinhard(color);
+            } else if (tonemap.tonemap_operator == TONEMAP_AGX) {
+                mapped = tonemap_agx(color);
+            } else {
+                mapped = tonemap_aces_filmic(color);
+            }
+            
+            // sRGB targets encode on write; other formats need it done here
+            if (tonemap.encode_srgb != 0u) {
+                mapped = linear_to_srgb(mapped);
+            }
+            return vec4<f32>(mapped, 1.0);
+        }
+        "#;
+        
+        let source = [TONEMAP_TYPES_WGSL, source].concat();
+        self.load_from_string(device, "tonemap", &source)
+    }
+    
+    /// Create the compute shader that measures the HDR target's average luminance for auto exposure.
+    /// `build_histogram` bins every pixel's log luminance, `average_histogram` reduces the bins
+    /// to a mean, adapts the stored luminance towards it and clears the histogram.
+    pub fn create_exposure_histogram_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        @group(0) @binding(0)
+        var<uniform> tonemap: TonemapUniforms;
+        @group(0) @binding(1)
+        var hdr_texture: texture_2d<f32>;
+        @group(0) @binding(2)
+        var<storage, read_write> histogram: array<atomic<u32>, 256>;
+        @group(0) @binding(3)
+        var<storage, read_write> exposure: Exposure;
+
+        var<workgroup> local_bins: array<atomic<u32>, 256>;
+        var<workgroup> weighted_bins: array<f32, 256>;
+
+        // Bin 0 holds black pixels, bins 1-255 span the log luminance range
+        fn luminance_bin(color: vec3<f32>) -> u32 {
+            let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+            if (luminance < exp2(tonemap.min_log_luminance)) {
+                return 0u;
+            }
+            let t = clamp((log2(luminance) - tonemap.min_log_luminance) / tonemap.log_luminance_range, 0.0, 1.0);
+            return u32(t * 254.0 + 1.0);
+        }
+
+        @compute @workgroup_size(16, 16)
+        fn build_histogram(
+            @builtin(global_invocation_id) global_id: vec3<u32>,
+            @builtin(local_invocation_index) local_index: u32,
+        ) {
+            atomicStore(&local_bins[local_index], 0u);
+            workgroupBarrier();
+            
+            let size = textureDimensions(hdr_texture);
+            if (global_id.x < size.x && global_id.y < size.y) {
+                let color = textureLoad(hdr_texture, vec2<i32>(global_id.xy), 0).rgb;
+                atomicAdd(&local_bins[luminance_bin(color)], 1u);
+            }
+            workgroupBarrier();
+            
+            atomicAdd(&histogram[local_index], atomicLoad(&local_bins[local_index]));
+        }
+
+        @compute @workgroup_size(256)
+        fn average_histogram(@builtin(local_invocation_index) local_index: u32) {
+            let count = atomicLoad(&histogram[local_index]);
+            weighted_bins[local_index] = f32(count) * f32(local_index);
+            atomicStore(&histogram[local_index], 0u);
+            workgroupBarrier();
+            
+            // Sum count * bin over all bins
+            for (var stride = 128u; stride > 0u; stride = stride >> 1u) {
+                if (local_index < stride) {
+                    weighted_bins[local_index] += weighted_bins[local_index + stride];
+                }
+                workgroupBarrier();
+            }
+            
+            if (local_index == 0u) {
+                // Thread 0 read bin 0, the black pixels left out of the average
+                let size = textureDimensions(hdr_texture);
+                let lit_pixels = max(f32(size.x * size.y) - f32(count), 1.0);
+                let average_bin = max(weighted_bins[0] / lit_pixels, 1.0);
+                let log_luminance = (average_bin - 1.0) / 254.0 * tonemap.log_luminance_range + tonemap.min_log_luminance;
+                let target_luminance = exp2(log_luminance);
+                
+                // The first measurement is taken as is
+                let previous = exposure.luminance;
+                exposure.luminance = select(
+                    mix(previous, target_luminance, tonemap.adaptation),
+                    target_luminance,
+                    previous <= 0.0,
+                );
+            }
+        }
+        "#;
+        
+        let source = [TONEMAP_TYPES_WGSL, source].concat();
+        self.load_from_string(device, "exposure_histogram", &source)
+    }
+    
+    /// Create the bloom passes: `fs_prefilter` keeps what is above the threshold while halving
+    /// the HDR scene, `fs_downsample` and `fs_upsample` form the dual-filter blur chain and
+    /// `fs_composite` scales the blurred result for adding onto the scene
+    pub fn create_bloom_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        @group(0) @binding(0)
+        var<uniform> post: PostProcessUniforms;
+        @group(0) @binding(1)
+        var source_texture: texture_2d<f32>;
+        @group(0) @binding(2)
+        var source_sampler: sampler;
+
+        fn sample_source(uv: vec2<f32>) -> vec3<f32> {
+            return textureSampleLevel(source_texture, source_sampler, uv, 0.0).rgb;
+        }
+
+        // Center plus four bilinear taps on the diagonals, covering the texels being halved
+        fn downsample(uv: vec2<f32>) -> vec3<f32> {
+            let offset = 0.5 / vec2<f32>(textureDimensions(source_texture));
+            var sum = sample_source(uv) * 4.0;
+            sum += sample_source(uv - offset);
+            sum += sample_source(uv + offset);
+            sum += sample_source(uv + vec2<f32>(offset.x, -offset.y));
+            sum += sample_source(uv - vec2<f32>(offset.x, -offset.y));
+            return sum / 8.0;
+        }
+
+        // Tent of eight bilinear taps around the center
+        fn upsample(uv: vec2<f32>) -> vec3<f32> {
+            let offset = 0.5 / vec2<f32>(textureDimensions(source_texture));
+            var sum = sample_source(uv + vec2<f32>(-2.0 * offset.x, 0.0));
+            sum += sample_source(uv + vec2<f32>(2.0 * offset.x, 0.0));
+            sum += sample_source(uv + vec2<f32>(0.0, -2.0 * offset.y));
+            sum += sample_source(uv + vec2<f32>(0.0, 2.0 * offset.y));
+            sum += sample_source(uv + vec2<f32>(-offset.x, offset.y)) * 2.0;
+            sum += sample_source(uv + vec2<f32>(offset.x, offset.y)) * 2.0;
+            sum += sample_source(uv + vec2<f32>(-offset.x, -offset.y)) * 2.0;
+            sum += sample_source(uv + vec2<f32>(offset.x, -offset.y)) * 2.0;
+            return sum / 12.0;
+        }
+
+        @fragment
+        fn fs_prefilter(in: FullscreenOutput) -> @location(0) vec4<f32> {
+            // Clamped so single very bright pixels do not flicker through the whole chain
+            let color = min(downsample(in.uv), vec3<f32>(256.0));
+            let brightness = max(color.r, max(color.g, color.b));
+            // Quadratic soft knee around the threshold
+            let knee = max(post.bloom_knee, 0.0001);
+            var soft = clamp(brightness - post.bloom_threshold + knee, 0.0, 2.0 * knee);
+            soft = soft * soft / (4.0 * knee);
+            let contribution = max(soft, brightness - post.bloom_threshold) / max(brightness, 0.0001);
+            return vec4<f32>(color * contribution, 1.0);
+        }
+
+        @fragment
+        fn fs_downsample(in: FullscreenOutput) -> @location(0) vec4<f32> {
+            return vec4<f32>(downsample(in.uv), 1.0);
+        }
+
+        @fragment
+        fn fs_upsample(in: FullscreenOutput) -> @location(0) vec4<f32> {
+            return vec4<f32>(upsample(in.uv), 1.0);
+        }
+
+        @fragment
+        fn fs_composite(in: FullscreenOutput) -> @location(0) vec4<f32> {
+            return vec4<f32>(upsample(in.uv) * post.bloom_intensity, 0.0);
+        }
+        "#;
+
+        let source = [POST_PROCESS_TYPES_WGSL, source].concat();
+        self.load_from_string(device, "bloom", &source)
+    }
+    
+    /// Create the effects that run on the tonemapped image, one fragment entry point each
+    pub fn create_post_process_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        @group(0) @binding(0)
+        var<uniform> post: PostProcessUniforms;
+        @group(0) @binding(1)
+        var source_texture: texture_2d<f32>;
+        @group(0) @binding(2)
+        var source_sampler: sampler;
+        @group(0) @binding(3)
+        var lut_texture: texture_3d<f32>;
+
+        fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+            let higher = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+            return select(higher, color * 12.92, color <= vec3<f32>(0.0031308));
+        }
+
+        fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+            let higher = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+            return select(higher, color / 12.92, color <= vec3<f32>(0.04045));
+        }
+
+        // sRGB targets decode when sampled and encode when written, others hold encoded values
+        fn to_display(color: vec3<f32>) -> vec3<f32> {
+            if (post.display_encoded != 0u) {
+                return color;
+            }
+            return linear_to_srgb(clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)));
+        }
+
+        fn from_display(color: vec3<f32>) -> vec4<f32> {
+            if (post.display_encoded != 0u) {
+                return vec4<f32>(color, 1.0);
+            }
+            return vec4<f32>(srgb_to_linear(color), 1.0);
+        }
+
+        fn sample_display(uv: vec2<f32>) -> vec3<f32> {
+            return to_display(textureSampleLevel(source_texture, source_sampler, uv, 0.0).rgb);
+        }
+
+        fn luma_at(uv: vec2<f32>) -> f32 {
+            return dot(sample_display(uv), vec3<f32>(0.299, 0.587, 0.114));
+        }
+
+        // Distance of each edge search step, growing with the number of steps taken
+        fn fxaa_step(i: i32) -> f32 {
+            if (i < 5) {
+                return 1.0;
+            } else if (i == 5) {
+                return 1.5;
+            } else if (i < 10) {
+                return 2.0;
+            } else if (i == 10) {
+                return 4.0;
+            }
+            return 8.0;
+        }
+
+        // FXAA 3.11 quality: find the edge through the pixel, search along it for both ends and
+        // blend towards the neighbor across it by how close the pixel is to an end
+        @fragment
+        fn fs_fxaa(in: FullscreenOutput) -> @location(0) vec4<f32> {
+            let texel = 1.0 / vec2<f32>(textureDimensions(source_texture));
+            let uv = in.uv;
+            let center = sample_display(uv);
+            let luma_center = dot(center, vec3<f32>(0.299, 0.587, 0.114));
+            let luma_up = luma_at(uv + vec2<f32>(0.0, -texel.y));
+            let luma_down = luma_at(uv + vec2<f32>(0.0, texel.y));
+            let luma_left = luma_at(uv + vec2<f32>(-texel.x, 0.0));
+            let luma_right = luma_at(uv + vec2<f32>(texel.x, 0.0));
+
+            let luma_min = min(luma_center, min(min(luma_up, luma_down), min(luma_left, luma_right)));
+            let luma_max = max(luma_center, max(max(luma_up, luma_down), max(luma_left, luma_right)));
+            let luma_range = luma_max - luma_min;
+            if (luma_range < max(post.fxaa_edge_threshold_min, luma_max * post.fxaa_edge_threshold)) {
+                return from_display(center);
+            }
+
+            let luma_up_left = luma_at(uv + vec2<f32>(-texel.x, -texel.y));
+            let luma_up_right = luma_at(uv + vec2<f32>(texel.x, -texel.y));
+            let luma_down_left = luma_at(uv + vec2<f32>(-texel.x, texel.y));
+            let luma_down_right = luma_at(uv + vec2<f32>(texel.x, texel.y));
+
+            let edge_horizontal = abs(luma_up_left + luma_down_left - 2.0 * luma_left)
+                + 2.0 * abs(luma_up + luma_down - 2.0 * luma_center)
+                + abs(luma_up_right + luma_down_right - 2.0 * luma_right);
+            let edge_vertical = abs(luma_up_left + luma_up_right - 2.0 * luma_up)
+                + 2.0 * abs(luma_left + luma_right - 2.0 * luma_center)
+                + abs(luma_down_left + luma_down_right - 2.0 * luma_down);
+            let is_horizontal = edge_horizontal >= edge_vertical;
+
+            // Pick the side of the edge with the steeper gradient
+            let luma_negative = select(luma_left, luma_up, is_horizontal);
+            let luma_positive = select(luma_right, luma_down, is_horizontal);
+            let gradient_negative = luma_negative - luma_center;
+            let gradient_positive = luma_positive - luma_center;
+            let negative_steepest = abs(gradient_negative) >= abs(gradient_positive);
+            let gradient_scaled = 0.25 * max(abs(gradient_negative), abs(gradient_positive));
+            var step_length = select(texel.x, texel.y, is_horizontal);
+            var luma_local_average = 0.5 * (luma_positive + luma_center);
+            if (negative_steepest) {
+                step_length = -step_length;
+                luma_local_average = 0.5 * (luma_negative + luma_center);
+            }
+
+            // Walk along the edge, half a pixel onto it, until the luma leaves the edge's average
+            var edge_uv = uv;
+            if (is_horizontal) {
+                edge_uv.y += step_length * 0.5;
+            } else {
+                edge_uv.x += step_length * 0.5;
+            }
+            let offset = select(vec2<f32>(0.0, texel.y), vec2<f32>(texel.x, 0.0), is_horizontal);
+            var uv_negative = edge_uv - offset;
+            var uv_positive = edge_uv + offset;
+            var luma_end_negative = luma_at(uv_negative) - luma_local_average;
+            var luma_end_positive = luma_at(uv_positive) - luma_local_average;
+            var reached_negative = abs(luma_end_negative) >= gradient_scaled;
+            var reached_positive = abs(luma_end_positive) >= gradient_scaled;
+            for (var i = 0; i < 12; i = i + 1) {
+                if (reached_negative && reached_positive) {
+                    break;
+                }
+                if (!reached_negative) {
+                    uv_negative -= offset * fxaa_step(i);
+                    luma_end_negative = luma_at(uv_negative) - luma_local_average;
+                    reached_negative = abs(luma_end_negative) >= gradient_scaled;
+                }
+                if (!reached_positive) {
+                    uv_positive += offset * fxaa_step(i);
+                    luma_end_positive = luma_at(uv_positive) - luma_local_average;
+                    reached_positive = abs(luma_end_positive) >= gradient_scaled;
+                }
+            }
+
+            // Only blend if the nearer end varies in the direction the center does
+            let distance_negative = select(uv.y - uv_negative.y, uv.x - uv_negative.x, is_horizontal);
+            let distance_positive = select(uv_positive.y - uv.y, uv_positive.x - uv.x, is_horizontal);
+            let negative_nearer = distance_negative < distance_positive;
+            let distance_nearest = min(distance_negative, distance_positive);
+            let edge_length = distance_negative + distance_positive;
+            let luma_end = select(luma_end_positive, luma_end_negative, negative_nearer);
+            let correct_variation = (luma_end < 0.0) != (luma_center < luma_local_average);
+            var pixel_offset = select(0.0, 0.5 - distance_nearest / edge_length, correct_variation);
+
+            // Subpixel aliasing from the contrast of the whole neighborhood
+            let luma_average = (2.0 * (luma_up + luma_down + luma_left + luma_right)
+                + luma_up_left + luma_up_right + luma_down_left + luma_down_right) / 12.0;
+            let subpixel_contrast = clamp(abs(luma_average - luma_center) / luma_range, 0.0, 1.0);
+            let subpixel_smoothed = (3.0 - 2.0 * subpixel_contrast) * subpixel_contrast * subpixel_contrast;
+            pixel_offset = max(pixel_offset, subpixel_smoothed * subpixel_smoothed * post.fxaa_subpixel);
+
+            var final_uv = uv;
+            if (is_horizontal) {
+                final_uv.y += pixel_offset * step_length;
+            } else {
+                final_uv.x += pixel_offset * step_length;
+            }
+            return from_display(sample_display(final_uv));
+        }
+
+        @fragment
+        fn fs_chromatic_aberration(in: FullscreenOutput) -> @location(0) vec4<f32> {
+            // Red and blue move apart towards the edges, green stays in place
+            let offset = (in.uv - 0.5) * 2.0 * post.chromatic_aberration;
+            let red = textureSampleLevel(source_texture, source_sampler, in.uv - offset, 0.0).r;
+            let green = textureSampleLevel(source_texture, source_sampler, in.uv, 0.0).g;
+            let blue = textureSampleLevel(source_texture, source_sampler, in.uv + offset, 0.0).b;
+            return vec4<f32>(red, green, blue, 1.0);
+        }
+
+        @fragment
+        fn fs_vignette(in: FullscreenOutput) -> @location(0) vec4<f32> {
+            // Zero at the center, one in the corners
+            let center_distance = length(in.uv - 0.5) * 1.41421356;
+            let falloff = smoothstep(
+                post.vignette_radius,
+                post.vignette_radius + max(post.vignette_smoothness, 0.0001),
+                center_distance,
+            );
+            return from_display(sample_display(in.uv) * (1.0 - post.vignette_intensity * falloff));
+        }
+
+        @fragment
+        fn fs_color_grading(in: FullscreenOutput) -> @location(0) vec4<f32> {
+            let color = clamp(sample_display(in.uv), vec3<f32>(0.0), vec3<f32>(1.0));
+            // Sample between the outer texel centers so the LUT's ends map to black and white
+            let scale = (post.lut_size - 1.0) / post.lut_size;
+            let offset = 0.5 / post.lut_size;
+            let graded = textureSampleLevel(lut_texture, source_sampler, color * scale + offset, 0.0).rgb;
+            return from_display(mix(color, graded, post.grading_contribution));
+        }
+        "#;
+
+        let source = [POST_PROCESS_TYPES_WGSL, source].concat();
+        self.load_from_string(device, "post_process", &source)
+    }
+    
+    /// Create the pass that projects an equirectangular image onto one cubemap face
+    pub fn create_environment_equirect_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        @group(0) @binding(1)
+        var equirect: texture_2d<f32>;
+
+        // Bilinear fetch that wraps around horizontally and clamps at the poles
+        fn load_equirect(uv: vec2<f32>) -> vec3<f32> {
+            let size = vec2<i32>(textureDimensions(equirect));
+            let texel = uv * vec2<f32>(size) - 0.5;
+            let base = floor(texel);
+            let f = texel - base;
+            let x0 = (i32(base.x) % size.x + size.x) % size.x;
+            let x1 = (x0 + 1) % size.x;
+            let y0 = clamp(i32(base.y), 0, size.y - 1);
+            let y1 = clamp(i32(base.y) + 1, 0, size.y - 1);
+            let top = mix(textureLoad(equirect, vec2<i32>(x0, y0), 0).rgb, textureLoad(equirect, vec2<i32>(x1, y0), 0).rgb, f.x);
+            let bottom = mix(textureLoad(equirect, vec2<i32>(x0, y1), 0).rgb, textureLoad(equirect, vec2<i32>(x1, y1), 0).rgb, f.x);
+            return mix(top, bottom, f.y);
+        }
+
+        @fragment
+        fn fs_main(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+            // Average a 4x4 grid across the texel so smaller mips do not alias
+            var color = vec3<f32>(0.0);
+            for (var y = 0u; y < 4u; y = y + 1u) {
+                for (var x = 0u; x < 4u; x = x + 1u) {
+                    let offset = (vec2<f32>(f32(x), f32(y)) + 0.5) / 4.0 - 0.5;
+                    let d = cube_direction(bake.face, frag_coord.xy + offset);
+                    let uv = vec2<f32>(atan2(d.z, d.x) / (2.0 * PI) + 0.5, acos(clamp(d.y, -1.0, 1.0)) / PI);
+                    color += load_equirect(uv);
+                }
+            }
+            return vec4<f32>(color / 16.0, 1.0);
+        }
+        "#;
+        
+        let source = [ENVIRONMENT_BAKE_WGSL, source].concat();
+        self.load_from_string(device, "environment_equirect", &source)
+    }
+    
+    /// Create the pass that convolves the environment cubemap into diffuse irradiance
+    pub fn create_environment_irradiance_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        @group(0) @binding(1)
+        var environment_map: texture_cube<f32>;
+        @group(0) @binding(2)
+        var environment_sampler: sampler;
+
+        const SAMPLE_COUNT: u32 = 512u;
+
+        @fragment
+        fn fs_main(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+            let N = cube_direction(bake.face, frag_coord.xy);
+            let frame = tangent_frame(N);
+            
+            // Cosine-weighted samples, so the average radiance is the irradiance over pi
+            var irradiance = vec3<f32>(0.0);
+            for (var i = 0u; i < SAMPLE_COUNT; i = i + 1u) {
+                let xi = hammersley(i, SAMPLE_COUNT);
+                let phi = 2.0 * PI * xi.x;
+                let cos_theta = sqrt(1.0 - xi.y);
+                let sin_theta = sqrt(xi.y);
+                let L = frame * vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+                let lod = sample_lod(cos_theta / PI, SAMPLE_COUNT);
+                irradiance += textureSampleLevel(environment_map, environment_sampler, L, lod).rgb;
+            }
+            return vec4<f32>(irradiance / f32(SAMPLE_COUNT), 1.0);
+        }
+        "#;
+        
+        let source = [ENVIRONMENT_BAKE_WGSL, source].concat();
+        self.load_from_string(device, "environment_irradiance", &source)
+    }
+    
+    /// Create the pass that prefilters the environment cubemap for one specular roughness
+    pub fn create_environment_prefilter_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        @group(0) @binding(1)
+        var environment_map: texture_cube<f32>;
+        @group(0) @binding(2)
+        var environment_sampler: sampler;
+
+        const SAMPLE_COUNT: u32 = 256u;
+
+        @fragment
+        fn fs_main(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+            let N = cube_direction(bake.face, frag_coord.xy);
+            
+            // A mirror reflects the environment as is, from the mip matching this face size
+            if (bake.roughness <= 0.0) {
+                let lod = max(log2(bake.source_size / bake.size), 0.0);
+                return vec4<f32>(textureSampleLevel(environment_map, environment_sampler, N, lod).rgb, 1.0);
+            }
+            
+            // GGX importance sampling, assuming the view and reflection directions equal N
+            var color = vec3<f32>(0.0);
+            var total_weight = 0.0;
+            let a = bake.roughness * bake.roughness;
+            for (var i = 0u; i < SAMPLE_COUNT; i = i + 1u) {
+                let H = importance_sample_ggx(hammersley(i, SAMPLE_COUNT), N, bake.roughness);
+                let L = normalize(2.0 * dot(N, H) * H - N);
+                let NdotL = dot(N, L);
+                if (NdotL > 0.0) {
+                    // With V = N the sample pdf reduces to D / 4
+                    let NdotH = max(dot(N, H), 0.0);
+                    let d = NdotH * NdotH * (a * a - 1.0) + 1.0;
+                    let D = a * a / (PI * d * d);
+                    let lod = sample_lod(D / 4.0, SAMPLE_COUNT);
+                    color += textureSampleLevel(environment_map, environment_sampler, L, lod).rgb * NdotL;
+                    total_weight += NdotL;
+                }
+            }
+            return vec4<f32>(color / max(total_weight, 0.0001), 1.0);
+        }
+        "#;
+        
+        let source = [ENVIRONMENT_BAKE_WGSL, source].concat();
+        self.load_from_string(device, "environment_prefilter", &source)
+    }
+    
+    /// Create the pass that integrates the split-sum BRDF scale and bias over NdotV and roughness
+    pub fn create_brdf_lut_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        const SAMPLE_COUNT: u32 = 512u;
+
+        fn geometry_schlick_ggx_ibl(NdotV: f32, roughness: f32) -> f32 {
+            let k = roughness * roughness / 2.0;
+            return NdotV / (NdotV * (1.0 - k) + k);
+        }
+
+        @fragment
+        fn fs_main(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+            let uv = frag_coord.xy / bake.size;
+            let NdotV = max(uv.x, 0.001);
+            let roughness = uv.y;
+            let V = vec3<f32>(sqrt(1.0 - NdotV * NdotV), 0.0, NdotV);
+            let N = vec3<f32>(0.0, 0.0, 1.0);
+            
+            var scale = 0.0;
+            var bias = 0.0;
+            for (var i = 0u; i < SAMPLE_COUNT; i = i + 1u) {
+                let H = importance_sample_ggx(hammersley(i, SAMPLE_COUNT), N, roughness);
+                let L = normalize(2.0 * dot(V, H) * H - V);
+                let NdotL = max(L.z, 0.0);
+                let NdotH = max(H.z, 0.0);
+                let VdotH = max(dot(V, H), 0.0);
+                if (NdotL > 0.0) {
+                    let G = geometry_schlick_ggx_ibl(NdotV, roughness) * geometry_schlick_ggx_ibl(NdotL, roughness);
+                    let G_vis = G * VdotH / (NdotH * NdotV);
+                    let Fc = pow(1.0 - VdotH, 5.0);
+                    scale += (1.0 - Fc) * G_vis;
+                    bias += Fc * G_vis;
+                }
+            }
+            return vec4<f32>(scale / f32(SAMPLE_COUNT), bias / f32(SAMPLE_COUNT), 0.0, 1.0);
+        }
+        "#;
+        
+        let source = [ENVIRONMENT_BAKE_WGSL, source].concat();
+        self.load_from_string(device, "brdf_lut", &source)
+    }
+    
+    /// Create the shader that draws the environment cubemap behind the scene
+    pub fn create_skybox_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        struct SkyboxUniforms {
+            inverse_view: mat4x4<f32>,
+            tan_half_fovy: f32,
+            aspect: f32,
+            intensity: f32,
+            _padding: f32,
+        };
+
+        @group(0) @binding(0)
+        var<uniform> skybox: SkyboxUniforms;
+        @group(0) @binding(1)
+        var environment_map: texture_cube<f32>;
+        @group(0) @binding(2)
+        var environment_sampler: sampler;
+
+        struct VertexOutput {
+            @builtin(position) clip_position: vec4<f32>,
+            @location(0) ndc: vec2<f32>,
+        };
+
+        // Fullscreen triangle on the far plane
+        @vertex
+        fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+            let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+            var out: VertexOutput;
+            out.ndc = uv * 2.0 - 1.0;
+            out.clip_position = vec4<f32>(out.ndc, 1.0, 1.0);
+            return out;
+        }
+
+        @fragment
+        fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+            let view_ray = vec3<f32>(
+                in.ndc.x * skybox.tan_half_fovy * skybox.aspect,
+                in.ndc.y * skybox.tan_half_fovy,
+                -1.0,
+            );
+            let direction = normalize((skybox.inverse_view * vec4<f32>(view_ray, 0.0)).xyz);
+            let color = textureSampleLevel(environment_map, environment_sampler, direction, 0.0).rgb;
+            return vec4<f32>(color * skybox.intensity, 1.0);
+        }
+        "#;
+        
+        self.load_from_string(device, "skybox", source)
+    }
+    
+    /// Create the procedural sky: single scattering through a Rayleigh and Mie atmosphere
+    pub fn create_atmosphere_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        struct SkyUniforms {
+            inverse_view: mat4x4<f32>,
+            tan_half_fovy: f32,
+            aspect: f32,
+            camera_height: f32,
+            sun_intensity: f32,
+            sun_direction: vec3<f32>,
+            planet_radius: f32,
+            rayleigh_scattering: vec3<f32>,
+            atmosphere_radius: f32,
+            rayleigh_scale_height: f32,
+            mie_scattering: f32,
+            mie_scale_height: f32,
+            mie_anisotropy: f32,
+        };
+
+        @group(0) @binding(0)
+        var<uniform> sky: SkyUniforms;
+
+        const PI: f32 = 3.14159265359;
+        const VIEW_SAMPLES: u32 = 16u;
+        const LIGHT_SAMPLES: u32 = 8u;
+        // Mie extinction is scattering plus absorption
+        const MIE_EXTINCTION_SCALE: f32 = 1.1;
+        // Angular radius of the sun disk, about 0.27 degrees
+        const SUN_COS_RADIUS: f32 = 0.99999;
+        const SUN_DISK_RADIANCE: f32 = 50.0;
+        const GROUND_ALBEDO: f32 = 0.1;
+
+        struct VertexOutput {
+            @builtin(position) clip_position: vec4<f32>,
+            @location(0) ndc: vec2<f32>,
+        };
+
+        // Fullscreen triangle on the far plane
+        @vertex
+        fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+            let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+            var out: VertexOutput;
+            out.ndc = uv * 2.0 - 1.0;
+            out.clip_position = vec4<f32>(out.ndc, 1.0, 1.0);
+            return out;
+        }
+
+        // Distances along the ray to the two intersections with a sphere at the planet center;
+        // x > y when the ray misses
+        fn ray_sphere(origin: vec3<f32>, direction: vec3<f32>, radius: f32) -> vec2<f32> {
+            let b = dot(origin, direction);
+            let c = dot(origin, origin) - radius * radius;
+            let discriminant = b * b - c;
+            if (discriminant < 0.0) {
+                return vec2<f32>(1.0, -1.0);
+            }
+            let root = sqrt(discriminant);
+            return vec2<f32>(-b - root, -b + root);
+        }
+
+        // Rayleigh and Mie densities relative to sea level
+        fn densities(position: vec3<f32>) -> vec2<f32> {
+            let height = max(length(position) - sky.planet_radius, 0.0);
+            return exp(-height / vec2<f32>(sky.rayleigh_scale_height, sky.mie_scale_height));
+        }
+
+        // Rayleigh and Mie optical depth from a point to the sun
+        fn sun_optical_depth(position: vec3<f32>, sun: vec3<f32>) -> vec2<f32> {
+            let light_step = ray_sphere(position, sun, sky.atmosphere_radius).y / f32(LIGHT_SAMPLES);
+            var depth = vec2<f32>(0.0);
+            for (var j = 0u; j < LIGHT_SAMPLES; j = j + 1u) {
+                depth += densities(position + sun * (f32(j) + 0.5) * light_step) * light_step;
+            }
+            return depth;
+        }
+
+        fn extinction(optical_depth: vec2<f32>) -> vec3<f32> {
+            return sky.rayleigh_scattering * optical_depth.x
+                + vec3<f32>(sky.mie_scattering * MIE_EXTINCTION_SCALE * optical_depth.y);
+        }
+
+        @fragment
+        fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+            let view_ray = vec3<f32>(
+                in.ndc.x * sky.tan_half_fovy * sky.aspect,
+                in.ndc.y * sky.tan_half_fovy,
+                -1.0,
+            );
+            let direction = normalize((sky.inverse_view * vec4<f32>(view_ray, 0.0)).xyz);
+            let origin = vec3<f32>(0.0, sky.camera_height, 0.0);
+            let sun = normalize(sky.sun_direction);
+            
+            // March from the camera to the edge of the atmosphere, or to the ground
+            let atmosphere_hit = ray_sphere(origin, direction, sky.atmosphere_radius);
+            let ground_hit = ray_sphere(origin, direction, sky.planet_radius);
+            let hits_ground = ground_hit.x <= ground_hit.y && ground_hit.x > 0.0;
+            let ray_length = select(max(atmosphere_hit.y, 0.0), ground_hit.x, hits_ground);
+            let step_length = ray_length / f32(VIEW_SAMPLES);
+            
+            var view_depth = vec2<f32>(0.0);
+            var rayleigh = vec3<f32>(0.0);
+            var mie = vec3<f32>(0.0);
+            for (var i = 0u; i < VIEW_SAMPLES; i = i + 1u) {
+                let position = origin + direction * (f32(i) + 0.5) * step_length;
+                let density = densities(position) * step_length;
+                view_depth += density;
+                
+                // Sunlight reaching this sample, unless the planet is in the way
+                let light_hit = ray_sphere(position, sun, sky.planet_radius);
+                if (light_hit.x <= light_hit.y && light_hit.x > 0.0) {
+                    continue;
+                }
+                let transmittance = exp(-extinction(view_depth + sun_optical_depth(position, sun)));
+                rayleigh += transmittance * density.x;
+                mie += transmittance * density.y;
+            }
+            
+            // Rayleigh and Henyey-Greenstein (Cornette-Shanks) phase functions
+            let mu = dot(direction, sun);
+            let g = sky.mie_anisotropy;
+            let rayleigh_phase = 3.0 / (16.0 * PI) * (1.0 + mu * mu);
+            let mie_phase = 3.0 / (8.0 * PI) * ((1.0 - g * g) * (1.0 + mu * mu))
+                / ((2.0 + g * g) * pow(1.0 + g * g - 2.0 * g * mu, 1.5));
+            var color = sky.sun_intensity
+                * (rayleigh * sky.rayleigh_scattering * rayleigh_phase + mie * sky.mie_scattering * mie_phase);
+            
+            let view_transmittance = exp(-extinction(view_depth));
+            if (hits_ground) {
+                // Diffuse ground lit by the sun through the atmosphere above it
+                let ground = origin + direction * ground_hit.x;
+                let sun_height = dot(normalize(ground), sun);
+                if (sun_height > 0.0) {
+                    let sun_transmittance = exp(-extinction(sun_optical_depth(ground, sun)));
+                    color += view_transmittance * sun_transmittance * sun_height * sky.sun_intensity * GROUND_ALBEDO / PI;
+                }
+            } else if (mu > SUN_COS_RADIUS) {
+                // Sun disk, dimmed and reddened by the air in front of it
+                color += view_transmittance * sky.sun_intensity * SUN_DISK_RADIANCE;
+            }
+            
+            return vec4<f32>(color, 1.0);
+        }
+        "#;
+        
+        self.load_from_string(device, "atmosphere", source)
+    }
+
+    /// Create the passes that copy the depth buffer into a depth pyramid and reduce each
+    /// level to the farthest depth of the texels it covers
+    pub fn create_hiz_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        @group(0) @binding(0)
+        var depth_texture: texture_2d<f32>;
+        @group(0) @binding(1)
+        var source_level: texture_2d<f32>;
+        @group(0) @binding(2)
+        var multisampled_depth_texture: texture_multisampled_2d<f32>;
+
+        // Single triangle covering the whole level
+        @vertex
+        fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+            let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+            return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+        }
+
+        @fragment
+        fn fs_copy(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+            return vec4<f32>(textureLoad(depth_texture, vec2<i32>(position.xy), 0).r, 0.0, 0.0, 0.0);
+        }
+
+        // Keeps the farthest sample so partly covered edge pixels never occlude. The count is
+        // passed in since GLSL ES cannot query it.
+        fn farthest_sample(position: vec4<f32>, sample_count: i32) -> vec4<f32> {
+            var depth = 0.0;
+            for (var i = 0; i < sample_count; i = i + 1) {
+                depth = max(depth, textureLoad(multisampled_depth_texture, vec2<i32>(position.xy), i).r);
+            }
+            return vec4<f32>(depth, 0.0, 0.0, 0.0);
+        }
+
+        @fragment
+        fn fs_copy_2x(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+            return farthest_sample(position, 2);
+        }
+
+        @fragment
+        fn fs_copy_4x(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+            return farthest_sample(position, 4);
+        }
+
+        @fragment
+        fn fs_copy_8x(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+            return farthest_sample(position, 8);
+        }
+
+        @fragment
+        fn fs_downsample(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+            let source_size = vec2<i32>(textureDimensions(source_level));
+            let base = vec2<i32>(position.xy) * 2;
+            // Odd-sized levels fold their last row and column into the texels before them
+            let extent = vec2<i32>(2) + (source_size & vec2<i32>(1));
+            var depth = 0.0;
+            for (var y = 0; y < extent.y; y = y + 1) {
+                for (var x = 0; x < extent.x; x = x + 1) {
+                    let coord = min(base + vec2<i32>(x, y), source_size - 1);
+                    depth = max(depth, textureLoad(source_level, coord, 0).r);
+                }
+            }
+            return vec4<f32>(depth, 0.0, 0.0, 0.0);
+        }
+        "#;
+
+        self.load_from_string(device, "hiz", source)
+    }
+
+    /// Create the compute shader that culls object bounds against the camera frustum and the
+    /// previous frame's depth pyramid, appending the survivors to their indirect draws
+    pub fn create_gpu_culling_shader(&mut self, device: &wgpu::Device) -> &wgpu::ShaderModule {
+        let source = r#"
+        struct CullUniforms {
+            previous_view_proj: mat4x4<f32>,
+            planes: array<vec4<f32>, 6>,
+            hiz_size: vec2<f32>,
+            hiz_mip_count: u32,
+            object_count: u32,
+            occlusion_enabled: u32,
+            lod_fade_range: f32,
+            camera_position: vec3<f32>,
+            lod_scale: f32,
+        };
+
+        struct InstanceData {
+            model: mat4x4<f32>,
+            tint: vec4<f32>,
+            lod_fade: f32,
+        };
+
+        struct CullObject {
+            instance: InstanceData,
+            aabb_min: vec3<f32>,
+            draw: u32,
+            aabb_max: vec3<f32>,
+            lod_count: u32,
+        };
+
+        // Arguments of draw_indexed_indirect followed by where the draw's instances start
+        struct DrawCommand {
+            index_count: u32,
+            instance_count: atomic<u32>,
+            first_index: u32,
+            base_vertex: i32,
+            first_instance: u32,
+            instance_base: u32,
+            lod_screen_size: f32,
+            padding: u32,
+        };
+
+        @group(0) @binding(0)
+        var<uniform> cull: CullUniforms;
+        @group(0) @binding(1)
+        var<storage, read> objects: array<CullObject>;
+        @group(0) @binding(2)
+        var<storage, read_write> draws: array<DrawCommand>;
+        @group(0) @binding(3)
+        var<storage, read_write> visible_instances: array<InstanceData>;
+        @group(1) @binding(0)
+        var hiz: texture_2d<f32>;
+
+        fn in_frustum(aabb_min: vec3<f32>, aabb_max: vec3<f32>) -> bool {
+            for (var i = 0u; i < 6u; i = i + 1u) {
+                let plane = cull.planes[i];
+                // The corner farthest along the plane normal
+                let corner = select(aabb_min, aabb_max, plane.xyz >= vec3<f32>(0.0));
+                if (dot(plane.xyz, corner) + plane.w < 0.0) {
+                    return false;
+                }
+            }
+            return true;
+        }
+
+        // True when the box lies behind the depth the previous frame stored over its screen rectangle
+        fn occluded(aabb_min: vec3<f32>, aabb_max: vec3<f32>) -> bool {
+            if (cull.occlusion_enabled == 0u) {
+                return false;
+            }
+            var ndc_min = vec3<f32>(1.0e9);
+            var ndc_max = vec3<f32>(-1.0e9);
+            for (var i = 0u; i < 8u; i = i + 1u) {
+                let corner = select(aabb_min, aabb_max, vec3<bool>((i & 1u) != 0u, (i & 2u) != 0u, (i & 4u) != 0u));
+                let clip = cull.previous_view_proj * vec4<f32>(corner, 1.0);
+                if (clip.w <= 1.0e-5) {
+                    // Crosses the camera plane, the rectangle is unbounded
+                    return false;
+                }
+                let ndc = clip.xyz / clip.w;
+                ndc_min = min(ndc_min, ndc);
+                ndc_max = max(ndc_max, ndc);
+            }
+            if (ndc_min.z <= 0.0) {
+                return false;
+            }
+
+            // Screen rectangle in UVs, rows count down from the top
+            let uv_min = clamp(vec2<f32>(ndc_min.x, -ndc_max.y) * 0.5 + 0.5, vec2<f32>(0.0), vec2<f32>(1.0));
+            let uv_max = clamp(vec2<f32>(ndc_max.x, -ndc_min.y) * 0.5 + 0.5, vec2<f32>(0.0), vec2<f32>(1.0));
+
+            // The level where the rectangle spans at most two texels each way
+            let size = (uv_max - uv_min) * cull.hiz_size;
+            let level = u32(clamp(ceil(log2(max(max(size.x, size.y), 1.0))), 0.0, f32(cull.hiz_mip_count - 1u)));
+            let level_size = max(vec2<i32>(cull.hiz_size) >> vec2<u32>(level), vec2<i32>(1));
+            let texel_min = clamp(vec2<i32>(uv_min * vec2<f32>(level_size)), vec2<i32>(0), level_size - 1);
+            let texel_max = clamp(vec2<i32>(uv_max * vec2<f32>(level_size)), vec2<i32>(0), level_size - 1);
+
+            let farthest = max(
+                max(textureLoad(hiz, texel_min, i32(level)).r, textureLoad(hiz, vec2<i32>(texel_max.x, texel_min.y), i32(level)).r),
+                max(textureLoad(hiz, vec2<i32>(texel_min.x, texel_max.y), i32(level)).r, textureLoad(hiz, texel_max, i32(level)).r),
+            );
+            return ndc_min.z > farthest;
+        }
+
+        // Add an instance to the draw of one level of detail
+        fn append(draw: u32, instance: InstanceData, lod_fade: f32) {
+            var faded = instance;
+            faded.lod_fade = lod_fade;
+            let slot = atomicAdd(&draws[draw].instance_count, 1u);
+            visible_instances[draws[draw].instance_base + slot] = faded;
+        }
+
+        @compute @workgroup_size(64)
+        fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+            if (id.x >= cull.object_count) {
+                return;
+            }
+            let object = objects[id.x];
+            if (!in_frustum(object.aabb_min, object.aabb_max) || occluded(object.aabb_min, object.aabb_max)) {
+                return;
+            }
+
+            // Pick the level by the screen height covered by the sphere around the box, like LodSelector
+            var level = 0u;
+            var fade = 0.0;
+            if (cull.lod_scale > 0.0) {
+                let center = (object.aabb_min + object.aabb_max) * 0.5;
+                let radius = length(object.aabb_max - center);
+                let distance = length(center - cull.camera_position);
+                let screen_size = select(radius * cull.lod_scale / distance, 3.0e38, distance <= radius);
+                for (var i = 1u; i < object.lod_count; i = i + 1u) {
+                    if (screen_size < draws[object.draw + i].lod_screen_size) {
+                        level = i;
+                    }
+                }
+
+                // Fade towards the next level over the range just above its switch, in whole dither steps
+                if (cull.lod_fade_range > 0.0 && level + 1u < object.lod_count) {
+                    let next = draws[object.draw + level + 1u].lod_screen_size;
+                    let start = next * (1.0 + cull.lod_fade_range);
+                    if (screen_size < start) {
+                        fade = round((start - screen_size) / (start - next) * 16.0) / 16.0;
+                        if (fade >= 1.0) {
+                            level = level + 1u;
+                            fade = 0.0;
+                        }
+                    }
+                }
+            }
+
+            append(object.draw + level, object.instance, fade);
+            if (fade > 0.0) {
+                append(object.draw + level + 1u, object.instance, -fade);
+            }
+        }
+        "#;
+
+        self.load_from_string(device, "gpu_culling", source)
+    }
+}
@@ -0,0 +1,589 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::{ortho, perspective, EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Transform, Vector3, Vector4};
+use wgpu::util::DeviceExt;
+use crate::engine::renderer::instance::{Instance, InstanceBuffer, InstancedModel};
+use crate::engine::renderer::mesh::Vertex;
+use crate::engine::renderer::texture::Texture;
+use crate::engine::scene::{Camera, Model, ShadowSettings, MAX_SHADOW_CASCADES};
+
+/// Converts cgmath's OpenGL clip space depth (-1..1) to the wgpu range (0..1)
+#[rustfmt::skip]
+pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+/// Closest distance to the camera that cascade splits start from. The camera's own
+/// near plane is too close for logarithmic splits to produce useful cascades.
+const MIN_CASCADE_NEAR: f32 = 0.1;
+
+/// WGSL declarations and PCF lookup shared by every shader that receives shadows.
+/// Expects the shadow bind group at group 1.
+pub const SHADOW_SAMPLING_WGSL: &str = r#"
+struct ShadowUniforms {
+    light_view_proj: array<mat4x4<f32>, 4>,
+    camera_view: mat4x4<f32>,
+    cascade_splits: vec4<f32>,
+    cascade_texel_sizes: vec4<f32>,
+    depth_bias: f32,
+    normal_bias: f32,
+    texel_size: f32,
+    pcf_radius: i32,
+    enabled: u32,
+    cascade_count: u32,
+    debug_cascades: u32,
+};
+
+@group(1) @binding(0)
+var<uniform> shadow: ShadowUniforms;
+@group(1) @binding(1)
+var t_shadow: texture_depth_2d_array;
+@group(1) @binding(2)
+var s_shadow: sampler_comparison;
+
+// Index of the cascade covering a world position, or -1 beyond the last cascade
+fn select_cascade(world_position: vec3<f32>) -> i32 {
+    let view_depth = -(shadow.camera_view * vec4<f32>(world_position, 1.0)).z;
+    for (var i = 0u; i < shadow.cascade_count; i = i + 1u) {
+        if (view_depth < shadow.cascade_splits[i]) {
+            return i32(i);
+        }
+    }
+    return -1;
+}
+
+// Returns 1.0 for fully lit and 0.0 for fully shadowed
+fn sample_shadow(world_position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    if (shadow.enabled == 0u) {
+        return 1.0;
+    }
+    let cascade = select_cascade(world_position);
+    if (cascade < 0) {
+        return 1.0;
+    }
+
+    // Push the receiver along its normal to avoid shadow acne on sloped surfaces
+    let offset_position = world_position + normal * shadow.normal_bias * shadow.cascade_texel_sizes[cascade];
+    let clip = shadow.light_view_proj[cascade] * vec4<f32>(offset_position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, -ndc.y * 0.5 + 0.5);
+
+    // Everything outside the light frustum is lit
+    if (ndc.z > 1.0 || any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0))) {
+        return 1.0;
+    }
+
+    // Percentage closer filtering over a square kernel
+    let depth = ndc.z - shadow.depth_bias;
+    var lit = 0.0;
+    var taps = 0.0;
+    for (var x = -shadow.pcf_radius; x <= shadow.pcf_radius; x = x + 1) {
+        for (var y = -shadow.pcf_radius; y <= shadow.pcf_radius; y = y + 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * shadow.texel_size;
+            lit += textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, cascade, depth);
+            taps += 1.0;
+        }
+    }
+    return lit / taps;
+}
+
+// Color multiplier for the cascade debug view; white when the view is off
+fn cascade_debug_tint(world_position: vec3<f32>) -> vec3<f32> {
+    if (shadow.enabled == 0u || shadow.debug_cascades == 0u) {
+        return vec3<f32>(1.0);
+    }
+    switch (select_cascade(world_position)) {
+        case 0: { return vec3<f32>(1.0, 0.45, 0.45); }
+        case 1: { return vec3<f32>(0.45, 1.0, 0.45); }
+        case 2: { return vec3<f32>(0.45, 0.45, 1.0); }
+        case 3: { return vec3<f32>(1.0, 1.0, 0.45); }
+        default: { return vec3<f32>(1.0); }
+    }
+}
+"#;
+
+/// Shadow parameters uploaded to the GPU
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct ShadowUniforms {
+    pub light_view_proj: [[[f32; 4]; 4]; MAX_SHADOW_CASCADES as usize],
+    pub camera_view: [[f32; 4]; 4],
+    pub cascade_splits: [f32; 4],       // Far view depth of each cascade
+    pub cascade_texel_sizes: [f32; 4],  // World-space size of a texel in each cascade
+    pub depth_bias: f32,
+    pub normal_bias: f32,
+    pub texel_size: f32,
+    pub pcf_radius: i32,
+    pub enabled: u32,
+    pub cascade_count: u32,
+    pub debug_cascades: u32,
+    _padding: u32,
+}
+
+impl ShadowUniforms {
+    /// Uniforms for a light that does not cast shadows
+    pub fn disabled() -> Self {
+        Self {
+            light_view_proj: [Matrix4::identity().into(); MAX_SHADOW_CASCADES as usize],
+            camera_view: Matrix4::identity().into(),
+            cascade_splits: [0.0; 4],
+            cascade_texel_sizes: [0.0; 4],
+            depth_bias: 0.0,
+            normal_bias: 0.0,
+            texel_size: 0.0,
+            pcf_radius: 0,
+            enabled: 0,
+            cascade_count: 0,
+            debug_cascades: 0,
+            _padding: 0,
+        }
+    }
+}
+
+/// Light transform of a single cascade, uploaded for the shadow pass
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct CascadeUniforms {
+    light_view_proj: [[f32; 4]; 4],
+}
+
+/// Returns the eight world-space corners of the camera frustum between `near` and `far`
+pub fn frustum_corners(camera: &Camera, near: f32, far: f32) -> [Point3<f32>; 8] {
+    let projection = perspective(camera.fovy, camera.aspect_ratio, near, far);
+    let inverse = (projection * camera.get_view_matrix())
+        .invert()
+        .unwrap_or_else(Matrix4::identity);
+
+    let mut corners = [Point3::new(0.0, 0.0, 0.0); 8];
+    let mut i = 0;
+    for z in [-1.0, 1.0] {
+        for y in [-1.0, 1.0] {
+            for x in [-1.0, 1.0] {
+                let corner = inverse * Vector4::new(x, y, z, 1.0);
+                corners[i] = Point3::from_homogeneous(corner);
+                i += 1;
+            }
+        }
+    }
+    corners
+}
+
+/// Computes the far distance of each cascade.
+///
+/// `lambda` blends between a linear split (0.0) and a logarithmic split (1.0).
+/// The last split is always `far`.
+pub fn cascade_splits(near: f32, far: f32, count: u32, lambda: f32) -> Vec<f32> {
+    let lambda = lambda.clamp(0.0, 1.0);
+    (1..=count)
+        .map(|i| {
+            let p = i as f32 / count as f32;
+            let logarithmic = near * (far / near).powf(p);
+            let linear = near + (far - near) * p;
+            lambda * logarithmic + (1.0 - lambda) * linear
+        })
+        .collect()
+}
+
+/// Builds an orthographic light view-projection for the camera frustum slice
+/// between `near` and `far`, returning it with the world-space size of a texel.
+///
+/// The projection is sized to the slice's bounding sphere and its origin snapped to
+/// whole texels, so it does not change size or shift by sub-texel amounts as the
+/// camera moves or turns. That keeps shadow edges from shimmering. The frustum is
+/// extended towards the light so objects outside the view still cast shadows into it.
+pub fn fit_cascade(direction: [f32; 3], camera: &Camera, near: f32, far: f32, resolution: u32) -> (Matrix4<f32>, f32) {
+    let corners = frustum_corners(camera, near, far);
+
+    // Bounding sphere of the slice; rounding the radius keeps float noise from resizing it
+    let center = Point3::from_vec(
+        corners.iter().fold(Vector3::new(0.0, 0.0, 0.0), |sum, corner| sum + corner.to_vec()) / 8.0
+    );
+    let radius = corners.iter()
+        .map(|corner| (corner - center).magnitude())
+        .fold(0.0, f32::max);
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    // Light orientation only depends on the light direction
+    let direction = Vector3::from(direction).normalize();
+    let up = if direction.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+    let light_view = Matrix4::look_at_rh(Point3::new(0.0, 0.0, 0.0), Point3::from_vec(direction), up);
+
+    // Snap the light-space center to the texel grid
+    let texel_size = 2.0 * radius / resolution as f32;
+    let light_center = light_view.transform_point(center);
+    let x = (light_center.x / texel_size).floor() * texel_size;
+    let y = (light_center.y / texel_size).floor() * texel_size;
+
+    // The view looks down -Z, so the slice spans depths around -light_center.z
+    let depth = -light_center.z;
+    let projection = ortho(x - radius, x + radius, y - radius, y + radius, depth - 2.0 * radius, depth + radius);
+    (OPENGL_TO_WGPU_MATRIX * projection * light_view, texel_size)
+}
+
+/// Cascaded directional shadow map for the sun
+pub struct ShadowMap {
+    resolution: u32,
+    enabled: bool,
+    cascade_count: u32,
+    texture: Texture,
+    layer_views: Vec<wgpu::TextureView>,
+    uniform_buffer: wgpu::Buffer,
+    cascade_buffers: Vec<wgpu::Buffer>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pass_bind_groups: Vec<wgpu::BindGroup>,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ShadowMap {
+    /// Create a shadow map and the depth-only pipeline that renders into it
+    pub fn new(
+        device: &wgpu::Device,
+        model_bind_group_layout: &wgpu::BindGroupLayout,
+        shader: &wgpu::ShaderModule,
+    ) -> Self {
+        let resolution = ShadowSettings::default().resolution;
+        let (texture, layer_views) = Self::create_texture(device, resolution);
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[ShadowUniforms::disabled()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // Layout used by the lit pipelines to sample the shadow map
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow Bind Group Layout"),
+            entries: &[
+                // Shadow uniforms
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Shadow map cascades
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                // Comparison sampler
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &uniform_buffer, &texture);
+
+        // Layout used while rendering a cascade; only its light transform is needed
+        let pass_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow Pass Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let cascade_buffers: Vec<_> = (0..MAX_SHADOW_CASCADES)
+            .map(|i| device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("Shadow Cascade {} Buffer", i)),
+                contents: bytemuck::cast_slice(&[CascadeUniforms {
+                    light_view_proj: Matrix4::identity().into(),
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }))
+            .collect();
+        let pass_bind_groups = cascade_buffers.iter()
+            .enumerate()
+            .map(|(i, buffer)| device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(&format!("Shadow Cascade {} Bind Group", i)),
+                layout: &pass_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    },
+                ],
+            }))
+            .collect();
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[
+                model_bind_group_layout,
+                &pass_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        // Depth-only pipeline rendering from the light's point of view
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc(), Instance::desc()],
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None, // OBJ meshes are not guaranteed to be closed
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        Self {
+            resolution,
+            enabled: false,
+            cascade_count: 0,
+            texture,
+            layer_views,
+            uniform_buffer,
+            cascade_buffers,
+            bind_group_layout,
+            bind_group,
+            pass_bind_groups,
+            pipeline,
+        }
+    }
+
+    /// Creates the cascade texture array and a render view for each cascade
+    fn create_texture(device: &wgpu::Device, resolution: u32) -> (Texture, Vec<wgpu::TextureView>) {
+        let texture = Texture::create_shadow_map(device, resolution, MAX_SHADOW_CASCADES, "Shadow Map");
+        let layer_views = (0..MAX_SHADOW_CASCADES)
+            .map(|layer| texture.create_layer_view(layer))
+            .collect();
+        (texture, layer_views)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        texture: &Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadow Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+        })
+    }
+
+    /// Layout of the bind group that lit pipelines use to sample shadows
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    /// Bind group that lit pipelines use to sample shadows
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Refit the cascades to the camera and upload the shadow settings
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        direction: [f32; 3],
+        settings: &ShadowSettings,
+        camera: &Camera,
+    ) {
+        // Recreate the shadow map when the resolution changes
+        let resolution = settings.resolution.max(1);
+        if resolution != self.resolution {
+            self.resolution = resolution;
+            (self.texture, self.layer_views) = Self::create_texture(device, resolution);
+            self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.uniform_buffer, &self.texture);
+        }
+
+        self.enabled = settings.enabled;
+        if !settings.enabled {
+            self.cascade_count = 0;
+            queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[ShadowUniforms::disabled()]));
+            return;
+        }
+
+        let mut uniforms = ShadowUniforms {
+            camera_view: camera.get_view_matrix().into(),
+            depth_bias: settings.depth_bias,
+            normal_bias: settings.normal_bias,
+            texel_size: 1.0 / resolution as f32,
+            pcf_radius: settings.pcf_radius as i32,
+            enabled: 1,
+            debug_cascades: settings.debug_cascades as u32,
+            ..ShadowUniforms::disabled()
+        };
+
+        // Fit each cascade to its slice of the view frustum
+        self.cascade_count = settings.cascade_count.clamp(1, MAX_SHADOW_CASCADES);
+        let near = camera.znear.max(MIN_CASCADE_NEAR);
+        let far = settings.max_distance.min(camera.zfar).max(near);
+        let splits = cascade_splits(near, far, self.cascade_count, settings.split_lambda);
+        let mut slice_near = near;
+        for (i, split) in splits.iter().enumerate() {
+            let (light_view_proj, texel_size) = fit_cascade(direction, camera, slice_near, *split, resolution);
+            uniforms.light_view_proj[i] = light_view_proj.into();
+            uniforms.cascade_splits[i] = *split;
+            uniforms.cascade_texel_sizes[i] = texel_size;
+            queue.write_buffer(
+                &self.cascade_buffers[i],
+                0,
+                bytemuck::cast_slice(&[CascadeUniforms { light_view_proj: light_view_proj.into() }])
+            );
+            slice_near = *split;
+        }
+        uniforms.cascade_count = self.cascade_count;
+
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+    }
+
+    /// Render the shadow casters into every active cascade. Plain models are drawn with
+    /// `single_instance` bound as their identity instance.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        models: &[Model],
+        instanced_models: &[InstancedModel],
+        single_instance: &InstanceBuffer,
+    ) {
+        if !self.enabled {
+            return;
+        }
+
+        for cascade in 0..self.cascade_count as usize {
+            let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.layer_views[cascade],
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            shadow_pass.set_pipeline(&self.pipeline);
+            shadow_pass.set_bind_group(1, &self.pass_bind_groups[cascade], &[]);
+            shadow_pass.set_vertex_buffer(1, single_instance.slice());
+            for model in models {
+                model.draw_geometry(&mut shadow_pass);
+            }
+            for model in instanced_models {
+                model.draw_geometry(&mut shadow_pass);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Rad;
+
+    fn test_camera() -> Camera {
+        let mut camera = Camera::new(Point3::new(0.0, 10.0, 10.0), Rad(4.7), Rad(-0.5));
+        camera.set_aspect_ratio(16, 9);
+        camera
+    }
+
+    #[test]
+    fn cascade_splits_blend_linear_and_logarithmic() {
+        let linear = cascade_splits(1.0, 100.0, 4, 0.0);
+        assert_eq!(linear, vec![25.75, 50.5, 75.25, 100.0]);
+
+        let logarithmic = cascade_splits(1.0, 100.0, 4, 1.0);
+        assert!((logarithmic[1] - 10.0).abs() < 1e-3);
+
+        let blended = cascade_splits(1.0, 100.0, 4, 0.5);
+        assert!(blended.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!((blended[3] - 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn cascade_encloses_camera_frustum_slice() {
+        let camera = test_camera();
+        let (light_view_proj, _) = fit_cascade([-0.5, -1.0, -0.3], &camera, 5.0, 40.0, 2048);
+
+        for corner in frustum_corners(&camera, 5.0, 40.0) {
+            let clip = light_view_proj * corner.to_homogeneous();
+            let ndc = clip.truncate() / clip.w;
+            assert!(ndc.x.abs() <= 1.001 && ndc.y.abs() <= 1.001, "corner outside light frustum: {:?}", ndc);
+            assert!((-0.001..=1.001).contains(&ndc.z), "corner outside light depth range: {:?}", ndc);
+        }
+    }
+
+    #[test]
+    fn cascade_moves_in_whole_texels() {
+        let resolution = 1024;
+        let mut camera = test_camera();
+        let (before, _) = fit_cascade([-0.5, -1.0, -0.3], &camera, 5.0, 40.0, resolution);
+        camera.position += Vector3::new(0.37, 0.0, 0.11);
+        let (after, _) = fit_cascade([-0.5, -1.0, -0.3], &camera, 5.0, 40.0, resolution);
+
+        // A fixed world point must land on the same sub-texel position in both fits
+        let point = Point3::new(1.3, 0.0, -2.7).to_homogeneous();
+        let texels = |m: Matrix4<f32>| (m * point).truncate().truncate() * (resolution as f32 / 2.0);
+        let shift = texels(after) - texels(before);
+        assert!((shift.x - shift.x.round()).abs() < 0.01, "x shifted by {} texels", shift.x);
+        assert!((shift.y - shift.y.round()).abs() < 0.01, "y shifted by {} texels", shift.y);
+    }
+}
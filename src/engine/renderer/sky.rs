@@ -0,0 +1,271 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::{Matrix, Vector4};
+use wgpu::util::DeviceExt;
+use crate::engine::renderer::environment::Environment;
+use crate::engine::renderer::tonemap::HDR_FORMAT;
+use crate::engine::scene::{Camera, SunLight};
+
+/// What is drawn behind the scene
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum SkyMode {
+    /// The render pass clear color
+    #[default]
+    ClearColor,
+    /// The environment cubemap
+    Cubemap,
+    /// Single-scattering Rayleigh and Mie atmosphere lit by the sun
+    Atmosphere,
+}
+
+impl SkyMode {
+    /// The mode after this one, wrapping around
+    pub fn next(self) -> Self {
+        match self {
+            SkyMode::ClearColor => SkyMode::Cubemap,
+            SkyMode::Cubemap => SkyMode::Atmosphere,
+            SkyMode::Atmosphere => SkyMode::ClearColor,
+        }
+    }
+}
+
+/// Physical parameters of the procedural atmosphere, in meters
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AtmosphereSettings {
+    pub planet_radius: f32,
+    pub atmosphere_radius: f32,
+    pub rayleigh_scattering: [f32; 3], // Scattering coefficients at sea level, per meter
+    pub rayleigh_scale_height: f32,
+    pub mie_scattering: f32,
+    pub mie_scale_height: f32,
+    pub mie_anisotropy: f32,           // Henyey-Greenstein g, how strongly haze scatters forward
+    pub sun_intensity: f32,            // Sky radiance scale for a sun of intensity 1
+    pub camera_altitude: f32,          // Height of the world origin above sea level
+}
+
+impl Default for AtmosphereSettings {
+    fn default() -> Self {
+        // Earth-like values
+        Self {
+            planet_radius: 6_371e3,
+            atmosphere_radius: 6_471e3,
+            rayleigh_scattering: [5.8e-6, 13.5e-6, 33.1e-6],
+            rayleigh_scale_height: 8e3,
+            mie_scattering: 21e-6,
+            mie_scale_height: 1.2e3,
+            mie_anisotropy: 0.76,
+            sun_intensity: 20.0,
+            camera_altitude: 1.0,
+        }
+    }
+}
+
+/// Camera, sun and atmosphere parameters of the sky shader
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct SkyUniforms {
+    inverse_view: [[f32; 4]; 4], // Rotates view-space rays into world space
+    tan_half_fovy: f32,
+    aspect: f32,
+    camera_height: f32,          // Distance of the camera from the planet center
+    sun_intensity: f32,
+    sun_direction: [f32; 3],     // Towards the sun
+    planet_radius: f32,
+    rayleigh_scattering: [f32; 3],
+    atmosphere_radius: f32,
+    rayleigh_scale_height: f32,
+    mie_scattering: f32,
+    mie_scale_height: f32,
+    mie_anisotropy: f32,
+}
+
+/// Background pass drawn after opaque geometry, wherever the depth buffer is still at the far plane
+pub struct Sky {
+    mode: SkyMode,
+    atmosphere: AtmosphereSettings,
+    uniforms: SkyUniforms,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    atmosphere_pipeline: wgpu::RenderPipeline,
+}
+
+impl Sky {
+    /// Create the atmosphere pipeline. The sky starts out as the clear color.
+    pub fn new(device: &wgpu::Device, atmosphere_shader: &wgpu::ShaderModule) -> Self {
+        let atmosphere = AtmosphereSettings::default();
+        let mut uniforms = SkyUniforms::zeroed();
+        uniforms.sun_direction = [0.0, 1.0, 0.0];
+        Self::apply_atmosphere(&mut uniforms, &atmosphere);
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sky Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Sky Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Sky Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let atmosphere_pipeline = Self::create_atmosphere_pipeline(device, &bind_group_layout, atmosphere_shader, 1);
+
+        Self {
+            mode: SkyMode::default(),
+            atmosphere,
+            uniforms,
+            uniform_buffer,
+            bind_group_layout,
+            bind_group,
+            atmosphere_pipeline,
+        }
+    }
+
+    /// Drawn at the far plane and only where nothing else was
+    fn create_atmosphere_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        shader: &wgpu::ShaderModule,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Atmosphere Pipeline Layout"),
+            bind_group_layouts: &[bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Atmosphere Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        })
+    }
+
+    /// Rebuild the atmosphere pipeline for scene targets with `sample_count` samples
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, atmosphere_shader: &wgpu::ShaderModule, sample_count: u32) {
+        self.atmosphere_pipeline = Self::create_atmosphere_pipeline(device, &self.bind_group_layout, atmosphere_shader, sample_count);
+    }
+
+    fn apply_atmosphere(uniforms: &mut SkyUniforms, atmosphere: &AtmosphereSettings) {
+        uniforms.camera_height = atmosphere.planet_radius + atmosphere.camera_altitude;
+        uniforms.planet_radius = atmosphere.planet_radius;
+        uniforms.atmosphere_radius = atmosphere.atmosphere_radius;
+        uniforms.rayleigh_scattering = atmosphere.rayleigh_scattering;
+        uniforms.rayleigh_scale_height = atmosphere.rayleigh_scale_height;
+        uniforms.mie_scattering = atmosphere.mie_scattering;
+        uniforms.mie_scale_height = atmosphere.mie_scale_height;
+        uniforms.mie_anisotropy = atmosphere.mie_anisotropy;
+    }
+
+    /// Returns what is drawn behind the scene
+    pub fn mode(&self) -> SkyMode {
+        self.mode
+    }
+
+    /// Choose what is drawn behind the scene
+    pub fn set_mode(&mut self, mode: SkyMode) {
+        self.mode = mode;
+    }
+
+    /// Returns the parameters of the procedural atmosphere
+    pub fn atmosphere(&self) -> &AtmosphereSettings {
+        &self.atmosphere
+    }
+
+    /// Change the parameters of the procedural atmosphere
+    pub fn set_atmosphere(&mut self, queue: &wgpu::Queue, atmosphere: AtmosphereSettings) {
+        self.atmosphere = atmosphere;
+        Self::apply_atmosphere(&mut self.uniforms, &atmosphere);
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniforms]));
+    }
+
+    /// Point the sky at the camera and light the atmosphere from the sun
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &Camera, sun: &SunLight) {
+        // The view matrix without its translation is a pure rotation, so its transpose inverts it
+        let mut rotation = camera.get_view_matrix();
+        rotation.w = Vector4::new(0.0, 0.0, 0.0, 1.0);
+        self.uniforms.inverse_view = rotation.transpose().into();
+        self.uniforms.tan_half_fovy = (camera.fovy.0 * 0.5).tan();
+        self.uniforms.aspect = camera.aspect_ratio;
+        self.uniforms.camera_height = self.atmosphere.planet_radius
+            + (self.atmosphere.camera_altitude + camera.position.y).max(1.0);
+
+        // The sun light shines along its direction, the sky needs the direction towards the sun
+        let [x, y, z] = sun.direction;
+        let length = (x * x + y * y + z * z).sqrt().max(f32::EPSILON);
+        self.uniforms.sun_direction = [-x / length, -y / length, -z / length];
+        self.uniforms.sun_intensity = sun.intensity * self.atmosphere.sun_intensity;
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniforms]));
+    }
+
+    /// Draw the sky wherever no geometry was rendered
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, environment: &'a Environment) {
+        match self.mode {
+            SkyMode::ClearColor => {}
+            SkyMode::Cubemap => environment.render_skybox(render_pass),
+            SkyMode::Atmosphere => {
+                render_pass.set_pipeline(&self.atmosphere_pipeline);
+                render_pass.set_bind_group(0, &self.bind_group, &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sky_modes_cycle_through_all() {
+        let mut mode = SkyMode::default();
+        let mut seen = vec![mode];
+        for _ in 0..2 {
+            mode = mode.next();
+            assert!(!seen.contains(&mode), "{:?} repeated", mode);
+            seen.push(mode);
+        }
+        assert_eq!(mode.next(), SkyMode::default());
+    }
+}
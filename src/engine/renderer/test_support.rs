@@ -0,0 +1,269 @@
+//! Helpers shared by the tests that render with a headless renderer: creating one,
+//! feeding its models, and reading back and comparing what it drew.
+
+use anyhow::Result;
+use cgmath::Matrix4;
+use image::{Rgba, RgbaImage};
+use wgpu::util::DeviceExt;
+use crate::engine::core::application::Uniforms;
+use crate::engine::renderer::{Renderer, Texture, TextureOptions};
+use crate::engine::resources::CompressedImage;
+use crate::engine::scene::{Camera, SunLight};
+
+/// Creates a headless renderer. Fails the test when no adapter is available rather than
+/// passing without rendering anything.
+pub(crate) fn headless_renderer(width: u32, height: u32) -> Renderer {
+    pollster::block_on(Renderer::new_headless(width, height))
+        .unwrap_or_else(|e| panic!("Headless render tests need a GPU or software adapter: {}", e))
+}
+
+/// True if the renderer culls on the GPU. Adapters without compute shaders or indirect
+/// draws cannot, and tests of GPU culling return early on them.
+pub(crate) fn gpu_culling_available(renderer: &Renderer) -> bool {
+    let available = renderer.gpu_culling_enabled();
+    if !available {
+        eprintln!("Skipping GPU culling test: compute or indirect draws unavailable");
+    }
+    available
+}
+
+/// Creates a uniform buffer holding the camera and model transforms
+pub(crate) fn create_uniform_buffer(renderer: &Renderer, camera: &Camera, model: Matrix4<f32>) -> wgpu::Buffer {
+    let uniforms = Uniforms {
+        view_proj: camera.get_view_projection_matrix().into(),
+        model: model.into(),
+    };
+    renderer.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Test Uniform Buffer"),
+        contents: bytemuck::cast_slice(&[uniforms]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+/// Creates a light buffer for the given sun
+pub(crate) fn create_light_buffer(renderer: &Renderer, sun_light: SunLight) -> wgpu::Buffer {
+    renderer.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Test Light Buffer"),
+        contents: bytemuck::cast_slice(&[sun_light]),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+/// Result of comparing a rendered image with its reference
+pub(crate) struct ImageComparison {
+    pub(crate) mismatched_pixels: usize,
+    pub(crate) max_channel_difference: u8,
+    pub(crate) diff: RgbaImage,
+}
+
+impl ImageComparison {
+    /// Fraction of all pixels that exceeded the tolerance
+    pub(crate) fn mismatch_ratio(&self) -> f64 {
+        let total = (self.diff.width() * self.diff.height()).max(1) as f64;
+        self.mismatched_pixels as f64 / total
+    }
+}
+
+/// Compare two images pixel by pixel.
+///
+/// The diff image shows the reference dimmed to grey where pixels match and
+/// red, scaled by the size of the error, where they do not.
+pub(crate) fn compare_images(actual: &RgbaImage, expected: &RgbaImage, tolerance: u8) -> Result<ImageComparison> {
+    if actual.dimensions() != expected.dimensions() {
+        return Err(anyhow::anyhow!(
+            "Image size {:?} does not match reference size {:?}",
+            actual.dimensions(),
+            expected.dimensions()
+        ));
+    }
+
+    let mut diff = RgbaImage::new(actual.width(), actual.height());
+    let mut mismatched_pixels = 0;
+    let mut max_channel_difference = 0;
+
+    for (x, y, actual_pixel) in actual.enumerate_pixels() {
+        let expected_pixel = expected.get_pixel(x, y);
+        let difference = actual_pixel.0.iter()
+            .zip(expected_pixel.0.iter())
+            .map(|(a, b)| a.abs_diff(*b))
+            .max()
+            .unwrap_or(0);
+        max_channel_difference = max_channel_difference.max(difference);
+
+        let diff_pixel = if difference > tolerance {
+            mismatched_pixels += 1;
+            Rgba([128 + difference / 2, 0, 0, 255])
+        } else {
+            let [r, g, b, _] = expected_pixel.0;
+            let luma = ((r as u32 + g as u32 + b as u32) / 3 / 3) as u8;
+            Rgba([luma, luma, luma, 255])
+        };
+        diff.put_pixel(x, y, diff_pixel);
+    }
+
+    Ok(ImageComparison {
+        mismatched_pixels,
+        max_channel_difference,
+        diff,
+    })
+}
+
+/// Reads every texel of the first level of `texture` back as the GPU decodes it
+pub(crate) fn read_texels(renderer: &Renderer, texture: &Texture, width: u32, height: u32) -> RgbaImage {
+    let device = renderer.device();
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Texel Readback Shader"),
+        source: wgpu::ShaderSource::Wgsl(
+            "@group(0) @binding(0) var t_source: texture_2d<f32>;
+            @vertex
+            fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+                let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+                return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+            }
+            @fragment
+            fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+                return textureLoad(t_source, vec2<i32>(position.xy), 0);
+            }"
+            .into(),
+        ),
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Texel Readback Pipeline"),
+        layout: None,
+        vertex: wgpu::VertexState { module: &shader, entry_point: "vs_main", buffers: &[] },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::TextureFormat::Rgba8Unorm.into())],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Texel Readback Bind Group"),
+        layout: &pipeline.get_bind_group_layout(0),
+        entries: &[wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&texture.view) }],
+    });
+    let size = wgpu::Extent3d { width, height, depth_or_array_layers: 1 };
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Texel Readback Target"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let bytes_per_row = (4 * width).div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Texel Readback Buffer"),
+        size: (bytes_per_row * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    {
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Texel Readback Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::BLACK), store: true },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+    encoder.copy_texture_to_buffer(
+        target.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout { offset: 0, bytes_per_row: Some(bytes_per_row), rows_per_image: Some(height) },
+        },
+        size,
+    );
+    renderer.queue().submit(std::iter::once(encoder.finish()));
+    buffer.slice(..).map_async(wgpu::MapMode::Read, |result| result.unwrap());
+    device.poll(wgpu::Maintain::Wait);
+
+    let data = buffer.slice(..).get_mapped_range();
+    let texels = data.chunks(bytes_per_row as usize).flat_map(|row| &row[..4 * width as usize]).copied().collect();
+    RgbaImage::from_raw(width, height, texels).unwrap()
+}
+
+/// Decode `blocks` by `blocks` random blocks of each format on the CPU and on the GPU, and
+/// check they agree
+pub(crate) fn assert_cpu_decoding_matches_gpu(renderer: &Renderer, formats: &[wgpu::TextureFormat], blocks: u32, seed: u64) {
+    use rand::{Rng, SeedableRng};
+
+    // Random blocks cover every mode of each format
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    for &format in formats {
+        if !renderer.device().features().contains(format.required_features()) {
+            eprintln!("Skipping {:?}: unsupported by the adapter", format);
+            continue;
+        }
+        // The GL backend creates these as sRGB textures, so the GPU decodes them wrongly
+        if format == wgpu::TextureFormat::Etc2Rgba8Unorm && renderer.adapter.get_info().backend == wgpu::Backend::Gl {
+            continue;
+        }
+        let (block_width, block_height) = format.block_dimensions();
+        let (width, height) = (blocks * block_width, blocks * block_height);
+        let mut data = vec![0; (blocks * blocks * format.block_size(None).unwrap()) as usize];
+        rng.fill(&mut data[..]);
+        // Most random ASTC blocks are errors, so half of them are redrawn until they decode
+        if let wgpu::TextureFormat::Astc { .. } = format {
+            for block in data.chunks_exact_mut(16).skip(1).step_by(2) {
+                loop {
+                    let single = CompressedImage { format, width: block_width, height: block_height, levels: vec![block.to_vec()] };
+                    if !single.decompress().unwrap()[0].pixels().any(|texel| texel.0 == [255, 0, 255, 255]) {
+                        break;
+                    }
+                    rng.fill(&mut *block);
+                }
+            }
+        }
+        let image = CompressedImage { format, width, height, levels: vec![data] };
+
+        let texture = Texture::from_levels(
+            renderer.device(),
+            renderer.queue(),
+            format,
+            (width, height),
+            &[&image.levels[0]],
+            None,
+            &TextureOptions::default(),
+        ).unwrap();
+        let gpu = read_texels(renderer, &texture, width, height);
+        let cpu = image.decompress().unwrap().remove(0);
+        // Implementations round the interpolated BC colors either way
+        let comparison = compare_images(&cpu, &gpu, 1).unwrap();
+        assert_eq!(comparison.mismatched_pixels, 0, "{:?} differs by up to {}", format, comparison.max_channel_difference);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_images_reports_mismatches() {
+        let expected = RgbaImage::from_pixel(4, 4, Rgba([100, 100, 100, 255]));
+        let mut actual = expected.clone();
+        actual.put_pixel(1, 1, Rgba([104, 100, 100, 255]));
+        actual.put_pixel(2, 2, Rgba([200, 100, 100, 255]));
+
+        let comparison = compare_images(&actual, &expected, 8).unwrap();
+        assert_eq!(comparison.mismatched_pixels, 1);
+        assert_eq!(comparison.max_channel_difference, 100);
+        assert_eq!(comparison.diff.get_pixel(2, 2).0, [178, 0, 0, 255]);
+        assert!(compare_images(&actual, &RgbaImage::new(2, 2), 8).is_err());
+    }
+}
@@ -4,6 +4,208 @@ use wgpu;
 #[allow(unused)]
 use image::{DynamicImage};
 
+/// Whether the texels of a texture are colors or linear data
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,   // Colors such as albedo and emissive maps, decoded to linear when sampled
+    Linear, // Data such as normal, roughness and occlusion maps
+}
+
+impl ColorSpace {
+    /// The RGBA8 format holding texels in this color space
+    pub fn format(self) -> wgpu::TextureFormat {
+        match self {
+            ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+/// What a material map holds, which decides the format it is stored in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextureRole {
+    Albedo,            // Base color and alpha
+    Emissive,          // Emitted color
+    Normal,            // Tangent-space normal
+    MetallicRoughness, // Roughness in green and metallic in blue, packed from single-channel maps
+    Occlusion,         // Ambient occlusion in a single channel
+}
+
+impl TextureRole {
+    /// The format maps of this role are uploaded in: sRGB for colors, linear for data,
+    /// and a single channel where only one is read
+    pub fn format(self) -> wgpu::TextureFormat {
+        match self {
+            TextureRole::Albedo | TextureRole::Emissive => wgpu::TextureFormat::Rgba8UnormSrgb,
+            TextureRole::Normal | TextureRole::MetallicRoughness => wgpu::TextureFormat::Rgba8Unorm,
+            TextureRole::Occlusion => wgpu::TextureFormat::R8Unorm,
+        }
+    }
+
+    /// Whether maps of this role hold colors or linear data
+    pub fn color_space(self) -> ColorSpace {
+        if self.format().is_srgb() {
+            ColorSpace::Srgb
+        } else {
+            ColorSpace::Linear
+        }
+    }
+}
+
+/// How an image is uploaded and sampled
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TextureOptions {
+    pub address_mode: wgpu::AddressMode, // Wrapping of coordinates outside 0 to 1
+    pub filter: wgpu::FilterMode,        // Magnification, minification and mip level filter
+    pub color_space: ColorSpace,
+    pub anisotropy: u16,                 // Most samples taken along a stretched footprint, 1 to disable
+    pub generate_mipmaps: bool,          // Build the full mip chain from the image
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        Self {
+            address_mode: wgpu::AddressMode::Repeat,
+            filter: wgpu::FilterMode::Linear,
+            color_space: ColorSpace::Srgb,
+            anisotropy: 16,
+            generate_mipmaps: true,
+        }
+    }
+}
+
+impl TextureOptions {
+    /// Create a sampler that wraps and filters like these options. Anisotropic filtering
+    /// needs linear filtering; wgpu turns it off on devices without
+    /// `DownlevelFlags::ANISOTROPIC_FILTERING`.
+    pub fn create_sampler(&self, device: &wgpu::Device, label: Option<&str>) -> wgpu::Sampler {
+        let anisotropy_clamp = match self.filter {
+            wgpu::FilterMode::Linear => self.anisotropy.clamp(1, 16),
+            wgpu::FilterMode::Nearest => 1,
+        };
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            label,
+            address_mode_u: self.address_mode,
+            address_mode_v: self.address_mode,
+            address_mode_w: self.address_mode,
+            mag_filter: self.filter,
+            min_filter: self.filter,
+            mipmap_filter: self.filter,
+            anisotropy_clamp,
+            ..Default::default()
+        })
+    }
+}
+
+/// Number of levels in a full mip chain down to 1x1
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+/// Source texels and weights of texel `index` of the next mip level, along an axis
+/// `size` texels long. Odd sizes spread three texels over each smaller one, so the last
+/// row and column are not dropped.
+fn downsample_taps(index: u32, size: u32) -> Vec<(u32, f32)> {
+    if size == 1 {
+        return vec![(0, 1.0)];
+    }
+    let first = index * 2;
+    if size.is_multiple_of(2) {
+        return vec![(first, 0.5), (first + 1, 0.5)];
+    }
+    let half = (size / 2) as f32;
+    let span = size as f32;
+    vec![
+        (first, (half - index as f32) / span),
+        (first + 1, half / span),
+        (first + 2, (index as f32 + 1.0) / span),
+    ]
+}
+
+/// Decode an 8-bit sRGB value to linear light
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encode linear light as an 8-bit sRGB value
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let encoded = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
+
+/// Build the mip levels below `image`, each half the size of the one before, down to 1x1.
+///
+/// Each texel averages a 2x2 block of the level above, with odd edges clamped. sRGB colors
+/// are averaged in linear light, so distant surfaces keep their brightness, and colors are
+/// weighted by alpha so fully transparent texels do not bleed into cut-out edges. Levels are
+/// filtered from the unrounded level above rather than from its 8-bit texels.
+pub fn generate_mip_chain(image: &image::RgbaImage, color_space: ColorSpace) -> Vec<image::RgbaImage> {
+    let decode: Vec<f32> = (0..=255u8)
+        .map(|value| match color_space {
+            ColorSpace::Srgb => srgb_to_linear(value),
+            ColorSpace::Linear => value as f32 / 255.0,
+        })
+        .collect();
+    let encode = |value: f32| match color_space {
+        ColorSpace::Srgb => linear_to_srgb(value),
+        ColorSpace::Linear => (value.clamp(0.0, 1.0) * 255.0).round() as u8,
+    };
+
+    let (mut width, mut height) = image.dimensions();
+    let mut texels: Vec<[f32; 4]> = image
+        .pixels()
+        .map(|pixel| {
+            let [r, g, b, a] = pixel.0;
+            [decode[r as usize], decode[g as usize], decode[b as usize], a as f32 / 255.0]
+        })
+        .collect();
+    let mut levels = Vec::new();
+
+    while width > 1 || height > 1 {
+        let (level_width, level_height) = ((width / 2).max(1), (height / 2).max(1));
+        let mut level = Vec::with_capacity((level_width * level_height) as usize);
+        for y in 0..level_height {
+            for x in 0..level_width {
+                let mut color = [0.0; 3];
+                let mut alpha = 0.0;
+                for (sy, y_weight) in downsample_taps(y, height) {
+                    for (sx, x_weight) in downsample_taps(x, width) {
+                        let texel = texels[(sy * width + sx) as usize];
+                        let weight = x_weight * y_weight * texel[3];
+                        for (sum, channel) in color.iter_mut().zip(texel) {
+                            *sum += channel * weight;
+                        }
+                        alpha += weight;
+                    }
+                }
+                let color = if alpha > 0.0 { color.map(|sum| sum / alpha) } else { [0.0; 3] };
+                level.push([color[0], color[1], color[2], alpha]);
+            }
+        }
+
+        levels.push(image::RgbaImage::from_fn(level_width, level_height, |x, y| {
+            let [r, g, b, a] = level[(y * level_width + x) as usize];
+            image::Rgba([encode(r), encode(g), encode(b), (a * 255.0).round() as u8])
+        }));
+        texels = level;
+        width = level_width;
+        height = level_height;
+    }
+
+    levels
+}
+
 /// Texture structure to hold texture data
 #[allow(dead_code)]
 pub struct Texture {
@@ -20,6 +222,23 @@ impl Texture {
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
         label: &str,
+    ) -> Self {
+        Self::create_multisampled_depth_texture(
+            device,
+            config,
+            1,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            label,
+        )
+    }
+
+    /// Creates a depth texture with `sample_count` samples per pixel, to match a multisampled color target
+    pub fn create_multisampled_depth_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+        usage: wgpu::TextureUsages,
+        label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
             width: config.width,
@@ -31,10 +250,10 @@ impl Texture {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Depth32Float,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            usage,
             view_formats: &[],
         };
         
@@ -60,43 +279,135 @@ impl Texture {
         }
     }
 
-    /// Creates a texture from raw bytes
-    pub fn from_bytes(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        bytes: &[u8],
-        label: &str,
-    ) -> Result<Self> {
-        let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+    /// Creates a square depth texture array with a comparison sampler for shadow mapping.
+    ///
+    /// The view covers every layer; render into single layers with `create_layer_view`.
+    pub fn create_shadow_map(device: &wgpu::Device, resolution: u32, layers: u32, label: &str) -> Self {
+        let size = wgpu::Extent3d {
+            width: resolution,
+            height: resolution,
+            depth_or_array_layers: layers,
+        };
+        
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Depth32Float,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(label),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+    
+    /// Creates a 2D view of a single layer of an array texture
+    pub fn create_layer_view(&self, layer: u32) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Texture Layer View"),
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_array_layer: layer,
+            array_layer_count: Some(1),
+            ..Default::default()
+        })
     }
 
-    /// Creates a texture from an image
-    pub fn from_image(
+    /// Creates a view of one mip level of a single layer, for rendering into cube faces
+    pub fn create_mip_layer_view(&self, layer: u32, mip_level: u32) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Texture Mip Layer View"),
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_mip_level: mip_level,
+            mip_level_count: Some(1),
+            base_array_layer: layer,
+            array_layer_count: Some(1),
+            ..Default::default()
+        })
+    }
+
+    /// Creates an HDR cubemap that each face and mip level can be rendered into
+    pub fn create_cube_render_target(device: &wgpu::Device, size: u32, mip_level_count: u32, label: &str) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(label),
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// Uploads a floating point image, such as an HDR environment, without conversion.
+    ///
+    /// `Rgba32Float` is not filterable, so shaders read it with `textureLoad`.
+    pub fn from_hdr_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        img: &image::DynamicImage,
-        label: Option<&str>,
-    ) -> Result<Self> {
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
-
+        img: &image::Rgba32FImage,
+        label: &str,
+    ) -> Self {
         let size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
+            width: img.width(),
+            height: img.height(),
             depth_or_array_layers: 1,
         };
         let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label,
+            label: Some(label),
             size,
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format: wgpu::TextureFormat::Rgba32Float,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[wgpu::TextureFormat::Rgba8UnormSrgb],
+            view_formats: &[],
         });
-
         queue.write_texture(
             wgpu::ImageCopyTexture {
                 aspect: wgpu::TextureAspect::All,
@@ -104,25 +415,255 @@ impl Texture {
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
             },
-            &rgba,
+            bytemuck::cast_slice(img.as_raw()),
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
+                bytes_per_row: Some(16 * img.width()),
+                rows_per_image: Some(img.height()),
             },
             size,
         );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
 
+    /// Creates a single-channel float texture with a full mip chain for a hierarchical depth
+    /// buffer. Levels are copied in rather than rendered, since sampling one mip level while
+    /// rendering another of the same texture is not reliable on every backend.
+    pub fn create_depth_pyramid(device: &wgpu::Device, width: u32, height: u32, label: &str) -> Self {
+        let mip_level_count = mip_level_count(width, height);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Levels are read with textureLoad, the sampler is never used
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// Creates a color texture that can be rendered to and copied back to the CPU
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+        
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::Repeat,
-            address_mode_v: wgpu::AddressMode::Repeat,
-            address_mode_w: wgpu::AddressMode::Repeat,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Nearest,
             ..Default::default()
         });
+        
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// Creates a texture from raw bytes
+    pub fn from_bytes(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+    ) -> Result<Self> {
+        let img = image::load_from_memory(bytes)?;
+        Self::from_image(device, queue, &img, Some(label))
+    }
+
+    /// Creates a texture from an image
+    pub fn from_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        Self::from_image_with_format(device, queue, img, label, wgpu::TextureFormat::Rgba8UnormSrgb)
+    }
+
+    /// Creates a texture from an image in the given format. Use `Rgba8Unorm` for data that
+    /// is not a color, such as normal or roughness maps; see `from_image_in_format`.
+    pub fn from_image_with_format(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        format: wgpu::TextureFormat,
+    ) -> Result<Self> {
+        Self::from_image_in_format(device, queue, img, label, format, &TextureOptions::default())
+    }
+
+    /// Creates a texture from an image, with its mip chain and sampler set up by `options`
+    pub fn from_image_with_options(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        options: &TextureOptions,
+    ) -> Result<Self> {
+        Self::from_image_in_format(device, queue, img, label, options.color_space.format(), options)
+    }
+
+    /// Creates a texture from an image in `format`: `Rgba8UnormSrgb` or `Rgba8Unorm`, or
+    /// `Rg8Unorm` or `R8Unorm` keeping only the first channels of the image. The format
+    /// decides the color space of the mip chain, instead of `options`.
+    pub fn from_image_in_format(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        format: wgpu::TextureFormat,
+        options: &TextureOptions,
+    ) -> Result<Self> {
+        let channels = match format {
+            wgpu::TextureFormat::Rgba8UnormSrgb | wgpu::TextureFormat::Rgba8Unorm => 4,
+            wgpu::TextureFormat::Rg8Unorm => 2,
+            wgpu::TextureFormat::R8Unorm => 1,
+            format => anyhow::bail!("images cannot be uploaded as {:?}", format),
+        };
+        let color_space = if format.is_srgb() { ColorSpace::Srgb } else { ColorSpace::Linear };
+
+        let rgba = img.to_rgba8();
+        let mip_levels = if options.generate_mipmaps {
+            generate_mip_chain(&rgba, color_space)
+        } else {
+            Vec::new()
+        };
+        let levels: Vec<Vec<u8>> = std::iter::once(&rgba)
+            .chain(&mip_levels)
+            .map(|level| level.pixels().flat_map(|pixel| &pixel.0[..channels]).copied().collect())
+            .collect();
+        let levels: Vec<&[u8]> = levels.iter().map(Vec::as_slice).collect();
+
+        Self::from_levels(device, queue, format, img.dimensions(), &levels, label, options)
+    }
+
+    /// Creates a texture of `format` from prepared mip levels, largest first. Each level is
+    /// tightly packed rows of texels, or of blocks for compressed formats. Only the wrapping
+    /// and filtering of `options` apply.
+    pub fn from_levels(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        (width, height): (u32, u32),
+        levels: &[&[u8]],
+        label: Option<&str>,
+        options: &TextureOptions,
+    ) -> Result<Self> {
+        let (block_width, block_height) = format.block_dimensions();
+        let block_size = format
+            .block_size(None)
+            .ok_or_else(|| anyhow::anyhow!("{:?} textures cannot be uploaded", format))?;
+        anyhow::ensure!(
+            width.is_multiple_of(block_width) && height.is_multiple_of(block_height),
+            "{}x{} is not whole {}x{} blocks of {:?}",
+            width,
+            height,
+            block_width,
+            block_height,
+            format
+        );
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        // Levels smaller than a block still take a whole one
+        let level_sizes: Vec<_> = (0..levels.len() as u32)
+            .map(|level| size.mip_level_size(level, wgpu::TextureDimension::D2).physical_size(format))
+            .collect();
+        for (mip_level, (data, level_size)) in levels.iter().zip(&level_sizes).enumerate() {
+            let expected = (level_size.width / block_width) * (level_size.height / block_height) * block_size;
+            anyhow::ensure!(
+                data.len() == expected as usize,
+                "mip level {} holds {} bytes instead of {}",
+                mip_level,
+                data.len(),
+                expected
+            );
+        }
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: levels.len() as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[format],
+        });
+
+        for (mip_level, (data, level_size)) in levels.iter().zip(level_sizes).enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: mip_level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                data,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(level_size.width / block_width * block_size),
+                    rows_per_image: Some(level_size.height / block_height),
+                },
+                level_size,
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = options.create_sampler(device, label);
 
         Ok(Self { texture, view, sampler })
     }
@@ -184,7 +725,25 @@ impl Texture {
         color: [f32; 3],
         label: &str,
     ) -> Self {
-        // Create a 1x1 texture with the given color
+        // Convert color to RGBA bytes
+        let rgba = [
+            (color[0] * 255.0) as u8,
+            (color[1] * 255.0) as u8,
+            (color[2] * 255.0) as u8,
+            255u8,
+        ];
+        Self::create_solid_texture(device, queue, rgba, wgpu::TextureFormat::Rgba8UnormSrgb, label)
+    }
+
+    /// Creates a 1x1 texture holding the given RGBA8 value
+    pub fn create_solid_texture(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: [u8; 4],
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        // Create a 1x1 texture with the given value
         let size = 1u32;
         let texture_size = wgpu::Extent3d {
             width: size,
@@ -198,19 +757,11 @@ impl Texture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
 
-        // Convert color to RGBA bytes
-        let rgba = [
-            (color[0] * 255.0) as u8,
-            (color[1] * 255.0) as u8,
-            (color[2] * 255.0) as u8,
-            255u8,
-        ];
-
         queue.write_texture(
             wgpu::ImageCopyTexture {
                 texture: &texture,
@@ -244,4 +795,68 @@ impl Texture {
             sampler,
         }
     }
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn texture_roles_store_colors_as_srgb_and_data_as_linear() {
+        assert_eq!(TextureRole::Albedo.format(), wgpu::TextureFormat::Rgba8UnormSrgb);
+        assert_eq!(TextureRole::Emissive.format(), wgpu::TextureFormat::Rgba8UnormSrgb);
+        assert_eq!(TextureRole::Normal.format(), wgpu::TextureFormat::Rgba8Unorm);
+        assert_eq!(TextureRole::MetallicRoughness.format(), wgpu::TextureFormat::Rgba8Unorm);
+        assert_eq!(TextureRole::Occlusion.format(), wgpu::TextureFormat::R8Unorm);
+        assert_eq!(TextureRole::Albedo.color_space(), ColorSpace::Srgb);
+        assert_eq!(TextureRole::Occlusion.color_space(), ColorSpace::Linear);
+    }
+
+    #[test]
+    fn mip_chain_halves_down_to_one_texel() {
+        let image = image::RgbaImage::new(8, 3);
+        let sizes: Vec<_> = generate_mip_chain(&image, ColorSpace::Linear)
+            .iter()
+            .map(|level| level.dimensions())
+            .collect();
+        assert_eq!(sizes, [(4, 1), (2, 1), (1, 1)]);
+        assert_eq!(mip_level_count(8, 3), 4);
+        assert_eq!(mip_level_count(1, 1), 1);
+    }
+
+    #[test]
+    fn mip_chain_averages_srgb_in_linear_light() {
+        // A black and white checkerboard is half as bright, which is 188 in sRGB
+        let checkerboard = image::RgbaImage::from_fn(2, 2, |x, y| {
+            let value = if (x + y) % 2 == 0 { 255 } else { 0 };
+            image::Rgba([value, value, value, 255])
+        });
+        let srgb = generate_mip_chain(&checkerboard, ColorSpace::Srgb);
+        assert_eq!(srgb[0].get_pixel(0, 0).0, [188, 188, 188, 255]);
+        let linear = generate_mip_chain(&checkerboard, ColorSpace::Linear);
+        assert_eq!(linear[0].get_pixel(0, 0).0, [128, 128, 128, 255]);
+    }
+
+    #[test]
+    fn mip_chain_ignores_the_color_of_transparent_texels() {
+        let image = image::RgbaImage::from_fn(2, 1, |x, _| {
+            if x == 0 { image::Rgba([255, 0, 0, 255]) } else { image::Rgba([0, 0, 255, 0]) }
+        });
+        let levels = generate_mip_chain(&image, ColorSpace::Srgb);
+        assert_eq!(levels[0].get_pixel(0, 0).0, [255, 0, 0, 128]);
+    }
+
+    #[test]
+    fn odd_sized_mip_chains_keep_the_last_column() {
+        // Five texels shrink to two, the second covering the white one at the edge
+        let image = image::RgbaImage::from_fn(5, 1, |x, _| {
+            let value = if x == 4 { 255 } else { 0 };
+            image::Rgba([value, value, value, 255])
+        });
+        let levels = generate_mip_chain(&image, ColorSpace::Linear);
+        assert_eq!(levels[0].dimensions(), (2, 1));
+        assert_eq!(levels[0].get_pixel(0, 0).0, [0, 0, 0, 255]);
+        assert_eq!(levels[0].get_pixel(1, 0).0, [102, 102, 102, 255]);
+        // The white texel is a fifth of the image, and of its last level
+        assert_eq!(levels[1].get_pixel(0, 0).0, [51, 51, 51, 255]);
+    }
+}
@@ -0,0 +1,456 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+use crate::engine::renderer::texture::Texture;
+
+/// Format of the HDR color target the scene is lit into
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Number of bins in the luminance histogram. Bin 0 collects (near) black pixels,
+/// which are left out of the average so dark backgrounds do not over-expose the scene.
+pub const HISTOGRAM_BINS: u32 = 256;
+
+/// Width and height of the histogram compute workgroups
+const HISTOGRAM_WORKGROUP_SIZE: u32 = 16;
+
+/// Scene luminance auto exposure maps to middle grey
+const MIDDLE_GREY: f32 = 0.18;
+
+/// WGSL structs shared by the tonemap pass and the exposure histogram compute shader
+pub const TONEMAP_TYPES_WGSL: &str = r#"
+struct TonemapUniforms {
+    exposure: f32,
+    tonemap_operator: u32,
+    auto_exposure: u32,
+    encode_srgb: u32,
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+    middle_grey: f32,
+};
+
+struct Exposure {
+    luminance: f32,
+};
+
+const TONEMAP_REINHARD: u32 = 0u;
+const TONEMAP_ACES_FILMIC: u32 = 1u;
+const TONEMAP_AGX: u32 = 2u;
+"#;
+
+/// Curve used to map HDR scene colors to the display range
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum TonemapOperator {
+    Reinhard,
+    #[default]
+    AcesFilmic,
+    AgX,
+}
+
+impl TonemapOperator {
+    /// The operator after this one, wrapping around
+    pub fn next(self) -> Self {
+        match self {
+            TonemapOperator::Reinhard => TonemapOperator::AcesFilmic,
+            TonemapOperator::AcesFilmic => TonemapOperator::AgX,
+            TonemapOperator::AgX => TonemapOperator::Reinhard,
+        }
+    }
+
+    /// Matches the `TONEMAP_*` constants in the tonemap shader
+    fn shader_id(self) -> u32 {
+        match self {
+            TonemapOperator::Reinhard => 0,
+            TonemapOperator::AcesFilmic => 1,
+            TonemapOperator::AgX => 2,
+        }
+    }
+}
+
+/// Settings of the tonemap pass
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TonemapSettings {
+    pub operator: TonemapOperator,
+    pub exposure: f32,          // In EV; exposure compensation when auto exposure is on
+    pub auto_exposure: bool,    // Expose for the average scene luminance
+    pub min_log_luminance: f32, // Darkest log2 luminance the histogram resolves
+    pub max_log_luminance: f32, // Brightest log2 luminance the histogram resolves
+    pub adaptation_speed: f32,  // How quickly auto exposure follows the scene, per second
+}
+
+impl Default for TonemapSettings {
+    fn default() -> Self {
+        Self {
+            operator: TonemapOperator::default(),
+            exposure: 0.0,
+            auto_exposure: false,
+            min_log_luminance: -10.0,
+            max_log_luminance: 6.0,
+            adaptation_speed: 1.5,
+        }
+    }
+}
+
+/// Tonemap parameters as laid out in the shaders
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct TonemapUniforms {
+    pub exposure: f32,
+    pub tonemap_operator: u32,
+    pub auto_exposure: u32,
+    pub encode_srgb: u32,      // Set when the output target does not encode sRGB itself
+    pub min_log_luminance: f32,
+    pub log_luminance_range: f32,
+    pub adaptation: f32,       // Fraction of the way auto exposure moves to the new average this frame
+    pub middle_grey: f32,
+}
+
+impl TonemapUniforms {
+    fn new(settings: &TonemapSettings, output_format: wgpu::TextureFormat, adaptation: f32) -> Self {
+        Self {
+            exposure: settings.exposure,
+            tonemap_operator: settings.operator.shader_id(),
+            auto_exposure: settings.auto_exposure as u32,
+            encode_srgb: (!output_format.is_srgb()) as u32,
+            min_log_luminance: settings.min_log_luminance,
+            log_luminance_range: (settings.max_log_luminance - settings.min_log_luminance).max(0.001),
+            adaptation,
+            middle_grey: MIDDLE_GREY,
+        }
+    }
+}
+
+/// HDR color target at one size, with the bind groups exposing and tonemapping it
+pub struct HdrTarget {
+    texture: Texture,
+    histogram_bind_group: wgpu::BindGroup,
+    tonemap_bind_group: wgpu::BindGroup,
+}
+
+/// HDR color target plus the passes that expose and tonemap it to the output
+pub struct Tonemapper {
+    hdr_target: HdrTarget,
+    settings: TonemapSettings,
+    output_format: wgpu::TextureFormat,
+    uniforms: TonemapUniforms,
+    uniform_buffer: wgpu::Buffer,
+    histogram_buffer: wgpu::Buffer,
+    exposure_buffer: wgpu::Buffer, // Adapted average luminance, kept across frames
+    histogram_bind_group_layout: wgpu::BindGroupLayout,
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+    tonemap_bind_group_layout: wgpu::BindGroupLayout,
+    tonemap_pipeline: wgpu::RenderPipeline,
+}
+
+impl Tonemapper {
+    /// Create the HDR target and the tonemap pipelines for the given output configuration
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        tonemap_shader: &wgpu::ShaderModule,
+        histogram_shader: &wgpu::ShaderModule,
+    ) -> Self {
+        let settings = TonemapSettings::default();
+        let uniforms = TonemapUniforms::new(&settings, config.format, 1.0);
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tonemap Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let histogram_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Luminance Histogram Buffer"),
+            contents: bytemuck::cast_slice(&[0u32; HISTOGRAM_BINS as usize]),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        // Zero luminance tells the first auto exposure frame to skip adaptation
+        let exposure_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Exposure Buffer"),
+            contents: bytemuck::cast_slice(&[0.0f32]),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let uniform_entry = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let hdr_entry = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let storage_entry = |binding, visibility, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        // Histogram and average passes
+        let histogram_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Exposure Histogram Bind Group Layout"),
+            entries: &[
+                uniform_entry(0, wgpu::ShaderStages::COMPUTE),
+                hdr_entry(1, wgpu::ShaderStages::COMPUTE),
+                storage_entry(2, wgpu::ShaderStages::COMPUTE, false),
+                storage_entry(3, wgpu::ShaderStages::COMPUTE, false),
+            ],
+        });
+        let histogram_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Exposure Histogram Pipeline Layout"),
+            bind_group_layouts: &[&histogram_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let histogram_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Luminance Histogram Pipeline"),
+            layout: Some(&histogram_pipeline_layout),
+            module: histogram_shader,
+            entry_point: "build_histogram",
+        });
+        let average_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Luminance Average Pipeline"),
+            layout: Some(&histogram_pipeline_layout),
+            module: histogram_shader,
+            entry_point: "average_histogram",
+        });
+
+        // Fullscreen tonemap pass
+        let tonemap_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tonemap Bind Group Layout"),
+            entries: &[
+                uniform_entry(0, wgpu::ShaderStages::FRAGMENT),
+                hdr_entry(1, wgpu::ShaderStages::FRAGMENT),
+                storage_entry(2, wgpu::ShaderStages::FRAGMENT, true),
+            ],
+        });
+        let tonemap_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tonemap Pipeline Layout"),
+            bind_group_layouts: &[&tonemap_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let tonemap_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tonemap Pipeline"),
+            layout: Some(&tonemap_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: tonemap_shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: tonemap_shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let hdr_target = Self::create_hdr_target(
+            device,
+            &histogram_bind_group_layout,
+            &tonemap_bind_group_layout,
+            config,
+            &uniform_buffer,
+            &histogram_buffer,
+            &exposure_buffer,
+        );
+
+        Self {
+            hdr_target,
+            settings,
+            output_format: config.format,
+            uniforms,
+            uniform_buffer,
+            histogram_buffer,
+            exposure_buffer,
+            histogram_bind_group_layout,
+            histogram_pipeline,
+            average_pipeline,
+            tonemap_bind_group_layout,
+            tonemap_pipeline,
+        }
+    }
+
+    fn create_hdr_target(
+        device: &wgpu::Device,
+        histogram_layout: &wgpu::BindGroupLayout,
+        tonemap_layout: &wgpu::BindGroupLayout,
+        config: &wgpu::SurfaceConfiguration,
+        uniform_buffer: &wgpu::Buffer,
+        histogram_buffer: &wgpu::Buffer,
+        exposure_buffer: &wgpu::Buffer,
+    ) -> HdrTarget {
+        let hdr_config = wgpu::SurfaceConfiguration {
+            format: HDR_FORMAT,
+            view_formats: vec![HDR_FORMAT],
+            ..config.clone()
+        };
+        let hdr_target = Texture::create_render_target(device, &hdr_config, "HDR Color Target");
+        let histogram_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Exposure Histogram Bind Group"),
+            layout: histogram_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&hdr_target.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: histogram_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: exposure_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let tonemap_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tonemap Bind Group"),
+            layout: tonemap_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&hdr_target.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: exposure_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        HdrTarget {
+            texture: hdr_target,
+            histogram_bind_group,
+            tonemap_bind_group,
+        }
+    }
+
+    /// Recreate the HDR target at the new output size, returning the one it replaces
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> HdrTarget {
+        let hdr_target = Self::create_hdr_target(
+            device,
+            &self.histogram_bind_group_layout,
+            &self.tonemap_bind_group_layout,
+            config,
+            &self.uniform_buffer,
+            &self.histogram_buffer,
+            &self.exposure_buffer,
+        );
+        self.swap_hdr_target(hdr_target)
+    }
+
+    /// Render into a HDR target kept from an earlier `resize`, returning the current one
+    pub fn swap_hdr_target(&mut self, hdr_target: HdrTarget) -> HdrTarget {
+        std::mem::replace(&mut self.hdr_target, hdr_target)
+    }
+
+    /// View of the HDR target the scene is rendered into
+    pub fn hdr_view(&self) -> &wgpu::TextureView {
+        &self.hdr_target.texture.view
+    }
+
+    /// The HDR target the scene is rendered into
+    pub fn hdr_texture(&self) -> &Texture {
+        &self.hdr_target.texture
+    }
+
+    /// Returns the current tonemap settings
+    pub fn settings(&self) -> &TonemapSettings {
+        &self.settings
+    }
+
+    /// Replace the tonemap settings
+    pub fn set_settings(&mut self, queue: &wgpu::Queue, settings: TonemapSettings) {
+        self.settings = settings;
+        self.uniforms = TonemapUniforms::new(&settings, self.output_format, self.uniforms.adaptation);
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniforms]));
+    }
+
+    /// Set how far auto exposure adapts in the next frame, given the time since the last one
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: f32) {
+        self.uniforms.adaptation = 1.0 - (-dt.max(0.0) * self.settings.adaptation_speed).exp();
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniforms]));
+    }
+
+    /// Measure the scene luminance if auto exposure is on, then tonemap the HDR target to `output`
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        if self.settings.auto_exposure {
+            let size = self.hdr_target.texture.texture.size();
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Exposure Histogram Pass"),
+            });
+            compute_pass.set_bind_group(0, &self.hdr_target.histogram_bind_group, &[]);
+            compute_pass.set_pipeline(&self.histogram_pipeline);
+            compute_pass.dispatch_workgroups(
+                size.width.div_ceil(HISTOGRAM_WORKGROUP_SIZE),
+                size.height.div_ceil(HISTOGRAM_WORKGROUP_SIZE),
+                1,
+            );
+            compute_pass.set_pipeline(&self.average_pipeline);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.tonemap_pipeline);
+        render_pass.set_bind_group(0, &self.hdr_target.tonemap_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tonemap_operators_cycle_through_all() {
+        let mut operator = TonemapOperator::default();
+        let mut seen = Vec::new();
+        for _ in 0..3 {
+            seen.push(operator.shader_id());
+            operator = operator.next();
+        }
+        seen.sort();
+        assert_eq!(seen, vec![0, 1, 2]);
+        assert_eq!(operator, TonemapOperator::default());
+    }
+}
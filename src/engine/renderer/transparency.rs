@@ -0,0 +1,246 @@
+use crate::engine::renderer::instance::Instance;
+use crate::engine::renderer::material::BlendMode;
+use crate::engine::renderer::mesh;
+use crate::engine::renderer::render_graph::TransientTextureDesc;
+use crate::engine::renderer::shader::ShaderManager;
+use crate::engine::renderer::tonemap::HDR_FORMAT;
+
+/// Weighted sum of the premultiplied colors of the transparent surfaces, and in alpha the
+/// revealage: the product of one minus their alphas
+const ACCUMULATION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// Weighted sum of the alphas of the transparent surfaces
+const WEIGHT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R16Float;
+
+/// Clear values of the order-independent transparency targets: nothing accumulated, everything revealed
+pub const ACCUMULATION_CLEAR: wgpu::Color = wgpu::Color::BLACK;
+pub const WEIGHT_CLEAR: wgpu::Color = wgpu::Color::TRANSPARENT;
+
+/// How alpha-blended meshes are composited over the scene
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum TransparencyMode {
+    #[default]
+    Sorted,          // Drawn back to front by the distance of their bounds to the camera
+    WeightedBlended, // Accumulated in any order and averaged, weighted towards the camera
+}
+
+/// Pipelines drawing the transparent meshes over the lit scene.
+///
+/// Sorted transparency blends each alpha-blended or additive mesh straight into the scene
+/// color, so overlapping meshes are only right when their bounds sort like their surfaces.
+/// Weighted blended order-independent transparency instead accumulates the alpha-blended
+/// meshes into two targets in any order, and a fullscreen pass blends their weighted
+/// average over the scene. It handles heavy overlap without sorting, at the cost of
+/// blurring the order of surfaces with similar depth. Additive meshes do not depend on
+/// the order and are drawn directly in both modes. None of them write depth.
+pub struct TransparencyPipelines {
+    alpha_blend: wgpu::RenderPipeline,
+    additive: wgpu::RenderPipeline,
+    accumulation: wgpu::RenderPipeline,
+    composite_bind_group_layout: wgpu::BindGroupLayout,
+    composite: wgpu::RenderPipeline,
+}
+
+impl TransparencyPipelines {
+    /// Create the pipelines for a scene pass with `sample_count` samples. The mesh pipelines
+    /// use the forward pipeline layout and the PBR shader.
+    pub fn new(
+        device: &wgpu::Device,
+        mesh_pipeline_layout: &wgpu::PipelineLayout,
+        shader_manager: &ShaderManager,
+        sample_count: u32,
+    ) -> Self {
+        let pbr_shader = shader_manager.get("pbr").expect("PBR shader should be available");
+        let composite_shader = shader_manager.get("oit_composite").expect("OIT composite shader should be available");
+
+        // Transparent surfaces are tested against the scene depth but leave it alone
+        let depth_stencil = |depth_compare| {
+            Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            })
+        };
+        let multisample = wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        };
+        let target = |format, blend| Some(wgpu::ColorTargetState {
+            format,
+            blend: Some(blend),
+            write_mask: wgpu::ColorWrites::ALL,
+        });
+        let mesh_pipeline = |entry_point, targets: &[Option<wgpu::ColorTargetState>], label| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(mesh_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: pbr_shader,
+                    entry_point: "vs_main",
+                    buffers: &[mesh::Vertex::desc(), Instance::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: pbr_shader,
+                    entry_point,
+                    targets,
+                }),
+                // Only the front faces, like the opaque meshes, so a closed mesh is one layer
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: depth_stencil(wgpu::CompareFunction::Less),
+                multisample,
+                multiview: None,
+            })
+        };
+
+        let additive_blend = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+        };
+        let alpha_blend = mesh_pipeline(
+            "fs_main",
+            &[target(HDR_FORMAT, wgpu::BlendState::ALPHA_BLENDING)],
+            "Alpha Blend Pipeline",
+        );
+        let additive = mesh_pipeline("fs_main", &[target(HDR_FORMAT, additive_blend)], "Additive Pipeline");
+
+        // Sums the weighted colors and alphas, and multiplies the revealage by one minus each
+        // alpha. Both targets share the blend state, since the GL backend cannot blend them apart.
+        let accumulate = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+        };
+        let accumulation = mesh_pipeline(
+            "fs_oit",
+            &[target(ACCUMULATION_FORMAT, accumulate), target(WEIGHT_FORMAT, accumulate)],
+            "OIT Accumulation Pipeline",
+        );
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let composite_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("OIT Composite Bind Group Layout"),
+            entries: &[texture_entry(0), texture_entry(1)],
+        });
+        let composite_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("OIT Composite Pipeline Layout"),
+            bind_group_layouts: &[&composite_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        // Runs in the scene pass, so it matches its depth attachment without testing it
+        let composite = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("OIT Composite Pipeline"),
+            layout: Some(&composite_layout),
+            vertex: wgpu::VertexState {
+                module: composite_shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: composite_shader,
+                entry_point: "fs_main",
+                targets: &[target(HDR_FORMAT, wgpu::BlendState::ALPHA_BLENDING)],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: depth_stencil(wgpu::CompareFunction::Always),
+            multisample,
+            multiview: None,
+        });
+
+        Self {
+            alpha_blend,
+            additive,
+            accumulation,
+            composite_bind_group_layout,
+            composite,
+        }
+    }
+
+    /// Returns the pipeline blending meshes of `mode` into the scene color
+    pub fn blend_pipeline(&self, mode: BlendMode) -> &wgpu::RenderPipeline {
+        match mode {
+            BlendMode::Additive => &self.additive,
+            _ => &self.alpha_blend,
+        }
+    }
+
+    /// Returns the pipeline accumulating alpha-blended meshes into the accumulation and weight targets
+    pub fn accumulation_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.accumulation
+    }
+
+    /// Descriptions of the accumulation and weight targets the composite pass reads
+    pub fn target_descs(width: u32, height: u32) -> [TransientTextureDesc; 2] {
+        [
+            TransientTextureDesc::new(width, height, ACCUMULATION_FORMAT),
+            TransientTextureDesc::new(width, height, WEIGHT_FORMAT),
+        ]
+    }
+
+    /// Descriptions of the multisampled accumulation and weight targets resolved into
+    /// those of `target_descs`
+    pub fn multisampled_target_descs(width: u32, height: u32, sample_count: u32) -> [TransientTextureDesc; 2] {
+        [
+            TransientTextureDesc::multisampled(width, height, ACCUMULATION_FORMAT, sample_count),
+            TransientTextureDesc::multisampled(width, height, WEIGHT_FORMAT, sample_count),
+        ]
+    }
+
+    /// Create the bind group the composite pass reads the resolved targets through
+    pub fn composite_bind_group(
+        &self,
+        device: &wgpu::Device,
+        accumulation: &wgpu::TextureView,
+        weight: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("OIT Composite Bind Group"),
+            layout: &self.composite_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(accumulation),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(weight),
+                },
+            ],
+        })
+    }
+
+    /// Blend the average of the accumulated surfaces over the scene color
+    pub fn composite<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, bind_group: &'a wgpu::BindGroup) {
+        render_pass.set_pipeline(&self.composite);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,470 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::SquareMatrix;
+use wgpu::util::DeviceExt;
+use crate::engine::renderer::shader::ShaderManager;
+use crate::engine::renderer::texture::Texture;
+use crate::engine::scene::Camera;
+
+/// Binding of the view in the light bind group, after the ambient occlusion
+pub const VIEW_BINDING: u32 = 13;
+
+/// WGSL declaration of the camera the lit meshes are drawn from. Expects the view in the
+/// light bind group at group 2, at `VIEW_BINDING`.
+pub const VIEW_WGSL: &str = r#"
+struct ViewUniforms {
+    view_proj: mat4x4<f32>,
+    has_camera: u32,
+    _padding1: u32,
+    _padding2: u32,
+    _padding3: u32,
+};
+
+@group(2) @binding(13)
+var<uniform> scene_view: ViewUniforms;
+
+// Camera a mesh is drawn from; falls back to the view-projection in the mesh's own
+// transforms until the renderer has been given a camera
+fn view_projection(object_view_proj: mat4x4<f32>) -> mat4x4<f32> {
+    if (scene_view.has_camera == 0u) {
+        return object_view_proj;
+    }
+    return scene_view.view_proj;
+}
+"#;
+
+/// Camera of the view being rendered, as laid out in the shaders
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct ViewUniforms {
+    view_proj: [[f32; 4]; 4],
+    has_camera: u32,
+    _padding: [u32; 3],
+}
+
+/// Uniform buffer holding the camera the lit mesh pipelines draw from, so one scene can be
+/// drawn from several cameras without rewriting every model's transforms
+pub struct ViewBuffer {
+    buffer: wgpu::Buffer,
+}
+
+impl ViewBuffer {
+    /// Create the buffer. Meshes use their own view-projection until a camera is set.
+    pub fn new(device: &wgpu::Device) -> Self {
+        let uniforms = ViewUniforms {
+            view_proj: cgmath::Matrix4::identity().into(),
+            has_camera: 0,
+            _padding: [0; 3],
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("View Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        Self { buffer }
+    }
+
+    /// Draw the meshes from the camera
+    pub fn update_camera(&self, queue: &wgpu::Queue, camera: &Camera) {
+        let uniforms = ViewUniforms {
+            view_proj: camera.get_view_projection_matrix().into(),
+            has_camera: 1,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniforms]));
+    }
+
+    /// Layout entry of the view in the light bind group, at `VIEW_BINDING`
+    pub fn bind_group_layout_entry() -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding: VIEW_BINDING,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+
+    /// Bind group entry matching `bind_group_layout_entry`
+    pub fn bind_group_entry(&self) -> wgpu::BindGroupEntry<'_> {
+        wgpu::BindGroupEntry {
+            binding: VIEW_BINDING,
+            resource: self.buffer.as_entire_binding(),
+        }
+    }
+}
+
+/// Rectangle of the surface a view is drawn into, in fractions of the surface's width and
+/// height from its top left corner
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self::FULL
+    }
+}
+
+#[allow(dead_code)]
+impl Viewport {
+    /// The whole surface
+    pub const FULL: Self = Self::new(0.0, 0.0, 1.0, 1.0);
+
+    pub const fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    /// Split-screen layout for `count` players: the whole surface for one, side by side
+    /// for two, and a two by two grid for three or four
+    pub fn split_screen(count: usize) -> Vec<Self> {
+        match count {
+            0 => Vec::new(),
+            1 => vec![Self::FULL],
+            2 => vec![Self::new(0.0, 0.0, 0.5, 1.0), Self::new(0.5, 0.0, 0.5, 1.0)],
+            _ => (0..count.min(4))
+                .map(|index| Self::new((index % 2) as f32 * 0.5, (index / 2) as f32 * 0.5, 0.5, 0.5))
+                .collect(),
+        }
+    }
+
+    /// Returns the x, y, width and height in pixels on a surface of the given size. Edges are
+    /// rounded to the nearest pixel, so viewports sharing an edge neither overlap nor leave a
+    /// gap, and every viewport covers at least one pixel.
+    pub fn pixels(&self, surface_width: u32, surface_height: u32) -> (u32, u32, u32, u32) {
+        let edges = |start: f32, size: f32, extent: u32| {
+            let extent_f = extent as f32;
+            let first = ((start * extent_f).round().max(0.0) as u32).min(extent.saturating_sub(1));
+            let last = (((start + size) * extent_f).round().max(0.0) as u32).clamp(first + 1, extent.max(first + 1));
+            (first, last - first)
+        };
+        let (x, width) = edges(self.x, self.width, surface_width);
+        let (y, height) = edges(self.y, self.height, surface_height);
+        (x, y, width, height)
+    }
+
+    /// Returns true if the viewport covers the whole surface
+    pub fn is_full(&self, surface_width: u32, surface_height: u32) -> bool {
+        self.pixels(surface_width, surface_height) == (0, 0, surface_width, surface_height)
+    }
+}
+
+/// Offscreen color texture a camera renders into. Its texture is a regular material map,
+/// so mirrors, security monitors and minimaps sample what the camera saw. A surface that
+/// shows its own render target sees the previous frame's image there.
+pub struct RenderTarget {
+    texture: Texture,
+    width: u32,
+    height: u32,
+}
+
+#[allow(dead_code)]
+impl RenderTarget {
+    /// Create a target of the given size in the format of the renderer's output
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, width: u32, height: u32, label: &str) -> Self {
+        let (width, height) = (width.max(1), height.max(1));
+        let config = wgpu::SurfaceConfiguration {
+            width,
+            height,
+            ..config.clone()
+        };
+        Self {
+            texture: Texture::create_render_target(device, &config, label),
+            width,
+            height,
+        }
+    }
+
+    /// The rendered image, to bind as a material map
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+/// Where a view's image ends up
+#[derive(Clone, Copy)]
+pub enum ViewTarget<'a> {
+    Surface(Viewport),             // A rectangle of the swapchain image, or of the offscreen target when headless
+    Texture(&'a RenderTarget),     // The whole of an offscreen render target
+}
+
+/// A camera and the target it renders into
+#[derive(Clone, Copy)]
+pub struct View<'a> {
+    pub camera: &'a Camera, // Its aspect ratio should match the viewport or render target
+    pub target: ViewTarget<'a>,
+}
+
+#[allow(dead_code)]
+impl<'a> View<'a> {
+    /// A view of the whole surface
+    pub fn new(camera: &'a Camera) -> Self {
+        Self { camera, target: ViewTarget::Surface(Viewport::FULL) }
+    }
+
+    /// A view drawn into a rectangle of the surface
+    pub fn with_viewport(camera: &'a Camera, viewport: Viewport) -> Self {
+        Self { camera, target: ViewTarget::Surface(viewport) }
+    }
+
+    /// A view drawn into an offscreen render target
+    pub fn with_target(camera: &'a Camera, target: &'a RenderTarget) -> Self {
+        Self { camera, target: ViewTarget::Texture(target) }
+    }
+}
+
+/// Pipeline copying a view's image into its viewport of the surface
+pub struct ViewportBlit {
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ViewportBlit {
+    /// Create the pipeline writing into targets of `format`
+    pub fn new(device: &wgpu::Device, shader_manager: &ShaderManager, format: wgpu::TextureFormat) -> Self {
+        let shader = shader_manager.get("viewport_blit").expect("Viewport blit shader should be available");
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Viewport Blit Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Viewport Blit Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Viewport Blit Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        Self { bind_group_layout, pipeline }
+    }
+
+    /// Copy `source` into the pixel rectangle `viewport` of `target`, clearing the rest of
+    /// the target first when `clear` is set
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+        viewport: (u32, u32, u32, u32),
+        clear: bool,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Viewport Blit Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(source),
+            }],
+        });
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Viewport Blit Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: if clear { wgpu::LoadOp::Clear(wgpu::Color::BLACK) } else { wgpu::LoadOp::Load },
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        let (x, y, width, height) = viewport;
+        render_pass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Matrix4, Point3, Rad, Vector3};
+    use super::*;
+    use crate::engine::renderer::test_support::{
+        compare_images, create_light_buffer, create_uniform_buffer, gpu_culling_available, headless_renderer, read_texels,
+    };
+    use crate::engine::renderer::{CullingStats, Mesh};
+    use crate::engine::scene::SunController;
+
+    /// Size of the window of the headless renderer
+    const WIDTH: u32 = 128;
+    const HEIGHT: u32 = 128;
+
+    #[test]
+    fn adjacent_viewports_tile_the_surface_without_gaps() {
+        // An odd width puts the shared edge between two pixels
+        let [left, right] = [Viewport::new(0.0, 0.0, 0.5, 1.0), Viewport::new(0.5, 0.0, 0.5, 1.0)];
+        let (left_x, _, left_width, left_height) = left.pixels(101, 60);
+        let (right_x, _, right_width, _) = right.pixels(101, 60);
+        assert_eq!(left_x, 0);
+        assert_eq!(left_x + left_width, right_x);
+        assert_eq!(right_x + right_width, 101);
+        assert_eq!(left_height, 60);
+        assert!(Viewport::FULL.is_full(101, 60));
+        assert!(!left.is_full(101, 60));
+    }
+
+    #[test]
+    fn degenerate_viewports_cover_one_pixel_inside_the_surface() {
+        assert_eq!(Viewport::new(0.5, 0.5, 0.0, 0.0).pixels(10, 10), (5, 5, 1, 1));
+        assert_eq!(Viewport::new(1.0, 1.0, 0.5, 0.5).pixels(10, 10), (9, 9, 1, 1));
+    }
+
+    #[test]
+    fn split_screen_layouts_cover_the_surface_once() {
+        for count in 1..=4 {
+            let viewports = Viewport::split_screen(count);
+            assert_eq!(viewports.len(), count);
+            let area: u32 = viewports.iter().map(|viewport| {
+                let (_, _, width, height) = viewport.pixels(64, 48);
+                width * height
+            }).sum();
+            // Three players leave the fourth quarter empty
+            let expected = if count == 3 { 64 * 48 * 3 / 4 } else { 64 * 48 };
+            assert_eq!(area, expected, "{count} players");
+        }
+    }
+
+    #[test]
+    fn gpu_culling_occludes_in_every_view_of_a_frame() {
+        let mut renderer = headless_renderer(WIDTH, HEIGHT);
+        if !gpu_culling_available(&renderer) {
+            return;
+        }
+        renderer.set_debug_grid_visible(false);
+
+        // Two players side by side in front of a wall, a cube hidden behind it and a cube
+        // behind them
+        let cameras = [-0.5, 0.5].map(|x| {
+            let mut camera = Camera::new(Point3::new(x, 0.0, 6.0), Rad(3.0 * std::f32::consts::PI / 2.0), Rad(0.0));
+            camera.set_aspect_ratio(WIDTH / 2, HEIGHT);
+            camera
+        });
+        let light_buffer = create_light_buffer(&renderer, SunController::new().sun_light);
+        for (size, position) in [
+            (4.0, Vector3::new(0.0, 0.0, 0.0)),
+            (1.0, Vector3::new(0.0, 0.0, -5.0)),
+            (1.0, Vector3::new(0.0, 0.0, 12.0)),
+        ] {
+            let transform = Matrix4::from_translation(position);
+            let uniform_buffer = create_uniform_buffer(&renderer, &cameras[0], transform);
+            let mut model = renderer.create_solid_model(
+                Mesh::create_cube(renderer.device(), size),
+                [1.0, 1.0, 1.0],
+                &uniform_buffer,
+                &light_buffer,
+                "Test Culled Cube",
+            );
+            model.set_transform(renderer.queue(), &uniform_buffer, transform);
+            renderer.add_model(model);
+        }
+
+        // Each view tests against the depth of its own previous frame, so from the second
+        // frame on both hide the cube behind the wall
+        let viewports = Viewport::split_screen(2);
+        let views = [View::with_viewport(&cameras[0], viewports[0]), View::with_viewport(&cameras[1], viewports[1])];
+        let sun = SunController::new();
+        for _ in 0..4 {
+            renderer.render_views(&views, &sun.sun_light, &sun.shadow).unwrap();
+        }
+        assert_eq!(renderer.culling_stats(), CullingStats { meshes_drawn: 1, meshes_culled: 2 });
+    }
+
+    #[test]
+    fn views_of_different_sizes_keep_their_scene_targets() {
+        let mut renderer = headless_renderer(WIDTH, HEIGHT);
+        if !gpu_culling_available(&renderer) {
+            return;
+        }
+        renderer.set_debug_grid_visible(false);
+
+        // Two offscreen cameras of different sizes in front of a wall hiding a cube
+        let targets = [(64, 64), (96, 48)].map(|(width, height)| renderer.create_render_target(width, height, "Test View Target"));
+        let cameras = targets.each_ref().map(|target| {
+            let mut camera = Camera::new(Point3::new(0.0, 0.0, 6.0), Rad(3.0 * std::f32::consts::PI / 2.0), Rad(0.0));
+            camera.set_aspect_ratio(target.width(), target.height());
+            camera
+        });
+        let light_buffer = create_light_buffer(&renderer, SunController::new().sun_light);
+        for (size, position) in [(4.0, Vector3::new(0.0, 0.0, 0.0)), (1.0, Vector3::new(0.0, 0.0, -5.0))] {
+            let transform = Matrix4::from_translation(position);
+            let uniform_buffer = create_uniform_buffer(&renderer, &cameras[0], transform);
+            let mut model = renderer.create_solid_model(
+                Mesh::create_cube(renderer.device(), size),
+                [1.0, 1.0, 1.0],
+                &uniform_buffer,
+                &light_buffer,
+                "Test Culled Cube",
+            );
+            model.set_transform(renderer.queue(), &uniform_buffer, transform);
+            renderer.add_model(model);
+        }
+
+        let views = [View::with_target(&cameras[0], &targets[0]), View::with_target(&cameras[1], &targets[1])];
+        let sun = SunController::new();
+        let mut frames = Vec::new();
+        for _ in 0..4 {
+            renderer.render_views(&views, &sun.sun_light, &sun.shadow).unwrap();
+            frames.push(targets.each_ref().map(|target| read_texels(&renderer, target.texture(), target.width(), target.height())));
+        }
+
+        // The targets of the window and of the first view wait while the second is drawn
+        let mut cached: Vec<_> = renderer.scene_target_cache.iter().map(|targets| (targets.width, targets.height)).collect();
+        cached.sort();
+        assert_eq!(cached, [(64, 64), (WIDTH, HEIGHT)]);
+
+        // Depth kept from frame to frame lets both views hide the cube behind the wall, and
+        // targets handed back and forth draw the same pictures
+        assert_eq!(renderer.culling_stats(), CullingStats { meshes_drawn: 1, meshes_culled: 1 });
+        for (previous, last) in frames[2].iter().zip(&frames[3]) {
+            assert_eq!(compare_images(previous, last, 0).unwrap().mismatched_pixels, 0);
+        }
+
+        // Resizing the window drops the targets of every other size
+        renderer.resize(WIDTH / 2, HEIGHT);
+        assert!(renderer.scene_target_cache.is_empty());
+    }
+}
@@ -0,0 +1,547 @@
+use std::array;
+
+/// Color of texels that break the rules of the format, or use HDR in the LDR profile
+const ERROR_COLOR: [u8; 4] = [255, 0, 255, 255];
+
+/// A range values of an integer sequence are quantized to: each value is `bits` low bits,
+/// and a trit or a quint shared with its neighbors on top when there is one
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Range {
+    trit: bool,
+    quint: bool,
+    bits: u32,
+}
+
+const fn range(trit: bool, quint: bool, bits: u32) -> Range {
+    Range { trit, quint, bits }
+}
+
+/// Every range, from 2 to 256 levels
+const RANGES: [Range; 21] = [
+    range(false, false, 1),
+    range(true, false, 0),
+    range(false, false, 2),
+    range(false, true, 0),
+    range(true, false, 1),
+    range(false, false, 3),
+    range(false, true, 1),
+    range(true, false, 2),
+    range(false, false, 4),
+    range(false, true, 2),
+    range(true, false, 3),
+    range(false, false, 5),
+    range(false, true, 3),
+    range(true, false, 4),
+    range(false, false, 6),
+    range(false, true, 4),
+    range(true, false, 5),
+    range(false, false, 7),
+    range(false, true, 5),
+    range(true, false, 6),
+    range(false, false, 8),
+];
+/// The first range color endpoints can use, of 6 levels
+const FIRST_COLOR_RANGE: usize = 4;
+
+impl Range {
+    /// Bits taken by `count` values
+    fn sequence_bits(&self, count: u32) -> u32 {
+        let shared = if self.trit {
+            (8 * count).div_ceil(5)
+        } else if self.quint {
+            (7 * count).div_ceil(3)
+        } else {
+            0
+        };
+        count * self.bits + shared
+    }
+}
+
+/// Weight grid and weight range of a block mode
+struct BlockMode {
+    grid_width: usize,
+    grid_height: usize,
+    dual_plane: bool,
+    weight_range: Range,
+}
+
+/// Decode one block of an ASTC LDR format into its `width` by `height` texels, row by
+/// row. sRGB blocks decode to sRGB bytes. Blocks that are not valid LDR blocks, and
+/// partitions with HDR endpoints, are magenta, as the GPU draws them.
+pub fn decode_block(block: &[u8], width: usize, height: usize, srgb: bool) -> Vec<[u8; 4]> {
+    let bits = u128::from_le_bytes(block[..16].try_into().unwrap());
+    decode_texels(bits, width, height, srgb).unwrap_or_else(|| vec![ERROR_COLOR; width * height])
+}
+
+/// Read `count` bits of `bits` from bit `start` up
+fn field(bits: u128, start: u32, count: u32) -> u32 {
+    if start >= 128 {
+        return 0;
+    }
+    ((bits >> start) & ((1u128 << count) - 1)) as u32
+}
+
+/// The texels of a block, or None when the whole block is an error
+fn decode_texels(bits: u128, width: usize, height: usize, srgb: bool) -> Option<Vec<[u8; 4]>> {
+    if field(bits, 0, 9) == 0x1FC {
+        return void_extent(bits, srgb).map(|color| vec![color; width * height]);
+    }
+
+    let mode = block_mode(field(bits, 0, 11))?;
+    let partitions = field(bits, 11, 2) as usize + 1;
+    let plane_count = if mode.dual_plane { 2 } else { 1 };
+    let weight_count = mode.grid_width * mode.grid_height * plane_count;
+    let weight_bits = mode.weight_range.sequence_bits(weight_count as u32);
+    if weight_count > 64
+        || !(24..=96).contains(&weight_bits)
+        || mode.grid_width > width
+        || mode.grid_height > height
+        || (mode.dual_plane && partitions == 4)
+    {
+        return None;
+    }
+
+    // Color endpoint modes, some of whose bits may sit below the weights
+    let mut below_weights = 128 - weight_bits;
+    let mut modes = [0; 4];
+    let color_start = if partitions == 1 {
+        modes[0] = field(bits, 13, 4);
+        17
+    } else {
+        let encoded = field(bits, 23, 6);
+        if encoded & 3 == 0 {
+            modes = [(encoded >> 2) & 0xF; 4];
+        } else {
+            let extra_bits = 3 * partitions as u32 - 4;
+            below_weights = below_weights.checked_sub(extra_bits)?;
+            let encoded = encoded | (field(bits, below_weights, extra_bits) << 6);
+            let base_class = (encoded & 3) - 1;
+            for (partition, mode) in modes[..partitions].iter_mut().enumerate() {
+                let class = base_class + ((encoded >> (2 + partition)) & 1);
+                *mode = (class << 2) | ((encoded >> (2 + partitions + 2 * partition)) & 3);
+            }
+        }
+        29
+    };
+    let plane2_channel = if mode.dual_plane {
+        below_weights = below_weights.checked_sub(2)?;
+        Some(field(bits, below_weights, 2) as usize)
+    } else {
+        None
+    };
+
+    // The color values take the finest range that fits in the bits left
+    let modes = &modes[..partitions];
+    let value_count: u32 = modes.iter().map(|mode| 2 * ((mode >> 2) + 1)).sum();
+    let color_bits = below_weights.checked_sub(color_start)?;
+    if value_count > 18 {
+        return None;
+    }
+    let color_range = RANGES[FIRST_COLOR_RANGE..]
+        .iter()
+        .rev()
+        .find(|range| range.sequence_bits(value_count) <= color_bits)?;
+    let values: Vec<u32> = decode_sequence(bits, color_start, *color_range, value_count as usize)
+        .into_iter()
+        .map(|value| unquantize_color(*color_range, value))
+        .collect();
+    let mut endpoints = Vec::new();
+    let mut values = values.as_slice();
+    for &mode in modes {
+        let count = 2 * ((mode as usize >> 2) + 1);
+        endpoints.push(endpoint_colors(mode, &values[..count]));
+        values = &values[count..];
+    }
+
+    // Weights are stored from the top bit of the block down
+    let weights: Vec<u32> = decode_sequence(bits.reverse_bits(), 0, mode.weight_range, weight_count)
+        .into_iter()
+        .map(|weight| unquantize_weight(mode.weight_range, weight))
+        .collect();
+
+    let seed = field(bits, 13, 10);
+    let small_block = width * height < 31;
+    let texels = (0..width * height)
+        .map(|texel| {
+            let (x, y) = (texel % width, texel / width);
+            let partition = if partitions > 1 { select_partition(seed, x as u32, y as u32, partitions as u32, small_block) } else { 0 };
+            let Some([start, end]) = endpoints[partition] else { return ERROR_COLOR };
+            let plane_weights: [u32; 2] = array::from_fn(|plane| {
+                infill_weight(&weights, &mode, width, height, x, y, plane.min(plane_count - 1), plane_count)
+            });
+            array::from_fn(|channel| {
+                let weight = plane_weights[(plane2_channel == Some(channel)) as usize];
+                interpolate(start[channel], end[channel], weight, srgb)
+            })
+        })
+        .collect();
+    Some(texels)
+}
+
+/// The color of a block of one color, or None for HDR colors and broken extents
+fn void_extent(bits: u128, srgb: bool) -> Option<[u8; 4]> {
+    if field(bits, 9, 1) == 1 {
+        return None;
+    }
+    let [s_min, s_max, t_min, t_max] = [12, 25, 38, 51].map(|start| field(bits, start, 13));
+    let no_extent = [s_min, s_max, t_min, t_max].iter().all(|&coordinate| coordinate == 0x1FFF);
+    if !no_extent && (s_min >= s_max || t_min >= t_max) {
+        return None;
+    }
+    Some([64, 80, 96, 112].map(|start| unorm16_to_unorm8(field(bits, start, 16), srgb)))
+}
+
+/// Decode the 11 bits of the block mode, or None for reserved modes
+fn block_mode(bits: u32) -> Option<BlockMode> {
+    let bit = |index: u32| (bits >> index) & 1;
+    let a = ((bits >> 5) & 3) as usize;
+    let mut dual_plane = bit(10) == 1;
+    let mut high_precision = bit(9) == 1;
+    let (range, grid_width, grid_height);
+    if bits & 3 != 0 {
+        range = bit(4) | ((bits & 3) << 1);
+        let b = ((bits >> 7) & 3) as usize;
+        (grid_width, grid_height) = match (bits >> 2) & 3 {
+            0 => (b + 4, a + 2),
+            1 => (b + 8, a + 2),
+            2 => (a + 2, b + 8),
+            _ if bit(8) == 0 => (a + 2, (b & 1) + 6),
+            _ => ((b & 1) + 2, a + 2),
+        };
+    } else {
+        range = bit(4) | (((bits >> 2) & 3) << 1);
+        let b = ((bits >> 9) & 3) as usize;
+        (grid_width, grid_height) = match ((bits >> 7) & 3, a) {
+            (0, a) => (12, a + 2),
+            (1, a) => (a + 2, 12),
+            (2, a) => {
+                dual_plane = false;
+                high_precision = false;
+                (a + 6, b + 6)
+            }
+            (_, 0) => (6, 10),
+            (_, 1) => (10, 6),
+            _ => return None,
+        };
+    }
+    if range < 2 {
+        return None;
+    }
+    let weight_range = RANGES[range as usize - 2 + 6 * high_precision as usize];
+    Some(BlockMode { grid_width, grid_height, dual_plane, weight_range })
+}
+
+/// Decode `count` values of `range` from bit `start` of `bits` up. Bits past the end of
+/// the sequence read as zero.
+fn decode_sequence(bits: u128, start: u32, range: Range, count: usize) -> Vec<u32> {
+    let end = start + range.sequence_bits(count as u32);
+    let mut position = start;
+    let mut read = |count: u32| {
+        let available = end.saturating_sub(position).min(count);
+        let value = field(bits, position, available);
+        position += count;
+        value
+    };
+
+    let mut values = Vec::with_capacity(count);
+    let (group, shared_bits): (usize, &[u32]) = if range.trit {
+        (5, &[2, 2, 1, 2, 1])
+    } else if range.quint {
+        (3, &[3, 2, 2])
+    } else {
+        (1, &[0])
+    };
+    while values.len() < count {
+        // Each value's own bits, followed by its share of the bits of the trits or quints
+        let mut low = [0; 5];
+        let mut shared = 0;
+        let mut shared_position = 0;
+        for (value, &share) in low.iter_mut().zip(shared_bits) {
+            *value = read(range.bits);
+            shared |= read(share) << shared_position;
+            shared_position += share;
+        }
+        let high = if range.trit {
+            decode_trits(shared).to_vec()
+        } else if range.quint {
+            decode_quints(shared).to_vec()
+        } else {
+            vec![0]
+        };
+        for index in 0..group {
+            values.push((high[index] << range.bits) | low[index]);
+        }
+    }
+    values.truncate(count);
+    values
+}
+
+/// The five trits packed in 8 bits
+fn decode_trits(packed: u32) -> [u32; 5] {
+    let bit = |index: u32| (packed >> index) & 1;
+    let bits = |high: u32, low: u32| (packed >> low) & ((1 << (high - low + 1)) - 1);
+    let (c, t4, t3);
+    if bits(4, 2) == 7 {
+        c = (bits(7, 5) << 2) | bits(1, 0);
+        (t4, t3) = (2, 2);
+    } else {
+        c = bits(4, 0);
+        (t4, t3) = if bits(6, 5) == 3 { (2, bit(7)) } else { (bit(7), bits(6, 5)) };
+    }
+    let c_bit = |index: u32| (c >> index) & 1;
+    let (t2, t1, t0) = if c & 3 == 3 {
+        (2, c_bit(4), (c_bit(3) << 1) | (c_bit(2) & !c_bit(3) & 1))
+    } else if (c >> 2) & 3 == 3 {
+        (2, 2, c & 3)
+    } else {
+        (c_bit(4), (c >> 2) & 3, (c_bit(1) << 1) | (c_bit(0) & !c_bit(1) & 1))
+    };
+    [t0, t1, t2, t3, t4]
+}
+
+/// The three quints packed in 7 bits
+fn decode_quints(packed: u32) -> [u32; 3] {
+    let bit = |index: u32| (packed >> index) & 1;
+    let bits = |high: u32, low: u32| (packed >> low) & ((1 << (high - low + 1)) - 1);
+    if bits(2, 1) == 3 && bits(6, 5) == 0 {
+        let q2 = (bit(0) << 2) | ((bit(4) & !bit(0) & 1) << 1) | (bit(3) & !bit(0) & 1);
+        return [4, 4, q2];
+    }
+    let (q2, c) = if bits(2, 1) == 3 {
+        (4, (bits(4, 3) << 3) | ((!bits(6, 5) & 3) << 1) | bit(0))
+    } else {
+        (bits(6, 5), bits(4, 0))
+    };
+    let (q1, q0) = if c & 7 == 5 { (4, (c >> 3) & 3) } else { ((c >> 3) & 3, c & 7) };
+    [q0, q1, q2]
+}
+
+/// Repeat the `bits` bits of `value` until they fill `to` bits
+fn replicate(value: u32, bits: u32, to: u32) -> u32 {
+    let (mut result, mut filled) = (0, 0);
+    while filled < to {
+        result = (result << bits) | value;
+        filled += bits;
+    }
+    result >> (filled - to)
+}
+
+/// Spread a color value of `range` over 0 to 255
+fn unquantize_color(range: Range, value: u32) -> u32 {
+    if !range.trit && !range.quint {
+        return replicate(value, range.bits, 8);
+    }
+    let low = value & ((1 << range.bits) - 1);
+    let high = value >> range.bits;
+    let a = if low & 1 == 1 { 0x1FF } else { 0 };
+    let x = low >> 1;
+    let (c, b) = match (range.trit, range.bits) {
+        (true, 1) => (204, 0),
+        (false, 1) => (113, 0),
+        (true, 2) => (93, (x << 8) | (x << 4) | (x << 2) | (x << 1)),
+        (false, 2) => (54, (x << 8) | (x << 3) | (x << 2)),
+        (true, 3) => (44, (x << 7) | (x << 2) | x),
+        (false, 3) => (26, (x << 7) | (x << 1) | (x >> 1)),
+        (true, 4) => (22, (x << 6) | x),
+        (false, 4) => (13, (x << 6) | (x >> 1)),
+        (true, 5) => (11, (x << 5) | (x >> 2)),
+        (false, 5) => (6, (x << 5) | (x >> 3)),
+        _ => (5, (x << 4) | (x >> 4)),
+    };
+    let t = (high * c + b) ^ a;
+    (a & 0x80) | (t >> 2)
+}
+
+/// Spread a weight of `range` over 0 to 64
+fn unquantize_weight(range: Range, value: u32) -> u32 {
+    let weight = if !range.trit && !range.quint {
+        replicate(value, range.bits, 6)
+    } else if range.bits == 0 {
+        if range.trit { [0, 32, 63][value as usize] } else { [0, 16, 32, 47, 63][value as usize] }
+    } else {
+        let low = value & ((1 << range.bits) - 1);
+        let high = value >> range.bits;
+        let a = if low & 1 == 1 { 0x7F } else { 0 };
+        let x = low >> 1;
+        let (c, b) = match (range.trit, range.bits) {
+            (true, 1) => (50, 0),
+            (false, 1) => (28, 0),
+            (true, 2) => (23, (x << 6) | (x << 2) | x),
+            (false, 2) => (13, (x << 6) | (x << 1)),
+            _ => (11, (x << 5) | x),
+        };
+        let t = (high * c + b) ^ a;
+        (a & 0x20) | (t >> 2)
+    };
+    if weight > 32 { weight + 1 } else { weight }
+}
+
+/// Move the top bit of `b` into `a` as a signed offset of 6 bits
+fn bit_transfer_signed(a: i32, b: i32) -> (i32, i32) {
+    let b = (b >> 1) | (a & 0x80);
+    let a = (a >> 1) & 0x3F;
+    (if a & 0x20 != 0 { a - 0x40 } else { a }, b)
+}
+
+/// Average red and green with blue, the other way values can be stored
+fn blue_contract([r, g, b, a]: [i32; 4]) -> [i32; 4] {
+    [(r + b) >> 1, (g + b) >> 1, b, a]
+}
+
+/// The two endpoint colors of an LDR color endpoint mode, or None for HDR modes
+fn endpoint_colors(mode: u32, values: &[u32]) -> Option<[[u32; 4]; 2]> {
+    let v: Vec<i32> = values.iter().map(|&value| value as i32).collect();
+    let [start, end] = match mode {
+        0 => [[v[0], v[0], v[0], 255], [v[1], v[1], v[1], 255]],
+        1 => {
+            let low = (v[0] >> 2) | (v[1] & 0xC0);
+            let high = (low + (v[1] & 0x3F)).min(255);
+            [[low, low, low, 255], [high, high, high, 255]]
+        }
+        4 => [[v[0], v[0], v[0], v[2]], [v[1], v[1], v[1], v[3]]],
+        5 => {
+            let (offset, base) = bit_transfer_signed(v[1], v[0]);
+            let (alpha_offset, alpha) = bit_transfer_signed(v[3], v[2]);
+            let high = base + offset;
+            [[base, base, base, alpha], [high, high, high, alpha + alpha_offset]]
+        }
+        6 => [[(v[0] * v[3]) >> 8, (v[1] * v[3]) >> 8, (v[2] * v[3]) >> 8, 255], [v[0], v[1], v[2], 255]],
+        8 | 12 => {
+            let alpha = if mode == 12 { [v[6], v[7]] } else { [255, 255] };
+            let (first, second) = ([v[0], v[2], v[4], alpha[0]], [v[1], v[3], v[5], alpha[1]]);
+            if v[1] + v[3] + v[5] >= v[0] + v[2] + v[4] {
+                [first, second]
+            } else {
+                [blue_contract(second), blue_contract(first)]
+            }
+        }
+        9 | 13 => {
+            let pairs: Vec<(i32, i32)> = (0..if mode == 13 { 4 } else { 3 }).map(|i| bit_transfer_signed(v[2 * i + 1], v[2 * i])).collect();
+            let alpha = if mode == 13 { [pairs[3].1, pairs[3].1 + pairs[3].0] } else { [255, 255] };
+            let base = [pairs[0].1, pairs[1].1, pairs[2].1, alpha[0]];
+            let moved = [pairs[0].1 + pairs[0].0, pairs[1].1 + pairs[1].0, pairs[2].1 + pairs[2].0, alpha[1]];
+            if pairs[0].0 + pairs[1].0 + pairs[2].0 >= 0 {
+                [base, moved]
+            } else {
+                [blue_contract(moved), blue_contract(base)]
+            }
+        }
+        10 => [[(v[0] * v[3]) >> 8, (v[1] * v[3]) >> 8, (v[2] * v[3]) >> 8, v[4]], [v[0], v[1], v[2], v[5]]],
+        _ => return None,
+    };
+    let clamp = |color: [i32; 4]| color.map(|channel| channel.clamp(0, 255) as u32);
+    Some([clamp(start), clamp(end)])
+}
+
+/// The weight of texel `x`, `y` of a `width` by `height` block on `plane`, bilinearly
+/// interpolated from the weight grid
+#[allow(clippy::too_many_arguments)]
+fn infill_weight(weights: &[u32], mode: &BlockMode, width: usize, height: usize, x: usize, y: usize, plane: usize, plane_count: usize) -> u32 {
+    let grid = |texel: usize, size: usize, grid_size: usize| {
+        let scale = (1024 + size / 2) / (size - 1).max(1);
+        ((scale * texel) * (grid_size - 1) + 32) >> 6
+    };
+    let (gs, gt) = (grid(x, width, mode.grid_width), grid(y, height, mode.grid_height));
+    let (js, fs, jt, ft) = (gs >> 4, (gs & 0xF) as u32, gt >> 4, (gt & 0xF) as u32);
+    let weight = |column: usize, row: usize| {
+        weights.get((row * mode.grid_width + column) * plane_count + plane).copied().unwrap_or(0)
+    };
+    let w11 = (fs * ft + 8) >> 4;
+    let (w10, w01) = (ft - w11, fs - w11);
+    let w00 = 16 + w11 - fs - ft;
+    (weight(js, jt) * w00 + weight(js + 1, jt) * w01 + weight(js, jt + 1) * w10 + weight(js + 1, jt + 1) * w11 + 8) >> 4
+}
+
+/// Mix two 8-bit endpoint channels by a weight out of 64, at the 16-bit precision of the
+/// format, and round back to 8 bits
+fn interpolate(start: u32, end: u32, weight: u32, srgb: bool) -> u8 {
+    let widen = |value: u32| if srgb { (value << 8) | 0x80 } else { value * 257 };
+    let value = (widen(start) * (64 - weight) + widen(end) * weight + 32) >> 6;
+    unorm16_to_unorm8(value, srgb)
+}
+
+/// Narrow a 16-bit channel to 8 bits, keeping the top byte of sRGB ones as the format says
+fn unorm16_to_unorm8(value: u32, srgb: bool) -> u8 {
+    if srgb { (value >> 8) as u8 } else { ((value * 255 + 32767) / 65535) as u8 }
+}
+
+/// Partition of texel `x`, `y` in partitioning `seed` of `partitions` partitions
+fn select_partition(seed: u32, x: u32, y: u32, partitions: u32, small_block: bool) -> usize {
+    let (x, y) = if small_block { (x << 1, y << 1) } else { (x, y) };
+    let seed = seed + (partitions - 1) * 1024;
+    let random = hash52(seed);
+    let mut seeds: [u32; 12] = array::from_fn(|i| {
+        let nibble = match i {
+            0..=7 => random >> (4 * i),
+            8 => random >> 18,
+            9 => random >> 22,
+            10 => random >> 26,
+            _ => random.rotate_left(2),
+        } & 0xF;
+        nibble * nibble
+    });
+    let (sh1, sh2) = if seed & 1 != 0 {
+        (if seed & 2 != 0 { 4 } else { 5 }, if partitions == 3 { 6 } else { 5 })
+    } else {
+        (if partitions == 3 { 6 } else { 5 }, if seed & 2 != 0 { 4 } else { 5 })
+    };
+    let sh3 = if seed & 0x10 != 0 { sh1 } else { sh2 };
+    for (i, seed) in seeds.iter_mut().enumerate() {
+        *seed >>= match i {
+            0..=7 if i % 2 == 0 => sh1,
+            0..=7 => sh2,
+            _ => sh3,
+        };
+    }
+
+    // The 2D form of the function, with z at zero
+    let a = (seeds[0] * x + seeds[1] * y + (random >> 14)) & 0x3F;
+    let b = (seeds[2] * x + seeds[3] * y + (random >> 10)) & 0x3F;
+    let c = if partitions >= 3 { (seeds[4] * x + seeds[5] * y + (random >> 6)) & 0x3F } else { 0 };
+    let d = if partitions >= 4 { (seeds[6] * x + seeds[7] * y + (random >> 2)) & 0x3F } else { 0 };
+    if a >= b && a >= c && a >= d {
+        0
+    } else if b >= c && b >= d {
+        1
+    } else if c >= d {
+        2
+    } else {
+        3
+    }
+}
+
+/// The hash partition seeds are scrambled with
+fn hash52(mut p: u32) -> u32 {
+    p ^= p >> 15;
+    p = p.wrapping_sub(p << 17);
+    p = p.wrapping_add(p << 7);
+    p = p.wrapping_add(p << 4);
+    p ^= p >> 5;
+    p = p.wrapping_add(p << 16);
+    p ^= p >> 7;
+    p ^= p >> 3;
+    p ^= p << 6;
+    p ^= p >> 17;
+    p
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::renderer::test_support::{assert_cpu_decoding_matches_gpu, headless_renderer};
+
+    #[test]
+    fn cpu_astc_decoding_matches_the_gpu() {
+        let renderer = headless_renderer(128, 128);
+        let astc = |block| wgpu::TextureFormat::Astc { block, channel: wgpu::AstcChannel::Unorm };
+        let formats = [
+            astc(wgpu::AstcBlock::B4x4),
+            astc(wgpu::AstcBlock::B5x4),
+            astc(wgpu::AstcBlock::B6x6),
+            astc(wgpu::AstcBlock::B8x5),
+            astc(wgpu::AstcBlock::B10x10),
+            astc(wgpu::AstcBlock::B12x12),
+        ];
+        // Block modes, partitions and endpoint modes take many random blocks to cover
+        assert_cpu_decoding_matches_gpu(&renderer, &formats, 64, 11);
+    }
+}
@@ -0,0 +1,724 @@
+use std::array;
+
+use super::astc_decode;
+
+/// The RGBA8 texels of one 4x4 block, row by row
+pub type Block = [[u8; 4]; 16];
+
+/// Intensity modifiers of ETC1 and ETC2 sub-blocks, by table codeword
+const ETC_MODIFIERS: [[i32; 2]; 8] = [[2, 8], [5, 17], [9, 29], [13, 42], [18, 60], [24, 80], [33, 106], [47, 183]];
+/// Distances between the paint colors of ETC2 T and H blocks
+const ETC_DISTANCES: [i32; 8] = [3, 6, 11, 16, 23, 32, 41, 64];
+/// Alpha modifiers of EAC blocks, by table index
+const EAC_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+/// Subset of each texel of BC6H and BC7 two-subset blocks, one bit per texel, by partition
+const BPTC_PARTITIONS_2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80,
+    0xC800, 0xFFEC, 0xFE80, 0xE800, 0xFFE8, 0xFF00, 0xFFF0, 0xF000,
+    0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE,
+    0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C,
+    0xAAAA, 0xF0F0, 0x5A5A, 0x33CC, 0x3C3C, 0x55AA, 0x9696, 0xA55A,
+    0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C,
+    0x9336, 0x9CC6, 0x817E, 0xE718, 0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+/// Subset of each texel of BC7 three-subset blocks, two bits per texel, by partition
+const BPTC_PARTITIONS_3: [u32; 64] = [
+    0xAA685050, 0x6A5A5040, 0x5A5A4200, 0x5450A0A8, 0xA5A50000, 0xA0A05050, 0x5555A0A0, 0x5A5A5050,
+    0xAA550000, 0xAA555500, 0xAAAA5500, 0x90909090, 0x94949494, 0xA4A4A4A4, 0xA9A59450, 0x2A0A4250,
+    0xA5945040, 0x0A425054, 0xA5A5A500, 0x55A0A0A0, 0xA8A85454, 0x6A6A4040, 0xA4A45000, 0x1A1A0500,
+    0x0050A4A4, 0xAAA59090, 0x14696914, 0x69691400, 0xA08585A0, 0xAA821414, 0x50A4A450, 0x6A5A0200,
+    0xA9A58000, 0x5090A0A8, 0xA8A09050, 0x24242424, 0x00AA5500, 0x24924924, 0x24499224, 0x50A50A50,
+    0x500AA550, 0xAAAA4444, 0x66660000, 0xA5A0A5A0, 0x50A050A0, 0x69286928, 0x44AAAA44, 0x66666600,
+    0xAA444444, 0x54A854A8, 0x95809580, 0x96969600, 0xA85454A8, 0x80959580, 0xAA141414, 0x96960000,
+    0xAAAA1414, 0xA05050A0, 0xA0A5A5A0, 0x96000000, 0x40804080, 0xA9A8A9A8, 0xAAAAAA44, 0x2A4A5254,
+];
+/// Texel whose index drops its top bit in the second subset of two-subset blocks, by partition
+const BPTC_ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15, 2, 8, 2, 2, 8, 8, 15, 2, 8, 2, 2, 8, 8, 2, 2,
+    15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6,
+    6, 2, 6, 8, 15, 15, 2, 2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+/// Anchor texels of the second and third subsets of three-subset blocks, by partition
+const BPTC_ANCHORS_3: [[u8; 64]; 2] = [
+    [
+        3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3,
+        3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5, 15, 15,
+        8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15,
+        3, 15, 5, 5, 5, 8, 5, 10, 5, 10, 8, 13, 15, 12, 3, 3,
+    ],
+    [
+        15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8,
+        15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6, 10, 15, 15, 10, 8,
+        15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8,
+        15, 3, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+    ],
+];
+/// Interpolation weights out of 64 of BC6H and BC7 indices, by index size
+const BPTC_WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const BPTC_WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BPTC_WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// How a BC7 mode splits its bits
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    selection_bits: u32,      // Index selection, swapping the index sizes of color and alpha
+    color_bits: u32,
+    alpha_bits: u32,          // Zero for opaque modes
+    endpoint_p_bits: bool,    // One low bit for each endpoint
+    shared_p_bits: bool,      // One low bit for both endpoints of a subset
+    index_bits: u32,
+    secondary_index_bits: u32, // Zero unless alpha has indices of its own
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode { subsets: 3, partition_bits: 4, rotation_bits: 0, selection_bits: 0, color_bits: 4, alpha_bits: 0, endpoint_p_bits: true, shared_p_bits: false, index_bits: 3, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, selection_bits: 0, color_bits: 6, alpha_bits: 0, endpoint_p_bits: false, shared_p_bits: true, index_bits: 3, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 3, partition_bits: 6, rotation_bits: 0, selection_bits: 0, color_bits: 5, alpha_bits: 0, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, selection_bits: 0, color_bits: 7, alpha_bits: 0, endpoint_p_bits: true, shared_p_bits: false, index_bits: 2, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, selection_bits: 1, color_bits: 5, alpha_bits: 6, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, secondary_index_bits: 3 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, selection_bits: 0, color_bits: 7, alpha_bits: 8, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, secondary_index_bits: 2 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 0, selection_bits: 0, color_bits: 7, alpha_bits: 7, endpoint_p_bits: true, shared_p_bits: false, index_bits: 4, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, selection_bits: 0, color_bits: 5, alpha_bits: 5, endpoint_p_bits: true, shared_p_bits: false, index_bits: 2, secondary_index_bits: 0 },
+];
+
+/// Endpoint fields of BC6H layouts: the red, green and blue of endpoints w, x, y and z
+const RW: u8 = 0;
+const GW: u8 = 1;
+const BW: u8 = 2;
+const RX: u8 = 3;
+const GX: u8 = 4;
+const BX: u8 = 5;
+const RY: u8 = 6;
+const GY: u8 = 7;
+const BY: u8 = 8;
+const RZ: u8 = 9;
+const GZ: u8 = 10;
+const BZ: u8 = 11;
+
+/// How a BC6H mode splits its bits. Each layout entry reads `count` bits into a field from
+/// its bit `first` up; bits stored in reverse order are listed one by one.
+struct Bc6hMode {
+    code: u32, // Two bits below 2, five bits otherwise
+    endpoint_bits: u32,
+    delta_bits: [u32; 3], // Of the red, green and blue of the endpoints after the first
+    transformed: bool,    // Endpoints after the first are stored as deltas from it
+    regions: usize,
+    layout: &'static [(u8, u8, u8)],
+}
+
+const BC6H_MODES: [Bc6hMode; 14] = [
+    Bc6hMode { code: 0b00, endpoint_bits: 10, delta_bits: [5, 5, 5], transformed: true, regions: 2, layout: &[
+        (GY, 4, 1), (BY, 4, 1), (BZ, 4, 1), (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 5), (GZ, 4, 1), (GY, 0, 4), (GX, 0, 5),
+        (BZ, 0, 1), (GZ, 0, 4), (BX, 0, 5), (BZ, 1, 1), (BY, 0, 4), (RY, 0, 5), (BZ, 2, 1), (RZ, 0, 5), (BZ, 3, 1),
+    ] },
+    Bc6hMode { code: 0b01, endpoint_bits: 7, delta_bits: [6, 6, 6], transformed: true, regions: 2, layout: &[
+        (GY, 5, 1), (GZ, 4, 1), (GZ, 5, 1), (RW, 0, 7), (BZ, 0, 1), (BZ, 1, 1), (BY, 4, 1), (GW, 0, 7), (BY, 5, 1), (BZ, 2, 1),
+        (GY, 4, 1), (BW, 0, 7), (BZ, 3, 1), (BZ, 5, 1), (BZ, 4, 1), (RX, 0, 6), (GY, 0, 4), (GX, 0, 6), (GZ, 0, 4), (BX, 0, 6),
+        (BY, 0, 4), (RY, 0, 6), (RZ, 0, 6),
+    ] },
+    Bc6hMode { code: 0b00010, endpoint_bits: 11, delta_bits: [5, 4, 4], transformed: true, regions: 2, layout: &[
+        (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 5), (RW, 10, 1), (GY, 0, 4), (GX, 0, 4), (GW, 10, 1), (BZ, 0, 1), (GZ, 0, 4),
+        (BX, 0, 4), (BW, 10, 1), (BZ, 1, 1), (BY, 0, 4), (RY, 0, 5), (BZ, 2, 1), (RZ, 0, 5), (BZ, 3, 1),
+    ] },
+    Bc6hMode { code: 0b00110, endpoint_bits: 11, delta_bits: [4, 5, 4], transformed: true, regions: 2, layout: &[
+        (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 4), (RW, 10, 1), (GZ, 4, 1), (GY, 0, 4), (GX, 0, 5), (GW, 10, 1), (GZ, 0, 4),
+        (BX, 0, 4), (BW, 10, 1), (BZ, 1, 1), (BY, 0, 4), (RY, 0, 4), (BZ, 0, 1), (BZ, 2, 1), (RZ, 0, 4), (GY, 4, 1), (BZ, 3, 1),
+    ] },
+    Bc6hMode { code: 0b01010, endpoint_bits: 11, delta_bits: [4, 4, 5], transformed: true, regions: 2, layout: &[
+        (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 4), (RW, 10, 1), (BY, 4, 1), (GY, 0, 4), (GX, 0, 4), (GW, 10, 1), (BZ, 0, 1),
+        (GZ, 0, 4), (BX, 0, 5), (BW, 10, 1), (BY, 0, 4), (RY, 0, 4), (BZ, 1, 1), (BZ, 2, 1), (RZ, 0, 4), (BZ, 4, 1), (BZ, 3, 1),
+    ] },
+    Bc6hMode { code: 0b01110, endpoint_bits: 9, delta_bits: [5, 5, 5], transformed: true, regions: 2, layout: &[
+        (RW, 0, 9), (BY, 4, 1), (GW, 0, 9), (GY, 4, 1), (BW, 0, 9), (BZ, 4, 1), (RX, 0, 5), (GZ, 4, 1), (GY, 0, 4), (GX, 0, 5),
+        (BZ, 0, 1), (GZ, 0, 4), (BX, 0, 5), (BZ, 1, 1), (BY, 0, 4), (RY, 0, 5), (BZ, 2, 1), (RZ, 0, 5), (BZ, 3, 1),
+    ] },
+    Bc6hMode { code: 0b10010, endpoint_bits: 8, delta_bits: [6, 5, 5], transformed: true, regions: 2, layout: &[
+        (RW, 0, 8), (GZ, 4, 1), (BY, 4, 1), (GW, 0, 8), (BZ, 2, 1), (GY, 4, 1), (BW, 0, 8), (BZ, 3, 1), (BZ, 4, 1), (RX, 0, 6),
+        (GY, 0, 4), (GX, 0, 5), (BZ, 0, 1), (GZ, 0, 4), (BX, 0, 5), (BZ, 1, 1), (BY, 0, 4), (RY, 0, 6), (RZ, 0, 6),
+    ] },
+    Bc6hMode { code: 0b10110, endpoint_bits: 8, delta_bits: [5, 6, 5], transformed: true, regions: 2, layout: &[
+        (RW, 0, 8), (BZ, 0, 1), (BY, 4, 1), (GW, 0, 8), (GY, 5, 1), (GY, 4, 1), (BW, 0, 8), (GZ, 5, 1), (BZ, 4, 1), (RX, 0, 5),
+        (GZ, 4, 1), (GY, 0, 4), (GX, 0, 6), (GZ, 0, 4), (BX, 0, 5), (BZ, 1, 1), (BY, 0, 4), (RY, 0, 5), (BZ, 2, 1), (RZ, 0, 5),
+        (BZ, 3, 1),
+    ] },
+    Bc6hMode { code: 0b11010, endpoint_bits: 8, delta_bits: [5, 5, 6], transformed: true, regions: 2, layout: &[
+        (RW, 0, 8), (BZ, 1, 1), (BY, 4, 1), (GW, 0, 8), (BY, 5, 1), (GY, 4, 1), (BW, 0, 8), (BZ, 5, 1), (BZ, 4, 1), (RX, 0, 5),
+        (GZ, 4, 1), (GY, 0, 4), (GX, 0, 5), (BZ, 0, 1), (GZ, 0, 4), (BX, 0, 6), (BY, 0, 4), (RY, 0, 5), (BZ, 2, 1), (RZ, 0, 5),
+        (BZ, 3, 1),
+    ] },
+    Bc6hMode { code: 0b11110, endpoint_bits: 6, delta_bits: [6, 6, 6], transformed: false, regions: 2, layout: &[
+        (RW, 0, 6), (GZ, 4, 1), (BZ, 0, 1), (BZ, 1, 1), (BY, 4, 1), (GW, 0, 6), (GY, 5, 1), (BY, 5, 1), (BZ, 2, 1), (GY, 4, 1),
+        (BW, 0, 6), (GZ, 5, 1), (BZ, 3, 1), (BZ, 5, 1), (BZ, 4, 1), (RX, 0, 6), (GY, 0, 4), (GX, 0, 6), (GZ, 0, 4), (BX, 0, 6),
+        (BY, 0, 4), (RY, 0, 6), (RZ, 0, 6),
+    ] },
+    Bc6hMode { code: 0b00011, endpoint_bits: 10, delta_bits: [10, 10, 10], transformed: false, regions: 1, layout: &[
+        (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 10), (GX, 0, 10), (BX, 0, 10),
+    ] },
+    Bc6hMode { code: 0b00111, endpoint_bits: 11, delta_bits: [9, 9, 9], transformed: true, regions: 1, layout: &[
+        (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 9), (RW, 10, 1), (GX, 0, 9), (GW, 10, 1), (BX, 0, 9), (BW, 10, 1),
+    ] },
+    Bc6hMode { code: 0b01011, endpoint_bits: 12, delta_bits: [8, 8, 8], transformed: true, regions: 1, layout: &[
+        (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 8), (RW, 11, 1), (RW, 10, 1), (GX, 0, 8), (GW, 11, 1), (GW, 10, 1),
+        (BX, 0, 8), (BW, 11, 1), (BW, 10, 1),
+    ] },
+    Bc6hMode { code: 0b01111, endpoint_bits: 16, delta_bits: [4, 4, 4], transformed: true, regions: 1, layout: &[
+        (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 4), (RW, 15, 1), (RW, 14, 1), (RW, 13, 1), (RW, 12, 1), (RW, 11, 1), (RW, 10, 1),
+        (GX, 0, 4), (GW, 15, 1), (GW, 14, 1), (GW, 13, 1), (GW, 12, 1), (GW, 11, 1), (GW, 10, 1),
+        (BX, 0, 4), (BW, 15, 1), (BW, 14, 1), (BW, 13, 1), (BW, 12, 1), (BW, 11, 1), (BW, 10, 1),
+    ] },
+];
+
+const TRANSPARENT: [u8; 4] = [0, 0, 0, 0];
+
+/// Decode one block of `format` on the CPU into its texels, row by row, or None when
+/// there is no decoder for it.
+///
+/// BC1 to BC7, the ETC2 color formats and LDR ASTC are decoded; EAC and HDR ASTC are not.
+/// BC6H is clamped to 0 to 1. Single and two-channel formats fill the channels they have
+/// and leave the rest at 0, like sampling them on the GPU does. sRGB formats decode to
+/// the same sRGB bytes.
+pub fn decode_block(format: wgpu::TextureFormat, block: &[u8]) -> Option<Vec<[u8; 4]>> {
+    use wgpu::{AstcChannel, TextureFormat as F};
+    let block = match format {
+        F::Bc1RgbaUnorm | F::Bc1RgbaUnormSrgb => bc1_colors(block, true),
+        F::Bc2RgbaUnorm | F::Bc2RgbaUnormSrgb => {
+            let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+            let mut texels = bc1_colors(&block[8..], false);
+            for (i, texel) in texels.iter_mut().enumerate() {
+                texel[3] = ((alpha >> (4 * i)) & 0xF) as u8 * 17;
+            }
+            texels
+        }
+        F::Bc3RgbaUnorm | F::Bc3RgbaUnormSrgb => {
+            let alpha = bc4_channel(block);
+            let mut texels = bc1_colors(&block[8..], false);
+            for (texel, alpha) in texels.iter_mut().zip(alpha) {
+                texel[3] = alpha;
+            }
+            texels
+        }
+        F::Bc4RUnorm => bc4_channel(block).map(|red| [red, 0, 0, 255]),
+        F::Bc5RgUnorm => {
+            let (red, green) = (bc4_channel(block), bc4_channel(&block[8..]));
+            array::from_fn(|i| [red[i], green[i], 0, 255])
+        }
+        F::Etc2Rgb8Unorm | F::Etc2Rgb8UnormSrgb => etc2_colors(block, false),
+        F::Etc2Rgb8A1Unorm | F::Etc2Rgb8A1UnormSrgb => etc2_colors(block, true),
+        F::Etc2Rgba8Unorm | F::Etc2Rgba8UnormSrgb => {
+            let alpha = eac_channel(block);
+            let mut texels = etc2_colors(&block[8..], false);
+            for (texel, alpha) in texels.iter_mut().zip(alpha) {
+                texel[3] = alpha;
+            }
+            texels
+        }
+        F::Bc6hRgbUfloat => bc6h_texels(block, false),
+        F::Bc6hRgbFloat => bc6h_texels(block, true),
+        F::Bc7RgbaUnorm | F::Bc7RgbaUnormSrgb => bc7_texels(block),
+        F::Astc { channel: channel @ (AstcChannel::Unorm | AstcChannel::UnormSrgb), .. } => {
+            let (width, height) = format.block_dimensions();
+            let srgb = channel == AstcChannel::UnormSrgb;
+            return Some(astc_decode::decode_block(block, width as usize, height as usize, srgb));
+        }
+        _ => return None,
+    };
+    Some(block.to_vec())
+}
+
+/// Expand a 5:6:5 color to 8 bits per channel
+fn rgb565(color: u16) -> [u32; 3] {
+    let (r, g, b) = ((color >> 11) as u32 & 0x1F, (color >> 5) as u32 & 0x3F, color as u32 & 0x1F);
+    [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)]
+}
+
+/// BC1 color block. `punch_through` allows the three-color mode with transparent black,
+/// which BC2 and BC3 color blocks do not have.
+fn bc1_colors(block: &[u8], punch_through: bool) -> Block {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let mix = |weight_a: u32, weight_b: u32| {
+        let channel = |i: usize| ((a[i] * weight_a + b[i] * weight_b) / (weight_a + weight_b)) as u8;
+        [channel(0), channel(1), channel(2), 255]
+    };
+    let palette = if c0 > c1 || !punch_through {
+        [mix(1, 0), mix(0, 1), mix(2, 1), mix(1, 2)]
+    } else {
+        [mix(1, 0), mix(0, 1), mix(1, 1), TRANSPARENT]
+    };
+
+    let indices = u32::from_le_bytes(block[4..8].try_into().unwrap());
+    array::from_fn(|i| palette[(indices >> (2 * i)) as usize & 3])
+}
+
+/// BC4 block of one channel, also the alpha of BC3 and each channel of BC5
+fn bc4_channel(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+    let palette: [u8; 8] = array::from_fn(|i| {
+        let i = i as u32;
+        (match i {
+            0 => a0,
+            1 => a1,
+            _ if a0 > a1 => ((8 - i) * a0 + (i - 1) * a1) / 7,
+            6 => 0,
+            7 => 255,
+            _ => ((6 - i) * a0 + (i - 1) * a1) / 5,
+        }) as u8
+    });
+
+    let mut bits = [0; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    array::from_fn(|i| palette[(indices >> (3 * i)) as usize & 7])
+}
+
+/// Store a texel computed from its column `x` and row `y` in a block
+fn block_from_fn(texel: impl Fn(usize, usize) -> [u8; 4]) -> Block {
+    array::from_fn(|i| texel(i % 4, i / 4))
+}
+
+/// Add a signed offset to each channel of a color, clamping to 8 bits
+fn offset(color: [i32; 3], offset: i32) -> [u8; 4] {
+    let channel = |value: i32| (value + offset).clamp(0, 255) as u8;
+    [channel(color[0]), channel(color[1]), channel(color[2]), 255]
+}
+
+/// ETC2 RGB block, in any of the individual, differential, T, H and planar modes. With
+/// `punch_through` the differential bit says whether the block is opaque instead, and a
+/// block that is not has transparent texels.
+fn etc2_colors(block: &[u8], punch_through: bool) -> Block {
+    let differential = block[3] & 2 != 0;
+    let opaque = !punch_through || differential;
+    let extend4 = |value: u8| (value as i32 & 0xF) * 17;
+    let extend5 = |value: i32| (value << 3) | (value >> 2);
+
+    // Two bits of the 4x4 texel index for each texel, stored column by column
+    let selectors = u32::from_be_bytes(block[4..8].try_into().unwrap());
+    let selector = |x: usize, y: usize| {
+        let k = x * 4 + y;
+        (((selectors >> (16 + k)) & 1) << 1 | ((selectors >> k) & 1)) as usize
+    };
+
+    if !punch_through && !differential {
+        let base = |half: fn(u8) -> u8| [half(block[0]), half(block[1]), half(block[2])].map(extend4);
+        return etc_sub_blocks(block, [base(|byte| byte >> 4), base(|byte| byte & 0xF)], opaque, selector);
+    }
+
+    let base = |byte: u8| (byte >> 3) as i32;
+    let delta = |byte: u8| ((byte & 7) as i32 ^ 4) - 4;
+    let first = [base(block[0]), base(block[1]), base(block[2])];
+    let second = [0, 1, 2].map(|i| first[i] + delta(block[i]));
+    let paint = |colors: [[u8; 4]; 4]| block_from_fn(|x, y| match selector(x, y) {
+        2 if !opaque => TRANSPARENT,
+        index => colors[index],
+    });
+
+    if !(0..32).contains(&second[0]) {
+        // T mode: one color alone, and three around the other
+        let first = [((block[0] >> 3) & 3) << 2 | (block[0] & 3), block[1] >> 4, block[1]].map(extend4);
+        let second = [block[2] >> 4, block[2], block[3] >> 4].map(extend4);
+        let distance = ETC_DISTANCES[(((block[3] >> 2) & 3) << 1 | (block[3] & 1)) as usize];
+        paint([offset(first, 0), offset(second, distance), offset(second, 0), offset(second, -distance)])
+    } else if !(0..32).contains(&second[1]) {
+        // H mode: two colors, each spread in two
+        let first = [
+            (block[0] >> 3) & 0xF,
+            ((block[0] & 7) << 1) | ((block[1] >> 4) & 1),
+            (block[1] & 8) | ((block[1] & 3) << 1) | (block[2] >> 7),
+        ];
+        let second = [(block[2] >> 3) & 0xF, ((block[2] & 7) << 1) | (block[3] >> 7), (block[3] >> 3) & 0xF];
+        let packed = |color: [u8; 3]| (color[0] as u32) << 8 | (color[1] as u32) << 4 | color[2] as u32;
+        let order = (packed(first) >= packed(second)) as u8;
+        let distance = ETC_DISTANCES[((block[3] & 4) | ((block[3] & 1) << 1) | order) as usize];
+        let (first, second) = (first.map(extend4), second.map(extend4));
+        paint([
+            offset(first, distance),
+            offset(first, -distance),
+            offset(second, distance),
+            offset(second, -distance),
+        ])
+    } else if !(0..32).contains(&second[2]) {
+        // Planar mode: a gradient from three colors, always opaque
+        let extend6 = |value: u8| ((value as i32) << 2) | (value as i32 >> 4);
+        let extend7 = |value: u8| ((value as i32) << 1) | (value as i32 >> 6);
+        let origin = [
+            extend6((block[0] >> 1) & 0x3F),
+            extend7(((block[0] & 1) << 6) | ((block[1] >> 1) & 0x3F)),
+            extend6(((block[1] & 1) << 5) | (((block[2] >> 3) & 3) << 3) | ((block[2] & 3) << 1) | (block[3] >> 7)),
+        ];
+        let horizontal = [
+            extend6((((block[3] >> 2) & 0x1F) << 1) | (block[3] & 1)),
+            extend7(block[4] >> 1),
+            extend6(((block[4] & 1) << 5) | (block[5] >> 3)),
+        ];
+        let vertical = [
+            extend6(((block[5] & 7) << 3) | (block[6] >> 5)),
+            extend7(((block[6] & 0x1F) << 2) | (block[7] >> 6)),
+            extend6(block[7] & 0x3F),
+        ];
+        block_from_fn(|x, y| {
+            let (x, y) = (x as i32, y as i32);
+            let channel = |i: usize| {
+                ((x * (horizontal[i] - origin[i]) + y * (vertical[i] - origin[i]) + 4 * origin[i] + 2) >> 2).clamp(0, 255) as u8
+            };
+            [channel(0), channel(1), channel(2), 255]
+        })
+    } else {
+        etc_sub_blocks(block, [first.map(extend5), second.map(extend5)], opaque, selector)
+    }
+}
+
+/// Two 2x4 or 4x2 sub-blocks, each a base color brightened or darkened by its modifier table
+fn etc_sub_blocks(block: &[u8], bases: [[i32; 3]; 2], opaque: bool, selector: impl Fn(usize, usize) -> usize) -> Block {
+    let flip = block[3] & 1 != 0;
+    let tables = [block[3] >> 5, (block[3] >> 2) & 7].map(|table| ETC_MODIFIERS[table as usize]);
+    block_from_fn(|x, y| {
+        let sub_block = if flip { y >= 2 } else { x >= 2 } as usize;
+        let [small, large] = tables[sub_block];
+        let modifier = match selector(x, y) {
+            0 if !opaque => 0,
+            2 if !opaque => return TRANSPARENT,
+            0 => small,
+            1 => large,
+            2 => -small,
+            _ => -large,
+        };
+        offset(bases[sub_block], modifier)
+    })
+}
+
+/// EAC block of one 8-bit channel, the alpha of ETC2 RGBA8
+fn eac_channel(block: &[u8]) -> [u8; 16] {
+    let base = block[0] as i32;
+    let multiplier = (block[1] >> 4) as i32;
+    let modifiers = EAC_MODIFIERS[(block[1] & 0xF) as usize];
+
+    let mut bits = [0; 8];
+    bits[2..].copy_from_slice(&block[2..8]);
+    let indices = u64::from_be_bytes(bits);
+    let texels = block_from_fn(|x, y| {
+        let index = (indices >> (45 - 3 * (x * 4 + y))) as usize & 7;
+        let value = (base + modifiers[index] * multiplier).clamp(0, 255) as u8;
+        [value; 4]
+    });
+    texels.map(|texel| texel[0])
+}
+
+/// Reads the fields of a 128-bit block from its lowest bit up
+struct BitReader {
+    bits: u128,
+    position: u32,
+}
+
+impl BitReader {
+    fn new(block: &[u8]) -> Self {
+        Self { bits: u128::from_le_bytes(block[..16].try_into().unwrap()), position: 0 }
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        let value = (self.bits >> self.position) as u32 & ((1u64 << count) - 1) as u32;
+        self.position += count;
+        value
+    }
+}
+
+/// Subset of `texel` in BC6H or BC7 `partition`, and whether its index drops its top bit
+fn bptc_subset(subsets: usize, partition: usize, texel: usize) -> (usize, bool) {
+    let subset = match subsets {
+        1 => 0,
+        2 => (BPTC_PARTITIONS_2[partition] >> texel) as usize & 1,
+        _ => (BPTC_PARTITIONS_3[partition] >> (2 * texel)) as usize & 3,
+    };
+    let anchor = match (subsets, subset) {
+        (_, 0) => 0,
+        (2, _) => BPTC_ANCHORS_2[partition],
+        (_, subset) => BPTC_ANCHORS_3[subset - 1][partition],
+    };
+    (subset, texel == anchor as usize)
+}
+
+/// Read an index of `bits` bits for each texel, one less for the anchors of the subsets
+fn bptc_indices(reader: &mut BitReader, bits: u32, subsets: usize, partition: usize) -> [u32; 16] {
+    array::from_fn(|texel| reader.read(bits - bptc_subset(subsets, partition, texel).1 as u32))
+}
+
+/// Weight out of 64 of an index of `bits` bits
+fn bptc_weight(bits: u32, index: u32) -> u32 {
+    match bits {
+        2 => BPTC_WEIGHTS_2[index as usize],
+        3 => BPTC_WEIGHTS_3[index as usize],
+        _ => BPTC_WEIGHTS_4[index as usize],
+    }
+}
+
+/// BC7 block in any of its eight modes. Blocks without a mode are transparent black.
+fn bc7_texels(block: &[u8]) -> Block {
+    let mut reader = BitReader::new(block);
+    let Some(mode) = (0..8).find(|_| reader.read(1) == 1).map(|mode| &BC7_MODES[mode]) else {
+        return [TRANSPARENT; 16];
+    };
+    let partition = reader.read(mode.partition_bits) as usize;
+    let rotation = reader.read(mode.rotation_bits);
+    let selection = reader.read(mode.selection_bits);
+
+    // Endpoints are stored channel by channel, then get their P-bits
+    let mut endpoints = [[[0; 4]; 2]; 3];
+    let channels = if mode.alpha_bits > 0 { 4 } else { 3 };
+    for channel in 0..channels {
+        let bits = if channel == 3 { mode.alpha_bits } else { mode.color_bits };
+        for subset in &mut endpoints[..mode.subsets] {
+            for endpoint in subset.iter_mut() {
+                endpoint[channel] = reader.read(bits);
+            }
+        }
+    }
+    let mut p_bits = [[0; 2]; 3];
+    for subset in &mut p_bits[..mode.subsets] {
+        if mode.endpoint_p_bits {
+            *subset = [reader.read(1), reader.read(1)];
+        } else if mode.shared_p_bits {
+            *subset = [reader.read(1); 2];
+        }
+    }
+    let has_p_bit = mode.endpoint_p_bits || mode.shared_p_bits;
+    for (subset, p_bits) in endpoints.iter_mut().zip(p_bits) {
+        for (endpoint, p_bit) in subset.iter_mut().zip(p_bits) {
+            for (channel, value) in endpoint.iter_mut().enumerate() {
+                let bits = if channel == 3 { mode.alpha_bits } else { mode.color_bits };
+                *value = match bits {
+                    0 => 255,
+                    bits if has_p_bit => expand_bits((*value << 1) | p_bit, bits + 1),
+                    bits => expand_bits(*value, bits),
+                };
+            }
+        }
+    }
+
+    let indices = bptc_indices(&mut reader, mode.index_bits, mode.subsets, partition);
+    let secondary = if mode.secondary_index_bits > 0 {
+        Some(bptc_indices(&mut reader, mode.secondary_index_bits, 1, 0))
+    } else {
+        None
+    };
+    array::from_fn(|texel| {
+        // Color and alpha share the indices unless alpha has its own, which may be swapped
+        let primary = (mode.index_bits, indices[texel]);
+        let (color, alpha) = match secondary {
+            None => (primary, primary),
+            Some(secondary) if selection == 0 => (primary, (mode.secondary_index_bits, secondary[texel])),
+            Some(secondary) => ((mode.secondary_index_bits, secondary[texel]), primary),
+        };
+        let [start, end] = endpoints[bptc_subset(mode.subsets, partition, texel).0];
+        let mut texel: [u8; 4] = array::from_fn(|channel| {
+            let (bits, index) = if channel == 3 { alpha } else { color };
+            let weight = bptc_weight(bits, index);
+            (((64 - weight) * start[channel] + weight * end[channel] + 32) >> 6) as u8
+        });
+        if rotation > 0 {
+            texel.swap(rotation as usize - 1, 3);
+        }
+        texel
+    })
+}
+
+/// Widen a value of `bits` bits to 8 by repeating its top bits below it
+fn expand_bits(value: u32, bits: u32) -> u32 {
+    (value << (8 - bits)) | (value >> (2 * bits - 8))
+}
+
+/// Extend the sign bit of a value of `bits` bits
+fn sign_extend(value: i32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    (value << shift) >> shift
+}
+
+/// BC6H block of half floats, clamped to the 0 to 1 range of 8-bit texels. Blocks in the
+/// reserved modes are black.
+fn bc6h_texels(block: &[u8], signed: bool) -> Block {
+    let mut reader = BitReader::new(block);
+    let mut code = reader.read(2);
+    if code > 1 {
+        code |= reader.read(3) << 2;
+    }
+    let Some(mode) = BC6H_MODES.iter().find(|mode| mode.code == code) else {
+        return [[0, 0, 0, 255]; 16];
+    };
+
+    let mut fields = [0i32; 12];
+    for &(field, first, count) in mode.layout {
+        fields[field as usize] |= (reader.read(count as u32) as i32) << first;
+    }
+    let partition = if mode.regions == 2 { reader.read(5) as usize } else { 0 };
+    let index_bits = if mode.regions == 2 { 3 } else { 4 };
+    let indices = bptc_indices(&mut reader, index_bits, mode.regions, partition);
+
+    // Endpoints after the first may be deltas from it, and wrap around its precision
+    let mask = (1 << mode.endpoint_bits) - 1;
+    let mut endpoints: [[i32; 3]; 4] = array::from_fn(|endpoint| array::from_fn(|channel| fields[endpoint * 3 + channel]));
+    let first = endpoints[0];
+    for (endpoint, values) in endpoints.iter_mut().enumerate() {
+        for (channel, value) in values.iter_mut().enumerate() {
+            if mode.transformed && endpoint > 0 {
+                let first = if signed { sign_extend(first[channel], mode.endpoint_bits) } else { first[channel] };
+                *value = (first + sign_extend(*value, mode.delta_bits[channel])) & mask;
+            }
+            if signed {
+                *value = sign_extend(*value, mode.endpoint_bits);
+            }
+            *value = bc6h_unquantize(*value, mode.endpoint_bits, signed);
+        }
+    }
+
+    array::from_fn(|texel| {
+        let region = bptc_subset(mode.regions, partition, texel).0;
+        let (start, end) = (endpoints[2 * region], endpoints[2 * region + 1]);
+        let weight = bptc_weight(index_bits, indices[texel]) as i32;
+        let channel = |channel: usize| {
+            let value = ((64 - weight) * start[channel] + weight * end[channel] + 32) >> 6;
+            // Scale to the largest finite half float, then read the bits as one
+            let half = if signed {
+                if value < 0 { 0x8000 | ((-value * 31) >> 5) } else { (value * 31) >> 5 }
+            } else {
+                (value * 31) >> 6
+            };
+            (half_to_f32(half as u16).clamp(0.0, 1.0) * 255.0).round() as u8
+        };
+        [channel(0), channel(1), channel(2), 255]
+    })
+}
+
+/// Spread a BC6H endpoint of `bits` bits over the range of 16-bit integers
+fn bc6h_unquantize(value: i32, bits: u32, signed: bool) -> i32 {
+    if signed {
+        let magnitude = value.abs();
+        let unquantized = if bits >= 16 || magnitude == 0 {
+            magnitude
+        } else if magnitude >= (1 << (bits - 1)) - 1 {
+            0x7FFF
+        } else {
+            ((magnitude << 15) + 0x4000) >> (bits - 1)
+        };
+        if value < 0 { -unquantized } else { unquantized }
+    } else if bits >= 15 || value == 0 {
+        value
+    } else if value == (1 << bits) - 1 {
+        0xFFFF
+    } else {
+        ((value << 16) + 0x8000) >> bits
+    }
+}
+
+/// The value of half float bits
+fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (half >> 10) as i32 & 0x1F;
+    let mantissa = (half & 0x3FF) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        exponent => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::renderer::test_support::{assert_cpu_decoding_matches_gpu, headless_renderer};
+
+    #[test]
+    fn bc1_interpolates_between_its_endpoints() {
+        // Pure red and pure blue endpoints, texels cycling through the four palette entries
+        let block = [0x00, 0xF8, 0x1F, 0x00, 0xE4, 0xE4, 0xE4, 0xE4];
+        let texels = decode_block(wgpu::TextureFormat::Bc1RgbaUnorm, &block).unwrap();
+        assert_eq!(texels[0], [255, 0, 0, 255]);
+        assert_eq!(texels[1], [0, 0, 255, 255]);
+        assert_eq!(texels[2], [170, 0, 85, 255]);
+        assert_eq!(texels[3], [85, 0, 170, 255]);
+
+        // Swapped endpoints select the three-color mode with transparent black
+        let block = [0x1F, 0x00, 0x00, 0xF8, 0xFF, 0, 0, 0];
+        let texels = decode_block(wgpu::TextureFormat::Bc1RgbaUnorm, &block).unwrap();
+        assert_eq!(texels[0], TRANSPARENT);
+        assert_eq!(texels[4], [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn etc2_individual_block_applies_modifiers_per_sub_block() {
+        // Left half gray 0x88 with table 0, right half gray 0x44 with table 7, every texel +a
+        let block = [0x84, 0x84, 0x84, 0b0001_1100, 0, 0, 0, 0];
+        let texels = decode_block(wgpu::TextureFormat::Etc2Rgb8Unorm, &block).unwrap();
+        assert_eq!(texels[0], [138, 138, 138, 255]);
+        assert_eq!(texels[3], [115, 115, 115, 255]);
+    }
+
+    #[test]
+    fn bc7_and_astc_solid_blocks_fill_every_texel() {
+        // BC7 mode 6 with every endpoint and p-bit set
+        let mut block = [0xFF; 16];
+        block[0] = 0xC0;
+        block[8..].copy_from_slice(&[0x01, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(decode_block(wgpu::TextureFormat::Bc7RgbaUnorm, &block).unwrap(), vec![[255; 4]; 16]);
+
+        // An LDR ASTC void-extent block with no extent, of 16-bit red, black, half blue and white
+        let mut block = 0xFFFF_FFFF_FFFF_FDFCu64.to_le_bytes().to_vec();
+        block.extend([0xFFFF, 0, 0x8000, 0xFFFF].iter().flat_map(|channel: &u16| channel.to_le_bytes()));
+        let format = wgpu::TextureFormat::Astc { block: wgpu::AstcBlock::B6x6, channel: wgpu::AstcChannel::Unorm };
+        assert_eq!(decode_block(format, &block).unwrap(), vec![[255, 0, 128, 255]; 36]);
+    }
+
+    #[test]
+    fn formats_without_a_decoder_are_reported() {
+        assert!(decode_block(wgpu::TextureFormat::EacR11Unorm, &[0; 8]).is_none());
+    }
+
+    #[test]
+    fn cpu_block_decoding_matches_the_gpu() {
+        let renderer = headless_renderer(128, 128);
+        let formats = [
+            wgpu::TextureFormat::Bc1RgbaUnorm,
+            wgpu::TextureFormat::Bc2RgbaUnorm,
+            wgpu::TextureFormat::Bc3RgbaUnorm,
+            wgpu::TextureFormat::Bc4RUnorm,
+            wgpu::TextureFormat::Bc5RgUnorm,
+            wgpu::TextureFormat::Etc2Rgb8Unorm,
+            wgpu::TextureFormat::Etc2Rgb8A1Unorm,
+            wgpu::TextureFormat::Etc2Rgba8Unorm,
+        ];
+        assert_cpu_decoding_matches_gpu(&renderer, &formats, 8, 7);
+    }
+
+    #[test]
+    fn cpu_bptc_decoding_matches_the_gpu() {
+        let renderer = headless_renderer(128, 128);
+        let formats = [
+            wgpu::TextureFormat::Bc6hRgbUfloat,
+            wgpu::TextureFormat::Bc6hRgbFloat,
+            wgpu::TextureFormat::Bc7RgbaUnorm,
+        ];
+        // These have many more modes, so they take more random blocks to cover
+        assert_cpu_decoding_matches_gpu(&renderer, &formats, 64, 11);
+    }
+}
@@ -0,0 +1,500 @@
+use anyhow::{anyhow, bail, ensure, Result};
+use crate::engine::renderer::ColorSpace;
+use super::block_decode::decode_block;
+
+/// First bytes of every KTX2 file
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n'];
+/// First bytes of every DDS file
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+
+/// DDS pixel format flags
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+/// DDS header flag saying the mip level count is set
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+/// DDS caps2 flags of cube maps and volume textures
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_VOLUME: u32 = 0x200000;
+
+/// A 2D texture read from a KTX2 or DDS container, with the mip levels baked into it
+pub struct CompressedImage {
+    pub format: wgpu::TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>, // Largest first, each tightly packed rows of blocks
+}
+
+impl CompressedImage {
+    /// True if `bytes` start like a KTX2 or DDS file
+    pub fn is_container(bytes: &[u8]) -> bool {
+        bytes.starts_with(&KTX2_IDENTIFIER) || bytes.starts_with(DDS_MAGIC)
+    }
+
+    /// Parse a KTX2 or DDS file. Only DDS files with a DXGI format say whether they hold
+    /// colors; the others, such as plain DXT5 files, are read in `color_space`.
+    pub fn parse(bytes: &[u8], color_space: ColorSpace) -> Result<Self> {
+        if bytes.starts_with(&KTX2_IDENTIFIER) {
+            Self::parse_ktx2(bytes)
+        } else if bytes.starts_with(DDS_MAGIC) {
+            Self::parse_dds(bytes, color_space)
+        } else {
+            bail!("not a KTX2 or DDS file")
+        }
+    }
+
+    fn parse_ktx2(bytes: &[u8]) -> Result<Self> {
+        let header = |offset: usize| read_u32(bytes, offset);
+        let vk_format = header(12)?;
+        let (width, height, depth) = (header(20)?, header(24)?, header(28)?);
+        let (layers, faces, level_count) = (header(32)?, header(36)?, header(40)?);
+        let supercompression = header(44)?;
+
+        match supercompression {
+            0 => {}
+            1 => bail!("KTX2 files supercompressed with BasisLZ are not supported"),
+            2 => bail!("KTX2 files supercompressed with Zstandard are not supported"),
+            3 => bail!("KTX2 files supercompressed with ZLIB are not supported"),
+            scheme => bail!("unknown KTX2 supercompression scheme {}", scheme),
+        }
+        ensure!(depth <= 1, "3D KTX2 textures are not supported");
+        ensure!(layers <= 1 && faces == 1, "KTX2 array textures and cube maps are not supported");
+        let format = match vk_format {
+            0 => bail!("KTX2 files without a format, such as Basis Universal ones, are not supported"),
+            vk_format => ktx2_format(vk_format).ok_or_else(|| anyhow!("unsupported KTX2 format VkFormat {}", vk_format))?,
+        };
+
+        // Each level is found through the level index after the header, largest first
+        let level_count = level_count.max(1);
+        check_level_count(width, height, level_count)?;
+        let levels = (0..level_count as usize)
+            .map(|level| {
+                let entry = 80 + level * 24;
+                let offset = usize::try_from(read_u64(bytes, entry)?).map_err(|_| anyhow!("KTX2 level {} is out of range", level))?;
+                let size = level_size(format, width, height, level as u32)?;
+                level_data(bytes, offset, size).map(<[u8]>::to_vec).ok_or_else(|| anyhow!("KTX2 level {} is truncated", level))
+            })
+            .collect::<Result<_>>()?;
+
+        Self::new(format, width, height, levels)
+    }
+
+    fn parse_dds(bytes: &[u8], color_space: ColorSpace) -> Result<Self> {
+        let header = |offset: usize| read_u32(bytes, offset);
+        let (flags, height, width) = (header(8)?, header(12)?, header(16)?);
+        let level_count = if flags & DDSD_MIPMAPCOUNT != 0 { header(28)?.max(1) } else { 1 };
+        check_level_count(width, height, level_count)?;
+        let (format_flags, four_cc) = (header(80)?, header(84)?);
+        ensure!(header(112)? & (DDSCAPS2_CUBEMAP | DDSCAPS2_VOLUME) == 0, "DDS cube maps and volume textures are not supported");
+
+        let mut data_offset = 128;
+        let format = if format_flags & DDPF_FOURCC == 0 {
+            // Uncompressed texels are converted to RGBA8 as the levels are read
+            None
+        } else if &four_cc.to_le_bytes() == b"DX10" {
+            data_offset += 20;
+            ensure!(header(140)? <= 1, "DDS array textures are not supported");
+            ensure!(header(136)? & 0x4 == 0, "DDS cube maps are not supported");
+            let dxgi_format = header(128)?;
+            Some(dxgi_format_to_wgpu(dxgi_format).ok_or_else(|| anyhow!("unsupported DDS format DXGI_FORMAT {}", dxgi_format))?)
+        } else {
+            let format = match &four_cc.to_le_bytes() {
+                b"DXT1" => wgpu::TextureFormat::Bc1RgbaUnorm,
+                b"DXT2" | b"DXT3" => wgpu::TextureFormat::Bc2RgbaUnorm,
+                b"DXT4" | b"DXT5" => wgpu::TextureFormat::Bc3RgbaUnorm,
+                b"ATI1" | b"BC4U" => wgpu::TextureFormat::Bc4RUnorm,
+                b"ATI2" | b"BC5U" => wgpu::TextureFormat::Bc5RgUnorm,
+                code => bail!("unsupported DDS format FourCC '{}'", String::from_utf8_lossy(code)),
+            };
+            Some(match color_space {
+                ColorSpace::Srgb => format.add_srgb_suffix(),
+                ColorSpace::Linear => format,
+            })
+        };
+
+        let Some(format) = format else {
+            let format = color_space.format();
+            let levels = read_dds_rgb_levels(bytes, data_offset, width, height, level_count, format_flags)?;
+            return Self::new(format, width, height, levels);
+        };
+
+        let mut levels = Vec::new();
+        for level in 0..level_count {
+            let size = level_size(format, width, height, level)?;
+            let data = level_data(bytes, data_offset, size).ok_or_else(|| anyhow!("DDS level {} is truncated", level))?;
+            levels.push(data.to_vec());
+            data_offset += size;
+        }
+
+        Self::new(format, width, height, levels)
+    }
+
+    fn new(format: wgpu::TextureFormat, width: u32, height: u32, levels: Vec<Vec<u8>>) -> Result<Self> {
+        check_level_count(width, height, levels.len() as u32)?;
+        Ok(Self { format, width, height, levels })
+    }
+
+    /// True if the texture can be uploaded as it is: the device has the format, and the
+    /// size is whole blocks, which the GPU needs of compressed textures
+    pub fn is_supported_by(&self, device: &wgpu::Device) -> bool {
+        let (block_width, block_height) = self.format.block_dimensions();
+        device.features().contains(self.format.required_features())
+            && self.width.is_multiple_of(block_width)
+            && self.height.is_multiple_of(block_height)
+    }
+
+    /// Decompress every level to RGBA8 on the CPU, in the color space of the format.
+    /// Fails for formats without a CPU decoder, naming the format, and for levels whose
+    /// data does not match their size.
+    pub fn decompress(&self) -> Result<Vec<image::RgbaImage>> {
+        check_level_count(self.width, self.height, self.levels.len() as u32)?;
+        for (level, data) in self.levels.iter().enumerate() {
+            let size = level_size(self.format, self.width, self.height, level as u32)?;
+            ensure!(data.len() == size, "mip level {} holds {} bytes instead of {}", level, data.len(), size);
+        }
+
+        if !self.format.is_compressed() {
+            return Ok(self
+                .levels
+                .iter()
+                .enumerate()
+                .map(|(level, data)| {
+                    let (width, height) = level_dimensions(self.width, self.height, level as u32);
+                    image::RgbaImage::from_raw(width, height, data.clone())
+                        .expect("level sizes were checked above")
+                })
+                .collect());
+        }
+
+        let (block_width, block_height) = self.format.block_dimensions();
+        let block_size = self.format.block_size(None).unwrap() as usize;
+        let mut images = Vec::new();
+        for (level, data) in self.levels.iter().enumerate() {
+            let (width, height) = level_dimensions(self.width, self.height, level as u32);
+            let blocks_wide = width.div_ceil(block_width);
+            let mut image = image::RgbaImage::new(width, height);
+            for (index, block) in data.chunks_exact(block_size).enumerate() {
+                let texels = decode_block(self.format, block).ok_or_else(|| {
+                    anyhow!("{:?} textures are not supported by this GPU and cannot be decompressed on the CPU", self.format)
+                })?;
+                let (block_x, block_y) = (index as u32 % blocks_wide * block_width, index as u32 / blocks_wide * block_height);
+                for (i, texel) in texels.iter().enumerate() {
+                    let (x, y) = (block_x + i as u32 % block_width, block_y + i as u32 / block_width);
+                    // Blocks past the edge of levels that are not whole blocks are cropped
+                    if x < width && y < height {
+                        image.put_pixel(x, y, image::Rgba(*texel));
+                    }
+                }
+            }
+            images.push(image);
+        }
+        Ok(images)
+    }
+}
+
+/// Width and height of mip `level` of a texture
+fn level_dimensions(width: u32, height: u32, level: u32) -> (u32, u32) {
+    ((width >> level).max(1), (height >> level).max(1))
+}
+
+/// Fails unless a texture of the size in a file's header has room for its `level_count`
+/// mip levels, so no level is smaller than a texel
+fn check_level_count(width: u32, height: u32, level_count: u32) -> Result<()> {
+    ensure!(width > 0 && height > 0, "{}x{} textures are empty", width, height);
+    let mip_level_count = 32 - width.max(height).leading_zeros();
+    ensure!(
+        level_count <= mip_level_count,
+        "{} mip levels do not fit a {}x{} texture",
+        level_count,
+        width,
+        height
+    );
+    Ok(())
+}
+
+/// Bytes in mip `level` of a texture of `format`, in whole blocks
+fn level_size(format: wgpu::TextureFormat, width: u32, height: u32, level: u32) -> Result<usize> {
+    let (level_width, level_height) = level_dimensions(width, height, level);
+    let (block_width, block_height) = format.block_dimensions();
+    let block_size = format.block_size(None).unwrap_or(4);
+    (level_width.div_ceil(block_width) as usize)
+        .checked_mul(level_height.div_ceil(block_height) as usize)
+        .and_then(|blocks| blocks.checked_mul(block_size as usize))
+        .ok_or_else(|| anyhow!("{}x{} textures are too large", width, height))
+}
+
+/// The `size` bytes of a level starting at `offset`, or none if the file ends before them
+fn level_data(bytes: &[u8], offset: usize, size: usize) -> Option<&[u8]> {
+    bytes.get(offset..offset.checked_add(size)?)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    let field = bytes.get(offset..offset + 4).ok_or_else(|| anyhow!("texture header is truncated"))?;
+    Ok(u32::from_le_bytes(field.try_into().unwrap()))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64> {
+    let field = bytes.get(offset..offset + 8).ok_or_else(|| anyhow!("texture header is truncated"))?;
+    Ok(u64::from_le_bytes(field.try_into().unwrap()))
+}
+
+/// Read the levels of an uncompressed DDS file as RGBA8, whatever order its channel masks give
+fn read_dds_rgb_levels(
+    bytes: &[u8],
+    mut offset: usize,
+    width: u32,
+    height: u32,
+    level_count: u32,
+    format_flags: u32,
+) -> Result<Vec<Vec<u8>>> {
+    ensure!(format_flags & DDPF_RGB != 0, "DDS files without RGB or compressed texels are not supported");
+    let bits = read_u32(bytes, 88)?;
+    let masks = [read_u32(bytes, 92)?, read_u32(bytes, 96)?, read_u32(bytes, 100)?, read_u32(bytes, 104)?];
+    ensure!(bits == 24 || bits == 32, "{}-bit DDS files are not supported", bits);
+    // Byte of each channel within a texel, from its mask
+    let channel_byte = |mask: u32| match mask {
+        0xFF => Ok(0),
+        0xFF00 => Ok(1),
+        0xFF0000 => Ok(2),
+        0xFF000000 => Ok(3),
+        mask => Err(anyhow!("unsupported DDS channel mask {:#x}", mask)),
+    };
+    let [red, green, blue] = [channel_byte(masks[0])?, channel_byte(masks[1])?, channel_byte(masks[2])?];
+    let alpha = if format_flags & DDPF_ALPHAPIXELS != 0 { Some(channel_byte(masks[3])?) } else { None };
+
+    let texel_size = bits as usize / 8;
+    let mut levels = Vec::new();
+    for level in 0..level_count {
+        let (level_width, level_height) = level_dimensions(width, height, level);
+        let size = (level_width as usize)
+            .checked_mul(level_height as usize)
+            .and_then(|texels| texels.checked_mul(texel_size))
+            .ok_or_else(|| anyhow!("{}x{} textures are too large", width, height))?;
+        let data = level_data(bytes, offset, size).ok_or_else(|| anyhow!("DDS level {} is truncated", level))?;
+        levels.push(
+            data.chunks_exact(texel_size)
+                .flat_map(|texel| [texel[red], texel[green], texel[blue], alpha.map_or(255, |alpha| texel[alpha])])
+                .collect(),
+        );
+        offset += size;
+    }
+    Ok(levels)
+}
+
+/// The format of a KTX2 VkFormat
+fn ktx2_format(vk_format: u32) -> Option<wgpu::TextureFormat> {
+    use wgpu::{AstcBlock, AstcChannel, TextureFormat as F};
+    Some(match vk_format {
+        37 => F::Rgba8Unorm,
+        43 => F::Rgba8UnormSrgb,
+        131 | 133 => F::Bc1RgbaUnorm,
+        132 | 134 => F::Bc1RgbaUnormSrgb,
+        135 => F::Bc2RgbaUnorm,
+        136 => F::Bc2RgbaUnormSrgb,
+        137 => F::Bc3RgbaUnorm,
+        138 => F::Bc3RgbaUnormSrgb,
+        139 => F::Bc4RUnorm,
+        140 => F::Bc4RSnorm,
+        141 => F::Bc5RgUnorm,
+        142 => F::Bc5RgSnorm,
+        143 => F::Bc6hRgbUfloat,
+        144 => F::Bc6hRgbFloat,
+        145 => F::Bc7RgbaUnorm,
+        146 => F::Bc7RgbaUnormSrgb,
+        147 => F::Etc2Rgb8Unorm,
+        148 => F::Etc2Rgb8UnormSrgb,
+        149 => F::Etc2Rgb8A1Unorm,
+        150 => F::Etc2Rgb8A1UnormSrgb,
+        151 => F::Etc2Rgba8Unorm,
+        152 => F::Etc2Rgba8UnormSrgb,
+        153 => F::EacR11Unorm,
+        154 => F::EacR11Snorm,
+        155 => F::EacRg11Unorm,
+        156 => F::EacRg11Snorm,
+        157..=184 => {
+            let blocks = [
+                AstcBlock::B4x4,
+                AstcBlock::B5x4,
+                AstcBlock::B5x5,
+                AstcBlock::B6x5,
+                AstcBlock::B6x6,
+                AstcBlock::B8x5,
+                AstcBlock::B8x6,
+                AstcBlock::B8x8,
+                AstcBlock::B10x5,
+                AstcBlock::B10x6,
+                AstcBlock::B10x8,
+                AstcBlock::B10x10,
+                AstcBlock::B12x10,
+                AstcBlock::B12x12,
+            ];
+            // Each block size has a unorm then an sRGB format
+            let index = vk_format - 157;
+            let channel = if index.is_multiple_of(2) { AstcChannel::Unorm } else { AstcChannel::UnormSrgb };
+            F::Astc { block: blocks[index as usize / 2], channel }
+        }
+        _ => return None,
+    })
+}
+
+/// The format of a DDS DXGI_FORMAT
+fn dxgi_format_to_wgpu(dxgi_format: u32) -> Option<wgpu::TextureFormat> {
+    use wgpu::TextureFormat as F;
+    Some(match dxgi_format {
+        28 => F::Rgba8Unorm,
+        29 => F::Rgba8UnormSrgb,
+        71 => F::Bc1RgbaUnorm,
+        72 => F::Bc1RgbaUnormSrgb,
+        74 => F::Bc2RgbaUnorm,
+        75 => F::Bc2RgbaUnormSrgb,
+        77 => F::Bc3RgbaUnorm,
+        78 => F::Bc3RgbaUnormSrgb,
+        80 => F::Bc4RUnorm,
+        81 => F::Bc4RSnorm,
+        83 => F::Bc5RgUnorm,
+        84 => F::Bc5RgSnorm,
+        95 => F::Bc6hRgbUfloat,
+        96 => F::Bc6hRgbFloat,
+        98 => F::Bc7RgbaUnorm,
+        99 => F::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A KTX2 file holding `levels`, largest first, with the level index pointing at each
+    fn ktx2_file(vk_format: u32, width: u32, height: u32, supercompression: u32, levels: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = KTX2_IDENTIFIER.to_vec();
+        for field in [vk_format, 1, width, height, 0, 0, 1, levels.len() as u32, supercompression] {
+            file.extend(field.to_le_bytes());
+        }
+        file.resize(80, 0);
+        let mut offset = 80 + 24 * levels.len();
+        for level in levels {
+            for field in [offset, level.len(), level.len()] {
+                file.extend((field as u64).to_le_bytes());
+            }
+            offset += level.len();
+        }
+        file.extend(levels.concat());
+        file
+    }
+
+    /// A DDS file with a legacy pixel format and `data` for every level
+    fn dds_file(width: u32, height: u32, level_count: u32, format_flags: u32, four_cc: &[u8; 4], masks: [u32; 5], data: &[u8]) -> Vec<u8> {
+        let mut file = vec![0; 128];
+        file[..4].copy_from_slice(DDS_MAGIC);
+        let mut set = |offset: usize, value: u32| file[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        set(4, 124);
+        set(8, DDSD_MIPMAPCOUNT);
+        set(12, height);
+        set(16, width);
+        set(28, level_count);
+        set(76, 32);
+        set(80, format_flags);
+        set(84, u32::from_le_bytes(*four_cc));
+        for (i, mask) in masks.into_iter().enumerate() {
+            set(88 + 4 * i, mask);
+        }
+        file.extend(data);
+        file
+    }
+
+    #[test]
+    fn ktx2_levels_are_read_through_the_level_index() {
+        let levels = vec![vec![1; 32], vec![2; 8], vec![3; 8], vec![4; 8]];
+        let image = CompressedImage::parse(&ktx2_file(134, 8, 8, 0, &levels), ColorSpace::Linear).unwrap();
+        assert_eq!(image.format, wgpu::TextureFormat::Bc1RgbaUnormSrgb);
+        assert_eq!((image.width, image.height), (8, 8));
+        assert_eq!(image.levels, levels);
+
+        let astc = CompressedImage::parse(&ktx2_file(172, 8, 8, 0, &[vec![0; 16]]), ColorSpace::Srgb).unwrap();
+        let block = wgpu::AstcBlock::B8x8;
+        assert_eq!(astc.format, wgpu::TextureFormat::Astc { block, channel: wgpu::AstcChannel::UnormSrgb });
+    }
+
+    #[test]
+    fn dds_files_read_their_format_and_mip_levels() {
+        // 8x4, 4x2 and 2x1 levels of 16-byte blocks
+        let data: Vec<u8> = (0..64).collect();
+        let file = dds_file(8, 4, 3, DDPF_FOURCC, b"DXT5", [0; 5], &data);
+        let image = CompressedImage::parse(&file, ColorSpace::Srgb).unwrap();
+        assert_eq!(image.format, wgpu::TextureFormat::Bc3RgbaUnormSrgb);
+        assert_eq!(image.levels, [data[..32].to_vec(), data[32..48].to_vec(), data[48..].to_vec()]);
+
+        let truncated = dds_file(8, 4, 3, DDPF_FOURCC, b"DXT5", [0; 5], &data[..60]);
+        assert!(CompressedImage::parse(&truncated, ColorSpace::Srgb).is_err());
+    }
+
+    #[test]
+    fn uncompressed_dds_texels_are_swizzled_to_rgba() {
+        // One BGRA texel, then one without alpha
+        let masks = [32, 0xFF0000, 0xFF00, 0xFF, 0xFF000000];
+        let file = dds_file(1, 1, 1, DDPF_RGB | DDPF_ALPHAPIXELS, &[0; 4], masks, &[30, 20, 10, 40]);
+        let image = CompressedImage::parse(&file, ColorSpace::Srgb).unwrap();
+        assert_eq!(image.format, wgpu::TextureFormat::Rgba8UnormSrgb);
+        assert_eq!(image.levels, [vec![10, 20, 30, 40]]);
+
+        let file = dds_file(1, 1, 1, DDPF_RGB, &[0; 4], masks, &[30, 20, 10, 40]);
+        let image = CompressedImage::parse(&file, ColorSpace::Linear).unwrap();
+        assert_eq!(image.decompress().unwrap()[0].get_pixel(0, 0).0, [10, 20, 30, 255]);
+    }
+
+    #[test]
+    fn unsupported_textures_name_their_format() {
+        let error = |result: Result<CompressedImage>| result.err().unwrap().to_string();
+        assert!(error(CompressedImage::parse(&ktx2_file(145, 4, 4, 2, &[vec![0; 16]]), ColorSpace::Srgb)).contains("Zstandard"));
+        assert!(error(CompressedImage::parse(&ktx2_file(1000, 4, 4, 0, &[vec![0; 16]]), ColorSpace::Srgb)).contains("VkFormat 1000"));
+        assert!(error(CompressedImage::parse(&dds_file(4, 4, 1, DDPF_FOURCC, b"ETC1", [0; 5], &[0; 8]), ColorSpace::Srgb)).contains("'ETC1'"));
+
+        let eac = CompressedImage::parse(&ktx2_file(153, 4, 4, 0, &[vec![0; 8]]), ColorSpace::Srgb).unwrap();
+        assert!(eac.decompress().err().unwrap().to_string().contains("EacR11Unorm"));
+    }
+
+    #[test]
+    fn malformed_headers_are_errors_not_panics() {
+        let error = |file: &[u8]| CompressedImage::parse(file, ColorSpace::Srgb).err().unwrap().to_string();
+        let ktx2 = ktx2_file(133, 4, 4, 0, &[vec![0; 8]]);
+        assert!(error(&ktx2[..30]).contains("truncated"));
+
+        // More levels than a 4x4 texture has, which would shift its size past 32 bits
+        let mut too_many_levels = ktx2.clone();
+        too_many_levels[40..44].copy_from_slice(&40u32.to_le_bytes());
+        assert!(error(&too_many_levels).contains("40 mip levels"));
+        assert!(error(&dds_file(4, 4, 64, DDPF_FOURCC, b"DXT1", [0; 5], &[0; 8])).contains("64 mip levels"));
+
+        // A level offset at the end of the address space
+        let mut far_level = ktx2;
+        far_level[80..88].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(error(&far_level).contains("level 0"));
+
+        // A size whose level would not fit in memory
+        assert!(error(&dds_file(u32::MAX, u32::MAX, 1, DDPF_FOURCC, b"DXT5", [0; 5], &[0; 16])).contains("too large"));
+        assert!(error(&dds_file(0, 4, 1, DDPF_FOURCC, b"DXT5", [0; 5], &[0; 16])).contains("empty"));
+    }
+
+    #[test]
+    fn decompression_crops_blocks_past_the_edge() {
+        // A 2x2 level in one BC1 block of solid red
+        let block = vec![0x00, 0xF8, 0x00, 0xF8, 0, 0, 0, 0];
+        let image = CompressedImage::parse(&ktx2_file(133, 2, 2, 0, &[block]), ColorSpace::Linear).unwrap();
+        let levels = image.decompress().unwrap();
+        assert_eq!(levels[0].dimensions(), (2, 2));
+        assert_eq!(levels[0].get_pixel(1, 1).0, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn decompressing_levels_of_the_wrong_size_is_an_error() {
+        let short = CompressedImage { format: wgpu::TextureFormat::Rgba8Unorm, width: 2, height: 2, levels: vec![vec![0; 12]] };
+        assert!(short.decompress().err().unwrap().to_string().contains("12 bytes instead of 16"));
+
+        let short = CompressedImage { format: wgpu::TextureFormat::Bc1RgbaUnorm, width: 4, height: 4, levels: vec![vec![0; 4]] };
+        assert!(short.decompress().err().unwrap().to_string().contains("4 bytes instead of 8"));
+
+        let empty = CompressedImage { format: wgpu::TextureFormat::Rgba8Unorm, width: 0, height: 0, levels: vec![Vec::new()] };
+        assert!(empty.decompress().is_err());
+    }
+}
@@ -1,9 +1,14 @@
 use std::path::{Path, PathBuf};
 use anyhow::Result;
+use cgmath::SquareMatrix;
 use std::fs;
-use crate::engine::renderer::{Texture, Mesh, MeshData, Vertex};
+use crate::engine::renderer::{ColorSpace, LodSettings, Texture, TextureOptions, TextureRole, Mesh, MeshData, Vertex};
+use crate::engine::renderer::{
+    create_material_instance, create_object_bind_group, BlendMode, DefaultMaterialTextures, MaterialTextures, MaterialUniforms,
+    MATERIAL_HAS_NORMAL_MAP,
+};
 use crate::engine::scene::{Model, Material};
-use crate::engine::scene::model::MaterialInstance;
+use super::compressed::CompressedImage;
 #[allow(unused_imports)]
 use wgpu::{self, util::DeviceExt};
 #[allow(unused_imports)]
@@ -21,19 +26,109 @@ pub trait ResourceLoader<T> {
 pub struct TextureLoader<'a> {
     pub device: &'a wgpu::Device,
     pub queue: &'a wgpu::Queue,
+    pub options: TextureOptions, // How loaded textures wrap, filter and build their mip chains
 }
 
 #[allow(dead_code)]
 impl<'a> TextureLoader<'a> {
     /// Create a new texture loader
     pub fn new(device: &'a wgpu::Device, queue: &'a wgpu::Queue) -> Self {
-        Self { device, queue }
+        Self {
+            device,
+            queue,
+            options: TextureOptions::default(),
+        }
     }
     
-    /// Load a texture from a file
+    /// Load a color texture from a file
     pub fn load_from_file(&self, path: &Path) -> Result<Texture> {
+        self.load_with_options(path, &TextureOptions { color_space: ColorSpace::Srgb, ..self.options })
+    }
+    
+    /// Load a texture that holds data rather than color, such as a normal map
+    pub fn load_linear_from_file(&self, path: &Path) -> Result<Texture> {
+        self.load_with_options(path, &TextureOptions { color_space: ColorSpace::Linear, ..self.options })
+    }
+    
+    /// Load a material map holding `role`, in the format of the role. KTX2 and DDS files
+    /// keep their own format.
+    pub fn load_role(&self, path: &Path, role: TextureRole) -> Result<Texture> {
+        let options = TextureOptions { color_space: role.color_space(), ..self.options };
+        self.load_in_format(path, role.format(), &options)
+    }
+    
+    /// Load a texture from a file, ignoring the loader's own options. KTX2 and DDS files
+    /// keep their compressed format and mip levels.
+    pub fn load_with_options(&self, path: &Path, options: &TextureOptions) -> Result<Texture> {
+        self.load_in_format(path, options.color_space.format(), options)
+    }
+    
+    /// Load an image file in `format`, or a KTX2 or DDS file in the format it holds
+    fn load_in_format(&self, path: &Path, format: wgpu::TextureFormat, options: &TextureOptions) -> Result<Texture> {
+        let bytes = fs::read(path)?;
+        let label = path.to_string_lossy();
+        if CompressedImage::is_container(&bytes) {
+            return self
+                .load_compressed(&bytes, &label, options)
+                .map_err(|e| anyhow::anyhow!("Failed to load {}: {}", label, e));
+        }
+        let img = image::load_from_memory(&bytes)?;
+        Texture::from_image_in_format(self.device, self.queue, &img, Some(&label), format, options)
+    }
+    
+    /// Read an image file, or the largest level of a KTX2 or DDS file decompressed on the
+    /// CPU, holding `role`. For maps that are combined before they are uploaded.
+    pub fn load_pixels(&self, path: &Path, role: TextureRole) -> Result<image::DynamicImage> {
         let bytes = fs::read(path)?;
-        Texture::from_bytes(self.device, self.queue, &bytes, &path.to_string_lossy())
+        if CompressedImage::is_container(&bytes) {
+            let mut levels = CompressedImage::parse(&bytes, role.color_space())
+                .and_then(|image| image.decompress())
+                .map_err(|e| anyhow::anyhow!("Failed to load {}: {}", path.to_string_lossy(), e))?;
+            return Ok(image::DynamicImage::ImageRgba8(levels.remove(0)));
+        }
+        Ok(image::load_from_memory(&bytes)?)
+    }
+    
+    /// Upload a KTX2 or DDS texture as it is when the device supports its format, and
+    /// decompressed to RGBA8 otherwise
+    pub fn load_compressed(&self, bytes: &[u8], label: &str, options: &TextureOptions) -> Result<Texture> {
+        let image = CompressedImage::parse(bytes, options.color_space)?;
+        if image.is_supported_by(self.device) {
+            let levels: Vec<&[u8]> = image.levels.iter().map(Vec::as_slice).collect();
+            return Texture::from_levels(
+                self.device,
+                self.queue,
+                image.format,
+                (image.width, image.height),
+                &levels,
+                Some(label),
+                options,
+            );
+        }
+
+        let levels = image.decompress()?;
+        let color_space = if image.format.is_srgb() { ColorSpace::Srgb } else { ColorSpace::Linear };
+        let options = TextureOptions { color_space, ..*options };
+        if let [level] = &levels[..] {
+            // A single level gets its mip chain like any other image
+            return Texture::from_image_with_options(
+                self.device,
+                self.queue,
+                &image::DynamicImage::ImageRgba8(level.clone()),
+                Some(label),
+                &options,
+            );
+        }
+        let levels: Vec<&[u8]> = levels.iter().map(|level| level.as_raw().as_slice()).collect();
+        Texture::from_levels(
+            self.device,
+            self.queue,
+            color_space.format(),
+            (image.width, image.height),
+            &levels,
+            Some(label),
+            &options,
+        )
     }
     
     /// Create a default texture for missing textures
@@ -60,6 +155,8 @@ fn find_pbr_texture(base_path: &Path, texture_type: &str, material_name: &str) -
         "normal" => vec!["_normal", "_nrm", "_n", "-normal", "-nrm", "-n", "_Normal"],
         "metallic" => vec!["_metallic", "_metalness", "_metal", "_m", "-metallic", "-m", "_Metallic"],
         "roughness" => vec!["_roughness", "_rough", "_r", "-roughness", "-r", "_Roughness"],
+        "occlusion" => vec!["_ao", "_occlusion", "_AO", "-ao", "-occlusion", "_Occlusion"],
+        "emissive" => vec!["_emissive", "_emission", "-emissive", "_Emissive"],
         _ => vec![],
     };
     
@@ -98,6 +195,138 @@ fn find_pbr_texture(base_path: &Path, texture_type: &str, material_name: &str) -
     None
 }
 
+/// Parse a single number from an MTL parameter such as `Pr 0.4`
+fn parse_mtl_float(material: &tobj::Material, key: &str) -> Option<f32> {
+    material.unknown_param.get(key)?.trim().parse().ok()
+}
+
+/// Parse an RGB color from an MTL parameter such as `Ke 1.0 0.5 0.0`
+fn parse_mtl_color(material: &tobj::Material, key: &str) -> Option<[f32; 3]> {
+    let values: Vec<f32> = material.unknown_param.get(key)?
+        .split_whitespace()
+        .map(|value| value.parse().ok())
+        .collect::<Option<_>>()?;
+    match values[..] {
+        [r, g, b] => Some([r, g, b]),
+        [v] => Some([v, v, v]),
+        _ => None,
+    }
+}
+
+/// Blend mode of an MTL material: cut out by its alpha map, or blended when partly dissolved
+fn material_blend_mode(material: &Material) -> BlendMode {
+    if material.dissolve < 1.0 {
+        BlendMode::AlphaBlend
+    } else if material.alpha_cutout {
+        BlendMode::AlphaTest
+    } else {
+        BlendMode::Opaque
+    }
+}
+
+/// Resolve a texture named in the MTL file relative to the OBJ's directory
+fn mtl_texture_path(obj_dir: &Path, name: Option<&String>) -> Option<PathBuf> {
+    name.filter(|name| !name.is_empty()).map(|name| obj_dir.join(name))
+}
+
+/// Read a material from the MTL file. Maps it does not name are looked for beside its
+/// diffuse map, and PBR factors it does not give are guessed from its Phong parameters.
+fn read_mtl_material(obj_dir: &Path, mat: tobj::Material) -> Material {
+    let diffuse_path = if !mat.diffuse_texture.is_empty() {
+        Some(obj_dir.join(&mat.diffuse_texture))
+    } else {
+        None
+    };
+    
+    // Use maps named in the MTL file, or find PBR textures based on naming conventions
+    let find_texture = |texture_type: &str| diffuse_path.as_ref()
+        .and_then(|diff_path| find_pbr_texture(diff_path, texture_type, &mat.name));
+    let normal_texture = mtl_texture_path(obj_dir, Some(&mat.normal_texture))
+        .or_else(|| mtl_texture_path(obj_dir, mat.unknown_param.get("norm")))
+        .or_else(|| find_texture("normal"));
+    let metallic_texture = mtl_texture_path(obj_dir, mat.unknown_param.get("map_Pm"))
+        .or_else(|| find_texture("metallic"));
+    let roughness_texture = mtl_texture_path(obj_dir, mat.unknown_param.get("map_Pr"))
+        .or_else(|| find_texture("roughness"));
+    let occlusion_texture = find_texture("occlusion");
+    let emissive_texture = mtl_texture_path(obj_dir, mat.unknown_param.get("map_Ke"))
+        .or_else(|| find_texture("emissive"));
+
+    // PBR values from the MTL extension (Pm, Pr, Ke). Without them, guess from
+    // the Phong parameters, or let a map control the value on its own.
+    let metallic = parse_mtl_float(&mat, "Pm").unwrap_or(if metallic_texture.is_some() {
+        1.0
+    } else if mat.specular[0] > 0.9 && mat.specular[1] > 0.9 && mat.specular[2] > 0.9 {
+        0.9 // Likely metallic if specular is high and uniform
+    } else {
+        0.0 // Non-metallic by default
+    });
+    
+    let roughness = parse_mtl_float(&mat, "Pr").unwrap_or(if roughness_texture.is_some() {
+        1.0
+    } else {
+        1.0 - (mat.shininess / 1000.0).min(1.0)
+    });
+    
+    let emissive = parse_mtl_color(&mat, "Ke").unwrap_or(if emissive_texture.is_some() {
+        [1.0, 1.0, 1.0]
+    } else {
+        [0.0, 0.0, 0.0]
+    });
+
+    Material {
+        name: mat.name,
+        diffuse_texture: diffuse_path,
+        normal_texture,
+        metallic_texture,
+        roughness_texture,
+        occlusion_texture,
+        emissive_texture,
+        ambient: mat.ambient,
+        diffuse: mat.diffuse,
+        specular: mat.specular,
+        emissive,
+        metallic,
+        roughness,
+        shininess: mat.shininess,
+        dissolve: mat.dissolve,
+        alpha_cutout: !mat.dissolve_texture.is_empty(),
+    }
+}
+
+/// The maps of one material. Maps it does not have are left to the default textures.
+struct MaterialMaps {
+    albedo: Texture,                     // The diffuse map, or a texel of the diffuse color
+    normal: Option<Texture>,
+    metallic_roughness: Option<Texture>, // Both maps packed into one
+    occlusion: Option<Texture>,
+    emissive: Option<Texture>,
+}
+
+/// Pack separate metallic and roughness maps into one image using the glTF layout:
+/// roughness in green and metallic in blue. A missing map leaves its channel at 1.0
+/// so the material factor alone controls it. Roughness is resized to the metallic map.
+pub fn pack_metallic_roughness(
+    metallic: Option<&image::DynamicImage>,
+    roughness: Option<&image::DynamicImage>,
+) -> Option<image::RgbaImage> {
+    let (width, height) = match (metallic, roughness) {
+        (Some(image), _) | (None, Some(image)) => (image.width(), image.height()),
+        (None, None) => return None,
+    };
+    let channel = |image: Option<&image::DynamicImage>| image.map(|image| {
+        image::imageops::resize(&image.to_luma8(), width, height, image::imageops::FilterType::Triangle)
+    });
+    let metallic = channel(metallic);
+    let roughness = channel(roughness);
+
+    Some(image::RgbaImage::from_fn(width, height, |x, y| {
+        let metallic = metallic.as_ref().map_or(255, |image| image.get_pixel(x, y).0[0]);
+        let roughness = roughness.as_ref().map_or(255, |image| image.get_pixel(x, y).0[0]);
+        image::Rgba([0, roughness, metallic, 255])
+    }))
+}
+
 #[allow(dead_code)]
 /// Model loader for loading 3D models
 pub struct ModelLoader<'a> {
@@ -105,6 +334,8 @@ pub struct ModelLoader<'a> {
     queue: &'a wgpu::Queue,
     texture_loader: TextureLoader<'a>,
     bind_group_layout: &'a wgpu::BindGroupLayout,
+    material_bind_group_layout: &'a wgpu::BindGroupLayout,
+    lod_settings: LodSettings, // How the levels of detail of loaded meshes are simplified
 }
 
 impl<'a> ModelLoader<'a> {
@@ -112,7 +343,8 @@ impl<'a> ModelLoader<'a> {
     pub fn new(
         device: &'a wgpu::Device, 
         queue: &'a wgpu::Queue, 
-        bind_group_layout: &'a wgpu::BindGroupLayout
+        bind_group_layout: &'a wgpu::BindGroupLayout,
+        material_bind_group_layout: &'a wgpu::BindGroupLayout,
     ) -> Self {
         let texture_loader = TextureLoader::new(device, queue);
         Self { 
@@ -120,6 +352,79 @@ impl<'a> ModelLoader<'a> {
             queue,
             texture_loader,
             bind_group_layout,
+            material_bind_group_layout,
+            lod_settings: LodSettings::default(),
+        }
+    }
+    
+    #[allow(dead_code)]
+    /// Change how many levels of detail loaded meshes get and when they are drawn
+    pub fn set_lod_settings(&mut self, settings: LodSettings) {
+        self.lod_settings = settings;
+    }
+    
+    #[allow(dead_code)]
+    /// Change how the maps of loaded materials wrap and filter, including their anisotropy.
+    /// Each map keeps its own color space.
+    pub fn set_texture_options(&mut self, options: TextureOptions) {
+        self.texture_loader.options = options;
+    }
+    
+    /// Load an optional map, falling back to the default texture when it cannot be read
+    fn load_optional_texture(&self, path: Option<&PathBuf>, role: TextureRole) -> Option<Texture> {
+        let path = path?;
+        match self.texture_loader.load_role(path, role) {
+            Ok(texture) => Some(texture),
+            Err(e) => {
+                eprintln!("Failed to load texture {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+    
+    /// Load the material's metallic and roughness maps packed into one texture. Either map
+    /// may be an image, KTX2 or DDS file.
+    fn load_metallic_roughness(&self, material: &Material) -> Option<Texture> {
+        let open = |path: Option<&PathBuf>| {
+            let path = path?;
+            match self.texture_loader.load_pixels(path, TextureRole::MetallicRoughness) {
+                Ok(image) => Some(image),
+                Err(e) => {
+                    eprintln!("Failed to load texture {:?}: {}", path, e);
+                    None
+                }
+            }
+        };
+        let metallic = open(material.metallic_texture.as_ref());
+        let roughness = open(material.roughness_texture.as_ref());
+        let packed = pack_metallic_roughness(metallic.as_ref(), roughness.as_ref())?;
+
+        let label = format!("{} Metallic Roughness", material.name);
+        match Texture::from_image_in_format(
+            self.device,
+            self.queue,
+            &image::DynamicImage::ImageRgba8(packed),
+            Some(&label),
+            TextureRole::MetallicRoughness.format(),
+            &self.texture_loader.options,
+        ) {
+            Ok(texture) => Some(texture),
+            Err(e) => {
+                eprintln!("Failed to load texture {:?}: {}", label, e);
+                None
+            }
+        }
+    }
+    
+    /// Load the maps of the `index`th material, each in the format of its role
+    fn load_material_maps(&self, material: &Material, index: usize) -> MaterialMaps {
+        MaterialMaps {
+            albedo: self.load_optional_texture(material.diffuse_texture.as_ref(), TextureRole::Albedo)
+                .unwrap_or_else(|| self.texture_loader.create_colored(material.diffuse, &format!("Material {}", index))),
+            normal: self.load_optional_texture(material.normal_texture.as_ref(), TextureRole::Normal),
+            metallic_roughness: self.load_metallic_roughness(material),
+            occlusion: self.load_optional_texture(material.occlusion_texture.as_ref(), TextureRole::Occlusion),
+            emissive: self.load_optional_texture(material.emissive_texture.as_ref(), TextureRole::Emissive),
         }
     }
     
@@ -168,63 +473,7 @@ impl<'a> ModelLoader<'a> {
         let mut mesh_data = Vec::new();
 
         // Process materials from MTL file
-        if !mtl_materials.is_empty() {
-            for mat in mtl_materials {
-                let diffuse_path = if !mat.diffuse_texture.is_empty() {
-                    Some(obj_dir.join(&mat.diffuse_texture))
-                } else {
-                    None
-                };
-                
-                // Try to find PBR textures based on naming conventions
-                let normal_texture = if let Some(diff_path) = &diffuse_path {
-                    find_pbr_texture(diff_path, "normal", &mat.name)
-                } else {
-                    None
-                };
-                
-                let metallic_texture = if let Some(diff_path) = &diffuse_path {
-                    find_pbr_texture(diff_path, "metallic", &mat.name)
-                } else {
-                    None
-                };
-                
-                let roughness_texture = if let Some(diff_path) = &diffuse_path {
-                    find_pbr_texture(diff_path, "roughness", &mat.name)
-                } else {
-                    None
-                };
-                
-                // Debug output
-                if normal_texture.is_some() || metallic_texture.is_some() || roughness_texture.is_some() {
-                    println!("Found PBR textures for {}: Normal: {:?}, Metallic: {:?}, Roughness: {:?}",
-                             mat.name, normal_texture, metallic_texture, roughness_texture);
-                }
-        
-                // Guess PBR values from MTL if available
-                let metallic = if mat.specular[0] > 0.9 && mat.specular[1] > 0.9 && mat.specular[2] > 0.9 {
-                    0.9 // Likely metallic if specular is high and uniform
-                } else {
-                    0.0 // Non-metallic by default
-                };
-                
-                let roughness = 1.0 - (mat.shininess / 1000.0).min(1.0);
-        
-                materials.push(Material {
-                    name: mat.name,
-                    diffuse_texture: diffuse_path,
-                    normal_texture,
-                    metallic_texture,
-                    roughness_texture,
-                    ambient: mat.ambient,
-                    diffuse: mat.diffuse,
-                    specular: mat.specular,
-                    metallic,
-                    roughness,
-                    shininess: mat.shininess,
-                });
-            }
-        }
+        materials.extend(mtl_materials.into_iter().map(|mat| read_mtl_material(obj_dir, mat)));
         
         // Add a default material if none was loaded
         if materials.is_empty() {
@@ -234,12 +483,17 @@ impl<'a> ModelLoader<'a> {
                 normal_texture: None,
                 metallic_texture: None,
                 roughness_texture: None,
+                occlusion_texture: None,
+                emissive_texture: None,
                 ambient: [0.1, 0.1, 0.1],
                 diffuse: [0.7, 0.7, 0.7],
                 specular: [1.0, 1.0, 1.0],
+                emissive: [0.0, 0.0, 0.0],
                 metallic: 0.0,
                 roughness: 0.5,
                 shininess: 32.0,
+                dissolve: 1.0,
+                alpha_cutout: false,
             });
         }
 
@@ -289,14 +543,17 @@ impl<'a> ModelLoader<'a> {
                     normal,
                     tex_coords,
                     color: [1.0, 1.0, 1.0], // Add default white color
+                    tangent: [0.0; 4],
                 });
             }
             
-            mesh_data.push(MeshData {
+            let mut data = MeshData {
                 vertices,
                 indices: mesh.indices.clone(),
                 material_id,
-            });
+            };
+            data.generate_tangents();
+            mesh_data.push(data);
         }
 
         // Create material instances
@@ -312,73 +569,155 @@ impl<'a> ModelLoader<'a> {
             }
         );
         
+        let light_buffer = light_buffer.unwrap_or(&default_light_buffer);
+        let default_textures = DefaultMaterialTextures::new(self.device, self.queue);
+        // Every map of a material is read through one sampler, so it follows the loader's options
+        let sampler = self.texture_loader.options.create_sampler(self.device, Some("Material Sampler"));
+        
         for (i, material) in materials.iter().enumerate() {
-            // Load diffuse texture
-            let texture = if let Some(path) = &material.diffuse_texture {
-                match self.texture_loader.load_from_file(path) {
-                    Ok(texture) => texture,
-                    Err(_) => self.texture_loader.create_colored(material.diffuse, &format!("Material {}", i)),
-                }
-            } else {
-                self.texture_loader.create_colored(material.diffuse, &format!("Material {}", i))
-            };
-            
-            // Create bind group entries
-            let mut entries = vec![
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: uniform_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
-                },
-            ];
-            
-            // Add light buffer if provided
-            if let Some(light_buf) = light_buffer {
-                entries.push(wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: light_buf.as_entire_binding(),
-                });
-            } else {
-                // Use the default light buffer created outside the loop
-                entries.push(wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: default_light_buffer.as_entire_binding(),
-                });
-            }
+            let maps = self.load_material_maps(material, i);
             
-            // Create bind group
-            let bind_group = self.device.create_bind_group(
-                &wgpu::BindGroupDescriptor {
-                    layout: self.bind_group_layout,
-                    entries: &entries,
-                    label: Some(&format!("material_bind_group_{}", i)),
-                }
-            );
+            let uniforms = MaterialUniforms {
+                base_color_factor: [1.0, 1.0, 1.0, material.dissolve],
+                emissive_factor: material.emissive,
+                metallic_factor: material.metallic,
+                roughness_factor: material.roughness,
+                flags: if maps.normal.is_some() { MATERIAL_HAS_NORMAL_MAP } else { 0 },
+                blend_mode: material_blend_mode(material) as u32,
+                ..MaterialUniforms::default()
+            };
+            let textures = MaterialTextures {
+                albedo: &maps.albedo,
+                normal: maps.normal.as_ref().unwrap_or(&default_textures.flat_normal),
+                metallic_roughness: maps.metallic_roughness.as_ref().unwrap_or(&default_textures.white_linear),
+                occlusion: maps.occlusion.as_ref().unwrap_or(&default_textures.white_linear),
+                emissive: maps.emissive.as_ref().unwrap_or(&default_textures.white),
+                sampler: &sampler,
+            };
             
-            material_instances.push(MaterialInstance {
-                material_id: i,
-                diffuse_bind_group: bind_group,
-            });
+            material_instances.push(create_material_instance(
+                self.device,
+                self.material_bind_group_layout,
+                i,
+                uniforms,
+                textures,
+                &format!("material_{}", i),
+            ));
         }
 
-        // Create meshes with vertex and index buffers
+        // Create meshes with vertex and index buffers, simplifying each into levels of detail
         let mut meshes = Vec::new();
         
         for mesh_data in &mesh_data {
-            let mesh = Mesh::new(&self.device, mesh_data);
+            let levels = mesh_data.lod_chain(&self.lod_settings);
+            let mesh = Mesh::with_lods(self.device, &levels, &self.lod_settings);
             meshes.push(mesh);
         }
         
-        Ok(Model {
+        let mut model = Model::with_meshes(
             meshes,
-            materials: material_instances,
-        })
+            material_instances,
+            create_object_bind_group(
+                self.device,
+                self.bind_group_layout,
+                uniform_buffer,
+                light_buffer,
+                &format!("{} Bind Group", path.display()),
+            ),
+        );
+        
+        // Start at the origin in the uniform buffer too, whatever it held before
+        model.set_transform(self.queue, uniform_buffer, cgmath::Matrix4::identity());
+        Ok(model)
+    }
+} 
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+    use super::*;
+    use crate::engine::renderer::test_support::{headless_renderer, read_texels};
+
+    #[test]
+    fn pack_metallic_roughness_uses_gltf_channels() {
+        let metallic = image::DynamicImage::ImageLuma8(image::GrayImage::from_pixel(2, 2, image::Luma([200])));
+        let roughness = image::DynamicImage::ImageLuma8(image::GrayImage::from_pixel(2, 2, image::Luma([50])));
+
+        let packed = pack_metallic_roughness(Some(&metallic), Some(&roughness)).unwrap();
+        assert_eq!(packed.get_pixel(1, 1).0, [0, 50, 200, 255]);
+
+        let roughness_only = pack_metallic_roughness(None, Some(&roughness)).unwrap();
+        assert_eq!(roughness_only.get_pixel(0, 0).0, [0, 50, 255, 255]);
+
+        assert!(pack_metallic_roughness(None, None).is_none());
+    }
+
+    #[test]
+    fn parse_mtl_color_accepts_rgb_and_scalar() {
+        let mut material = tobj::Material::default();
+        material.unknown_param.insert("Ke".to_string(), "1.0 0.5 0.25".to_string());
+        material.unknown_param.insert("Pr".to_string(), " 0.4 ".to_string());
+        assert_eq!(parse_mtl_color(&material, "Ke"), Some([1.0, 0.5, 0.25]));
+        assert_eq!(parse_mtl_float(&material, "Pr"), Some(0.4));
+
+        material.unknown_param.insert("Ke".to_string(), "0.8".to_string());
+        assert_eq!(parse_mtl_color(&material, "Ke"), Some([0.8, 0.8, 0.8]));
+        assert_eq!(parse_mtl_color(&material, "Kx"), None);
+    }
+
+    #[test]
+    fn texture_loader_uploads_each_role_in_its_format() {
+        let renderer = headless_renderer(128, 128);
+        let path = std::env::temp_dir().join(format!("emather_role_{}.png", std::process::id()));
+        RgbaImage::from_pixel(8, 4, Rgba([200, 100, 50, 255])).save(&path).unwrap();
+        let loader = TextureLoader::new(renderer.device(), renderer.queue());
+
+        let roles = [
+            (TextureRole::Albedo, wgpu::TextureFormat::Rgba8UnormSrgb),
+            (TextureRole::Emissive, wgpu::TextureFormat::Rgba8UnormSrgb),
+            (TextureRole::Normal, wgpu::TextureFormat::Rgba8Unorm),
+            (TextureRole::MetallicRoughness, wgpu::TextureFormat::Rgba8Unorm),
+            (TextureRole::Occlusion, wgpu::TextureFormat::R8Unorm),
+        ];
+        for (role, format) in roles {
+            let texture = loader.load_role(&path, role).unwrap();
+            assert_eq!(texture.texture.format(), format, "{:?}", role);
+            assert_eq!(texture.texture.mip_level_count(), 4, "{:?}", role);
+        }
+
+        // Linear maps read back the bytes of the image, and occlusion only keeps its red channel
+        let normal = loader.load_role(&path, TextureRole::Normal).unwrap();
+        assert_eq!(read_texels(&renderer, &normal, 8, 4).get_pixel(3, 2).0, [200, 100, 50, 255]);
+        let occlusion = loader.load_role(&path, TextureRole::Occlusion).unwrap();
+        assert_eq!(read_texels(&renderer, &occlusion, 8, 4).get_pixel(3, 2).0, [200, 0, 0, 255]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn model_loader_loads_each_mtl_map_in_the_format_of_its_role() {
+        let renderer = headless_renderer(128, 128);
+        let dir = std::env::temp_dir().join(format!("emather_mtl_maps_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, width, height) in [("albedo", 8, 4), ("normal", 8, 4), ("metallic", 8, 4), ("roughness", 4, 2), ("emissive", 8, 4)] {
+            RgbaImage::from_pixel(width, height, Rgba([200, 100, 50, 255])).save(dir.join(format!("{}.png", name))).unwrap();
+        }
+        std::fs::write(
+            dir.join("maps.mtl"),
+            "newmtl Painted\nmap_Kd albedo.png\nnorm normal.png\nmap_Pm metallic.png\nmap_Pr roughness.png\nmap_Ke emissive.png\n",
+        ).unwrap();
+        let (mut mtl_materials, _) = tobj::load_mtl(dir.join("maps.mtl")).unwrap();
+        let material = read_mtl_material(&dir, mtl_materials.remove(0));
+
+        let loader = ModelLoader::new(renderer.device(), renderer.queue(), renderer.bind_group_layout(), renderer.material_bind_group_layout());
+        let maps = loader.load_material_maps(&material, 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Metallic and roughness are packed into one map the size of the metallic one, and the
+        // missing occlusion map is left to the default texture
+        let format_and_size = |texture: &Texture| (texture.texture.format(), (texture.texture.width(), texture.texture.height()));
+        assert_eq!(format_and_size(&maps.albedo), (wgpu::TextureFormat::Rgba8UnormSrgb, (8, 4)));
+        assert_eq!(format_and_size(maps.normal.as_ref().unwrap()), (wgpu::TextureFormat::Rgba8Unorm, (8, 4)));
+        assert_eq!(format_and_size(maps.metallic_roughness.as_ref().unwrap()), (wgpu::TextureFormat::Rgba8Unorm, (8, 4)));
+        assert!(maps.occlusion.is_none());
+        assert_eq!(format_and_size(maps.emissive.as_ref().unwrap()), (wgpu::TextureFormat::Rgba8UnormSrgb, (8, 4)));
     }
-} 
\ No newline at end of file
+}
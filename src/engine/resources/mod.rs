@@ -1,3 +1,8 @@
 mod loader;
+mod compressed;
+mod block_decode;
+mod astc_decode;
 #[allow(unused_imports)]
-pub use loader::{ModelLoader, ResourceLoader, TextureLoader}; 
\ No newline at end of file
+pub use loader::{ModelLoader, ResourceLoader, TextureLoader};
+#[allow(unused_imports)]
+pub use compressed::CompressedImage; 
\ No newline at end of file
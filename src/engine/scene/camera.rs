@@ -306,8 +306,7 @@ impl Camera {
         let rolled_up = -right * sin_roll + up * cos_roll;
         
         // Create view matrix with roll
-        let mut view = Matrix4::look_at_rh(self.position, self.position + forward, rolled_up);
-        view
+        Matrix4::look_at_rh(self.position, self.position + forward, rolled_up)
     }
     
     /// Get the projection matrix for rendering
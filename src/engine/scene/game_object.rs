@@ -54,6 +54,7 @@ impl Transform {
     }
     
     /// Get the model matrix (immutably)
+    #[allow(unused)]
     pub fn get_model_matrix(&self) -> Matrix4<f32> {
         // Return the cached matrix even if dirty
         // Call model_matrix() first if you need an up-to-date matrix
@@ -114,6 +115,7 @@ pub struct ModelComponent {
     pub model: Option<Model>,
     pub model_path: Option<String>,
     pub uniform_buffer: Option<wgpu::Buffer>,
+    pub tint: [f32; 4], // Multiplies the model's base color when drawn as an instance
 }
 
 #[allow(unused)]
@@ -124,6 +126,7 @@ impl ModelComponent {
             model: None,
             model_path: None,
             uniform_buffer: None,
+            tint: [1.0; 4],
         }
     }
     
@@ -133,6 +136,7 @@ impl ModelComponent {
             model: None,
             model_path: Some(path.to_string()),
             uniform_buffer: None,
+            tint: [1.0; 4],
         }
     }
     
@@ -142,11 +146,12 @@ impl ModelComponent {
         device: &wgpu::Device, 
         queue: &wgpu::Queue, 
         bind_group_layout: &wgpu::BindGroupLayout,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
         uniform_buffer: wgpu::Buffer,
         light_buffer: Option<&wgpu::Buffer>,
     ) -> Result<()> {
         if let Some(path) = &self.model_path {
-            let model_loader = ModelLoader::new(device, queue, bind_group_layout);
+            let model_loader = ModelLoader::new(device, queue, bind_group_layout, material_bind_group_layout);
             let model = model_loader.load_obj(
                 Path::new(path),
                 &uniform_buffer,
@@ -265,6 +270,7 @@ impl GameObject {
     }
     
     /// Create a game object with a model from a file path
+    #[allow(unused, clippy::too_many_arguments)]
     pub async fn with_model(
         name: &str, 
         model_path: &str,
@@ -272,6 +278,7 @@ impl GameObject {
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         bind_group_layout: &wgpu::BindGroupLayout,
+        material_bind_group_layout: &wgpu::BindGroupLayout,
         light_buffer: Option<&wgpu::Buffer>,
     ) -> Result<Self> {
         let mut game_object = Self::new(name);
@@ -291,6 +298,7 @@ impl GameObject {
             device, 
             queue, 
             bind_group_layout,
+            material_bind_group_layout,
             uniform_buffer,
             light_buffer,
         ).await?;
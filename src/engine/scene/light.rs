@@ -20,9 +20,7 @@ pub struct SunLight {
     pub color: [f32; 3],
     pub intensity: f32,
     pub use_pbr: u32,
-    pub metallic_factor: f32,
-    pub roughness_factor: f32,
-    _padding2: f32,
+    _padding2: [u32; 3],
 }
 
 impl SunLight {
@@ -34,9 +32,7 @@ impl SunLight {
             color: [1.0, 1.0, 1.0], // White light
             intensity: 1.0,
             use_pbr: 0, // Not using PBR by default
-            metallic_factor: 0.5,
-            roughness_factor: 0.5,
-            _padding2: 0.0,
+            _padding2: [0; 3],
         }
     }
     
@@ -48,9 +44,7 @@ impl SunLight {
             color: [1.0, 1.0, 1.0],     // White light
             intensity: 1.0,
             use_pbr: 1,
-            metallic_factor: 0.0,
-            roughness_factor: 0.5,
-            _padding2: 0.0,
+            _padding2: [0; 3],
         }
     }
 }
@@ -73,9 +67,43 @@ impl Light for SunLight {
     }
 }
 
+/// Shadow casting settings for a directional light
+#[derive(Debug, Copy, Clone)]
+pub struct ShadowSettings {
+    pub enabled: bool,
+    pub resolution: u32,      // Width and height of each cascade in texels
+    pub depth_bias: f32,      // Constant offset subtracted from the receiver depth
+    pub normal_bias: f32,     // Offset of the receiver along its normal, in shadow map texels
+    pub pcf_radius: u32,      // PCF kernel covers (2 * radius + 1)^2 texels
+    pub max_distance: f32,    // How far from the camera shadows are rendered
+    pub cascade_count: u32,   // Number of cascades, 1 to MAX_SHADOW_CASCADES
+    pub split_lambda: f32,    // Cascade split blend, 0 = linear and 1 = logarithmic
+    pub debug_cascades: bool, // Tint each cascade a different color
+}
+
+/// Maximum number of shadow cascades supported by the renderer
+pub const MAX_SHADOW_CASCADES: u32 = 4;
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            resolution: 2048,
+            depth_bias: 0.002,
+            normal_bias: 1.5,
+            pcf_radius: 1,
+            max_distance: 100.0,
+            cascade_count: MAX_SHADOW_CASCADES,
+            split_lambda: 0.75,
+            debug_cascades: false,
+        }
+    }
+}
+
 /// Sun light controller for interactive control
 pub struct SunController {
     pub sun_light: SunLight,
+    pub shadow: ShadowSettings,
     elevation: f32, // vertical angle (in degrees)
     azimuth: f32,   // horizontal angle (in degrees)
 }
@@ -89,6 +117,7 @@ impl SunController {
 
         let mut controller = Self {
             sun_light: SunLight::new(),
+            shadow: ShadowSettings::default(),
             elevation,
             azimuth,
         };
@@ -120,7 +149,8 @@ impl SunController {
 
         let mut handled = true;
         const ANGLE_DELTA: f32 = 5.0;
-        const INTENSITY_DELTA: f32 = 0.1;
+        const INTENSITY_DELTA: f32 = 0.25;
+        const MAX_INTENSITY: f32 = 20.0; // Scene is rendered in HDR, exposure brings it back into range
 
         match key {
             // Sun elevation (up/down)
@@ -143,11 +173,15 @@ impl SunController {
             }
             // Sun intensity
             VirtualKeyCode::U => {
-                self.sun_light.intensity = (self.sun_light.intensity + INTENSITY_DELTA).min(2.0);
+                self.sun_light.intensity = (self.sun_light.intensity + INTENSITY_DELTA).min(MAX_INTENSITY);
             }
             VirtualKeyCode::O => {
                 self.sun_light.intensity = (self.sun_light.intensity - INTENSITY_DELTA).max(0.0);
             }
+            // Shadow cascade debug view
+            VirtualKeyCode::C => {
+                self.shadow.debug_cascades = !self.shadow.debug_cascades;
+            }
             _ => handled = false,
         }
 
@@ -163,8 +197,8 @@ pub struct PointLight {
     _padding: u32,
     pub color: [f32; 3],
     pub intensity: f32,
-    pub radius: f32,
-    pub falloff: f32,
+    pub radius: f32,   // Distance at which the light fades out completely
+    pub falloff: f32,  // Exponent of the range window, 1.0 is the standard smooth cutoff
     _padding2: [f32; 2],
 }
 
@@ -200,4 +234,211 @@ impl Light for PointLight {
     fn set_intensity(&mut self, intensity: f32) {
         self.intensity = intensity;
     }
-} 
\ No newline at end of file
+}
+
+/// Spot light with position, direction and cone angles
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct SpotLight {
+    pub position: [f32; 3],
+    pub range: f32,
+    pub direction: [f32; 3],
+    pub intensity: f32,
+    pub color: [f32; 3],
+    pub inner_cone_cos: f32, // Cosine of the angle where the falloff starts
+    pub outer_cone_cos: f32, // Cosine of the angle where the light reaches zero
+    _padding: [f32; 3],
+}
+
+#[allow(dead_code)]
+impl SpotLight {
+    /// Create a new spot light with cone half-angles
+    pub fn new(
+        position: Point3<f32>,
+        direction: [f32; 3],
+        color: [f32; 3],
+        intensity: f32,
+        range: f32,
+        inner_angle: Deg<f32>,
+        outer_angle: Deg<f32>,
+    ) -> Self {
+        let mut light = Self {
+            position: [position.x, position.y, position.z],
+            range,
+            direction: [0.0, -1.0, 0.0],
+            intensity,
+            color,
+            inner_cone_cos: 1.0,
+            outer_cone_cos: 1.0,
+            _padding: [0.0; 3],
+        };
+        light.set_direction(direction);
+        light.set_cone_angles(inner_angle, outer_angle);
+        light
+    }
+    
+    /// Point the light in a new direction
+    pub fn set_direction(&mut self, direction: [f32; 3]) {
+        let length = (direction[0] * direction[0] + direction[1] * direction[1] + direction[2] * direction[2]).sqrt();
+        if length > 0.0 {
+            self.direction = [direction[0] / length, direction[1] / length, direction[2] / length];
+        }
+    }
+    
+    /// Set the inner and outer cone half-angles
+    pub fn set_cone_angles(&mut self, inner_angle: Deg<f32>, outer_angle: Deg<f32>) {
+        let outer = Rad::from(outer_angle).0;
+        let inner = Rad::from(inner_angle).0.min(outer);
+        self.inner_cone_cos = inner.cos();
+        self.outer_cone_cos = outer.cos();
+    }
+}
+
+impl Light for SpotLight {
+    fn get_color(&self) -> [f32; 3] {
+        self.color
+    }
+    
+    fn set_color(&mut self, color: [f32; 3]) {
+        self.color = color;
+    }
+    
+    fn get_intensity(&self) -> f32 {
+        self.intensity
+    }
+    
+    fn set_intensity(&mut self, intensity: f32) {
+        self.intensity = intensity;
+    }
+}
+
+/// Handle to a light added to `SceneLights`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct LightId(u32);
+
+/// Point and spot lights in the scene. The sun is lit separately through its own buffer.
+#[derive(Debug, Default)]
+pub struct SceneLights {
+    point_lights: Vec<(LightId, PointLight)>,
+    spot_lights: Vec<(LightId, SpotLight)>,
+    next_id: u32,
+    dirty: bool,
+}
+
+#[allow(dead_code)]
+impl SceneLights {
+    /// Create an empty light list
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    fn next_id(&mut self) -> LightId {
+        let id = LightId(self.next_id);
+        self.next_id += 1;
+        self.dirty = true;
+        id
+    }
+    
+    /// Add a point light to the scene
+    pub fn add_point_light(&mut self, light: PointLight) -> LightId {
+        let id = self.next_id();
+        self.point_lights.push((id, light));
+        id
+    }
+    
+    /// Add a spot light to the scene
+    pub fn add_spot_light(&mut self, light: SpotLight) -> LightId {
+        let id = self.next_id();
+        self.spot_lights.push((id, light));
+        id
+    }
+    
+    /// Remove a light from the scene, returning false if it was not found
+    pub fn remove(&mut self, id: LightId) -> bool {
+        let count = self.point_lights.len() + self.spot_lights.len();
+        self.point_lights.retain(|(light_id, _)| *light_id != id);
+        self.spot_lights.retain(|(light_id, _)| *light_id != id);
+        let removed = self.point_lights.len() + self.spot_lights.len() != count;
+        self.dirty |= removed;
+        removed
+    }
+    
+    /// Remove every point and spot light
+    pub fn clear(&mut self) {
+        self.point_lights.clear();
+        self.spot_lights.clear();
+        self.dirty = true;
+    }
+    
+    /// Get a point light for modification
+    pub fn point_light_mut(&mut self, id: LightId) -> Option<&mut PointLight> {
+        let light = self.point_lights.iter_mut().find(|(light_id, _)| *light_id == id)?;
+        self.dirty = true;
+        Some(&mut light.1)
+    }
+    
+    /// Get a spot light for modification
+    pub fn spot_light_mut(&mut self, id: LightId) -> Option<&mut SpotLight> {
+        let light = self.spot_lights.iter_mut().find(|(light_id, _)| *light_id == id)?;
+        self.dirty = true;
+        Some(&mut light.1)
+    }
+    
+    /// Iterate over the point lights
+    pub fn point_lights(&self) -> impl Iterator<Item = &PointLight> {
+        self.point_lights.iter().map(|(_, light)| light)
+    }
+    
+    /// Iterate over the spot lights
+    pub fn spot_lights(&self) -> impl Iterator<Item = &SpotLight> {
+        self.spot_lights.iter().map(|(_, light)| light)
+    }
+    
+    /// Number of point lights
+    pub fn point_light_count(&self) -> usize {
+        self.point_lights.len()
+    }
+    
+    /// Number of spot lights
+    pub fn spot_light_count(&self) -> usize {
+        self.spot_lights.len()
+    }
+    
+    /// Returns true once after any change, so the GPU copy can be refreshed
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scene_lights_add_and_remove_by_id() {
+        let mut lights = SceneLights::new();
+        let point = lights.add_point_light(PointLight::new(Point3::new(0.0, 1.0, 0.0), [1.0, 0.0, 0.0], 5.0, 10.0));
+        let spot = lights.add_spot_light(SpotLight::new(
+            Point3::new(0.0, 5.0, 0.0),
+            [0.0, -2.0, 0.0],
+            [1.0, 1.0, 1.0],
+            10.0,
+            20.0,
+            Deg(20.0),
+            Deg(30.0),
+        ));
+        assert_ne!(point, spot);
+        assert!(lights.take_changed());
+        assert!(!lights.take_changed());
+
+        assert_eq!(lights.spot_lights().next().unwrap().direction, [0.0, -1.0, 0.0]);
+        lights.point_light_mut(point).unwrap().intensity = 2.0;
+        assert!(lights.take_changed());
+
+        assert!(lights.remove(point));
+        assert!(!lights.remove(point));
+        assert_eq!(lights.point_light_count(), 0);
+        assert_eq!(lights.spot_light_count(), 1);
+        assert!(lights.point_light_mut(point).is_none());
+    }
+}
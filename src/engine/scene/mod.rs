@@ -6,7 +6,9 @@ pub mod game_object;
 #[allow(unused)]
 pub use camera::Camera;
 #[allow(unused_imports)]
-pub use light::{Light, SunLight, SunController};
+pub use light::{Light, SunLight, SunController, ShadowSettings, MAX_SHADOW_CASCADES};
+#[allow(unused_imports)]
+pub use light::{PointLight, SpotLight, SceneLights, LightId};
 pub use model::{Model, Material, MaterialInstance};
 #[allow(unused)]
 pub use game_object::{GameObject, Component, Transform, ModelComponent}; 
\ No newline at end of file
@@ -1,6 +1,9 @@
+use std::ops::Range;
 use std::path::PathBuf;
 use wgpu;
+use cgmath::{Matrix4, Point3, SquareMatrix};
 use crate::engine::renderer::mesh::Mesh;
+use crate::engine::renderer::{BlendMode, CullingStats, Frustum, LodChoice, LodSelector, MaterialPass, MaterialUniforms};
 
 /// Material data for rendering
 #[derive(Debug)]
@@ -11,37 +14,82 @@ pub struct Material {
     pub normal_texture: Option<PathBuf>,
     pub metallic_texture: Option<PathBuf>,
     pub roughness_texture: Option<PathBuf>,
+    pub occlusion_texture: Option<PathBuf>,
+    pub emissive_texture: Option<PathBuf>,
     pub ambient: [f32; 3],
     pub diffuse: [f32; 3],
     pub specular: [f32; 3],
+    pub emissive: [f32; 3],
     pub metallic: f32,
     pub roughness: f32,
     pub shininess: f32,
+    pub dissolve: f32,      // Opacity from the MTL `d`, below 1 blends the material
+    pub alpha_cutout: bool, // The MTL names an alpha map (`map_d`), so the albedo alpha cuts the surface out
 }
 
 #[allow(dead_code)]
 /// Material instance with GPU resources
 pub struct MaterialInstance {
     pub material_id: usize,
-    pub diffuse_bind_group: wgpu::BindGroup,
+    pub bind_group: wgpu::BindGroup,
+    pub uniform_buffer: wgpu::Buffer,
+    pub uniforms: MaterialUniforms,
+}
+
+#[allow(dead_code)]
+impl MaterialInstance {
+    /// Change the material's factors
+    pub fn set_uniforms(&mut self, queue: &wgpu::Queue, uniforms: MaterialUniforms) {
+        self.uniforms = uniforms;
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+    }
 }
 
 /// 3D Model with meshes and materials
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<MaterialInstance>,
+    pub bind_group: wgpu::BindGroup, // Transforms and sun light shared by every mesh
+    transform: Matrix4<f32>,         // Model-to-world transform used for culling, kept equal to the uniform buffer's
 }
 
 #[allow(dead_code)]
 impl Model {
-    /// Create a new empty model
-    pub fn new() -> Self {
+    /// Create a new model without meshes
+    pub fn new(bind_group: wgpu::BindGroup) -> Self {
         Self {
             meshes: Vec::new(),
             materials: Vec::new(),
+            bind_group,
+            transform: Matrix4::identity(),
+        }
+    }
+    
+    /// Create a model from its meshes and materials, placed at the origin
+    pub fn with_meshes(meshes: Vec<Mesh>, materials: Vec<MaterialInstance>, bind_group: wgpu::BindGroup) -> Self {
+        Self {
+            meshes,
+            materials,
+            bind_group,
+            transform: Matrix4::identity(),
         }
     }
     
+    /// The model-to-world transform
+    pub fn transform(&self) -> Matrix4<f32> {
+        self.transform
+    }
+    
+    /// Place the model in the world, writing the transform to the model matrix of the uniform
+    /// buffer its bind group holds so that culling and drawing agree on where it is
+    pub fn set_transform(&mut self, queue: &wgpu::Queue, uniform_buffer: &wgpu::Buffer, transform: Matrix4<f32>) {
+        self.transform = transform;
+        let model: [[f32; 4]; 4] = transform.into();
+        // The model matrix follows the view-projection matrix
+        let offset = std::mem::size_of::<[[f32; 4]; 4]>() as wgpu::BufferAddress;
+        queue.write_buffer(uniform_buffer, offset, bytemuck::cast_slice(&[model]));
+    }
+    
     /// Add a mesh to the model
     pub fn add_mesh(&mut self, mesh: Mesh) {
         self.meshes.push(mesh);
@@ -55,13 +103,121 @@ impl Model {
     /// Draw the model with the given render pass
     pub fn draw<'a, 'b>(&'a self, render_pass: &mut wgpu::RenderPass<'b>) 
     where 'a: 'b {
+        self.draw_instanced(render_pass, 0..1);
+    }
+    
+    /// Draw a range of the instances bound to vertex buffer 1
+    pub fn draw_instanced<'a, 'b>(&'a self, render_pass: &mut wgpu::RenderPass<'b>, instances: Range<u32>) 
+    where 'a: 'b {
+        self.draw_meshes(render_pass, instances, |_| Some(0));
+    }
+    
+    /// Returns true if `pass` draws the mesh at `index`
+    pub fn mesh_in_pass(&self, index: usize, pass: MaterialPass) -> bool {
+        pass.includes(&self.materials[self.meshes[index].material_id].uniforms)
+    }
+    
+    /// Draw the meshes of the model that belong in `pass`, skipping those whose bounds lie
+    /// outside the frustum and drawing the rest at the levels of detail `lods` picks for them
+    pub fn draw_culled<'a, 'b>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'b>,
+        pass: MaterialPass,
+        frustum: &Frustum,
+        lods: &'a LodSelector,
+        stats: &mut CullingStats,
+    ) where 'a: 'b {
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        for index in 0..self.meshes.len() {
+            if self.mesh_in_pass(index, pass) {
+                self.draw_mesh_culled(render_pass, index, frustum, lods, stats);
+            }
+        }
+    }
+    
+    /// Draw the mesh at `index` unless its bounds lie outside the frustum, with the model's
+    /// bind group and an identity instance already set
+    pub fn draw_mesh_culled<'a, 'b>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'b>,
+        index: usize,
+        frustum: &Frustum,
+        lods: &'a LodSelector,
+        stats: &mut CullingStats,
+    ) where 'a: 'b {
+        let mesh = &self.meshes[index];
+        let aabb = mesh.aabb.transform(&self.transform);
+        let visible = frustum.intersects_sphere(&mesh.bounding_sphere.transform(&self.transform))
+            && frustum.intersects_aabb(&aabb);
+        stats.record(visible);
+        if !visible {
+            return;
+        }
+        match lods.select(&mesh.lods, &aabb) {
+            LodChoice::Level(level) => self.draw_mesh(render_pass, index, level, 0..1),
+            LodChoice::CrossFade { from, to, fade } => {
+                // Each level dithered with an identity instance carrying the fade
+                render_pass.set_vertex_buffer(1, lods.fade_instance(fade));
+                self.draw_mesh(render_pass, index, from, 0..1);
+                render_pass.set_vertex_buffer(1, lods.fade_instance(-fade));
+                self.draw_mesh(render_pass, index, to, 0..1);
+                render_pass.set_vertex_buffer(1, lods.fade_instance(0.0));
+            }
+        }
+    }
+    
+    /// Returns the world-space center of the bounds of the mesh at `index`
+    pub fn mesh_center(&self, index: usize) -> Point3<f32> {
+        self.meshes[index].aabb.transform(&self.transform).center()
+    }
+    
+    /// Returns the blend mode of the material of the mesh at `index`
+    pub fn mesh_blend_mode(&self, index: usize) -> BlendMode {
+        self.materials[self.meshes[index].material_id].uniforms.blend_mode()
+    }
+    
+    /// Draw a range of instances of each mesh at the level `level` returns, skipping meshes it returns None for
+    pub fn draw_meshes<'a, 'b>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'b>,
+        instances: Range<u32>,
+        mut level: impl FnMut(usize) -> Option<usize>,
+    ) where 'a: 'b {
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        for index in 0..self.meshes.len() {
+            if let Some(level) = level(index) {
+                self.draw_mesh(render_pass, index, level, instances.clone());
+            }
+        }
+    }
+    
+    /// Draw a range of instances of one level of detail of a mesh. The model's bind group must already be set.
+    pub fn draw_mesh<'a, 'b>(&'a self, render_pass: &mut wgpu::RenderPass<'b>, index: usize, level: usize, instances: Range<u32>) 
+    where 'a: 'b {
+        let mesh = &self.meshes[index];
+        let lod = &mesh.lods[level];
+        let material = &self.materials[mesh.material_id];
+        
+        render_pass.set_bind_group(3, &material.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(lod.first_index..lod.first_index + lod.index_count, lod.base_vertex, instances);
+    }
+    
+    /// Draw the model's geometry without binding materials, for depth-only and debug passes
+    pub fn draw_geometry<'a, 'b>(&'a self, render_pass: &mut wgpu::RenderPass<'b>) 
+    where 'a: 'b {
+        self.draw_geometry_instanced(render_pass, 0..1);
+    }
+    
+    /// Draw a range of instances of the model's geometry without binding materials
+    pub fn draw_geometry_instanced<'a, 'b>(&'a self, render_pass: &mut wgpu::RenderPass<'b>, instances: Range<u32>) 
+    where 'a: 'b {
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
         for mesh in &self.meshes {
-            let material = &self.materials[mesh.material_id];
-            
-            render_pass.set_bind_group(0, &material.diffuse_bind_group, &[]);
             render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
             render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-            render_pass.draw_indexed(0..mesh.num_indices, 0, 0..1);
+            render_pass.draw_indexed(0..mesh.num_indices, 0, instances.clone());
         }
     }
 } 
\ No newline at end of file
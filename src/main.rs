@@ -1,6 +1,6 @@
 use winit::event_loop::EventLoop;
 use winit::window::WindowBuilder;
-use cgmath::Vector3;
+use cgmath::{Point3, Rad, Vector3};
 
 // Import the engine module
 mod engine;
@@ -18,8 +18,34 @@ async fn main() -> anyhow::Result<()> {
         .build(&event_loop)
         .unwrap();
     
+    // `--deferred` lights the scene with the deferred path, `--oit` composites transparent meshes
+    // with order-independent transparency, `--split-screen` adds a second camera on the right
+    // half of the window, any other argument is an environment map
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let deferred = args.iter().any(|arg| arg == "--deferred");
+    let oit = args.iter().any(|arg| arg == "--oit");
+    let split_screen = args.iter().any(|arg| arg == "--split-screen");
+    let environment = args.iter().find(|arg| !arg.starts_with("--"));
+    
     // Initialize the application
     let mut app = engine::core::application::Application::new(window).await;
+    if deferred {
+        app.set_render_path(engine::renderer::RenderPath::Deferred)?;
+    }
+    if oit {
+        app.set_transparency_mode(engine::renderer::TransparencyMode::WeightedBlended);
+    }
+    if split_screen {
+        // The player on the left, a fixed camera looking at the origin from the side on the right
+        let viewports = engine::renderer::Viewport::split_screen(2);
+        app.set_viewport(viewports[0]);
+        let side_camera = engine::scene::Camera::new(
+            Point3::new(12.0, 6.0, 0.0),
+            Rad(std::f32::consts::PI),
+            Rad(-0.4),
+        );
+        app.add_viewport_camera(side_camera, viewports[1]);
+    }
     
     // Add a custom model as a game object (example usage)
     app.add_game_object_with_model(
@@ -28,6 +54,11 @@ async fn main() -> anyhow::Result<()> {
         Vector3::new(0.0, 2.0, 0.0)
     ).await?;
     
+    // Light the scene with an environment map passed on the command line
+    if let Some(path) = environment {
+        app.load_environment(path)?;
+    }
+    
     // Run the application
     app.run(event_loop);
     